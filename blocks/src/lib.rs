@@ -24,11 +24,18 @@ impl std::fmt::Display for BlockTypes {
     }
 }
 
-pub fn decode_block<T: TdfOutput>(tdf_output: &mut T, block: &[u8]) -> std::io::Result<BlockTypes> {
+/// Whether a block has never been written (erased or zeroed)
+pub fn is_empty_block(block: &[u8]) -> bool {
     let wrap_count = block[0];
     let block_type = block[1];
 
-    if (wrap_count == 0x00 && block_type == 0x00) || (wrap_count == 0xFF && block_type == 0xFF) {
+    (wrap_count == 0x00 && block_type == 0x00) || (wrap_count == 0xFF && block_type == 0xFF)
+}
+
+pub fn decode_block<T: TdfOutput>(tdf_output: &mut T, block: &[u8]) -> std::io::Result<BlockTypes> {
+    let block_type = block[1];
+
+    if is_empty_block(block) {
         return Ok(BlockTypes::EMPTY);
     } else if block_type == 0x02 {
         tdf::block_decode(None, &block[2..], tdf_output)?;
@@ -418,6 +418,28 @@ def decoders_gen(tdf_defs, output):
             return base * field["num"]
         return base
 
+    def csv_value(fmt, expr):
+        if m := re.fullmatch(r"0x\{:0?(\d*)x\}", fmt):
+            return ("hex", [expr, m.group(1) or "0"])
+        if expr.startswith("tdf_field_read_string_to_str"):
+            return ("quoted", [f"&{expr}"])
+        if expr.startswith(("tdf_field_read_vla_to_str", "tdf_field_read_fixed_bytes_to_hex")):
+            return ("text", [f"&{expr}"])
+        if " as f64" in expr or expr.startswith(("cursor.read_f32", "cursor.read_f64")):
+            return ("float", [expr])
+        return ("display", [expr])
+
+    def csv_push(fmt, conv):
+        method, args = csv_value(fmt, conv[1])
+        return f"row.{method}({', '.join(args)})"
+
+    def csv_json_push(fmt, conv, key):
+        method, args = csv_value(fmt, conv[1])
+        if method == "quoted":
+            method = "text"
+        key_arg = f'Some("{key}")' if key is not None else "None"
+        return f"items.{method}({', '.join([key_arg] + args)})"
+
     # Generate rust conversion functions
     for _tdf_id, info in tdf_defs["definitions"].items():
         info["rust_convs"] = []
@@ -446,21 +468,33 @@ def decoders_gen(tdf_defs, output):
                 variable_item=True,
             )
             prefix_columns = len(info["rust_convs"]) + 1
+            variable_name = variable_field["name"]
+            variable_objects = variable_field["type"] in structs
             info["csv_variable"] = {
+                "name": variable_name,
+                "base_count": len(info["rust_convs"]),
+                "objects": "true" if variable_objects else "false",
+                "json_pushes": [
+                    csv_json_push(
+                        f,
+                        c,
+                        c[0][len(variable_name) + 1 :] if variable_objects else None,
+                    )
+                    for f, c in zip(variable_fmt, variable_convs)
+                ],
+                "base_pushes": [
+                    csv_push(f, c) for f, c in zip(fmt, info["rust_convs"])
+                ],
+                "pushes": [csv_push(f, c) for f, c in zip(variable_fmt, variable_convs)],
+                "prefix_columns": prefix_columns,
                 "base_size": sum(csv_field_byte_size(field) for field in fields),
                 "item_size": csv_field_byte_size(variable_field, repeated_item=True),
-                "fmt": ",".join(variable_fmt),
-                "empty_fmt": ",".join(fmt + ["{}"] * len(variable_fmt)),
-                "base_convs": list(info["rust_convs"]),
-                "convs": variable_convs,
-                "empty_suffix": "," * len(variable_fmt),
-                "continuation_prefix": "," * prefix_columns,
             }
             info["rust_convs"] += variable_convs
             fmt += variable_fmt
 
+        info["csv_pushes"] = [csv_push(f, c) for f, c in zip(fmt, info["rust_convs"])]
         info["rust_head"] = ",".join([f'"{c[0]}"' for c in info["rust_convs"]])
-        info["rust_fmt"] = ",".join(fmt)
 
     rust_array_type = {
         "i8": "Int8Array",
@@ -0,0 +1,518 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::CancellationToken;
+use crate::{disk_image, fs_util, input};
+
+const COPY_BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+    TarZst,
+    /// A single compressed file
+    Zst,
+}
+
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+        Some(ArchiveKind::TarZst)
+    } else if name.ends_with(".tar") {
+        Some(ArchiveKind::Tar)
+    } else if name.ends_with(".zst") {
+        Some(ArchiveKind::Zst)
+    } else {
+        None
+    }
+}
+
+/// Whether `path` is an archive, compressed file or disk image that Infuse-IoT files can be read
+/// from
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file() && (archive_kind(path).is_some() || disk_image::is_disk_image(path))
+}
+
+fn archive_error(path: &Path, err: impl Into<io::Error>) -> io::Error {
+    let err = err.into();
+    io::Error::new(
+        err.kind(),
+        format!("Failed to read archive '{}': {err}", path.display()),
+    )
+}
+
+/// Device ID of an archive entry that should be decoded, `None` for other entries.
+///
+/// A single compressed file only needs a hex device ID in its name, like the fallback for
+/// uncompressed files.
+fn entry_device_id(kind: ArchiveKind, name: &str) -> Option<u64> {
+    match kind {
+        ArchiveKind::Zst => {
+            fs_util::infuse_iot_device_id(name).or_else(|| fs_util::fallback_device_id(name))
+        }
+        _ => fs_util::infuse_iot_device_id(name),
+    }
+}
+
+/// Call `f` with the file name, without any folders, and contents of each file in the archive.
+///
+/// Entries that are stored without compression also have the `(offset, length)` of their data
+/// in the archive, so that they can be read in place.
+fn for_each_entry<F: FnMut(&str, &mut dyn Read, Option<(u64, u64)>) -> io::Result<()>>(
+    path: &Path,
+    kind: ArchiveKind,
+    mut f: F,
+) -> io::Result<()> {
+    let file = File::open(path).map_err(|err| archive_error(path, err))?;
+
+    match kind {
+        ArchiveKind::Zip => {
+            let mut archive = zip::ZipArchive::new(file).map_err(|err| archive_error(path, err))?;
+            for idx in 0..archive.len() {
+                let mut entry = archive
+                    .by_index(idx)
+                    .map_err(|err| archive_error(path, err))?;
+                // Entries are compressed individually, so skipped entries are never decompressed
+                if !entry.is_file() {
+                    continue;
+                }
+                let Some(name) = entry.name().rsplit(['/', '\\']).next().map(str::to_string) else {
+                    continue;
+                };
+                let stored = (entry.compression() == zip::CompressionMethod::Stored
+                    && !entry.encrypted())
+                .then(|| (entry.data_start(), entry.size()));
+                f(&name, &mut entry, stored)?;
+            }
+            Ok(())
+        }
+        ArchiveKind::Tar => tar_entries(path, file, true, f),
+        ArchiveKind::TarGz => tar_entries(path, flate2::read::GzDecoder::new(file), false, f),
+        ArchiveKind::TarZst => {
+            let decoder = zstd::Decoder::new(file).map_err(|err| archive_error(path, err))?;
+            tar_entries(path, decoder, false, f)
+        }
+        ArchiveKind::Zst => {
+            let name = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or_default();
+            let mut decoder = zstd::Decoder::new(file).map_err(|err| archive_error(path, err))?;
+            f(name, &mut decoder, None)
+        }
+    }
+}
+
+/// `in_place` when `reader` is the archive file itself, rather than a decompressor
+fn tar_entries<R: Read, F: FnMut(&str, &mut dyn Read, Option<(u64, u64)>) -> io::Result<()>>(
+    path: &Path,
+    reader: R,
+    in_place: bool,
+    mut f: F,
+) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries().map_err(|err| archive_error(path, err))? {
+        let mut entry = entry.map_err(|err| archive_error(path, err))?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+        let entry_path = entry.path().map_err(|err| archive_error(path, err))?;
+        let Some(name) = entry_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .map(str::to_string)
+        else {
+            continue;
+        };
+        let stored = in_place.then(|| (entry.raw_file_position(), entry.size()));
+        f(&name, &mut entry, stored)?;
+    }
+    Ok(())
+}
+
+/// Names of the Infuse-IoT files in an archive, by device ID.
+///
+/// Compressed tar archives are decompressed to list them, but nothing is written to disk. Disk
+/// images are listed from their filesystem.
+pub fn find_archive_infuse_iot_files(path: &Path) -> io::Result<HashMap<u64, Vec<String>>> {
+    let Some(kind) = archive_kind(path) else {
+        if disk_image::is_disk_image(path) {
+            return disk_image::find_image_infuse_iot_files(path);
+        }
+        return Err(unsupported_archive(path));
+    };
+
+    let mut files: HashMap<u64, Vec<String>> = HashMap::new();
+    for_each_entry(path, kind, |name, _, _| {
+        if let Some(device_id) = entry_device_id(kind, name) {
+            files.entry(device_id).or_default().push(name.to_string());
+        }
+        Ok(())
+    })?;
+    for names in files.values_mut() {
+        names.sort_by_key(|name| fs_util::subfile_number(Path::new(name)));
+    }
+    Ok(files)
+}
+
+/// Infuse-IoT files extracted from an archive into a temporary folder, which is removed when
+/// this is dropped.
+///
+/// Files that are stored in the archive without compression aren't copied, their paths in the
+/// folder are read from the archive in place through [`ExtractedArchive::mapped_inputs`].
+pub struct ExtractedArchive {
+    folder: tempfile::TempDir,
+    /// Archive the files were extracted from
+    source: PathBuf,
+    files: HashMap<u64, Vec<PathBuf>>,
+    /// Paths that are read in place from the archive
+    mapped: input::MappedInputs,
+    warnings: Vec<String>,
+}
+
+impl ExtractedArchive {
+    /// Problems found while extracting, such as a damaged disk image filesystem
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Extracted files by device ID, as returned by [`fs_util::find_infuse_iot_files`]
+    pub fn files(&self) -> &HashMap<u64, Vec<PathBuf>> {
+        &self.files
+    }
+
+    /// Files that are read in place from the archive, to pass to the decode along with [`Self::files`]
+    pub fn mapped_inputs(&self) -> &input::MappedInputs {
+        &self.mapped
+    }
+
+    /// Add a file of `device_id`, read in place from `extents` if it wasn't written to the folder
+    fn add_file(&mut self, device_id: u64, path: PathBuf, extents: Option<input::FileExtents>) {
+        if let Some(extents) = extents {
+            self.mapped.insert(path.clone(), extents);
+        }
+        if let Some(name) = path.file_name() {
+            self.mapped
+                .insert_entry(path.clone(), self.source.join(name));
+        }
+        self.files.entry(device_id).or_default().push(path);
+    }
+
+    fn sort_files(&mut self) {
+        for files in self.files.values_mut() {
+            files.sort_by_key(|file| fs_util::subfile_number(file));
+        }
+    }
+}
+
+fn unsupported_archive(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("'{}' is not a supported archive", path.display()),
+    )
+}
+
+/// Decompress the Infuse-IoT files of an archive, optionally only those of `device_ids`.
+///
+/// Only the compressed Infuse-IoT files are written to disk, each is streamed out of the archive
+/// so the archive is never fully decompressed in memory. Files of plain tar archives and files
+/// stored in zip archives without compression are read in place. `block_size` is only used to
+/// recover blocks from a disk image with a damaged filesystem.
+pub fn extract_infuse_iot_files(
+    path: &Path,
+    device_ids: Option<&[u64]>,
+    block_size: usize,
+    cancel: &CancellationToken,
+) -> io::Result<ExtractedArchive> {
+    let kind = archive_kind(path);
+    if kind.is_none() && !disk_image::is_disk_image(path) {
+        return Err(unsupported_archive(path));
+    }
+    // Created before extracting so that a failed extraction is cleaned up
+    let mut extracted = ExtractedArchive {
+        folder: tempfile::Builder::new()
+            .prefix("infuse_decoder_archive_")
+            .tempdir()?,
+        source: path.to_path_buf(),
+        files: HashMap::new(),
+        mapped: input::MappedInputs::default(),
+        warnings: Vec::new(),
+    };
+
+    let Some(kind) = kind else {
+        let (entries, warnings) = disk_image::extract_image_infuse_iot_files(
+            path,
+            device_ids,
+            block_size,
+            cancel,
+            extracted.folder.path(),
+        )?;
+        for entry in entries {
+            extracted.add_file(entry.device_id, entry.path, entry.extents);
+        }
+        extracted.warnings = warnings;
+        extracted.sort_files();
+        return Ok(extracted);
+    };
+    for_each_entry(path, kind, |name, reader, stored| {
+        cancel.check()?;
+        let Some(device_id) = entry_device_id(kind, name) else {
+            return Ok(());
+        };
+        if device_ids.is_some_and(|device_ids| !device_ids.contains(&device_id)) {
+            return Ok(());
+        }
+
+        let destination = extracted.folder.path().join(name);
+        if extracted
+            .files
+            .get(&device_id)
+            .is_some_and(|files| files.contains(&destination))
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Archive '{}' contains {name} more than once",
+                    path.display()
+                ),
+            ));
+        }
+        if let Some((offset, len)) = stored {
+            let extents = input::FileExtents {
+                source: path.to_path_buf(),
+                extents: vec![(offset, len)],
+            };
+            extracted.add_file(device_id, destination, Some(extents));
+            return Ok(());
+        }
+
+        let mut file = File::create(&destination)?;
+        extracted.add_file(device_id, destination, None);
+        let mut buffer = vec![0; COPY_BUFFER_SIZE];
+        loop {
+            cancel.check()?;
+            let count = reader
+                .read(&mut buffer)
+                .map_err(|err| archive_error(path, err))?;
+            if count == 0 {
+                break;
+            }
+            file.write_all(&buffer[..count])?;
+        }
+        Ok(())
+    })?;
+
+    extracted.sort_files();
+    Ok(extracted)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    fn write_tar_gz(path: &Path, entries: &[(&str, &[u8])]) {
+        let encoder =
+            flate2::write::GzEncoder::new(File::create(path).unwrap(), flate2::Compression::fast());
+        let mut builder = tar::Builder::new(encoder);
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, *data).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn infuse_files_are_extracted_from_tar_gz() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("dump.tar.gz");
+        write_tar_gz(
+            &archive,
+            &[
+                ("INFUSE/infuse_0123456789abcdef_1.bin", &[1; 512]),
+                ("INFUSE/infuse_0123456789abcdef_0.bin", &[0; 512]),
+                ("INFUSE/infuse_fedcba9876543210_0.bin", &[2; 512]),
+                ("INFUSE/notes.txt", b"ignored"),
+            ],
+        );
+
+        let found = find_archive_infuse_iot_files(&archive).unwrap();
+        assert_eq!(found.len(), 2);
+        assert_eq!(
+            found[&0x0123_4567_89ab_cdef],
+            vec![
+                "infuse_0123456789abcdef_0.bin",
+                "infuse_0123456789abcdef_1.bin"
+            ]
+        );
+
+        let extracted = extract_infuse_iot_files(
+            &archive,
+            Some(&[0x0123_4567_89ab_cdef]),
+            512,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let files = &extracted.files()[&0x0123_4567_89ab_cdef];
+        assert_eq!(extracted.files().len(), 1);
+        assert_eq!(fs::read(&files[1]).unwrap(), vec![1; 512]);
+
+        let folder = extracted.folder.path().to_path_buf();
+        drop(extracted);
+        assert!(!folder.exists());
+    }
+
+    #[test]
+    fn zip_and_zst_inputs_are_extracted() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("dump.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        writer
+            .start_file(
+                "INFUSE/infuse_0123456789abcdef_0.bin",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        writer.write_all(&[3; 512]).unwrap();
+        writer.finish().unwrap();
+
+        let extracted =
+            extract_infuse_iot_files(&archive, None, 512, &CancellationToken::new()).unwrap();
+        let files = &extracted.files()[&0x0123_4567_89ab_cdef];
+        assert_eq!(fs::read(&files[0]).unwrap(), vec![3; 512]);
+
+        let compressed = dir.join("capture_fedcba9876543210.bin.zst");
+        fs::write(&compressed, zstd::encode_all(&[4u8; 512][..], 3).unwrap()).unwrap();
+        let extracted =
+            extract_infuse_iot_files(&compressed, None, 512, &CancellationToken::new()).unwrap();
+        let files = &extracted.files()[&0xfedc_ba98_7654_3210];
+        assert_eq!(fs::read(&files[0]).unwrap(), vec![4; 512]);
+    }
+
+    /// Contents of an extracted file, read the way the decoder reads its inputs
+    pub(crate) fn read_input(extracted: &ExtractedArchive, path: &Path) -> Vec<u8> {
+        let mut data = Vec::new();
+        extracted
+            .mapped_inputs()
+            .open(path)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        data
+    }
+
+    #[test]
+    fn uncompressed_entries_are_read_in_place() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("dump.tar");
+        let mut builder = tar::Builder::new(File::create(&archive).unwrap());
+        for (name, data) in [
+            ("infuse_0123456789abcdef_0.bin", [5; 512]),
+            ("infuse_0123456789abcdef_1.bin", [6; 512]),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &data[..]).unwrap();
+        }
+        builder.into_inner().unwrap();
+
+        let extracted =
+            extract_infuse_iot_files(&archive, None, 512, &CancellationToken::new()).unwrap();
+        let files = extracted.files()[&0x0123_4567_89ab_cdef].clone();
+        assert!(!files[0].exists(), "Nothing is copied out of a plain tar");
+        assert_eq!(read_input(&extracted, &files[0]), vec![5; 512]);
+        assert_eq!(read_input(&extracted, &files[1]), vec![6; 512]);
+        // The files are only read in place by decodes given the archive's mapped inputs
+        assert!(input::MappedInputs::default().input_len(&files[0]).is_err());
+
+        let archive = dir.join("dump.zip");
+        let mut writer = zip::ZipWriter::new(File::create(&archive).unwrap());
+        let stored = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        writer
+            .start_file("infuse_0123456789abcdef_0.bin", stored)
+            .unwrap();
+        writer.write_all(&[7; 512]).unwrap();
+        writer
+            .start_file(
+                "infuse_0123456789abcdef_1.bin",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        writer.write_all(&[8; 512]).unwrap();
+        writer.finish().unwrap();
+
+        let extracted =
+            extract_infuse_iot_files(&archive, None, 512, &CancellationToken::new()).unwrap();
+        let files = &extracted.files()[&0x0123_4567_89ab_cdef];
+        assert!(!files[0].exists());
+        assert_eq!(read_input(&extracted, &files[0]), vec![7; 512]);
+        assert!(files[1].exists(), "Compressed entries are extracted");
+        assert_eq!(read_input(&extracted, &files[1]), vec![8; 512]);
+    }
+
+    #[test]
+    fn warnings_name_the_archive_entries() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("dump.tar.gz");
+        write_tar_gz(
+            &archive,
+            &[
+                ("infuse_0123456789abcdef_0.bin", &[0; 512]),
+                ("infuse_0123456789abcdef_2.bin", &[0; 100]),
+            ],
+        );
+
+        let extracted =
+            extract_infuse_iot_files(&archive, None, 512, &CancellationToken::new()).unwrap();
+        let warnings = fs_util::input_file_warnings(
+            &extracted.files()[&0x0123_4567_89ab_cdef],
+            extracted.mapped_inputs(),
+            512,
+        );
+        let entry = archive.join("infuse_0123456789abcdef_2.bin");
+        assert_eq!(
+            warnings,
+            vec![
+                format!("Missing sub-file 1 before {}", entry.display()),
+                format!(
+                    "{} is 100 bytes, which is not a multiple of the 512 byte block size",
+                    entry.display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn cancelled_extraction_is_cleaned_up() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let archive = dir.join("dump.tar.gz");
+        write_tar_gz(&archive, &[("infuse_0123456789abcdef_0.bin", &[0; 512])]);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let err = extract_infuse_iot_files(&archive, None, 512, &cancel)
+            .err()
+            .expect("Cancelled extraction should fail");
+
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+    }
+}
@@ -35,3 +35,288 @@ impl fmt::Display for OutputFormat {
         }
     }
 }
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CsvDelimiter {
+    #[value(name = "comma")]
+    Comma,
+    #[value(name = "semicolon")]
+    Semicolon,
+    #[value(name = "tab")]
+    Tab,
+}
+
+impl CsvDelimiter {
+    pub fn as_char(&self) -> char {
+        match self {
+            CsvDelimiter::Comma => ',',
+            CsvDelimiter::Semicolon => ';',
+            CsvDelimiter::Tab => '\t',
+        }
+    }
+}
+
+impl fmt::Display for CsvDelimiter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CsvDelimiter::Comma => write!(f, "comma"),
+            CsvDelimiter::Semicolon => write!(f, "semicolon"),
+            CsvDelimiter::Tab => write!(f, "tab"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum DecimalSeparator {
+    #[value(name = "point")]
+    Point,
+    #[value(name = "comma")]
+    Comma,
+}
+
+impl DecimalSeparator {
+    pub fn as_char(&self) -> char {
+        match self {
+            DecimalSeparator::Point => '.',
+            DecimalSeparator::Comma => ',',
+        }
+    }
+}
+
+impl fmt::Display for DecimalSeparator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecimalSeparator::Point => write!(f, "point"),
+            DecimalSeparator::Comma => write!(f, "comma"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum VlaMode {
+    #[value(name = "rows")]
+    Rows,
+    #[value(name = "json")]
+    Json,
+    #[value(name = "wide")]
+    Wide,
+}
+
+impl VlaMode {
+    pub fn csv_mode(&self, max_items: usize) -> tdf::csv_format::CsvVlaMode {
+        match self {
+            VlaMode::Rows => tdf::csv_format::CsvVlaMode::Rows,
+            VlaMode::Json => tdf::csv_format::CsvVlaMode::Json,
+            VlaMode::Wide => tdf::csv_format::CsvVlaMode::Wide(max_items),
+        }
+    }
+}
+
+impl fmt::Display for VlaMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VlaMode::Rows => write!(f, "rows"),
+            VlaMode::Json => write!(f, "json"),
+            VlaMode::Wide => write!(f, "wide"),
+        }
+    }
+}
+
+/// How TDF streams are sampled onto the grid of a resampled output
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ResampleMethod {
+    /// Linear interpolation between the samples either side of the grid time
+    #[value(name = "linear")]
+    Linear,
+    /// The sample closest to the grid time
+    #[value(name = "nearest")]
+    Nearest,
+}
+
+impl fmt::Display for ResampleMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResampleMethod::Linear => write!(f, "linear"),
+            ResampleMethod::Nearest => write!(f, "nearest"),
+        }
+    }
+}
+
+/// Parse a UTC time from RFC3339 (`2025-01-01T12:00:00Z`), `2025-01-01 12:00:00` or `2025-01-01`
+pub fn parse_utc_time(value: &str) -> Result<chrono::DateTime<chrono::Utc>, String> {
+    let value = value.trim();
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.to_utc());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"] {
+        if let Ok(time) = chrono::NaiveDateTime::parse_from_str(value, format) {
+            return Ok(time.and_utc());
+        }
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    Err(format!(
+        "Invalid UTC time '{value}', expected e.g. 2025-01-01T12:00:00Z or 2025-01-01"
+    ))
+}
+
+/// Parse a finite number greater than zero
+pub fn parse_positive_f64(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if number.is_finite() && number > 0.0 => Ok(number),
+        _ => Err(format!(
+            "Invalid value '{value}', expected a number greater than zero"
+        )),
+    }
+}
+
+/// Parse a percentage from 0 to 100
+pub fn parse_percent(value: &str) -> Result<f64, String> {
+    match value.trim().parse::<f64>() {
+        Ok(number) if (0.0..=100.0).contains(&number) => Ok(number),
+        _ => Err(format!(
+            "Invalid value '{value}', expected a percentage from 0 to 100"
+        )),
+    }
+}
+
+/// TDFs and remote IDs to decode
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TdfSelection {
+    /// Only decode these TDF IDs (all TDFs when empty)
+    pub include_tdfs: Vec<u16>,
+    /// Never decode these TDF IDs
+    pub exclude_tdfs: Vec<u16>,
+    /// Only decode data from these remote IDs, `None` being the local device (all when empty)
+    pub include_remotes: Vec<Option<u64>>,
+    /// Never decode data from these remote IDs
+    pub exclude_remotes: Vec<Option<u64>>,
+}
+
+impl TdfSelection {
+    pub fn is_all(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn allows_tdf(&self, tdf_id: u16) -> bool {
+        (self.include_tdfs.is_empty() || self.include_tdfs.contains(&tdf_id))
+            && !self.exclude_tdfs.contains(&tdf_id)
+    }
+
+    pub fn allows_remote(&self, remote_id: Option<u64>) -> bool {
+        (self.include_remotes.is_empty() || self.include_remotes.contains(&remote_id))
+            && !self.exclude_remotes.contains(&remote_id)
+    }
+}
+
+/// TDFs with a known name, in ID order
+pub fn known_tdfs() -> Vec<(u16, String)> {
+    (0..=tdf::TDF_ID_MAX)
+        .map(|tdf_id| (tdf_id, tdf::decoders::tdf_name(&tdf_id)))
+        .filter(|(tdf_id, name)| *name != tdf_id.to_string())
+        .collect()
+}
+
+/// Parse a TDF from its name (e.g. `BATTERY_STATE`, case insensitive) or numeric ID
+pub fn parse_tdf_id(value: &str) -> Result<u16, String> {
+    let value = value.trim();
+    if let Ok(tdf_id) = value.parse::<u16>()
+        && tdf_id <= tdf::TDF_ID_MAX
+    {
+        return Ok(tdf_id);
+    }
+    known_tdfs()
+        .into_iter()
+        .find(|(_, name)| name.eq_ignore_ascii_case(value))
+        .map(|(tdf_id, _)| tdf_id)
+        .ok_or_else(|| format!("Unknown TDF '{value}'"))
+}
+
+/// Parse a remote device ID in hexadecimal, or `local` for data from the logging device itself
+pub fn parse_remote_id(value: &str) -> Result<Option<u64>, String> {
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("local") {
+        return Ok(None);
+    }
+    let digits = value.strip_prefix("0x").unwrap_or(value);
+    u64::from_str_radix(digits, 16)
+        .map(Some)
+        .map_err(|_| format!("Invalid remote ID '{value}', expected hexadecimal or 'local'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tdfs_parse_from_name_or_id() {
+        assert_eq!(parse_tdf_id("BATTERY_STATE"), Ok(2));
+        assert_eq!(parse_tdf_id("acc_4g"), Ok(11));
+        assert_eq!(parse_tdf_id("53"), Ok(53));
+        assert!(parse_tdf_id("NOT_A_TDF").is_err());
+    }
+
+    #[test]
+    fn remote_ids_parse_as_hex_or_local() {
+        assert_eq!(parse_remote_id("local"), Ok(None));
+        assert_eq!(
+            parse_remote_id("0123456789abcdef"),
+            Ok(Some(0x0123_4567_89ab_cdef))
+        );
+        assert_eq!(parse_remote_id("0x10"), Ok(Some(0x10)));
+        assert!(parse_remote_id("0x0x10").is_err());
+        assert!(parse_remote_id("xyz").is_err());
+    }
+
+    #[test]
+    fn exclusions_override_inclusions() {
+        let selection = TdfSelection {
+            include_tdfs: vec![2, 11],
+            exclude_tdfs: vec![11],
+            exclude_remotes: vec![Some(1)],
+            ..TdfSelection::default()
+        };
+
+        assert!(selection.allows_tdf(2));
+        assert!(!selection.allows_tdf(11));
+        assert!(!selection.allows_tdf(4));
+        assert!(selection.allows_remote(None));
+        assert!(!selection.allows_remote(Some(1)));
+    }
+
+    #[test]
+    fn percentages_are_within_range() {
+        assert_eq!(parse_percent("0"), Ok(0.0));
+        assert_eq!(parse_percent("12.5"), Ok(12.5));
+        assert_eq!(parse_percent("100"), Ok(100.0));
+        assert!(parse_percent("-1").is_err());
+        assert!(parse_percent("100.1").is_err());
+        assert!(parse_percent("NaN").is_err());
+        assert!(parse_percent("inf").is_err());
+    }
+
+    #[test]
+    fn positive_numbers_must_be_finite() {
+        assert_eq!(parse_positive_f64("2.5"), Ok(2.5));
+        assert!(parse_positive_f64("0").is_err());
+        assert!(parse_positive_f64("-1").is_err());
+        assert!(parse_positive_f64("inf").is_err());
+        assert!(parse_positive_f64("NaN").is_err());
+    }
+
+    #[test]
+    fn utc_times_parse_in_multiple_formats() {
+        let expected = chrono::DateTime::parse_from_rfc3339("2025-01-02T03:04:05Z")
+            .unwrap()
+            .to_utc();
+
+        assert_eq!(parse_utc_time("2025-01-02T03:04:05Z"), Ok(expected));
+        assert_eq!(parse_utc_time("2025-01-02 03:04:05"), Ok(expected));
+        assert_eq!(
+            parse_utc_time("2025-01-02"),
+            Ok(expected - chrono::Duration::seconds(3 * 3600 + 4 * 60 + 5))
+        );
+        assert!(parse_utc_time("yesterday").is_err());
+    }
+}
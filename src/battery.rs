@@ -0,0 +1,524 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use tdf::time::TDF_TICKS_PER_SECOND;
+
+use crate::continuity::serialize_tdf_time;
+
+const TDF_BATTERY_STATE: u16 = 2;
+const TDF_BATTERY_CHARGE_ACCUMULATED: u16 = 38;
+const TDF_BATTERY_VOLTAGE: u16 = 53;
+const TDF_BATTERY_SOC: u16 = 54;
+
+/// Change in state of charge (%) that reverses the direction of a cycle
+const CYCLE_HYSTERESIS_SOC: f64 = 2.0;
+/// Change in voltage (mV) that reverses the direction of a cycle, when state of charge isn't logged
+const CYCLE_HYSTERESIS_MV: f64 = 50.0;
+/// Voltage drop between consecutive samples that is flagged as abnormal
+const ABNORMAL_DROP_MV: u32 = 150;
+/// Only drops within this time are flagged, slow drops are normal discharge
+const ABNORMAL_DROP_WINDOW_SECONDS: f64 = 900.0;
+/// Period at the end of the last discharge used to estimate the time to empty
+const TIME_TO_EMPTY_WINDOW_SECONDS: f64 = 86400.0;
+
+/// Battery measurements of the logging device at a single time
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct BatteryPoint {
+    pub time: i64,
+    pub voltage_mv: Option<u32>,
+    pub current_ua: Option<i32>,
+    /// State of charge in percent
+    pub soc: Option<u8>,
+    pub charge: Option<i32>,
+}
+
+impl BatteryPoint {
+    /// Parse a battery TDF payload, `None` for other TDFs or truncated payloads
+    fn parse(tdf_id: u16, time: i64, payload: &[u8]) -> Option<Self> {
+        let mut cursor = Cursor::new(payload);
+        let mut point = BatteryPoint {
+            time,
+            ..Default::default()
+        };
+        match tdf_id {
+            TDF_BATTERY_STATE => {
+                point.voltage_mv = Some(cursor.read_u32::<LittleEndian>().ok()?);
+                point.current_ua = Some(cursor.read_i32::<LittleEndian>().ok()?);
+                point.soc = Some(cursor.read_u8().ok()?);
+            }
+            TDF_BATTERY_CHARGE_ACCUMULATED => {
+                point.charge = Some(cursor.read_i32::<LittleEndian>().ok()?);
+            }
+            TDF_BATTERY_VOLTAGE => {
+                point.voltage_mv = Some(cursor.read_u16::<LittleEndian>().ok()? as u32);
+            }
+            TDF_BATTERY_SOC => point.soc = Some(cursor.read_u8().ok()?),
+            _ => return None,
+        }
+        Some(point)
+    }
+
+    /// Fill in measurements from another TDF logged at the same time
+    fn merge(&mut self, other: &BatteryPoint) {
+        self.voltage_mv = self.voltage_mv.or(other.voltage_mv);
+        self.current_ua = self.current_ua.or(other.current_ua);
+        self.soc = self.soc.or(other.soc);
+        self.charge = self.charge.or(other.charge);
+    }
+}
+
+/// Collects the battery measurements decoded by one worker.
+///
+/// Like [`crate::continuity::ContinuityTracker`], the trackers of all workers are combined in
+/// block order, with [`BatteryTracker::append`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BatteryTracker {
+    points: Vec<BatteryPoint>,
+}
+
+impl BatteryTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a sample of the logging device if it is from a battery TDF
+    pub fn sample(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        time: i64,
+        size: u8,
+        cursor: &Cursor<&[u8]>,
+    ) {
+        if remote_id.is_some()
+            || !matches!(
+                tdf_id,
+                TDF_BATTERY_STATE
+                    | TDF_BATTERY_CHARGE_ACCUMULATED
+                    | TDF_BATTERY_VOLTAGE
+                    | TDF_BATTERY_SOC
+            )
+        {
+            return;
+        }
+        let start = cursor.position() as usize;
+        let Some(point) = cursor
+            .get_ref()
+            .get(start..start + size as usize)
+            .and_then(|payload| BatteryPoint::parse(tdf_id, time, payload))
+        else {
+            return;
+        };
+        push_point(&mut self.points, point);
+    }
+
+    /// Append the tracker of the following range of blocks
+    pub fn append(&mut self, next: BatteryTracker) {
+        self.points.extend(next.points);
+    }
+}
+
+fn push_point(points: &mut Vec<BatteryPoint>, point: BatteryPoint) {
+    match points.last_mut() {
+        Some(last) if last.time == point.time => last.merge(&point),
+        _ => points.push(point),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CycleKind {
+    Charge,
+    Discharge,
+}
+
+impl std::fmt::Display for CycleKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CycleKind::Charge => write!(f, "charging"),
+            CycleKind::Discharge => write!(f, "discharging"),
+        }
+    }
+}
+
+/// Period of continuous charging or discharging
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatteryCycle {
+    pub kind: CycleKind,
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub start: Option<i64>,
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub end: Option<i64>,
+    pub start_soc: Option<u8>,
+    pub end_soc: Option<u8>,
+    pub start_voltage_mv: Option<u32>,
+    pub end_voltage_mv: Option<u32>,
+    #[serde(skip)]
+    first_point: usize,
+    #[serde(skip)]
+    last_point: usize,
+}
+
+/// Battery usage over a UTC day
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct BatteryDay {
+    #[serde(serialize_with = "serialize_date")]
+    pub date: NaiveDate,
+    /// Total decrease in state of charge (%)
+    pub soc_consumed: u32,
+    /// Total increase in state of charge (%)
+    pub soc_charged: u32,
+    /// Change in the accumulated charge counter
+    pub charge_delta: Option<i64>,
+    pub min_voltage_mv: Option<u32>,
+    pub max_voltage_mv: Option<u32>,
+}
+
+fn serialize_date<S: serde::Serializer>(
+    date: &NaiveDate,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(date)
+}
+
+/// Voltage drop that is too large or too fast for normal discharge
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct VoltageDrop {
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub start: Option<i64>,
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub end: Option<i64>,
+    pub from_mv: u32,
+    pub to_mv: u32,
+}
+
+/// Row of the merged battery timeline
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatteryRow {
+    pub point: BatteryPoint,
+    pub state: Option<CycleKind>,
+    /// Index into [`BatteryReport::cycles`]
+    pub cycle: Option<u32>,
+    pub abnormal_drop: bool,
+}
+
+/// Charge cycles, daily usage and abnormal voltage drops of the logging device's battery
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BatteryReport {
+    pub samples: usize,
+    pub cycles: Vec<BatteryCycle>,
+    pub days: Vec<BatteryDay>,
+    /// Discharge rate over the end of the last discharge (% per hour)
+    pub discharge_rate_percent_per_hour: Option<f64>,
+    /// Estimated time until the state of charge reaches 0%, while still discharging
+    pub time_to_empty_hours: Option<f64>,
+    pub abnormal_drops: Vec<VoltageDrop>,
+}
+
+impl BatteryReport {
+    pub fn count(&self, kind: CycleKind) -> usize {
+        self.cycles
+            .iter()
+            .filter(|cycle| cycle.kind == kind)
+            .count()
+    }
+}
+
+/// Report and merged timeline of the measurements collected by `tracker`, `None` if no battery
+/// data was decoded
+pub fn battery_report(tracker: &BatteryTracker) -> Option<(BatteryReport, Vec<BatteryRow>)> {
+    let mut points = tracker.points.clone();
+    if points.is_empty() {
+        return None;
+    }
+    // Merge measurements from different TDFs, which may not be in time order in the input
+    points.sort_by_key(|point| point.time);
+    let points = points.into_iter().fold(Vec::new(), |mut merged, point| {
+        push_point(&mut merged, point);
+        merged
+    });
+
+    let cycles = find_cycles(&points);
+    let mut rows: Vec<BatteryRow> = points
+        .iter()
+        .map(|point| BatteryRow {
+            point: *point,
+            state: None,
+            cycle: None,
+            abnormal_drop: false,
+        })
+        .collect();
+    for (idx, cycle) in cycles.iter().enumerate() {
+        for row in rows[cycle.first_point..=cycle.last_point].iter_mut() {
+            row.state = Some(cycle.kind);
+            row.cycle = Some(idx as u32);
+        }
+    }
+
+    let mut abnormal_drops = Vec::new();
+    let mut last_voltage: Option<(usize, u32)> = None;
+    for (idx, point) in points.iter().enumerate() {
+        let Some(voltage) = point.voltage_mv else {
+            continue;
+        };
+        if let Some((prev_idx, prev_voltage)) = last_voltage
+            && prev_voltage.saturating_sub(voltage) >= ABNORMAL_DROP_MV
+            && seconds(points[prev_idx].time, point.time) <= ABNORMAL_DROP_WINDOW_SECONDS
+        {
+            rows[idx].abnormal_drop = true;
+            abnormal_drops.push(VoltageDrop {
+                start: Some(points[prev_idx].time),
+                end: Some(point.time),
+                from_mv: prev_voltage,
+                to_mv: voltage,
+            });
+        }
+        last_voltage = Some((idx, voltage));
+    }
+
+    let discharge_rate = discharge_rate(&points, &cycles);
+    let last_soc = points.iter().rev().find_map(|point| point.soc);
+    let report = BatteryReport {
+        samples: points.len(),
+        days: daily_usage(&points),
+        discharge_rate_percent_per_hour: discharge_rate,
+        time_to_empty_hours: discharge_rate
+            .zip(last_soc)
+            .map(|(rate, soc)| soc as f64 / rate),
+        cycles,
+        abnormal_drops,
+    };
+    Some((report, rows))
+}
+
+fn seconds(from: i64, to: i64) -> f64 {
+    (to - from) as f64 / TDF_TICKS_PER_SECOND as f64
+}
+
+/// Split the timeline into charge and discharge cycles, using the state of charge if it was
+/// logged and otherwise the voltage
+fn find_cycles(points: &[BatteryPoint]) -> Vec<BatteryCycle> {
+    let use_soc = points.iter().any(|point| point.soc.is_some());
+    let (level, hysteresis): (fn(&BatteryPoint) -> Option<f64>, f64) = if use_soc {
+        (|p| p.soc.map(f64::from), CYCLE_HYSTERESIS_SOC)
+    } else {
+        (|p| p.voltage_mv.map(f64::from), CYCLE_HYSTERESIS_MV)
+    };
+    let levels: Vec<(usize, f64)> = points
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, point)| level(point).map(|level| (idx, level)))
+        .collect();
+    let Some(&(first, _)) = levels.first() else {
+        return Vec::new();
+    };
+
+    let cycle = |kind, start: usize, end: usize| BatteryCycle {
+        kind,
+        start: Some(points[start].time),
+        end: Some(points[end].time),
+        start_soc: points[start].soc,
+        end_soc: points[end].soc,
+        start_voltage_mv: points[start].voltage_mv,
+        end_voltage_mv: points[end].voltage_mv,
+        first_point: start,
+        last_point: end,
+    };
+    let mut cycles = Vec::new();
+    let mut direction: Option<CycleKind> = None;
+    // Start of the current cycle, and the furthest point reached in its direction
+    let mut start = first;
+    let mut extreme = first;
+    // Lowest and highest points before the first direction is known
+    let (mut min, mut max) = (first, first);
+    let value = |idx: usize| level(&points[idx]).unwrap_or_default();
+
+    for &(idx, level) in levels.iter() {
+        match direction {
+            None => {
+                if level < value(min) {
+                    min = idx;
+                }
+                if level > value(max) {
+                    max = idx;
+                }
+                if level - value(min) >= hysteresis {
+                    (direction, start, extreme) = (Some(CycleKind::Charge), min, idx);
+                } else if value(max) - level >= hysteresis {
+                    (direction, start, extreme) = (Some(CycleKind::Discharge), max, idx);
+                }
+            }
+            Some(kind) => {
+                let further = match kind {
+                    CycleKind::Charge => level >= value(extreme),
+                    CycleKind::Discharge => level <= value(extreme),
+                };
+                if further {
+                    extreme = idx;
+                } else if (level - value(extreme)).abs() >= hysteresis {
+                    cycles.push(cycle(kind, start, extreme));
+                    let reversed = match kind {
+                        CycleKind::Charge => CycleKind::Discharge,
+                        CycleKind::Discharge => CycleKind::Charge,
+                    };
+                    (direction, start, extreme) = (Some(reversed), extreme, idx);
+                }
+            }
+        }
+    }
+    if let Some(kind) = direction {
+        cycles.push(cycle(kind, start, extreme));
+    }
+    cycles
+}
+
+/// Consumption and charging for each UTC day, times that can't be represented are skipped
+fn daily_usage(points: &[BatteryPoint]) -> Vec<BatteryDay> {
+    let mut days: BTreeMap<NaiveDate, BatteryDay> = BTreeMap::new();
+    let mut last_soc: Option<u8> = None;
+    let mut day_charge: BTreeMap<NaiveDate, (i32, i32)> = BTreeMap::new();
+
+    for point in points {
+        let Some(date) = tdf::time::tdf_time_to_datetime(point.time).map(|time| time.date_naive())
+        else {
+            continue;
+        };
+        let day = days.entry(date).or_insert_with(|| BatteryDay {
+            date,
+            soc_consumed: 0,
+            soc_charged: 0,
+            charge_delta: None,
+            min_voltage_mv: None,
+            max_voltage_mv: None,
+        });
+
+        if let Some(soc) = point.soc {
+            if let Some(last) = last_soc {
+                if soc < last {
+                    day.soc_consumed += (last - soc) as u32;
+                } else {
+                    day.soc_charged += (soc - last) as u32;
+                }
+            }
+            last_soc = Some(soc);
+        }
+        if let Some(voltage) = point.voltage_mv {
+            day.min_voltage_mv = Some(day.min_voltage_mv.map_or(voltage, |v| v.min(voltage)));
+            day.max_voltage_mv = Some(day.max_voltage_mv.map_or(voltage, |v| v.max(voltage)));
+        }
+        if let Some(charge) = point.charge {
+            day_charge
+                .entry(date)
+                .and_modify(|(_, last)| *last = charge)
+                .or_insert((charge, charge));
+        }
+    }
+
+    for (date, (first, last)) in day_charge {
+        if let Some(day) = days.get_mut(&date) {
+            day.charge_delta = Some(last as i64 - first as i64);
+        }
+    }
+    days.into_values().collect()
+}
+
+/// Rate of discharge at the end of the timeline (% per hour), `None` unless the battery is
+/// still discharging and state of charge was logged
+fn discharge_rate(points: &[BatteryPoint], cycles: &[BatteryCycle]) -> Option<f64> {
+    let last = cycles
+        .last()
+        .filter(|cycle| cycle.kind == CycleKind::Discharge)?;
+    let end = points[last.last_point];
+    let end_soc = end.soc?;
+    let start = points[last.first_point..=last.last_point]
+        .iter()
+        .filter(|point| point.soc.is_some())
+        .find(|point| seconds(point.time, end.time) <= TIME_TO_EMPTY_WINDOW_SECONDS)?;
+
+    let hours = seconds(start.time, end.time) / 3600.0;
+    let consumed = start.soc? as f64 - end_soc as f64;
+    (hours > 0.0 && consumed > 0.0).then(|| consumed / hours)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: i64 = 3600 * 65536;
+
+    fn soc_point(hours: i64, soc: u8, voltage_mv: u32) -> BatteryPoint {
+        BatteryPoint {
+            time: hours * HOUR,
+            voltage_mv: Some(voltage_mv),
+            soc: Some(soc),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn measurements_at_the_same_time_are_merged() {
+        let mut tracker = BatteryTracker::new();
+        let voltage = 3700u16.to_le_bytes();
+        tracker.sample(None, TDF_BATTERY_VOLTAGE, 10, 2, &Cursor::new(&voltage[..]));
+        tracker.sample(None, TDF_BATTERY_SOC, 10, 1, &Cursor::new(&[80u8][..]));
+        tracker.sample(Some(1), TDF_BATTERY_SOC, 20, 1, &Cursor::new(&[50u8][..]));
+        tracker.sample(None, 4, 20, 1, &Cursor::new(&[50u8][..]));
+
+        let (report, rows) = battery_report(&tracker).unwrap();
+
+        assert_eq!(report.samples, 1);
+        assert_eq!(rows[0].point.voltage_mv, Some(3700));
+        assert_eq!(rows[0].point.soc, Some(80));
+    }
+
+    #[test]
+    fn cycles_usage_and_time_to_empty() {
+        let mut first = BatteryTracker {
+            points: vec![
+                soc_point(0, 90, 4000),
+                soc_point(10, 60, 3800),
+                // Charge back to full
+                soc_point(11, 80, 3900),
+                soc_point(12, 100, 4100),
+            ],
+        };
+        let second = BatteryTracker {
+            points: vec![
+                soc_point(13, 99, 4090),
+                // Sudden voltage drop
+                BatteryPoint {
+                    time: 13 * HOUR + 600 * 65536,
+                    ..soc_point(0, 99, 3900)
+                },
+                soc_point(24, 90, 3850),
+                soc_point(48, 66, 3800),
+            ],
+        };
+
+        first.append(second);
+        let (report, rows) = battery_report(&first).unwrap();
+
+        let kinds: Vec<CycleKind> = report.cycles.iter().map(|cycle| cycle.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                CycleKind::Discharge,
+                CycleKind::Charge,
+                CycleKind::Discharge
+            ]
+        );
+        assert_eq!(report.cycles[1].start_soc, Some(60));
+        assert_eq!(report.cycles[1].end_soc, Some(100));
+        assert_eq!(rows[2].state, Some(CycleKind::Charge));
+        assert_eq!(report.abnormal_drops.len(), 1);
+        assert!(rows[5].abnormal_drop);
+        // 1% per hour over the last day, 66% remaining
+        assert_eq!(report.discharge_rate_percent_per_hour, Some(1.0));
+        assert_eq!(report.time_to_empty_hours, Some(66.0));
+        let consumed: u32 = report.days.iter().map(|day| day.soc_consumed).sum();
+        assert_eq!(consumed, 30 + 34);
+    }
+}
@@ -0,0 +1,444 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::args::{
+    BlockSizeOptions, CsvDelimiter, DecimalSeparator, OutputFormat, ResampleMethod, VlaMode,
+};
+
+/// Name of the configuration file in the user's configuration folder
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Saved decode settings.
+///
+/// Settings that aren't in a profile are left at their defaults, or whatever was given on the
+/// command line.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub output_folder: Option<PathBuf>,
+    #[serde(with = "value_enum")]
+    pub format: Option<OutputFormat>,
+    /// Write Unix timestamps instead of UTC strings
+    pub unix_time: Option<bool>,
+    #[serde(with = "value_enum")]
+    pub block_size: Option<BlockSizeOptions>,
+    pub max_readings_per_output_file: Option<usize>,
+    pub linearize_output: Option<bool>,
+    pub sort_by_time: Option<bool>,
+    pub incremental: Option<bool>,
+    pub session_id: Option<bool>,
+    pub dedup: Option<bool>,
+    /// Write the boot sessions output
+    pub sessions: Option<bool>,
+    /// Write the battery timeline output
+    pub battery: Option<bool>,
+    #[serde(with = "utc_time")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(with = "utc_time")]
+    pub until: Option<DateTime<Utc>>,
+    #[serde(with = "tdf_ids")]
+    pub include_tdfs: Option<Vec<u16>>,
+    #[serde(with = "tdf_ids")]
+    pub exclude_tdfs: Option<Vec<u16>>,
+    #[serde(with = "remote_ids")]
+    pub include_remotes: Option<Vec<Option<u64>>>,
+    #[serde(with = "remote_ids")]
+    pub exclude_remotes: Option<Vec<Option<u64>>>,
+    #[serde(with = "value_enum")]
+    pub csv_delimiter: Option<CsvDelimiter>,
+    #[serde(with = "value_enum")]
+    pub csv_decimal_separator: Option<DecimalSeparator>,
+    pub csv_float_precision: Option<usize>,
+    pub csv_bom: Option<bool>,
+    #[serde(with = "value_enum")]
+    pub csv_vla_mode: Option<VlaMode>,
+    pub csv_vla_max_items: Option<usize>,
+    pub gap_multiple: Option<f64>,
+    pub gap_min_empty_blocks: Option<usize>,
+    #[serde(with = "tdf_ids")]
+    pub resample_tdfs: Option<Vec<u16>>,
+    /// Rows per second of the resampled output
+    pub resample_rate: Option<f64>,
+    #[serde(with = "value_enum")]
+    pub resample_method: Option<ResampleMethod>,
+    /// Furthest a sample can be from a resampled grid time, in seconds
+    pub resample_tolerance: Option<f64>,
+}
+
+/// Named profiles and the settings of the last GUI session, stored as TOML
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Settings the GUI was last used with
+    pub last_used: Option<Profile>,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Path of the configuration file in the user's configuration folder, if there is one
+pub fn default_config_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("com", "Embeint", "infuse_decoder")
+        .map(|dirs| dirs.config_dir().join(CONFIG_FILE_NAME))
+}
+
+fn invalid_data(path: &Path, msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Invalid configuration file '{}': {msg}", path.display()),
+    )
+}
+
+impl Config {
+    /// Read the configuration file, a missing file being an empty configuration
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => return Err(err),
+        };
+        toml_edit::de::from_str(&text).map_err(|err| invalid_data(path, err.to_string()))
+    }
+
+    /// Write the configuration file, creating its folder if needed.
+    ///
+    /// The settings are updated in the existing file, so that comments added to it by hand are
+    /// kept for the settings and profiles that remain.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(folder) = path.parent() {
+            fs::create_dir_all(folder)?;
+        }
+        let mut document = match fs::read_to_string(path) {
+            Ok(text) => text
+                .parse::<DocumentMut>()
+                .map_err(|err| invalid_data(path, err.to_string()))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => DocumentMut::new(),
+            Err(err) => return Err(err),
+        };
+        let root = document.as_table_mut();
+
+        match &self.last_used {
+            Some(profile) => update_profile(root, "last_used", profile)?,
+            None => {
+                root.remove("last_used");
+            }
+        }
+        let profiles = root
+            .entry("profiles")
+            .or_insert_with(|| {
+                let mut table = Table::new();
+                table.set_implicit(true);
+                Item::Table(table)
+            })
+            .as_table_mut()
+            .ok_or_else(|| invalid_data(path, "'profiles' must be a table".to_string()))?;
+        profiles.retain(|name, _| self.profiles.contains_key(name));
+        for (name, profile) in &self.profiles {
+            update_profile(profiles, name, profile)?;
+        }
+
+        // Replace the file in one step, so that a failed write doesn't lose the old settings
+        let temp = path.with_extension("toml.tmp");
+        fs::write(&temp, document.to_string())?;
+        fs::rename(&temp, path)
+    }
+
+    /// Load the named profile
+    pub fn profile(&self, name: &str) -> io::Result<&Profile> {
+        self.profiles.get(name).ok_or_else(|| {
+            let known = self.profiles.keys().cloned().collect::<Vec<_>>();
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                if known.is_empty() {
+                    format!("Unknown profile '{name}', no profiles have been saved")
+                } else {
+                    format!(
+                        "Unknown profile '{name}', saved profiles are: {}",
+                        known.join(", ")
+                    )
+                },
+            )
+        })
+    }
+}
+
+/// Replace the settings of the profile table `name` in `parent` with those of `profile`, keeping
+/// the comments of the settings that are still set
+fn update_profile(parent: &mut Table, name: &str, profile: &Profile) -> io::Result<()> {
+    let settings = toml_edit::ser::to_document(profile)
+        .map_err(io::Error::other)?
+        .into_table();
+    let table = match parent.get_mut(name) {
+        Some(Item::Table(table)) => table,
+        _ => {
+            parent.insert(name, Item::Table(Table::new()));
+            parent[name].as_table_mut().unwrap()
+        }
+    };
+    table.retain(|key, _| settings.contains_key(key));
+    for (key, item) in settings {
+        match (table.get_mut(&key), item) {
+            (Some(Item::Value(existing)), Item::Value(mut value)) => {
+                *value.decor_mut() = existing.decor().clone();
+                *existing = value;
+            }
+            (_, item) => {
+                table.insert(&key, item);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Settings stored as the names of their command line values, in any case
+mod value_enum {
+    use clap::ValueEnum;
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<T: ValueEnum, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value.as_ref().and_then(ValueEnum::to_possible_value) {
+            Some(value) => serializer.serialize_str(value.get_name()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, T: ValueEnum, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| T::from_str(&value, true).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// Times stored as RFC3339 strings, or dates
+mod utc_time {
+    use chrono::{DateTime, Utc};
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(time) => serializer.serialize_str(&crate::summary::format_time(*time)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|value| crate::args::parse_utc_time(&value).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// TDF IDs stored by name
+mod tdf_ids {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Vec<u16>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .map(|ids| ids.iter().map(tdf::decoders::tdf_name).collect::<Vec<_>>())
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<u16>>, D::Error> {
+        Option::<Vec<String>>::deserialize(deserializer)?
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| crate::args::parse_tdf_id(name).map_err(D::Error::custom))
+                    .collect()
+            })
+            .transpose()
+    }
+}
+
+/// Remote IDs stored as hexadecimal, with `local` for the logging device
+mod remote_ids {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(
+        value: &Option<Vec<Option<u64>>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value
+            .as_ref()
+            .map(|ids| {
+                ids.iter()
+                    .map(|id| match id {
+                        Some(id) => format!("{id:016x}"),
+                        None => "local".to_string(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Vec<Option<u64>>>, D::Error> {
+        Option::<Vec<String>>::deserialize(deserializer)?
+            .map(|ids| {
+                ids.iter()
+                    .map(|id| crate::args::parse_remote_id(id).map_err(D::Error::custom))
+                    .collect()
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::parse_utc_time;
+
+    #[test]
+    fn profiles_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("settings").join(CONFIG_FILE_NAME);
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        let full = Profile {
+            output_folder: Some(PathBuf::from("C:\\data\\\"quoted\"")),
+            format: Some(OutputFormat::PARQUET),
+            unix_time: Some(true),
+            block_size: Some(BlockSizeOptions::B4096),
+            max_readings_per_output_file: Some(1_000_000),
+            linearize_output: Some(false),
+            sort_by_time: Some(true),
+            incremental: Some(false),
+            session_id: Some(true),
+            dedup: Some(true),
+            sessions: Some(true),
+            battery: Some(false),
+            since: parse_utc_time("2025-01-01T00:00:00.5Z").ok(),
+            until: parse_utc_time("2025-02-01").ok(),
+            include_tdfs: Some(vec![2, 11]),
+            exclude_tdfs: Some(vec![]),
+            include_remotes: Some(vec![None, Some(0xabcd)]),
+            exclude_remotes: Some(vec![Some(1)]),
+            csv_delimiter: Some(CsvDelimiter::Tab),
+            csv_decimal_separator: Some(DecimalSeparator::Comma),
+            csv_float_precision: Some(3),
+            csv_bom: Some(true),
+            csv_vla_mode: Some(VlaMode::Wide),
+            csv_vla_max_items: Some(4),
+            gap_multiple: Some(2.0),
+            gap_min_empty_blocks: Some(5),
+            resample_tdfs: Some(vec![11]),
+            resample_rate: Some(12.5),
+            resample_method: Some(ResampleMethod::Nearest),
+            resample_tolerance: Some(0.2),
+        };
+        let config = Config {
+            last_used: Some(Profile {
+                format: Some(OutputFormat::CSV),
+                ..Profile::default()
+            }),
+            profiles: BTreeMap::from([
+                ("lab bench".to_string(), full.clone()),
+                ("empty".to_string(), Profile::default()),
+            ]),
+        };
+        config.save(&path).unwrap();
+
+        assert_eq!(Config::load(&path).unwrap(), config);
+        assert_eq!(
+            Config::load(&path).unwrap().profile("lab bench").unwrap(),
+            &full
+        );
+        let err = config.profile("missing").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("empty, lab bench"));
+    }
+
+    #[test]
+    fn hand_written_profiles_are_checked() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join(CONFIG_FILE_NAME);
+        fs::write(
+            &path,
+            "[profiles.field]\n# Comments and unknown keys are allowed\nformat = \"Parquet\"\n\
+             gap_multiple = 3\nexclude_tdfs = [\"acc_4g\", \"2\"]\nresample_method = \"Nearest\"\n\
+             unknown = 1\n",
+        )
+        .unwrap();
+        let config = Config::load(&path).unwrap();
+        let profile = config.profile("field").unwrap();
+        assert_eq!(profile.format, Some(OutputFormat::PARQUET));
+        assert_eq!(profile.gap_multiple, Some(3.0));
+        assert_eq!(profile.exclude_tdfs, Some(vec![11, 2]));
+        assert_eq!(profile.resample_method, Some(ResampleMethod::Nearest));
+
+        for (text, expected) in [
+            ("[profiles.a]\nblock_size = 1024\n", "expected a string"),
+            ("[profiles.a]\nblock_size = \"1024\"\n", "block_size ="),
+            (
+                "[profiles.a]\ninclude_tdfs = [\"NOT_A_TDF\"]\n",
+                "Unknown TDF",
+            ),
+            ("[profiles.a]\nsince = \"yesterday\"\n", "since ="),
+            (
+                "[profiles.a]\nresample_method = \"cubic\"\n",
+                "resample_method =",
+            ),
+            ("profiles = 1\n", "expected a map"),
+            ("[profiles.a\n", "Invalid configuration file"),
+        ] {
+            fs::write(&path, text).unwrap();
+            let err = Config::load(&path).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+            assert!(err.to_string().contains(expected), "{err}");
+        }
+    }
+
+    #[test]
+    fn saving_keeps_hand_written_comments() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join(CONFIG_FILE_NAME);
+        fs::write(
+            &path,
+            "# Shared settings\n\n[profiles.field]\n# Field trial output\nformat = \"csv\" # for Excel\n\
+             dedup = true\n\n[profiles.old]\nformat = \"csv\"\n",
+        )
+        .unwrap();
+
+        let mut config = Config::load(&path).unwrap();
+        config.profiles.remove("old");
+        let field = config.profiles.get_mut("field").unwrap();
+        field.format = Some(OutputFormat::PARQUET);
+        field.dedup = None;
+        field.unix_time = Some(true);
+        config.save(&path).unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("# Shared settings\n"), "{text}");
+        assert!(
+            text.contains("# Field trial output\nformat = \"parquet\" # for Excel\n"),
+            "{text}"
+        );
+        assert!(
+            !text.contains("dedup") && !text.contains("[profiles.old]"),
+            "{text}"
+        );
+        assert_eq!(Config::load(&path).unwrap(), config);
+    }
+}
@@ -0,0 +1,544 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io;
+
+use tdf::time::TDF_TICKS_PER_SECOND;
+
+use crate::output_common::{OutputKey, key_map};
+
+/// Maximum number of gaps and timestamp regressions kept for each TDF
+const MAX_EVENTS_PER_TDF: usize = 1000;
+/// Maximum number of runs of empty blocks kept
+const MAX_EMPTY_RUNS: usize = 1000;
+/// Interval histogram buckets per power of two
+const BUCKETS_PER_OCTAVE: usize = 32;
+const NUM_BUCKETS: usize = 64 * BUCKETS_PER_OCTAVE;
+
+pub const DEFAULT_GAP_INTERVAL_MULTIPLE: f64 = 5.0;
+pub const DEFAULT_GAP_MIN_EMPTY_BLOCKS: usize = 2;
+
+/// Thresholds for reporting logging discontinuities
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GapConfig {
+    /// Gaps between samples of a TDF are reported when they exceed this multiple of the
+    /// typical interval of the TDF
+    pub interval_multiple: f64,
+    /// Runs of empty blocks are reported when they are at least this long
+    pub min_empty_blocks: usize,
+}
+
+impl GapConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        if !(self.interval_multiple.is_finite() && self.interval_multiple > 0.0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Gap interval multiple ({}) must be greater than zero",
+                    self.interval_multiple
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Default for GapConfig {
+    fn default() -> Self {
+        Self {
+            interval_multiple: DEFAULT_GAP_INTERVAL_MULTIPLE,
+            min_empty_blocks: DEFAULT_GAP_MIN_EMPTY_BLOCKS,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContinuityEventKind {
+    /// Samples of a TDF stopped for longer than expected
+    Gap,
+    /// Consecutive blocks without any data
+    EmptyBlocks,
+    /// A sample of a TDF is earlier than the previous sample
+    Regression,
+}
+
+impl std::fmt::Display for ContinuityEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ContinuityEventKind::Gap => write!(f, "Gap"),
+            ContinuityEventKind::EmptyBlocks => write!(f, "Empty Blocks"),
+            ContinuityEventKind::Regression => write!(f, "Time Regression"),
+        }
+    }
+}
+
+/// A single discontinuity in the decoded timeline
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ContinuityEvent {
+    pub kind: ContinuityEventKind,
+    #[serde(serialize_with = "serialize_remote_id")]
+    pub remote_id: Option<u64>,
+    /// TDF the event applies to, `None` for runs of empty blocks
+    pub tdf_id: Option<u16>,
+    /// TDF time of the last sample before the event
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub start: Option<i64>,
+    /// TDF time of the first sample after the event
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub end: Option<i64>,
+    pub first_block: usize,
+    pub last_block: usize,
+}
+
+impl ContinuityEvent {
+    /// Time from `start` to `end`, negative for timestamp regressions
+    pub fn duration_seconds(&self) -> Option<f64> {
+        Some((self.end? - self.start?) as f64 / TDF_TICKS_PER_SECOND as f64)
+    }
+
+    pub fn start_time(&self) -> Option<DateTime<Utc>> {
+        self.start.and_then(tdf::time::tdf_time_to_datetime)
+    }
+
+    pub fn end_time(&self) -> Option<DateTime<Utc>> {
+        self.end.and_then(tdf::time::tdf_time_to_datetime)
+    }
+
+    /// TDF name for display, prefixed by the remote ID for forwarded data
+    pub fn tdf_label(&self) -> String {
+        let name = match self.tdf_id {
+            Some(tdf_id) => tdf::decoders::tdf_name(&tdf_id),
+            None => "-".to_string(),
+        };
+        match self.remote_id {
+            Some(remote_id) => format!("{remote_id:016x}/{name}"),
+            None => name,
+        }
+    }
+}
+
+/// Typical interval between consecutive samples of a TDF
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TdfInterval {
+    #[serde(serialize_with = "serialize_remote_id")]
+    pub remote_id: Option<u64>,
+    pub tdf_id: u16,
+    pub typical_interval_seconds: f64,
+}
+
+/// Gaps, runs of empty blocks and timestamp regressions found while decoding
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ContinuityReport {
+    pub interval_multiple: f64,
+    pub intervals: Vec<TdfInterval>,
+    /// Events in block order
+    pub events: Vec<ContinuityEvent>,
+    /// More events were found than could be listed
+    pub truncated: bool,
+}
+
+impl ContinuityReport {
+    pub fn count(&self, kind: ContinuityEventKind) -> usize {
+        self.events
+            .iter()
+            .filter(|event| event.kind == kind)
+            .count()
+    }
+}
+
+fn serialize_remote_id<S: Serializer>(id: &Option<u64>, s: S) -> Result<S::Ok, S::Error> {
+    match id {
+        Some(id) => s.serialize_str(&format!("{id:016x}")),
+        None => s.serialize_none(),
+    }
+}
+
+pub(crate) fn serialize_tdf_time<S: Serializer>(
+    time: &Option<i64>,
+    s: S,
+) -> Result<S::Ok, S::Error> {
+    match time.and_then(tdf::time::tdf_time_to_datetime) {
+        Some(time) => s.serialize_str(&crate::summary::format_time(time)),
+        None => s.serialize_none(),
+    }
+}
+
+/// Sample time and the block it was decoded from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+struct TimePoint {
+    time: i64,
+    block: usize,
+}
+
+/// Interval between two consecutive samples of a TDF, ordered by length
+type Interval = (i64, TimePoint, TimePoint);
+
+/// Timeline of a single TDF within a range of blocks
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct TdfTimeline {
+    first: TimePoint,
+    last: TimePoint,
+    /// Count of intervals on a logarithmic scale
+    histogram: Vec<usize>,
+    /// Longest intervals, as a min-heap so the shortest can be dropped
+    longest: BinaryHeap<Reverse<Interval>>,
+    regressions: Vec<(TimePoint, TimePoint)>,
+    num_regressions: usize,
+}
+
+impl TdfTimeline {
+    fn new(point: TimePoint) -> Self {
+        Self {
+            first: point,
+            last: point,
+            histogram: vec![0; NUM_BUCKETS],
+            longest: BinaryHeap::new(),
+            regressions: Vec::new(),
+            num_regressions: 0,
+        }
+    }
+
+    /// Record the step from `from` to `to`
+    fn step(&mut self, from: TimePoint, to: TimePoint) {
+        let interval = to.time - from.time;
+        if interval > 0 {
+            self.histogram[bucket(interval)] += 1;
+            self.longest.push(Reverse((interval, from, to)));
+            if self.longest.len() > MAX_EVENTS_PER_TDF {
+                self.longest.pop();
+            }
+        } else if interval < 0 {
+            self.num_regressions += 1;
+            if self.regressions.len() < MAX_EVENTS_PER_TDF {
+                self.regressions.push((from, to));
+            }
+        }
+    }
+
+    fn sample(&mut self, point: TimePoint) {
+        // Index array samples share the time of the array and don't form an interval
+        if point.time != self.last.time {
+            self.step(self.last, point);
+        }
+        self.last = point;
+    }
+
+    /// Append the timeline of the following range of blocks
+    fn append(&mut self, next: TdfTimeline) {
+        if next.first.time != self.last.time {
+            self.step(self.last, next.first);
+        }
+        for (count, next_count) in self.histogram.iter_mut().zip(next.histogram) {
+            *count += next_count;
+        }
+        for interval in next.longest {
+            self.longest.push(interval);
+            if self.longest.len() > MAX_EVENTS_PER_TDF {
+                self.longest.pop();
+            }
+        }
+        let space = MAX_EVENTS_PER_TDF - self.regressions.len();
+        self.regressions
+            .extend(next.regressions.into_iter().take(space));
+        self.num_regressions += next.num_regressions;
+        self.last = next.last;
+    }
+
+    /// Median interval between samples, in TDF time units
+    fn typical_interval(&self) -> Option<i64> {
+        let total: usize = self.histogram.iter().sum();
+        let mut seen = 0;
+        for (idx, count) in self.histogram.iter().enumerate() {
+            seen += count;
+            if total > 0 && seen * 2 >= total {
+                return Some(bucket_value(idx));
+            }
+        }
+        None
+    }
+}
+
+fn bucket(interval: i64) -> usize {
+    (((interval as f64).log2() * BUCKETS_PER_OCTAVE as f64) as usize).min(NUM_BUCKETS - 1)
+}
+
+/// Representative interval of a histogram bucket
+fn bucket_value(bucket: usize) -> i64 {
+    ((bucket as f64 + 0.5) / BUCKETS_PER_OCTAVE as f64).exp2() as i64
+}
+
+/// Run of consecutive empty blocks
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+struct EmptyRun {
+    first_block: usize,
+    last_block: usize,
+    time_before: Option<i64>,
+    time_after: Option<i64>,
+}
+
+/// Tracks the timeline of the samples and blocks decoded by one worker.
+///
+/// Each worker decodes a contiguous range of blocks, so the trackers of all workers are
+/// combined in block order with [`ContinuityTracker::append`]. The combined tracker is saved in
+/// the manifest of an incremental decode, so that the next decode can continue it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ContinuityTracker {
+    #[serde(with = "key_map")]
+    tdfs: HashMap<OutputKey, TdfTimeline>,
+    empty_runs: Vec<EmptyRun>,
+    block: usize,
+    first_time: Option<i64>,
+    last_time: Option<i64>,
+}
+
+impl ContinuityTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn start_block(&mut self, block: usize) {
+        self.block = block;
+    }
+
+    pub fn end_block(&mut self, block_type: blocks::BlockTypes) {
+        if block_type != blocks::BlockTypes::EMPTY {
+            return;
+        }
+        match self.empty_runs.last_mut() {
+            Some(run) if run.last_block + 1 == self.block && run.time_after.is_none() => {
+                run.last_block = self.block;
+            }
+            _ => self.empty_runs.push(EmptyRun {
+                first_block: self.block,
+                last_block: self.block,
+                time_before: self.last_time,
+                time_after: None,
+            }),
+        }
+    }
+
+    pub fn sample(&mut self, remote_id: Option<u64>, tdf_id: u16, time: i64) {
+        let point = TimePoint {
+            time,
+            block: self.block,
+        };
+        self.tdfs
+            .entry((remote_id, tdf_id))
+            .and_modify(|timeline| timeline.sample(point))
+            .or_insert_with(|| TdfTimeline::new(point));
+
+        if let Some(run) = self.empty_runs.last_mut()
+            && run.time_after.is_none()
+        {
+            run.time_after = Some(time);
+        }
+        self.first_time.get_or_insert(time);
+        self.last_time = Some(time);
+    }
+
+    /// Append the tracker of the following range of blocks
+    pub fn append(&mut self, next: ContinuityTracker) {
+        for (key, timeline) in next.tdfs {
+            match self.tdfs.get_mut(&key) {
+                Some(combined) => combined.append(timeline),
+                None => {
+                    self.tdfs.insert(key, timeline);
+                }
+            }
+        }
+
+        // Runs that end a range have their next sample in a later range
+        if let Some(run) = self.empty_runs.last_mut()
+            && run.time_after.is_none()
+        {
+            run.time_after = next.first_time;
+        }
+        for mut run in next.empty_runs {
+            if run.time_before.is_none() {
+                run.time_before = self.last_time;
+            }
+            match self.empty_runs.last_mut() {
+                Some(prev) if prev.last_block + 1 == run.first_block => {
+                    prev.last_block = run.last_block;
+                    prev.time_after = run.time_after;
+                }
+                _ => self.empty_runs.push(run),
+            }
+        }
+        self.block = next.block;
+        self.first_time = self.first_time.or(next.first_time);
+        self.last_time = next.last_time.or(self.last_time);
+    }
+}
+
+impl ContinuityReport {
+    /// Report of the timeline followed by `tracker`
+    pub fn from_tracker(tracker: &ContinuityTracker, config: &GapConfig) -> Self {
+        let mut report = ContinuityReport {
+            interval_multiple: config.interval_multiple,
+            ..Default::default()
+        };
+        let mut keys: Vec<OutputKey> = tracker.tdfs.keys().copied().collect();
+        keys.sort();
+
+        for (remote_id, tdf_id) in keys {
+            let timeline = &tracker.tdfs[&(remote_id, tdf_id)];
+            let event = |kind, from: TimePoint, to: TimePoint| ContinuityEvent {
+                kind,
+                remote_id,
+                tdf_id: Some(tdf_id),
+                start: Some(from.time),
+                end: Some(to.time),
+                first_block: from.block,
+                last_block: to.block,
+            };
+
+            if let Some(typical) = timeline.typical_interval() {
+                report.intervals.push(TdfInterval {
+                    remote_id,
+                    tdf_id,
+                    typical_interval_seconds: typical as f64 / TDF_TICKS_PER_SECOND as f64,
+                });
+
+                let threshold = typical as f64 * config.interval_multiple;
+                let gaps: Vec<&Interval> = timeline
+                    .longest
+                    .iter()
+                    .map(|Reverse(interval)| interval)
+                    .filter(|(interval, _, _)| *interval as f64 > threshold)
+                    .collect();
+                // Every kept interval is a gap, so longer intervals may have been dropped
+                report.truncated |= gaps.len() == MAX_EVENTS_PER_TDF;
+                for (_, from, to) in gaps {
+                    report
+                        .events
+                        .push(event(ContinuityEventKind::Gap, *from, *to));
+                }
+            }
+
+            report.truncated |= timeline.num_regressions > timeline.regressions.len();
+            for (from, to) in timeline.regressions.iter() {
+                report
+                    .events
+                    .push(event(ContinuityEventKind::Regression, *from, *to));
+            }
+        }
+
+        let long_runs: Vec<&EmptyRun> = tracker
+            .empty_runs
+            .iter()
+            .filter(|run| run.last_block + 1 - run.first_block >= config.min_empty_blocks.max(1))
+            .collect();
+        report.truncated |= long_runs.len() > MAX_EMPTY_RUNS;
+        for run in long_runs.into_iter().take(MAX_EMPTY_RUNS) {
+            report.events.push(ContinuityEvent {
+                kind: ContinuityEventKind::EmptyBlocks,
+                remote_id: None,
+                tdf_id: None,
+                start: run.time_before,
+                end: run.time_after,
+                first_block: run.first_block,
+                last_block: run.last_block,
+            });
+        }
+
+        report
+            .events
+            .sort_by_key(|event| (event.first_block, event.last_block));
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: i64 = 65536;
+
+    fn tracker(samples: &[(usize, i64)], empty_blocks: &[usize]) -> ContinuityTracker {
+        let mut tracker = ContinuityTracker::new();
+        let last_block = samples
+            .iter()
+            .map(|(block, _)| *block)
+            .chain(empty_blocks.iter().copied())
+            .max()
+            .unwrap_or_default();
+        let first_block = samples
+            .iter()
+            .map(|(block, _)| *block)
+            .chain(empty_blocks.iter().copied())
+            .min()
+            .unwrap_or_default();
+        for block in first_block..=last_block {
+            tracker.start_block(block);
+            for (_, time) in samples.iter().filter(|(b, _)| *b == block) {
+                tracker.sample(None, 4, *time);
+            }
+            if empty_blocks.contains(&block) {
+                tracker.end_block(blocks::BlockTypes::EMPTY);
+            } else {
+                tracker.end_block(blocks::BlockTypes::TDF);
+            }
+        }
+        tracker
+    }
+
+    #[test]
+    fn gaps_exceed_multiple_of_typical_interval() {
+        let samples: Vec<(usize, i64)> = (0..20)
+            .map(|idx| (idx / 5, idx as i64 * SECOND))
+            .chain([(4, 60 * SECOND), (4, 61 * SECOND)])
+            .collect();
+
+        let report = ContinuityReport::from_tracker(&tracker(&samples, &[]), &GapConfig::default());
+
+        assert_eq!(report.intervals[0].typical_interval_seconds.round(), 1.0);
+        assert_eq!(report.count(ContinuityEventKind::Gap), 1);
+        let gap = &report.events[0];
+        assert_eq!(gap.start, Some(19 * SECOND));
+        assert_eq!(gap.end, Some(60 * SECOND));
+        assert_eq!(gap.duration_seconds(), Some(41.0));
+        assert!(!report.truncated);
+    }
+
+    #[test]
+    fn gap_multiple_must_be_positive() {
+        for interval_multiple in [0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let config = GapConfig {
+                interval_multiple,
+                ..GapConfig::default()
+            };
+            assert_eq!(
+                config.validate().unwrap_err().kind(),
+                io::ErrorKind::InvalidInput
+            );
+        }
+        assert!(GapConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn timelines_are_stitched_across_workers() {
+        let mut first = tracker(&[(0, 0), (0, SECOND), (1, 2 * SECOND)], &[2, 3]);
+        let second = tracker(
+            &[(5, 20 * SECOND), (5, 21 * SECOND), (6, 10 * SECOND)],
+            &[4],
+        );
+
+        first.append(second);
+        let report = ContinuityReport::from_tracker(&first, &GapConfig::default());
+
+        assert_eq!(report.count(ContinuityEventKind::Gap), 1);
+        assert_eq!(report.count(ContinuityEventKind::Regression), 1);
+        let empty: Vec<&ContinuityEvent> = report
+            .events
+            .iter()
+            .filter(|event| event.kind == ContinuityEventKind::EmptyBlocks)
+            .collect();
+        assert_eq!(empty.len(), 1);
+        assert_eq!((empty[0].first_block, empty[0].last_block), (2, 4));
+        assert_eq!(empty[0].start, Some(2 * SECOND));
+        assert_eq!(empty[0].end, Some(20 * SECOND));
+    }
+}
@@ -0,0 +1,476 @@
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hash, RandomState};
+use std::io::{self, Cursor};
+use std::ops::{ControlFlow, Range};
+use std::path::PathBuf;
+
+use tdf::TdfOutput;
+
+use crate::CancellationToken;
+use crate::input::ConcatenatedInput;
+use crate::output_filter::skip_payload;
+
+/// Blocks and samples that repeat data from earlier in the input, such as overlapping dumps of
+/// the same card or samples relayed by another device
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    /// Blocks with the same contents as an earlier block
+    blocks: HashSet<usize>,
+    /// Position in their block of the samples that were decoded from an earlier block, in order
+    samples: HashMap<usize, Vec<u16>>,
+}
+
+impl Duplicates {
+    pub fn is_duplicate_block(&self, block_idx: usize) -> bool {
+        self.blocks.contains(&block_idx)
+    }
+
+    /// Positions of the duplicate samples in a block
+    pub fn block_samples(&self, block_idx: usize) -> &[u16] {
+        self.samples.get(&block_idx).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn num_samples(&self) -> usize {
+        self.samples.values().map(Vec::len).sum()
+    }
+}
+
+/// 128 bit hashes of blocks and samples, from two independently keyed hashers
+struct ContentHasher {
+    first: RandomState,
+    second: RandomState,
+}
+
+impl ContentHasher {
+    fn new() -> Self {
+        Self {
+            first: RandomState::new(),
+            second: RandomState::new(),
+        }
+    }
+
+    fn hash<T: Hash>(&self, value: T) -> u128 {
+        (u128::from(self.first.hash_one(&value)) << 64) | u128::from(self.second.hash_one(&value))
+    }
+}
+
+/// Time of the samples decoded before the first absolute timestamp of their block
+const NO_TIME: i64 = 0;
+
+/// Hashes of the samples of a block with their times, in the order they are decoded
+struct SampleKeys<'a> {
+    hasher: &'a ContentHasher,
+    device_id: u64,
+    keys: Vec<(i64, u128)>,
+}
+
+impl TdfOutput for SampleKeys<'_> {
+    fn write(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        tdf_time: i64,
+        tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        // Samples relayed from the logging device are the same samples it logged itself
+        let remote_id = remote_id.filter(|id| *id != self.device_id);
+        let start = cursor.position() as usize;
+        let payload = cursor
+            .get_ref()
+            .get(start..start + size as usize)
+            .unwrap_or_default();
+        self.keys.push((
+            tdf_time,
+            self.hasher
+                .hash((remote_id, tdf_id, tdf_time, tdf_idx, payload)),
+        ));
+        skip_payload(size, cursor)
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        std::iter::empty()
+    }
+
+    fn written(&self, _remote_id: Option<u64>, _tdf_id: u16) -> usize {
+        0
+    }
+
+    fn output_path(&self, _remote_id: Option<u64>, _tdf_id: u16) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Earliest and latest time of the samples of a block that have an absolute time
+#[derive(Default)]
+struct SampleTimes {
+    range: Option<(i64, i64)>,
+}
+
+impl TdfOutput for SampleTimes {
+    fn write(
+        &mut self,
+        _remote_id: Option<u64>,
+        _tdf_id: u16,
+        tdf_time: i64,
+        _tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        if tdf_time != NO_TIME {
+            self.range = Some(self.range.map_or((tdf_time, tdf_time), |(first, last)| {
+                (first.min(tdf_time), last.max(tdf_time))
+            }));
+        }
+        skip_payload(size, cursor)
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        std::iter::empty()
+    }
+
+    fn written(&self, _remote_id: Option<u64>, _tdf_id: u16) -> usize {
+        0
+    }
+
+    fn output_path(&self, _remote_id: Option<u64>, _tdf_id: u16) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Run of blocks of an input file whose samples don't go back in time, with their time range
+#[derive(Clone, Debug)]
+struct Segment {
+    blocks: Range<usize>,
+    times: (i64, i64),
+}
+
+impl Segment {
+    fn overlaps(&self, (first, last): (i64, i64)) -> bool {
+        self.times.0 <= last && first <= self.times.1
+    }
+}
+
+/// Reads the blocks of the input that are compared for duplicates
+struct BlockScan<'a> {
+    input: &'a ConcatenatedInput,
+    block_size: usize,
+    hasher: ContentHasher,
+    device_id: u64,
+    cancel: &'a CancellationToken,
+}
+
+impl BlockScan<'_> {
+    /// Split the blocks of a file into segments, starting a new segment at each block without a
+    /// sample after the latest sample of the segment, as when a dump is repeated within the file.
+    /// Blocks without timed samples belong to the segment before them.
+    fn segments(&self, block_range: Range<usize>) -> io::Result<Vec<Segment>> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut block_idx = block_range.start;
+        self.input.for_each_block(
+            self.block_size,
+            block_range.start,
+            block_range.len(),
+            |block| {
+                if self.cancel.is_cancelled() {
+                    return ControlFlow::Break(());
+                }
+                let mut times = SampleTimes::default();
+                // Samples before a malformed part of a block are still decoded
+                let _ = blocks::decode_block(&mut times, block);
+                if let Some((first, last)) = times.range {
+                    match segments.last_mut() {
+                        Some(segment) if last > segment.times.1 => {
+                            segment.times = (segment.times.0.min(first), last);
+                        }
+                        Some(segment) => {
+                            segment.blocks.end = block_idx;
+                            segments.push(Segment {
+                                blocks: block_idx..block_range.end,
+                                times: (first, last),
+                            });
+                        }
+                        None => segments.push(Segment {
+                            blocks: block_range.clone(),
+                            times: (first, last),
+                        }),
+                    }
+                }
+                block_idx += 1;
+                ControlFlow::Continue(())
+            },
+        )?;
+        self.cancel.check()?;
+        Ok(segments)
+    }
+
+    /// Call `f` with the index and hash of each block in the range with timed samples in
+    /// `window`, and the positions in the block and hashes of those samples
+    fn for_each_block_in_window<F: FnMut(usize, u128, Vec<(u16, u128)>)>(
+        &self,
+        block_range: Range<usize>,
+        window: (i64, i64),
+        mut f: F,
+    ) -> io::Result<()> {
+        let mut block_idx = block_range.start;
+        self.input.for_each_block(
+            self.block_size,
+            block_range.start,
+            block_range.len(),
+            |block| {
+                if self.cancel.is_cancelled() {
+                    return ControlFlow::Break(());
+                }
+                if !blocks::is_empty_block(block) {
+                    let mut samples = SampleKeys {
+                        hasher: &self.hasher,
+                        device_id: self.device_id,
+                        keys: Vec::new(),
+                    };
+                    let _ = blocks::decode_block(&mut samples, block);
+                    let in_window: Vec<(u16, u128)> = samples
+                        .keys
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(_, (time, _))| {
+                            *time != NO_TIME && (window.0..=window.1).contains(time)
+                        })
+                        .map(|(idx, (_, key))| (idx as u16, key))
+                        .collect();
+                    if !in_window.is_empty() {
+                        f(block_idx, self.hasher.hash(block), in_window);
+                    }
+                }
+                block_idx += 1;
+                ControlFlow::Continue(())
+            },
+        )?;
+        self.cancel.check()
+    }
+}
+
+/// Find the blocks with the same contents as an earlier block, and the samples of the remaining
+/// blocks with the same remote ID, TDF ID, time, array index and payload as an earlier sample.
+///
+/// Each input file is split into segments where its time goes back, such as a dump repeated
+/// within the file, and each segment is only compared with the earlier segments over the time
+/// range their samples overlap. Only the hashes of the blocks and samples in that range are held
+/// in memory, about 16 bytes each. Samples without an absolute time can't be told apart from
+/// other samples of the same payload, so they are never dropped. The first copy of each block
+/// and sample is kept, so the result doesn't depend on how the input is split between the
+/// decode workers.
+pub(crate) fn find_duplicates(
+    input: &ConcatenatedInput,
+    block_size: usize,
+    device_id: u64,
+    cancel: &CancellationToken,
+) -> io::Result<Duplicates> {
+    let mut duplicates = Duplicates::default();
+    let scan = BlockScan {
+        input,
+        block_size,
+        hasher: ContentHasher::new(),
+        device_id,
+        cancel,
+    };
+
+    // The segments of each file are found in parallel
+    let files = input.file_blocks(block_size);
+    let segments: Vec<Segment> = std::thread::scope(|scope| {
+        let workers: Vec<_> = files
+            .iter()
+            .map(|block_range| scope.spawn(|| scan.segments(block_range.clone())))
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().unwrap())
+            .collect::<io::Result<Vec<_>>>()
+    })?
+    .into_iter()
+    .flatten()
+    .collect();
+
+    for (segment_idx, segment) in segments.iter().enumerate().skip(1) {
+        // Samples can only repeat earlier samples with the same time
+        let (earlier_first, earlier_last) = segments[..segment_idx]
+            .iter()
+            .map(|earlier| earlier.times)
+            .reduce(|(first, last), (next_first, next_last)| {
+                (first.min(next_first), last.max(next_last))
+            })
+            .unwrap();
+        let window = (
+            segment.times.0.max(earlier_first),
+            segment.times.1.min(earlier_last),
+        );
+        if window.0 > window.1 {
+            continue;
+        }
+
+        let mut seen_blocks = HashSet::new();
+        let mut seen_samples = HashSet::new();
+        for earlier in &segments[..segment_idx] {
+            if !earlier.overlaps(window) {
+                continue;
+            }
+            scan.for_each_block_in_window(
+                earlier.blocks.clone(),
+                window,
+                |_, block_key, samples| {
+                    seen_blocks.insert(block_key);
+                    seen_samples.extend(samples.into_iter().map(|(_, key)| key));
+                },
+            )?;
+        }
+        scan.for_each_block_in_window(
+            segment.blocks.clone(),
+            window,
+            |block_idx, block_key, samples| {
+                if !seen_blocks.insert(block_key) {
+                    duplicates.blocks.insert(block_idx);
+                    return;
+                }
+                let repeated: Vec<u16> = samples
+                    .into_iter()
+                    .filter(|(_, key)| !seen_samples.insert(*key))
+                    .map(|(idx, _)| idx)
+                    .collect();
+                if !repeated.is_empty() {
+                    duplicates.samples.insert(block_idx, repeated);
+                }
+            },
+        )?;
+    }
+
+    Ok(duplicates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::MappedInputs;
+    use crate::sessions::tests::tdf;
+
+    fn block(tdfs: &[Vec<u8>]) -> Vec<u8> {
+        let mut block = vec![0x01, 0x02];
+        block.extend(tdfs.concat());
+        block.resize(512, 0);
+        block
+    }
+
+    fn remote_block(remote_id: u64, tdfs: &[Vec<u8>]) -> Vec<u8> {
+        let mut block = vec![0x01, 0x0B];
+        block.extend(remote_id.to_le_bytes());
+        block.extend(tdfs.concat());
+        block.resize(512, 0);
+        block
+    }
+
+    #[test]
+    fn repeated_blocks_and_samples_are_found() {
+        let first = tdf(4, 1000, &2500i32.to_le_bytes());
+        let second = tdf(4, 1001, &2600i32.to_le_bytes());
+        let earlier = [block(&[first.clone(), second.clone()]), vec![0; 512]];
+        let later = [
+            block(&[first.clone(), second.clone()]),
+            vec![0; 512],
+            block(&[tdf(4, 1002, &2700i32.to_le_bytes()), second]),
+            remote_block(0xabcd, std::slice::from_ref(&first)),
+            remote_block(0x1234, std::slice::from_ref(&first)),
+            remote_block(0xabcd, &[tdf(4, 1000, &2501i32.to_le_bytes())]),
+        ];
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let paths = [dir.join("earlier.bin"), dir.join("later.bin")];
+        std::fs::write(&paths[0], earlier.concat()).unwrap();
+        std::fs::write(&paths[1], later.concat()).unwrap();
+        let input = ConcatenatedInput::open(&paths, &MappedInputs::default()).unwrap();
+        let duplicates = find_duplicates(&input, 512, 0xabcd, &CancellationToken::new()).unwrap();
+
+        // Empty blocks aren't duplicates, and relayed copies of the logging device's samples are
+        assert!(duplicates.is_duplicate_block(2));
+        assert!(!duplicates.is_duplicate_block(3));
+        assert_eq!(duplicates.num_blocks(), 1);
+        assert_eq!(duplicates.block_samples(4), &[1]);
+        assert_eq!(duplicates.block_samples(5), &[0]);
+        assert!(duplicates.block_samples(6).is_empty());
+        assert!(duplicates.block_samples(7).is_empty());
+        assert_eq!(duplicates.num_samples(), 2);
+    }
+
+    #[test]
+    fn samples_without_a_time_are_kept() {
+        // A constant status payload logged before the first timestamp of each block
+        let untimed = |payload: i32| {
+            let mut sample = 4u16.to_le_bytes().to_vec();
+            sample.push(4);
+            sample.extend(payload.to_le_bytes());
+            sample
+        };
+        let earlier = block(&[untimed(1), tdf(4, 1000, &2500i32.to_le_bytes())]);
+        let later = block(&[untimed(1), tdf(4, 1000, &2600i32.to_le_bytes())]);
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let paths = [dir.join("earlier.bin"), dir.join("later.bin")];
+        std::fs::write(&paths[0], [earlier.clone(), later.clone()].concat()).unwrap();
+        std::fs::write(&paths[1], later).unwrap();
+        let input = ConcatenatedInput::open(&paths, &MappedInputs::default()).unwrap();
+        let duplicates = find_duplicates(&input, 512, 0xabcd, &CancellationToken::new()).unwrap();
+
+        assert!(duplicates.block_samples(1).is_empty());
+        assert!(duplicates.is_duplicate_block(2));
+        assert_eq!(duplicates.num_blocks(), 1);
+        assert_eq!(duplicates.num_samples(), 0);
+    }
+
+    #[test]
+    fn repeats_within_a_file_are_found() {
+        let first = tdf(4, 1000, &2500i32.to_le_bytes());
+        let later = tdf(4, 2000, &2600i32.to_le_bytes());
+        let repeated = block(std::slice::from_ref(&first));
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let single = dir.join("single.bin");
+        std::fs::write(
+            &single,
+            [
+                repeated.clone(),
+                repeated.clone(),
+                block(&[later.clone(), first.clone()]),
+            ]
+            .concat(),
+        )
+        .unwrap();
+        let input =
+            ConcatenatedInput::open(std::slice::from_ref(&single), &MappedInputs::default())
+                .unwrap();
+        let duplicates = find_duplicates(&input, 512, 0xabcd, &CancellationToken::new()).unwrap();
+        assert!(duplicates.is_duplicate_block(1));
+        assert_eq!(duplicates.num_blocks(), 1);
+        assert_eq!(duplicates.block_samples(2), &[1]);
+
+        // A later dump repeats the blocks of the first, and itself
+        let extended = dir.join("extended.bin");
+        let later_block = block(std::slice::from_ref(&later));
+        std::fs::write(
+            &extended,
+            [repeated, later_block.clone(), later_block].concat(),
+        )
+        .unwrap();
+        let input = ConcatenatedInput::open(&[single, extended], &MappedInputs::default()).unwrap();
+        let duplicates = find_duplicates(&input, 512, 0xabcd, &CancellationToken::new()).unwrap();
+        assert!(duplicates.is_duplicate_block(3));
+        assert!(!duplicates.is_duplicate_block(4));
+        assert!(duplicates.is_duplicate_block(5));
+        assert_eq!(duplicates.num_blocks(), 3);
+        assert_eq!(duplicates.block_samples(4), &[0]);
+    }
+}
@@ -0,0 +1,1171 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::CancellationToken;
+use crate::fs_util;
+use crate::input::FileExtents;
+use crate::output_filter::TimeScan;
+
+const SECTOR_SIZE: u64 = 512;
+const COPY_BUFFER_SIZE: usize = 1 << 20;
+/// Extensions of raw disk images that are accepted even when no filesystem can be found in them
+const IMAGE_EXTENSIONS: &[&str] = &["img", "dd", "raw"];
+/// Folder levels below the root directory that are searched for Infuse-IoT files
+const MAX_DIRECTORY_DEPTH: usize = 4;
+/// Longer directories are assumed to be a cluster chain that loops
+const MAX_DIRECTORY_CLUSTERS: usize = 4096;
+/// Partition table entries read from a GPT
+const MAX_GPT_ENTRIES: usize = 128;
+/// Boot sector copies kept by FAT32 and exFAT, in sectors from the start of the volume
+const BACKUP_BOOT_SECTORS: [u64; 2] = [6, 12];
+
+/// FAT32 entries only use the low 28 bits
+const FAT32_ENTRY_MASK: u32 = 0x0FFF_FFFF;
+const FAT32_BAD_CLUSTER: u32 = 0x0FFF_FFF7;
+const FAT32_END_OF_CHAIN: u32 = 0x0FFF_FFF8;
+const EXFAT_BAD_CLUSTER: u32 = 0xFFFF_FFF7;
+const EXFAT_END_OF_CHAIN: u32 = 0xFFFF_FFF8;
+
+const FAT_ATTR_VOLUME_ID: u8 = 0x08;
+const FAT_ATTR_DIRECTORY: u8 = 0x10;
+const FAT_ATTR_LONG_NAME: u8 = 0x0F;
+const FAT_DELETED_ENTRY: u8 = 0xE5;
+
+const EXFAT_ENTRY_FILE: u8 = 0x85;
+const EXFAT_ENTRY_STREAM: u8 = 0xC0;
+const EXFAT_ENTRY_NAME: u8 = 0xC1;
+const EXFAT_ATTR_DIRECTORY: u16 = 0x10;
+const EXFAT_NO_FAT_CHAIN: u8 = 0x02;
+const EXFAT_NAME_CHARS_PER_ENTRY: usize = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum FsKind {
+    Fat32,
+    ExFat,
+}
+
+/// Byte range of the image holding part of a file
+#[derive(Clone, Copy)]
+struct Extent {
+    offset: u64,
+    len: u64,
+}
+
+enum Link {
+    Next(u32),
+    End,
+    Broken,
+}
+
+/// A FAT32 or exFAT filesystem inside the image
+struct Volume {
+    kind: FsKind,
+    cluster_size: u64,
+    /// Image offset of cluster 2, the first data cluster
+    heap_offset: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    /// Each copy of the FAT
+    fats: Vec<Vec<u32>>,
+    image_len: u64,
+}
+
+struct DirEntry {
+    name: String,
+    is_dir: bool,
+    first_cluster: u32,
+    size: u64,
+    /// exFAT files that are stored contiguously don't have a cluster chain in the FAT
+    contiguous: bool,
+}
+
+/// An Infuse-IoT file found in the filesystem, with the error if its data can't be located
+struct ImageFile {
+    device_id: u64,
+    name: String,
+    extents: io::Result<Vec<Extent>>,
+}
+
+/// Where the Infuse-IoT files of an image are found
+enum Located {
+    Files(Vec<ImageFile>),
+    /// The filesystem couldn't be read, with the devices that could still be identified from it
+    Damaged {
+        reason: String,
+        device_ids: Vec<u64>,
+    },
+}
+
+fn damaged(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn image_error(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("Failed to read disk image '{}': {err}", path.display()),
+    )
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    let mut buffer = vec![0; len];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+/// Whether `path` is a raw disk image, either by extension or because it contains a FAT32 or
+/// exFAT filesystem
+pub fn is_disk_image(path: &Path) -> bool {
+    if !path.is_file() {
+        return false;
+    }
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| ext.to_ascii_lowercase());
+    if extension.is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str())) {
+        return true;
+    }
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    partition_offsets(&mut file)
+        .into_iter()
+        .any(|offset| boot_sector(&mut file, offset).is_some())
+}
+
+/// Offsets of the volumes that may hold a filesystem, from the MBR or GPT partition table.
+///
+/// A card formatted without a partition table has its filesystem at the start of the image.
+fn partition_offsets(file: &mut File) -> Vec<u64> {
+    let Ok(sector) = read_at(file, 0, SECTOR_SIZE as usize) else {
+        return Vec::new();
+    };
+    if sector[510..512] != [0x55, 0xAA] {
+        return Vec::new();
+    }
+    if parse_boot_sector(&sector).is_some() {
+        return vec![0];
+    }
+
+    let mut offsets = Vec::new();
+    for entry in sector[446..510].chunks_exact(16) {
+        let partition_type = entry[4];
+        let start = LittleEndian::read_u32(&entry[8..12]) as u64;
+        if partition_type == 0xEE {
+            offsets.extend(gpt_partition_offsets(file));
+        } else if partition_type != 0 && start != 0 {
+            offsets.push(start * SECTOR_SIZE);
+        }
+    }
+    offsets
+}
+
+fn gpt_partition_offsets(file: &mut File) -> Vec<u64> {
+    let Ok(header) = read_at(file, SECTOR_SIZE, SECTOR_SIZE as usize) else {
+        return Vec::new();
+    };
+    if &header[0..8] != b"EFI PART" {
+        return Vec::new();
+    }
+    let entries_lba = LittleEndian::read_u64(&header[72..80]);
+    let num_entries = (LittleEndian::read_u32(&header[80..84]) as usize).min(MAX_GPT_ENTRIES);
+    let entry_size = LittleEndian::read_u32(&header[84..88]) as usize;
+    if !(128..=4096).contains(&entry_size) {
+        return Vec::new();
+    }
+    let Some(entries_offset) = entries_lba.checked_mul(SECTOR_SIZE) else {
+        return Vec::new();
+    };
+    let Ok(entries) = read_at(file, entries_offset, num_entries * entry_size) else {
+        return Vec::new();
+    };
+    entries
+        .chunks_exact(entry_size)
+        // Unused entries have an all zero partition type GUID
+        .filter(|entry| entry[0..16].iter().any(|byte| *byte != 0))
+        .map(|entry| LittleEndian::read_u64(&entry[32..40]).saturating_mul(SECTOR_SIZE))
+        .collect()
+}
+
+/// Filesystem parameters from a boot sector, relative to the start of the volume
+struct BootSector {
+    kind: FsKind,
+    bytes_per_sector: u64,
+    cluster_size: u64,
+    fat_offset: u64,
+    fat_len: u64,
+    num_fats: u64,
+    heap_offset: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+}
+
+fn parse_boot_sector(sector: &[u8]) -> Option<BootSector> {
+    if sector[510..512] != [0x55, 0xAA] {
+        return None;
+    }
+
+    if &sector[3..11] == b"EXFAT   " {
+        let bytes_per_sector_shift = sector[108];
+        let sectors_per_cluster_shift = sector[109];
+        let num_fats = sector[110] as u64;
+        if !(9..=12).contains(&bytes_per_sector_shift)
+            || sectors_per_cluster_shift > 25 - bytes_per_sector_shift
+            || !(1..=2).contains(&num_fats)
+        {
+            return None;
+        }
+        let bytes_per_sector = 1u64 << bytes_per_sector_shift;
+        let root_cluster = LittleEndian::read_u32(&sector[96..100]);
+        if root_cluster < 2 {
+            return None;
+        }
+        return Some(BootSector {
+            kind: FsKind::ExFat,
+            bytes_per_sector,
+            cluster_size: bytes_per_sector << sectors_per_cluster_shift,
+            fat_offset: LittleEndian::read_u32(&sector[80..84]) as u64 * bytes_per_sector,
+            fat_len: LittleEndian::read_u32(&sector[84..88]) as u64 * bytes_per_sector,
+            num_fats,
+            heap_offset: LittleEndian::read_u32(&sector[88..92]) as u64 * bytes_per_sector,
+            cluster_count: LittleEndian::read_u32(&sector[92..96]),
+            root_cluster,
+        });
+    }
+
+    let bytes_per_sector = LittleEndian::read_u16(&sector[11..13]) as u64;
+    let sectors_per_cluster = sector[13] as u64;
+    let reserved_sectors = LittleEndian::read_u16(&sector[14..16]) as u64;
+    let num_fats = sector[16] as u64;
+    let root_entries = LittleEndian::read_u16(&sector[17..19]);
+    let total_sectors = match LittleEndian::read_u16(&sector[19..21]) {
+        0 => LittleEndian::read_u32(&sector[32..36]) as u64,
+        sectors => sectors as u64,
+    };
+    let fat16_sectors = LittleEndian::read_u16(&sector[22..24]);
+    let fat_sectors = LittleEndian::read_u32(&sector[36..40]) as u64;
+    let root_cluster = LittleEndian::read_u32(&sector[44..48]);
+    // FAT12 and FAT16 have a fixed size root directory and a 16 bit FAT size
+    if ![512, 1024, 2048, 4096].contains(&bytes_per_sector)
+        || !sectors_per_cluster.is_power_of_two()
+        || reserved_sectors == 0
+        || !(1..=2).contains(&num_fats)
+        || root_entries != 0
+        || fat16_sectors != 0
+        || fat_sectors == 0
+        || root_cluster < 2
+    {
+        return None;
+    }
+    let data_start = reserved_sectors + num_fats * fat_sectors;
+    let data_clusters = total_sectors.checked_sub(data_start)? / sectors_per_cluster;
+    let fat_entries = fat_sectors * bytes_per_sector / 4 - 2;
+    Some(BootSector {
+        kind: FsKind::Fat32,
+        bytes_per_sector,
+        cluster_size: bytes_per_sector * sectors_per_cluster,
+        fat_offset: reserved_sectors * bytes_per_sector,
+        fat_len: fat_sectors * bytes_per_sector,
+        num_fats,
+        heap_offset: data_start * bytes_per_sector,
+        cluster_count: data_clusters.min(fat_entries).min(u32::MAX as u64) as u32,
+        root_cluster,
+    })
+}
+
+/// The boot sector of the volume at `offset`, falling back to its backup copy if it is damaged
+fn boot_sector(file: &mut File, offset: u64) -> Option<BootSector> {
+    std::iter::once(0)
+        .chain(BACKUP_BOOT_SECTORS)
+        .find_map(|sector| {
+            let sector_offset = offset.checked_add(sector * SECTOR_SIZE)?;
+            let data = read_at(file, sector_offset, SECTOR_SIZE as usize).ok()?;
+            let boot = parse_boot_sector(&data)?;
+            // The backup of each filesystem is only valid at its own location
+            let backup = match boot.kind {
+                FsKind::Fat32 => 6,
+                FsKind::ExFat => 12,
+            };
+            (sector == 0 || (sector == backup && boot.bytes_per_sector == SECTOR_SIZE))
+                .then_some(boot)
+        })
+}
+
+impl Volume {
+    fn open(file: &mut File, offset: u64) -> io::Result<Self> {
+        let boot = boot_sector(file, offset)
+            .ok_or_else(|| damaged(format!("No FAT32 or exFAT boot sector at offset {offset}")))?;
+        let image_len = file.metadata()?.len();
+
+        // Offsets from a damaged partition table can be anywhere, so the volume is skipped when
+        // its structures would lie past the largest possible offset
+        let outside = || {
+            damaged(format!(
+                "Volume at offset {offset} extends past the end of the image"
+            ))
+        };
+        let num_entries = (boot.cluster_count as u64 + 2).min(boot.fat_len / 4) as usize;
+        let fat_offsets = (0..boot.num_fats)
+            .map(|idx| offset.checked_add(boot.fat_offset + idx * boot.fat_len))
+            .collect::<Option<Vec<u64>>>()
+            .ok_or_else(outside)?;
+        let heap_offset = offset.checked_add(boot.heap_offset).ok_or_else(outside)?;
+        let mut fats = Vec::new();
+        for fat_offset in &fat_offsets {
+            // A damaged copy is only an error if all copies are unreadable
+            if fat_offset
+                .checked_add(num_entries as u64 * 4)
+                .is_none_or(|end| end > image_len)
+            {
+                continue;
+            }
+            let Ok(data) = read_at(file, *fat_offset, num_entries * 4) else {
+                continue;
+            };
+            fats.push(
+                data.chunks_exact(4)
+                    .map(LittleEndian::read_u32)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        if fats.is_empty() {
+            return Err(damaged(format!(
+                "File allocation table at offset {} is unreadable",
+                fat_offsets[0]
+            )));
+        }
+
+        Ok(Self {
+            kind: boot.kind,
+            cluster_size: boot.cluster_size,
+            heap_offset,
+            cluster_count: boot.cluster_count,
+            root_cluster: boot.root_cluster,
+            fats,
+            image_len,
+        })
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= 2 && (cluster as u64) < self.cluster_count as u64 + 2
+    }
+
+    /// Image offset of a valid cluster, `None` if it would be past the largest possible offset
+    fn cluster_offset(&self, cluster: u32) -> Option<u64> {
+        self.heap_offset
+            .checked_add((cluster as u64 - 2) * self.cluster_size)
+    }
+
+    /// Cluster following `cluster`, from the first copy of the FAT that has a usable entry
+    fn link(&self, cluster: u32) -> Link {
+        for fat in &self.fats {
+            let Some(entry) = fat.get(cluster as usize) else {
+                continue;
+            };
+            let link = match self.kind {
+                FsKind::Fat32 => match entry & FAT32_ENTRY_MASK {
+                    FAT32_BAD_CLUSTER => Link::Broken,
+                    value if value >= FAT32_END_OF_CHAIN => Link::End,
+                    value => Link::Next(value),
+                },
+                FsKind::ExFat => match *entry {
+                    EXFAT_BAD_CLUSTER => Link::Broken,
+                    value if value >= EXFAT_END_OF_CHAIN => Link::End,
+                    value => Link::Next(value),
+                },
+            };
+            match link {
+                Link::Next(next) if !self.is_valid_cluster(next) => continue,
+                Link::Broken => continue,
+                link => return link,
+            }
+        }
+        Link::Broken
+    }
+
+    /// Byte ranges of the image holding `len` bytes starting at `first_cluster`
+    fn extents(&self, first_cluster: u32, len: u64, contiguous: bool) -> io::Result<Vec<Extent>> {
+        let mut extents: Vec<Extent> = Vec::new();
+        let mut remaining = len;
+        let mut cluster = first_cluster;
+        while remaining > 0 {
+            if !self.is_valid_cluster(cluster) {
+                return Err(damaged(format!("Cluster {cluster} is outside the volume")));
+            }
+            let extent = Extent {
+                offset: self.cluster_offset(cluster).unwrap_or(u64::MAX),
+                len: remaining.min(self.cluster_size),
+            };
+            if extent
+                .offset
+                .checked_add(extent.len)
+                .is_none_or(|end| end > self.image_len)
+            {
+                return Err(damaged(format!(
+                    "Cluster {cluster} is beyond the end of the image"
+                )));
+            }
+            match extents.last_mut() {
+                Some(last) if last.offset + last.len == extent.offset => last.len += extent.len,
+                _ => extents.push(extent),
+            }
+            remaining -= extent.len;
+            if remaining == 0 {
+                break;
+            }
+
+            cluster = if contiguous {
+                cluster + 1
+            } else {
+                match self.link(cluster) {
+                    Link::Next(next) => next,
+                    Link::End => {
+                        return Err(damaged(format!(
+                            "Cluster chain from cluster {first_cluster} ends {remaining} bytes early"
+                        )));
+                    }
+                    Link::Broken => {
+                        return Err(damaged(format!(
+                            "Cluster chain from cluster {first_cluster} is broken at cluster {cluster}"
+                        )));
+                    }
+                }
+            };
+        }
+        Ok(extents)
+    }
+
+    /// Contents of a directory, whose length is given by its cluster chain unless `len` is known
+    fn read_directory(
+        &self,
+        file: &mut File,
+        first_cluster: u32,
+        len: Option<u64>,
+        contiguous: bool,
+    ) -> io::Result<Vec<u8>> {
+        let extents = match len {
+            Some(len) if len > MAX_DIRECTORY_CLUSTERS as u64 * self.cluster_size => {
+                return Err(damaged(format!(
+                    "Directory at cluster {first_cluster} is {len} bytes long"
+                )));
+            }
+            Some(len) => self.extents(first_cluster, len, contiguous)?,
+            None => {
+                let mut clusters = 1;
+                let mut cluster = first_cluster;
+                loop {
+                    match self.link(cluster) {
+                        Link::Next(next) => cluster = next,
+                        Link::End => break,
+                        Link::Broken => {
+                            return Err(damaged(format!(
+                                "Directory at cluster {first_cluster} is broken at cluster {cluster}"
+                            )));
+                        }
+                    }
+                    clusters += 1;
+                    if clusters > MAX_DIRECTORY_CLUSTERS {
+                        return Err(damaged(format!(
+                            "Directory at cluster {first_cluster} has a looping cluster chain"
+                        )));
+                    }
+                }
+                self.extents(first_cluster, clusters as u64 * self.cluster_size, false)?
+            }
+        };
+
+        let mut data = Vec::new();
+        for extent in extents {
+            data.extend(read_at(file, extent.offset, extent.len as usize)?);
+        }
+        Ok(data)
+    }
+
+    fn directory_entries(
+        &self,
+        file: &mut File,
+        first_cluster: u32,
+        len: Option<u64>,
+        contiguous: bool,
+    ) -> io::Result<Vec<DirEntry>> {
+        let data = self.read_directory(file, first_cluster, len, contiguous)?;
+        match self.kind {
+            FsKind::Fat32 => Ok(fat32_entries(&data)),
+            FsKind::ExFat => exfat_entries(&data),
+        }
+    }
+
+    /// Infuse-IoT files in the root directory and the folders below it
+    fn find_files(&self, file: &mut File) -> io::Result<Vec<ImageFile>> {
+        let mut found = Vec::new();
+        let mut visited = HashSet::new();
+        // The root directory of an exFAT volume always has a cluster chain
+        let mut pending = vec![(self.root_cluster, None, false, 0)];
+        while let Some((cluster, len, contiguous, depth)) = pending.pop() {
+            if !visited.insert(cluster) {
+                continue;
+            }
+            for entry in self.directory_entries(file, cluster, len, contiguous)? {
+                if entry.is_dir {
+                    if depth < MAX_DIRECTORY_DEPTH && self.is_valid_cluster(entry.first_cluster) {
+                        let len = (self.kind == FsKind::ExFat).then_some(entry.size);
+                        pending.push((entry.first_cluster, len, entry.contiguous, depth + 1));
+                    }
+                } else if let Some(device_id) = fs_util::infuse_iot_device_id(&entry.name) {
+                    found.push(ImageFile {
+                        device_id,
+                        extents: self.extents(entry.first_cluster, entry.size, entry.contiguous),
+                        name: entry.name,
+                    });
+                }
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Checksum of an 8.3 name, stored in each of its long name entries
+fn short_name_checksum(name: &[u8]) -> u8 {
+    name.iter()
+        .fold(0u8, |sum, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+fn short_name(entry: &[u8]) -> String {
+    let base = String::from_utf8_lossy(&entry[0..8]).trim_end().to_string();
+    let extension = String::from_utf8_lossy(&entry[8..11])
+        .trim_end()
+        .to_string();
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{base}.{extension}")
+    }
+}
+
+fn fat32_entries(data: &[u8]) -> Vec<DirEntry> {
+    let mut entries = Vec::new();
+    // Long name parts preceding the next short entry, by sequence number
+    let mut long_name: Vec<(u8, Vec<u16>)> = Vec::new();
+    let mut long_name_checksum = 0;
+
+    for entry in data.chunks_exact(32) {
+        match entry[0] {
+            0x00 => break,
+            FAT_DELETED_ENTRY => {
+                long_name.clear();
+                continue;
+            }
+            _ => {}
+        }
+        let attributes = entry[11];
+
+        if attributes & 0x3F == FAT_ATTR_LONG_NAME {
+            // The last part of a long name is stored first
+            if entry[0] & 0x40 != 0 {
+                long_name.clear();
+                long_name_checksum = entry[13];
+            }
+            let chars = [&entry[1..11], &entry[14..26], &entry[28..32]]
+                .concat()
+                .chunks_exact(2)
+                .map(LittleEndian::read_u16)
+                .collect();
+            if entry[13] == long_name_checksum {
+                long_name.push((entry[0] & 0x1F, chars));
+            }
+            continue;
+        }
+        let mut parts = std::mem::take(&mut long_name);
+        if attributes & FAT_ATTR_VOLUME_ID != 0 || entry[0] == b'.' {
+            continue;
+        }
+
+        // Long names that don't belong to this entry were left behind by a damaged directory
+        parts.sort_by_key(|(sequence, _)| *sequence);
+        let complete = !parts.is_empty()
+            && long_name_checksum == short_name_checksum(&entry[0..11])
+            && parts
+                .iter()
+                .enumerate()
+                .all(|(idx, (sequence, _))| *sequence as usize == idx + 1);
+        let name = if complete {
+            let chars: Vec<u16> = parts
+                .into_iter()
+                .flat_map(|(_, chars)| chars)
+                .take_while(|c| *c != 0x0000 && *c != 0xFFFF)
+                .collect();
+            String::from_utf16_lossy(&chars)
+        } else {
+            short_name(entry)
+        };
+
+        entries.push(DirEntry {
+            name,
+            is_dir: attributes & FAT_ATTR_DIRECTORY != 0,
+            first_cluster: ((LittleEndian::read_u16(&entry[20..22]) as u32) << 16)
+                | LittleEndian::read_u16(&entry[26..28]) as u32,
+            size: LittleEndian::read_u32(&entry[28..32]) as u64,
+            contiguous: false,
+        });
+    }
+    entries
+}
+
+/// Checksum of an exFAT directory entry set, excluding the checksum field itself
+fn exfat_set_checksum(set: &[u8]) -> u16 {
+    set.iter()
+        .enumerate()
+        .filter(|(idx, _)| *idx != 2 && *idx != 3)
+        .fold(0u16, |sum, (_, byte)| {
+            sum.rotate_right(1).wrapping_add(*byte as u16)
+        })
+}
+
+fn exfat_entries(data: &[u8]) -> io::Result<Vec<DirEntry>> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset + 32 <= data.len() {
+        let entry = &data[offset..offset + 32];
+        match entry[0] {
+            0x00 => break,
+            EXFAT_ENTRY_FILE => {}
+            _ => {
+                offset += 32;
+                continue;
+            }
+        }
+
+        let secondary_count = entry[1] as usize;
+        let set_len = (secondary_count + 1) * 32;
+        let set = data
+            .get(offset..offset + set_len)
+            .filter(|_| secondary_count >= 2);
+        let Some(set) = set.filter(|set| {
+            exfat_set_checksum(set) == LittleEndian::read_u16(&set[2..4])
+                && set[32] == EXFAT_ENTRY_STREAM
+        }) else {
+            return Err(damaged(format!(
+                "Directory entry at offset {offset} is damaged"
+            )));
+        };
+
+        let stream = &set[32..64];
+        let name_len = stream[3] as usize;
+        let chars: Vec<u16> = set[64..]
+            .chunks_exact(32)
+            .take(name_len.div_ceil(EXFAT_NAME_CHARS_PER_ENTRY))
+            .take_while(|name| name[0] == EXFAT_ENTRY_NAME)
+            .flat_map(|name| name[2..32].chunks_exact(2).map(LittleEndian::read_u16))
+            .take(name_len)
+            .collect();
+        if name_len == 0 || chars.len() != name_len {
+            return Err(damaged(format!(
+                "Directory entry at offset {offset} has a truncated name"
+            )));
+        }
+
+        entries.push(DirEntry {
+            name: String::from_utf16_lossy(&chars),
+            is_dir: LittleEndian::read_u16(&entry[4..6]) & EXFAT_ATTR_DIRECTORY != 0,
+            first_cluster: LittleEndian::read_u32(&stream[20..24]),
+            size: LittleEndian::read_u64(&stream[24..32]),
+            contiguous: stream[1] & EXFAT_NO_FAT_CHAIN != 0,
+        });
+        offset += set_len;
+    }
+    Ok(entries)
+}
+
+/// Find the Infuse-IoT files of the first volume that has any
+fn locate_infuse_iot_files(file: &mut File) -> io::Result<Located> {
+    let offsets = partition_offsets(file);
+    if offsets.is_empty() {
+        return Ok(Located::Damaged {
+            reason: "No partition table or filesystem found".to_string(),
+            device_ids: Vec::new(),
+        });
+    }
+
+    let mut reason = None;
+    for offset in offsets {
+        let found = match Volume::open(file, offset).and_then(|volume| volume.find_files(file)) {
+            Ok(found) => found,
+            Err(err) if crate::is_malformed_data(&err) => {
+                reason.get_or_insert(err.to_string());
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if found.is_empty() {
+            continue;
+        }
+
+        if let Some((name, err)) = found.iter().find_map(|image_file| {
+            image_file
+                .extents
+                .as_ref()
+                .err()
+                .map(|err| (&image_file.name, err))
+        }) {
+            let mut device_ids: Vec<u64> = found.iter().map(|file| file.device_id).collect();
+            device_ids.sort();
+            device_ids.dedup();
+            return Ok(Located::Damaged {
+                reason: format!("{name}: {err}"),
+                device_ids,
+            });
+        }
+        return Ok(Located::Files(found));
+    }
+
+    Ok(Located::Damaged {
+        reason: reason.unwrap_or_else(|| "No Infuse-IoT files found in the filesystem".to_string()),
+        device_ids: Vec::new(),
+    })
+}
+
+/// Device ID the blocks recovered from a damaged image are assigned to, as the blocks themselves
+/// don't record which device wrote them
+fn recovered_device_id(device_ids: &[u64]) -> u64 {
+    match device_ids {
+        [device_id] => *device_id,
+        _ => 0,
+    }
+}
+
+fn recovered_file_name(device_id: u64) -> String {
+    format!("recovered_{device_id:016x}.bin")
+}
+
+/// Names of the Infuse-IoT files in a disk image, by device ID.
+///
+/// Images with a damaged filesystem list the single file that blocks will be recovered into.
+pub(crate) fn find_image_infuse_iot_files(path: &Path) -> io::Result<HashMap<u64, Vec<String>>> {
+    let mut file = File::open(path).map_err(|err| image_error(path, err))?;
+    let located = locate_infuse_iot_files(&mut file).map_err(|err| image_error(path, err))?;
+
+    let mut files: HashMap<u64, Vec<String>> = HashMap::new();
+    match located {
+        Located::Files(found) => {
+            for image_file in found {
+                files
+                    .entry(image_file.device_id)
+                    .or_default()
+                    .push(image_file.name);
+            }
+            for names in files.values_mut() {
+                names.sort_by_key(|name| fs_util::subfile_number(Path::new(name)));
+            }
+        }
+        Located::Damaged { device_ids, .. } => {
+            let device_id = recovered_device_id(&device_ids);
+            files.insert(device_id, vec![recovered_file_name(device_id)]);
+        }
+    }
+    Ok(files)
+}
+
+/// Infuse-IoT file of a disk image, read in place from `extents` of the image unless its blocks
+/// had to be recovered into a new file
+pub(crate) struct ImageEntry {
+    pub(crate) device_id: u64,
+    pub(crate) path: PathBuf,
+    pub(crate) extents: Option<FileExtents>,
+}
+
+/// The Infuse-IoT files of a disk image as paths in `folder`, optionally only those of
+/// `device_ids`, with warnings about how the files were found.
+///
+/// Files are read through their extents in the image rather than copied, so they can only be read
+/// through [`crate::input::MappedInputs`] holding the extents. When the filesystem is
+/// damaged the image is scanned for valid TDF blocks instead, which are written in the order they
+/// are found into a single file in `folder`.
+pub(crate) fn extract_image_infuse_iot_files(
+    path: &Path,
+    device_ids: Option<&[u64]>,
+    block_size: usize,
+    cancel: &CancellationToken,
+    folder: &Path,
+) -> io::Result<(Vec<ImageEntry>, Vec<String>)> {
+    let mut file = File::open(path).map_err(|err| image_error(path, err))?;
+    let located = locate_infuse_iot_files(&mut file).map_err(|err| image_error(path, err))?;
+
+    match located {
+        Located::Files(found) => {
+            let mut names = HashSet::new();
+            let mut entries = Vec::new();
+            for image_file in found {
+                if device_ids.is_some_and(|ids| !ids.contains(&image_file.device_id)) {
+                    continue;
+                }
+                if !names.insert(image_file.name.clone()) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!(
+                            "Disk image '{}' contains {} more than once",
+                            path.display(),
+                            image_file.name
+                        ),
+                    ));
+                }
+                entries.push(ImageEntry {
+                    device_id: image_file.device_id,
+                    path: folder.join(&image_file.name),
+                    extents: Some(FileExtents {
+                        source: path.to_path_buf(),
+                        extents: image_file
+                            .extents?
+                            .into_iter()
+                            .map(|extent| (extent.offset, extent.len))
+                            .collect(),
+                    }),
+                });
+            }
+            Ok((entries, Vec::new()))
+        }
+        Located::Damaged {
+            reason,
+            device_ids: found_ids,
+        } => {
+            let device_id = recovered_device_id(&found_ids);
+            if device_ids.is_some_and(|ids| !ids.contains(&device_id)) {
+                return Ok((Vec::new(), Vec::new()));
+            }
+            let mut warnings = vec![format!(
+                "Filesystem of '{}' is damaged ({reason}), scanning the image for TDF blocks",
+                path.display()
+            )];
+            if found_ids.len() > 1 {
+                warnings.push(format!(
+                    "Blocks recovered from {} devices can't be separated, they are decoded as device {device_id:016x}",
+                    found_ids.len()
+                ));
+            }
+
+            let recovered_path = folder.join(recovered_file_name(device_id));
+            let mut output = File::create(&recovered_path)?;
+            let recovered = scan_tdf_blocks(&mut file, block_size, &mut output, cancel)
+                .map_err(|err| image_error(path, err))?;
+            warnings.push(format!("Recovered {recovered} TDF blocks"));
+            let entry = ImageEntry {
+                device_id,
+                path: recovered_path,
+                extents: None,
+            };
+            Ok((vec![entry], warnings))
+        }
+    }
+}
+
+/// Whether a block holds TDFs that decode without errors
+fn is_tdf_block(block: &[u8]) -> bool {
+    if block[1] != 0x02 && block[1] != 0x0B {
+        return false;
+    }
+    let mut scan = TimeScan::default();
+    blocks::decode_block(&mut scan, block).is_ok() && scan.samples > 0
+}
+
+/// Copy every valid TDF block of the image to `output`, returning the number of blocks.
+///
+/// Blocks are searched for at every sector, as file data always starts on a sector boundary.
+fn scan_tdf_blocks(
+    file: &mut File,
+    block_size: usize,
+    output: &mut File,
+    cancel: &CancellationToken,
+) -> io::Result<usize> {
+    let step = block_size.min(SECTOR_SIZE as usize);
+    file.seek(SeekFrom::Start(0))?;
+    let mut reader = BufReader::with_capacity(COPY_BUFFER_SIZE, &mut *file);
+    let mut window = Vec::with_capacity(block_size);
+    let mut recovered = 0;
+    let mut steps = 0usize;
+
+    loop {
+        if steps.is_multiple_of(COPY_BUFFER_SIZE / step) {
+            cancel.check()?;
+        }
+        steps += 1;
+
+        // Fill the window, stopping at the end of the image
+        let filled = window.len();
+        window.resize(block_size, 0);
+        let mut count = filled;
+        while count < block_size {
+            match reader.read(&mut window[count..])? {
+                0 => break,
+                read => count += read,
+            }
+        }
+        if count < block_size {
+            break;
+        }
+
+        if is_tdf_block(&window) {
+            output.write_all(&window)?;
+            recovered += 1;
+            window.clear();
+        } else {
+            window.drain(..step);
+        }
+    }
+    Ok(recovered)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::archive::tests::read_input;
+    use crate::archive::{ExtractedArchive, extract_infuse_iot_files};
+    use std::fs;
+
+    const TEST_BLOCKS: &[u8] = include_bytes!("../data/tdf_test_blocks.bin");
+    const CLUSTER_SIZE: usize = 1024;
+    const RESERVED_SECTORS: usize = 32;
+    const FAT_SECTORS: usize = 8;
+    const NUM_CLUSTERS: usize = 240;
+    /// Sectors before the partition, so that the MBR has to be followed
+    const PARTITION_START: usize = 8;
+
+    fn utf16(name: &str) -> Vec<u16> {
+        name.encode_utf16().collect()
+    }
+
+    /// Image with an MBR and a FAT32 volume holding `files` in its root directory, each stored
+    /// in consecutive clusters after the root directory
+    pub(crate) fn fat32_image(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let volume = PARTITION_START * 512;
+        let fat_offset = volume + RESERVED_SECTORS * 512;
+        let heap = fat_offset + FAT_SECTORS * 512;
+        let total_sectors = RESERVED_SECTORS + FAT_SECTORS + NUM_CLUSTERS * CLUSTER_SIZE / 512;
+        let mut image = vec![0u8; volume + total_sectors * 512];
+
+        image[446 + 4] = 0x0C;
+        LittleEndian::write_u32(&mut image[446 + 8..], PARTITION_START as u32);
+        LittleEndian::write_u32(&mut image[446 + 12..], total_sectors as u32);
+        image[510] = 0x55;
+        image[511] = 0xAA;
+
+        let boot = &mut image[volume..volume + 512];
+        boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+        LittleEndian::write_u16(&mut boot[11..], 512);
+        boot[13] = (CLUSTER_SIZE / 512) as u8;
+        LittleEndian::write_u16(&mut boot[14..], RESERVED_SECTORS as u16);
+        boot[16] = 1;
+        LittleEndian::write_u32(&mut boot[32..], total_sectors as u32);
+        LittleEndian::write_u32(&mut boot[36..], FAT_SECTORS as u32);
+        LittleEndian::write_u32(&mut boot[44..], 2);
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+
+        let mut fat = vec![0x0FFF_FFF8, FAT32_ENTRY_MASK, FAT32_END_OF_CHAIN];
+        let mut directory = Vec::new();
+        for (idx, (name, data)) in files.iter().enumerate() {
+            let short = format!("INFUSE~{}BIN", idx + 1);
+            let checksum = short_name_checksum(short.as_bytes());
+            let mut chars = utf16(name);
+            chars.push(0);
+            chars.resize(chars.len().div_ceil(13) * 13, 0xFFFF);
+            let parts: Vec<&[u16]> = chars.chunks(13).collect();
+            for (part_idx, part) in parts.iter().enumerate().rev() {
+                let mut entry = [0u8; 32];
+                entry[0] = (part_idx + 1) as u8;
+                if part_idx == parts.len() - 1 {
+                    entry[0] |= 0x40;
+                }
+                entry[11] = FAT_ATTR_LONG_NAME;
+                entry[13] = checksum;
+                for (char_idx, c) in part.iter().enumerate() {
+                    let pos = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30][char_idx];
+                    LittleEndian::write_u16(&mut entry[pos..], *c);
+                }
+                directory.extend(entry);
+            }
+
+            let first_cluster = fat.len() as u32;
+            let clusters = data.len().div_ceil(CLUSTER_SIZE);
+            for cluster in 0..clusters as u32 {
+                fat.push(if cluster + 1 == clusters as u32 {
+                    FAT32_END_OF_CHAIN
+                } else {
+                    first_cluster + cluster + 1
+                });
+            }
+            let start = heap + (first_cluster as usize - 2) * CLUSTER_SIZE;
+            image[start..start + data.len()].copy_from_slice(data);
+
+            let mut entry = [0u8; 32];
+            entry[0..11].copy_from_slice(short.as_bytes());
+            entry[11] = 0x20;
+            LittleEndian::write_u16(&mut entry[20..], (first_cluster >> 16) as u16);
+            LittleEndian::write_u16(&mut entry[26..], first_cluster as u16);
+            LittleEndian::write_u32(&mut entry[28..], data.len() as u32);
+            directory.extend(entry);
+        }
+        image[heap..heap + directory.len()].copy_from_slice(&directory);
+        for (idx, entry) in fat.iter().enumerate() {
+            LittleEndian::write_u32(&mut image[fat_offset + idx * 4..], *entry);
+        }
+        image
+    }
+
+    /// Image of a single exFAT volume, without a partition table, holding `files` stored
+    /// contiguously after the root directory
+    fn exfat_image(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let fat_offset = RESERVED_SECTORS * 512;
+        let heap = fat_offset + FAT_SECTORS * 512;
+        let mut image = vec![0u8; heap + NUM_CLUSTERS * CLUSTER_SIZE];
+
+        let boot = &mut image[0..512];
+        boot[3..11].copy_from_slice(b"EXFAT   ");
+        LittleEndian::write_u32(&mut boot[80..], RESERVED_SECTORS as u32);
+        LittleEndian::write_u32(&mut boot[84..], FAT_SECTORS as u32);
+        LittleEndian::write_u32(&mut boot[88..], (heap / 512) as u32);
+        LittleEndian::write_u32(&mut boot[92..], NUM_CLUSTERS as u32);
+        LittleEndian::write_u32(&mut boot[96..], 2);
+        boot[108] = 9;
+        boot[109] = (CLUSTER_SIZE / 512).trailing_zeros() as u8;
+        boot[110] = 1;
+        boot[510] = 0x55;
+        boot[511] = 0xAA;
+        LittleEndian::write_u32(&mut image[fat_offset + 8..], 0xFFFF_FFFF);
+
+        let mut directory = Vec::new();
+        let mut next_cluster = 3;
+        for (name, data) in files {
+            let chars = utf16(name);
+            let name_entries = chars.len().div_ceil(EXFAT_NAME_CHARS_PER_ENTRY);
+            let mut set = vec![0u8; (2 + name_entries) * 32];
+            set[0] = EXFAT_ENTRY_FILE;
+            set[1] = (1 + name_entries) as u8;
+            set[4] = 0x20;
+            set[32] = EXFAT_ENTRY_STREAM;
+            set[33] = 0x01 | EXFAT_NO_FAT_CHAIN;
+            set[35] = chars.len() as u8;
+            LittleEndian::write_u64(&mut set[40..], data.len() as u64);
+            LittleEndian::write_u32(&mut set[52..], next_cluster);
+            LittleEndian::write_u64(&mut set[56..], data.len() as u64);
+            for (idx, c) in chars.iter().enumerate() {
+                let entry = 64 + idx / EXFAT_NAME_CHARS_PER_ENTRY * 32;
+                set[entry] = EXFAT_ENTRY_NAME;
+                LittleEndian::write_u16(
+                    &mut set[entry + 2 + (idx % EXFAT_NAME_CHARS_PER_ENTRY) * 2..],
+                    *c,
+                );
+            }
+            let checksum = exfat_set_checksum(&set);
+            LittleEndian::write_u16(&mut set[2..], checksum);
+            directory.extend(set);
+
+            let start = heap + (next_cluster as usize - 2) * CLUSTER_SIZE;
+            image[start..start + data.len()].copy_from_slice(data);
+            next_cluster += data.len().div_ceil(CLUSTER_SIZE) as u32;
+        }
+        image[heap..heap + directory.len()].copy_from_slice(&directory);
+        image
+    }
+
+    fn extract(image: &Path) -> ExtractedArchive {
+        extract_infuse_iot_files(image, None, 512, &CancellationToken::new()).unwrap()
+    }
+
+    #[test]
+    fn fat32_files_are_read_through_the_partition_table() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = dir.join("card");
+        let first = &TEST_BLOCKS[..3000];
+        let second = &TEST_BLOCKS[3000..3512];
+        fs::write(
+            &image,
+            fat32_image(&[
+                ("infuse_0123456789abcdef_1.bin", second),
+                ("infuse_0123456789abcdef_0.bin", first),
+            ]),
+        )
+        .unwrap();
+
+        assert!(is_disk_image(&image));
+        let names = find_image_infuse_iot_files(&image).unwrap();
+        assert_eq!(
+            names[&0x0123_4567_89ab_cdef],
+            vec![
+                "infuse_0123456789abcdef_0.bin",
+                "infuse_0123456789abcdef_1.bin"
+            ]
+        );
+
+        let extracted = extract(&image);
+        let files = &extracted.files()[&0x0123_4567_89ab_cdef];
+        assert!(extracted.warnings().is_empty());
+        assert!(!files[0].exists(), "Files are read in place");
+        assert_eq!(read_input(&extracted, &files[0]), first);
+        assert_eq!(read_input(&extracted, &files[1]), second);
+    }
+
+    #[test]
+    fn exfat_files_are_read_without_a_partition_table() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = dir.join("card.dd");
+        let data = &TEST_BLOCKS[..5120];
+        fs::write(
+            &image,
+            exfat_image(&[
+                ("notes.txt", b"ignored"),
+                ("infuse_fedcba9876543210_0.bin", data),
+            ]),
+        )
+        .unwrap();
+
+        let extracted = extract(&image);
+        assert!(extracted.warnings().is_empty());
+        assert_eq!(extracted.files().len(), 1);
+        assert_eq!(
+            read_input(&extracted, &extracted.files()[&0xfedc_ba98_7654_3210][0]),
+            data
+        );
+    }
+
+    #[test]
+    fn damaged_filesystem_is_scanned_for_tdf_blocks() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let image = dir.join("card.img");
+        let data = &TEST_BLOCKS[..4096];
+        let mut bytes = fat32_image(&[("infuse_0123456789abcdef_0.bin", data)]);
+        // Break the cluster chain of the file half way through
+        let fat_offset = (PARTITION_START + RESERVED_SECTORS) * 512;
+        LittleEndian::write_u32(&mut bytes[fat_offset + 4 * 4..], 0);
+        fs::write(&image, bytes).unwrap();
+
+        let names = find_image_infuse_iot_files(&image).unwrap();
+        assert_eq!(
+            names[&0x0123_4567_89ab_cdef],
+            vec!["recovered_0123456789abcdef.bin"]
+        );
+
+        let extracted = extract(&image);
+        assert!(extracted.warnings()[0].contains("damaged"));
+        // A partition table entry can point past the largest possible offset
+        let mut file = File::open(&image).unwrap();
+        assert!(boot_sector(&mut file, u64::MAX - SECTOR_SIZE).is_none());
+        let expected: Vec<u8> = data
+            .chunks_exact(512)
+            .filter(|block| is_tdf_block(block))
+            .flatten()
+            .copied()
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(
+            fs::read(&extracted.files()[&0x0123_4567_89ab_cdef][0]).unwrap(),
+            expected
+        );
+    }
+}
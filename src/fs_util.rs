@@ -1,70 +1,195 @@
 use regex::Regex;
-use std::{collections::HashMap, fs, io, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
 
-pub fn find_infuse_iot_files(dir: &PathBuf) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
-    // Regular expression to match the pattern "infuse_%016x_%d.bin"
-    let pattern = Regex::new(r"^infuse_([0-9a-fA-F]{16})_[0-9]+\.bin$").unwrap();
-    let mut matching_files: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+/// Matches `infuse_%016x_%d.bin`, capturing the device ID and sub-file number
+static INFUSE_IOT_PATTERN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^infuse_([0-9a-fA-F]{16})_([0-9]+)\.bin$").unwrap());
+/// Matches `.bin` files whose name contains a standalone 16-character hex ID, capturing the ID
+static FALLBACK_PATTERN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:^|[^0-9a-fA-F])([0-9a-fA-F]{16})(?:[^0-9a-fA-F].*)?\.bin$").unwrap()
+});
 
-    for entry in fs::read_dir(dir)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                if let Some(captures) = pattern.captures(file_name) {
-                    let device_id = u64::from_str_radix(&captures[1], 16).unwrap();
-
-                    match matching_files.get_mut(&device_id) {
-                        Some(path_list) => {
-                            path_list.push(path);
-                        }
-                        None => {
-                            matching_files.insert(device_id, vec![path]);
-                        }
-                    }
-                }
+/// Subfile number `n` of a file named `infuse_%016x_%d.bin`
+pub fn subfile_number(path: &Path) -> Option<u64> {
+    let file_name = path.file_name()?.to_str()?;
+    INFUSE_IOT_PATTERN.captures(file_name)?[2].parse().ok()
+}
+
+/// Problems with a set of input files for a single device that may indicate missing data
+pub fn input_file_warnings(
+    files: &[PathBuf],
+    mapped: &crate::input::MappedInputs,
+    block_size: usize,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+
+    let mut previous: Option<u64> = None;
+    for (idx, file) in files.iter().enumerate() {
+        // Each folder holds a separate dump of the device
+        if idx > 0 && file.parent() != files[idx - 1].parent() {
+            previous = None;
+        }
+        if let Some(num) = subfile_number(file) {
+            if let Some(prev) = previous
+                && num > prev + 1
+            {
+                let missing = if num == prev + 2 {
+                    format!("{}", prev + 1)
+                } else {
+                    format!("{} to {}", prev + 1, num - 1)
+                };
+                warnings.push(format!(
+                    "Missing sub-file {missing} before {}",
+                    mapped.display_path(file).display()
+                ));
             }
+            previous = Some(num);
+        }
+
+        if let Ok(len) = mapped.input_len(file)
+            && block_size != 0
+            && len % block_size as u64 != 0
+        {
+            warnings.push(format!(
+                "{} is {len} bytes, which is not a multiple of the {block_size} byte block size",
+                mapped.display_path(file).display(),
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Device ID of a file named `infuse_%016x_%d.bin`
+pub fn infuse_iot_device_id(file_name: &str) -> Option<u64> {
+    let captures = INFUSE_IOT_PATTERN.captures(file_name)?;
+    u64::from_str_radix(&captures[1], 16).ok()
+}
+
+/// Device ID of a `.bin` file whose name contains a standalone 16-character hex ID
+pub fn fallback_device_id(file_name: &str) -> Option<u64> {
+    let captures = FALLBACK_PATTERN.captures(file_name)?;
+    u64::from_str_radix(&captures[1], 16).ok()
+}
+
+/// Infuse-IoT files directly inside a folder, by device ID, with the sub-files of each device in
+/// order. Standalone hex ID files are only used when there are no `infuse_%016x_%d.bin` files.
+pub fn find_infuse_iot_files(dir: &Path) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
+    let mut files = Vec::new();
+    list_files(dir, false, &mut files)?;
+    group_device_files(files)
+}
+
+/// Infuse-IoT files in a folder and its subfolders, by device ID.
+///
+/// Folders holding separate dumps of the same device give the files of every dump, grouped by
+/// folder, so that they are decoded and deduplicated together.
+pub fn find_infuse_iot_files_recursive(dir: &Path) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
+    let mut files = Vec::new();
+    list_files(dir, true, &mut files)?;
+    group_device_files(files)
+}
+
+/// Infuse-IoT files of an input folder, by device ID. The dumps in its subfolders are only
+/// included when they are deduplicated, as they would otherwise repeat the same data.
+pub fn find_input_folder_files(dir: &Path, dedup: bool) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
+    if dedup {
+        find_infuse_iot_files_recursive(dir)
+    } else {
+        find_infuse_iot_files(dir)
+    }
+}
+
+fn group_device_files(mut files: Vec<PathBuf>) -> io::Result<HashMap<u64, Vec<PathBuf>>> {
+    // Directory iteration order is filesystem dependent
+    files.sort();
+
+    let mut matching_files: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in &files {
+        if let Some(file_name) = path.file_name().and_then(|n| n.to_str())
+            && let Some(device_id) = infuse_iot_device_id(file_name)
+        {
+            matching_files
+                .entry(device_id)
+                .or_default()
+                .push(path.clone());
         }
     }
 
     if !matching_files.is_empty() {
+        for path_list in matching_files.values_mut() {
+            path_list
+                .sort_by_key(|path| (path.parent().map(Path::to_path_buf), subfile_number(path)));
+        }
         return Ok(matching_files);
     }
 
     // Fallback for files that include a standalone 16-character hex ID and end in ".bin".
-    let fallback_pattern =
-        Regex::new(r"(?:^|[^0-9a-fA-F])([0-9a-fA-F]{16})(?:[^0-9a-fA-F].*)?\.bin$").unwrap();
+    for path in files {
+        if let Some(file_name) = path.file_name().and_then(|n| n.to_str())
+            && let Some(device_id) = fallback_device_id(file_name)
+        {
+            let path_list = matching_files.entry(device_id).or_default();
+            if let Some(existing) = path_list
+                .iter()
+                .find(|existing| existing.parent() == path.parent())
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "Multiple fallback files found for Infuse-IoT device ID {device_id:016x}: {:?} and {:?}",
+                        existing, path
+                    ),
+                ));
+            }
+            path_list.push(path);
+        }
+    }
+
+    Ok(matching_files)
+}
+
+/// Add the files of each device found in another input after those already found, so that
+/// overlapping dumps of a device are decoded and deduplicated together
+pub fn merge_device_files(
+    files: &mut HashMap<u64, Vec<PathBuf>>,
+    more_files: HashMap<u64, Vec<PathBuf>>,
+) {
+    for (device_id, paths) in more_files {
+        files.entry(device_id).or_default().extend(paths);
+    }
+}
 
+/// Files in a folder, and in its subfolders if `recursive`, without following links to folders
+fn list_files(dir: &Path, recursive: bool, files: &mut Vec<PathBuf>) -> io::Result<()> {
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
         let path = entry.path();
-        if path.is_file() {
-            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
-                if let Some(captures) = fallback_pattern.captures(file_name) {
-                    let device_id = u64::from_str_radix(&captures[1], 16).unwrap();
-
-                    if let Some(existing_paths) = matching_files.get(&device_id) {
-                        return Err(io::Error::new(
-                            io::ErrorKind::InvalidData,
-                            format!(
-                                "Multiple fallback files found for Infuse-IoT device ID {device_id:016x}: {:?} and {:?}",
-                                existing_paths[0], path
-                            ),
-                        ));
-                    }
-
-                    matching_files.insert(device_id, vec![path]);
+        if entry.file_type()?.is_dir() {
+            // Folders of the card that can't be read, such as `System Volume Information`, can't
+            // hold dumps
+            if recursive {
+                match list_files(&path, recursive, files) {
+                    Err(err) if err.kind() == io::ErrorKind::PermissionDenied => {}
+                    result => result?,
                 }
             }
+        } else if path.is_file() {
+            files.push(path);
         }
     }
-
-    Ok(matching_files)
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::input::MappedInputs;
     use std::{
         fs::File,
         time::{SystemTime, UNIX_EPOCH},
@@ -80,7 +205,7 @@ mod tests {
         dir
     }
 
-    fn touch(dir: &PathBuf, file_name: &str) {
+    fn touch(dir: &Path, file_name: &str) {
         File::create(dir.join(file_name)).unwrap();
     }
 
@@ -128,4 +253,99 @@ mod tests {
 
         fs::remove_dir_all(dir).unwrap();
     }
+
+    #[test]
+    fn finds_dumps_in_subfolders() {
+        let dir = temp_dir("subfolders");
+        for folder in ["dump_b", "dump_a", "dump_a/nested"] {
+            fs::create_dir(dir.join(folder)).unwrap();
+        }
+        touch(&dir.join("dump_b"), "infuse_0123456789abcdef_12.bin");
+        touch(&dir.join("dump_a"), "infuse_0123456789abcdef_10.bin");
+        touch(&dir.join("dump_a"), "infuse_0123456789abcdef_2.bin");
+        touch(&dir.join("dump_a/nested"), "infuse_fedcba9876543210_0.bin");
+
+        assert!(find_infuse_iot_files(&dir).unwrap().is_empty());
+        let files = find_infuse_iot_files_recursive(&dir).unwrap();
+        assert_eq!(
+            files[&0x0123_4567_89ab_cdef],
+            vec![
+                dir.join("dump_a/infuse_0123456789abcdef_2.bin"),
+                dir.join("dump_a/infuse_0123456789abcdef_10.bin"),
+                dir.join("dump_b/infuse_0123456789abcdef_12.bin"),
+            ]
+        );
+        assert_eq!(files[&0xfedc_ba98_7654_3210].len(), 1);
+        // Sub-file numbers are only checked within each dump
+        assert_eq!(
+            input_file_warnings(
+                &files[&0x0123_4567_89ab_cdef],
+                &MappedInputs::default(),
+                512
+            )
+            .len(),
+            1
+        );
+
+        // Fallback files of the same device are only ambiguous within a folder
+        let fallback = temp_dir("subfolders_fallback");
+        for folder in ["first", "second"] {
+            fs::create_dir(fallback.join(folder)).unwrap();
+            touch(&fallback.join(folder), "capture_0123456789abcdef.bin");
+        }
+        let files = find_infuse_iot_files_recursive(&fallback).unwrap();
+        assert_eq!(files[&0x0123_4567_89ab_cdef].len(), 2);
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(fallback).unwrap();
+    }
+
+    #[test]
+    fn sorts_sub_files_numerically() {
+        let dir = temp_dir("sub_file_order");
+        for num in [10, 2, 1, 0] {
+            touch(&dir, &format!("infuse_0123456789abcdef_{num}.bin"));
+        }
+
+        let files = find_infuse_iot_files(&dir).unwrap();
+        let numbers: Vec<Option<u64>> = files[&0x0123_4567_89ab_cdef]
+            .iter()
+            .map(|path| subfile_number(path))
+            .collect();
+
+        assert_eq!(numbers, vec![Some(0), Some(1), Some(2), Some(10)]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn warns_on_missing_sub_files_and_partial_blocks() {
+        let dir = temp_dir("sub_file_warnings");
+        for (num, len) in [(0, 512), (1, 1024), (3, 100), (7, 512)] {
+            let path = dir.join(format!("infuse_0123456789abcdef_{num}.bin"));
+            fs::write(path, vec![0u8; len]).unwrap();
+        }
+
+        let files = find_infuse_iot_files(&dir).unwrap();
+        let warnings = input_file_warnings(
+            &files[&0x0123_4567_89ab_cdef],
+            &MappedInputs::default(),
+            512,
+        );
+
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("Missing sub-file 2 before"));
+        assert!(warnings[1].contains("infuse_0123456789abcdef_3.bin is 100 bytes"));
+        assert!(warnings[2].starts_with("Missing sub-file 4 to 6 before"));
+        assert!(
+            input_file_warnings(
+                &files[&0x0123_4567_89ab_cdef][..2],
+                &MappedInputs::default(),
+                512
+            )
+            .is_empty()
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,461 @@
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::ops::{ControlFlow, Range};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+/// SHA-256 hashes of the input files that were read from start to end, by path
+pub type FileHashes = Arc<Mutex<HashMap<PathBuf, String>>>;
+
+/// Byte ranges of another file that are read in place of an input path, such as an archive
+/// entry that isn't compressed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct FileExtents {
+    pub(crate) source: PathBuf,
+    /// `(offset, length)` of each part of the file in `source`, in order
+    pub(crate) extents: Vec<(u64, u64)>,
+}
+
+impl FileExtents {
+    fn len(&self) -> u64 {
+        self.extents.iter().map(|(_, len)| len).sum()
+    }
+}
+
+/// Input paths that are read from the extents of another file instead of being opened, such as
+/// the files of an archive or disk image that are read in place. Other paths are opened as usual.
+#[derive(Clone, Debug, Default)]
+pub struct MappedInputs {
+    files: HashMap<PathBuf, Arc<FileExtents>>,
+    /// Archive entries that the files of an extracted archive are shown as
+    entries: HashMap<PathBuf, PathBuf>,
+}
+
+impl MappedInputs {
+    /// Read `path` from the extents of another file
+    pub(crate) fn insert(&mut self, path: PathBuf, extents: FileExtents) {
+        self.files.insert(path, Arc::new(extents));
+    }
+
+    /// Show `path` as the archive entry `entry` that it was extracted from
+    pub(crate) fn insert_entry(&mut self, path: PathBuf, entry: PathBuf) {
+        self.entries.insert(path, entry);
+    }
+
+    /// Add the inputs of another archive or disk image
+    pub fn extend(&mut self, other: MappedInputs) {
+        self.files.extend(other.files);
+        self.entries.extend(other.entries);
+    }
+
+    /// Path to show for an input file, which is its archive entry for the files of an archive
+    /// rather than their temporary path
+    pub fn display_path<'a>(&'a self, path: &'a Path) -> &'a Path {
+        self.entries.get(path).map_or(path, PathBuf::as_path)
+    }
+
+    /// Size of an input file
+    pub fn input_len(&self, path: &Path) -> io::Result<u64> {
+        match self.files.get(path) {
+            Some(mapped) => Ok(mapped.len()),
+            None => Ok(path.metadata()?.len()),
+        }
+    }
+
+    /// Open an input file for reading
+    pub(crate) fn open(&self, path: &Path) -> io::Result<InputFile> {
+        match self.files.get(path) {
+            Some(mapped) => Ok(InputFile::Mapped(ExtentReader {
+                file: File::open(&mapped.source)?,
+                len: mapped.len(),
+                extents: mapped.clone(),
+                pos: 0,
+            })),
+            None => Ok(InputFile::File(File::open(path)?)),
+        }
+    }
+}
+
+pub(crate) enum InputFile {
+    File(File),
+    Mapped(ExtentReader),
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            InputFile::File(file) => file.read(buf),
+            InputFile::Mapped(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl Seek for InputFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            InputFile::File(file) => file.seek(pos),
+            InputFile::Mapped(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// Reads the extents of a mapped input file as if they were one file
+pub(crate) struct ExtentReader {
+    file: File,
+    extents: Arc<FileExtents>,
+    len: u64,
+    pos: u64,
+}
+
+impl Read for ExtentReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut start = 0;
+        for (offset, len) in self.extents.extents.iter() {
+            if self.pos < start + len {
+                let skip = self.pos - start;
+                let count = buf.len().min((len - skip) as usize);
+                self.file.seek(SeekFrom::Start(offset + skip))?;
+                let count = self.file.read(&mut buf[..count])?;
+                if count == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!(
+                            "'{}' is shorter than expected",
+                            self.extents.source.display()
+                        ),
+                    ));
+                }
+                self.pos += count as u64;
+                return Ok(count);
+            }
+            start += len;
+        }
+        Ok(0)
+    }
+}
+
+impl Seek for ExtentReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Seek before the start of the file",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Logical concatenation of several input files, read without copying them.
+///
+/// Block boundaries are computed over the concatenated data, so a block may span two files
+/// when a file size is not a multiple of the block size.
+///
+/// Files are read with ordinary reads rather than memory mapped, so that an input that disappears
+/// part way through (e.g. a removed SD card) is reported as an error instead of faulting the process.
+pub struct ConcatenatedInput {
+    paths: Vec<PathBuf>,
+    offsets: Vec<usize>,
+    len: usize,
+    mapped: MappedInputs,
+    hashes: Option<FileHashes>,
+}
+
+/// Sequential reader positioned inside one of the input files
+struct InputReader {
+    file_idx: usize,
+    reader: BufReader<InputFile>,
+    /// Hash of the file so far, if it was read from the start
+    hasher: Option<Sha256>,
+    offset: usize,
+}
+
+impl ConcatenatedInput {
+    /// Open `files` in order, reading the paths in `mapped` from their extents
+    pub fn open(files: &[PathBuf], mapped: &MappedInputs) -> io::Result<Self> {
+        let mut paths = Vec::new();
+        let mut offsets = Vec::new();
+        let mut len = 0;
+
+        for path in files {
+            let file_len = mapped
+                .input_len(path)
+                .map_err(|err| input_error(path, "open", err))? as usize;
+            // Empty files contribute no data
+            if file_len == 0 {
+                continue;
+            }
+            paths.push(path.clone());
+            offsets.push(len);
+            len += file_len;
+        }
+
+        Ok(Self {
+            paths,
+            offsets,
+            len,
+            mapped: mapped.clone(),
+            hashes: None,
+        })
+    }
+
+    /// Record the hash of each file that is read from its start to its end in `hashes`
+    pub fn with_hashes(mut self, hashes: FileHashes) -> Self {
+        self.hashes = Some(hashes);
+        self
+    }
+
+    /// Non-empty files whose data lies entirely in bytes `start..end` of the concatenated data
+    pub fn files_within(&self, start: usize, end: usize) -> impl Iterator<Item = &Path> {
+        self.paths
+            .iter()
+            .enumerate()
+            .filter(move |(file_idx, _)| {
+                let file_start = self.offsets[*file_idx];
+                file_start >= start && file_start + self.file_len(*file_idx) <= end
+            })
+            .map(|(_, path)| path.as_path())
+    }
+
+    /// Total size of all input files
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// File containing the byte at `offset` of the concatenated data, and the offset in that file
+    pub fn locate(&self, offset: usize) -> Option<(&Path, usize)> {
+        if offset >= self.len {
+            return None;
+        }
+        let file_idx = self.offsets.partition_point(|start| *start <= offset) - 1;
+        Some((&self.paths[file_idx], offset - self.offsets[file_idx]))
+    }
+
+    /// Blocks starting in each non-empty file, in order
+    pub fn file_blocks(&self, block_size: usize) -> Vec<Range<usize>> {
+        let num_blocks = self.len / block_size;
+        (0..self.paths.len())
+            .map(|file_idx| {
+                let start = self.offsets[file_idx];
+                let end = start + self.file_len(file_idx);
+                start.div_ceil(block_size).min(num_blocks)..end.div_ceil(block_size).min(num_blocks)
+            })
+            .collect()
+    }
+
+    fn file_len(&self, file_idx: usize) -> usize {
+        self.offsets.get(file_idx + 1).copied().unwrap_or(self.len) - self.offsets[file_idx]
+    }
+
+    /// Call `f` on each block in the range, in order, until it returns `ControlFlow::Break`
+    pub fn for_each_block<F: FnMut(&[u8]) -> ControlFlow<()>>(
+        &self,
+        block_size: usize,
+        start_block: usize,
+        num_blocks: usize,
+        mut f: F,
+    ) -> io::Result<()> {
+        let mut block = vec![0; block_size];
+        let mut current: Option<InputReader> = None;
+
+        for block_idx in start_block..start_block + num_blocks {
+            let start = block_idx * block_size;
+            if start + block_size > self.len {
+                break;
+            }
+
+            // Fill the block, continuing into the following files if it spans a boundary
+            let mut filled = 0;
+            while filled < block_size {
+                let offset = start + filled;
+                let file_idx = self.offsets.partition_point(|start| *start <= offset) - 1;
+                let file_offset = offset - self.offsets[file_idx];
+                let path = &self.paths[file_idx];
+
+                let input = match current.as_mut() {
+                    Some(input) if input.file_idx == file_idx => input,
+                    _ => {
+                        let mut file = self
+                            .mapped
+                            .open(path)
+                            .map_err(|err| input_error(path, "open", err))?;
+                        file.seek(SeekFrom::Start(file_offset as u64))
+                            .map_err(|err| input_error(path, "read", err))?;
+                        current.insert(InputReader {
+                            file_idx,
+                            reader: BufReader::with_capacity(READ_BUFFER_SIZE, file),
+                            hasher: (self.hashes.is_some() && file_offset == 0).then(Sha256::new),
+                            offset: file_offset,
+                        })
+                    }
+                };
+
+                let count = (block_size - filled).min(self.file_len(file_idx) - file_offset);
+                input
+                    .reader
+                    .read_exact(&mut block[filled..filled + count])
+                    .map_err(|err| {
+                        input_error(path, &format!("read offset {file_offset} of"), err)
+                    })?;
+                input.offset += count;
+                if let Some(hasher) = input.hasher.as_mut() {
+                    hasher.update(&block[filled..filled + count]);
+                }
+                if input.offset == self.file_len(file_idx)
+                    && let (Some(hasher), Some(hashes)) = (input.hasher.take(), &self.hashes)
+                {
+                    let sha256 = crate::summary::hex_string(&hasher.finalize());
+                    hashes.lock().unwrap().insert(path.clone(), sha256);
+                }
+                filled += count;
+            }
+
+            if f(&block).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn input_error(path: &Path, action: &str, err: io::Error) -> io::Error {
+    io::Error::new(
+        err.kind(),
+        format!("Failed to {action} input file '{}': {err}", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn blocks_span_file_boundaries() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let data: Vec<u8> = (0..40).collect();
+        let files = vec![dir.join("a.bin"), dir.join("b.bin"), dir.join("c.bin")];
+        fs::write(&files[0], &data[..10]).unwrap();
+        fs::write(&files[1], []).unwrap();
+        fs::write(&files[2], &data[10..]).unwrap();
+
+        let input = ConcatenatedInput::open(&files, &MappedInputs::default()).unwrap();
+        let mut blocks = Vec::new();
+        input
+            .for_each_block(8, 1, 4, |block| {
+                blocks.push(block.to_vec());
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+        assert_eq!(input.len(), 40);
+        let expected: Vec<Vec<u8>> = data[8..40].chunks(8).map(|c| c.to_vec()).collect();
+        assert_eq!(blocks, expected);
+        assert_eq!(input.locate(12), Some((files[2].as_path(), 2)));
+        assert_eq!(
+            input.files_within(8, 40).collect::<Vec<_>>(),
+            vec![files[2].as_path()]
+        );
+    }
+
+    #[test]
+    fn files_read_to_the_end_are_hashed() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let files = vec![dir.join("a.bin"), dir.join("b.bin")];
+        fs::write(&files[0], b"abc").unwrap();
+        fs::write(&files[1], [0; 14]).unwrap();
+        let hashes = FileHashes::default();
+
+        let input = ConcatenatedInput::open(&files, &MappedInputs::default())
+            .unwrap()
+            .with_hashes(hashes.clone());
+        input
+            .for_each_block(8, 0, 2, |_| ControlFlow::Continue(()))
+            .unwrap();
+
+        // The second file ends in a partial block that isn't read
+        let hashes = hashes.lock().unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(
+            hashes[&files[0]],
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn mapped_files_are_read_from_their_extents() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let source = dir.join("image.bin");
+        let data: Vec<u8> = (0..32).collect();
+        fs::write(&source, &data).unwrap();
+        let path = dir.join("mapped.bin");
+        let mut mapped = MappedInputs::default();
+        mapped.insert(
+            path.clone(),
+            FileExtents {
+                source,
+                extents: vec![(20, 8), (4, 8)],
+            },
+        );
+
+        let input = ConcatenatedInput::open(std::slice::from_ref(&path), &mapped).unwrap();
+        let mut blocks = Vec::new();
+        input
+            .for_each_block(4, 1, 3, |block| {
+                blocks.push(block.to_vec());
+                ControlFlow::Continue(())
+            })
+            .unwrap();
+
+        assert_eq!(input.len(), 16);
+        assert_eq!(
+            blocks,
+            vec![
+                data[24..28].to_vec(),
+                data[4..8].to_vec(),
+                data[8..12].to_vec()
+            ]
+        );
+        // Only the inputs given the mapping read the path from the extents
+        assert!(MappedInputs::default().input_len(&path).is_err());
+    }
+
+    #[test]
+    fn missing_input_file_is_an_error() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let files = vec![dir.join("a.bin"), dir.join("b.bin")];
+        fs::write(&files[0], [0; 16]).unwrap();
+        fs::write(&files[1], [0; 16]).unwrap();
+        let input = ConcatenatedInput::open(&files, &MappedInputs::default()).unwrap();
+        fs::remove_file(&files[1]).unwrap();
+
+        let mut blocks = 0;
+        let err = input
+            .for_each_block(8, 0, 4, |_| {
+                blocks += 1;
+                ControlFlow::Continue(())
+            })
+            .unwrap_err();
+
+        assert_eq!(blocks, 2);
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("b.bin"));
+    }
+}
@@ -1,20 +1,45 @@
-use memmap::Mmap;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io;
+use std::io::{self, Read};
+use std::ops::ControlFlow;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
+use arrow_array::RecordBatch;
+use itertools::Itertools;
 use tdf::TdfOutput;
+use tdf::csv_format::CsvFormat;
 
+pub mod archive;
 pub mod args;
+pub mod battery;
+pub mod config;
+pub mod continuity;
+pub mod dedup;
+pub mod disk_image;
 pub mod fs_util;
+pub mod input;
+pub mod manifest;
+mod output_arrow;
 mod output_common;
 mod output_csv;
+mod output_filter;
 mod output_parquet;
+pub mod output_preview;
+mod output_sort;
+pub mod plot;
+pub mod resample;
+pub mod sessions;
+pub mod summary;
+pub mod watch;
 
 pub const DEFAULT_MAX_READINGS_PER_OUTPUT_FILE: usize = 0;
+pub const DEFAULT_CSV_VLA_MAX_ITEMS: usize = 8;
+
+/// Number of blocks read from a reader at a time by [`decode_reader_to_arrow`]
+const READER_CHUNK_BLOCKS: usize = 1024;
 
 pub trait ProgressReporter {
     /// Called when progress starts. Could be used to initialize the state or display a start message.
@@ -27,30 +52,46 @@ pub trait ProgressReporter {
     fn stop(&mut self);
 }
 
-pub fn merge_input_files<T: ProgressReporter>(
-    output_prefix: &String,
-    input_files: &Vec<PathBuf>,
-    output_folder: &PathBuf,
-    reporter: &mut T,
-) -> io::Result<(PathBuf, usize)> {
-    let output_filename = format!("{output_prefix}.bin");
-    let output_filepath = output_folder.join(output_filename);
-    let mut merged_output = File::create(&output_filepath)?;
+/// Shared flag used to request that a decode stops early.
+///
+/// Clones share the same flag, so a token can be cancelled from another thread
+/// (e.g. a GUI button or a Ctrl-C handler) while `run` is in progress.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
 
-    reporter.start("Merging input files", input_files.len());
-    for i in input_files {
-        let mut input = File::open(i)?;
-        io::copy(&mut input, &mut merged_output)?;
-        reporter.increment(1);
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Returns an `Interrupted` error if the token has been cancelled
+    pub fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            return Err(io::Error::new(
+                io::ErrorKind::Interrupted,
+                "Decode cancelled",
+            ));
+        }
+        Ok(())
     }
-    reporter.stop();
-    Ok((output_filepath, merged_output.metadata()?.len() as usize))
 }
 
 #[derive(Debug)]
 pub struct DecodeWorkerArgs {
     pub decoder_idx: usize,
-    pub input_file: std::path::PathBuf,
+    pub input_files: Vec<PathBuf>,
+    /// Input files read in place from an archive or disk image
+    pub mapped_inputs: input::MappedInputs,
     pub output_folder: std::path::PathBuf,
     pub output_prefix: String,
     pub output_unix_time: bool,
@@ -58,166 +99,755 @@ pub struct DecodeWorkerArgs {
     pub num_blocks: usize,
     pub block_size: usize,
     pub output_format: args::OutputFormat,
+    pub csv_format: CsvFormat,
+    pub csv_utf8_bom: bool,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+    pub selection: args::TdfSelection,
+    /// Add a `session_id` column to each output row
+    pub session_id_column: bool,
+    /// TDFs of the logging device to collect for the resampled output
+    pub resample_tdfs: Vec<u16>,
+    /// Keep a copy of the samples written as Arrow batches
+    pub collect_batches: bool,
+    /// Blocks and samples to drop for repeating earlier data
+    pub duplicates: Arc<dedup::Duplicates>,
+    /// Hashes of the input files read from start to end by the worker
+    pub input_hashes: input::FileHashes,
+    pub cancel: CancellationToken,
 }
 
 #[derive(Clone)]
 pub struct TdfDecoderOutputs {
     pub output: PathBuf,
     pub num_output: usize,
+    /// Earliest and latest TDF time of the decoded samples
+    pub time_range: Option<(i64, i64)>,
 }
 
+/// Per-worker decoder outputs for each `(remote_id, tdf_id)` pair
+pub type TdfWorkerStats =
+    Arc<Mutex<HashMap<(Option<u64>, u16), HashMap<usize, TdfDecoderOutputs>>>>;
+
+/// Timelines collected by a decode worker for the reports derived from the whole input
+#[derive(Default)]
+pub struct WorkerTrackers {
+    pub continuity: continuity::ContinuityTracker,
+    pub sessions: sessions::SessionTracker,
+    pub battery: battery::BatteryTracker,
+    /// Selected samples outside the time window
+    pub filtered: usize,
+    /// Blocks dropped for repeating the contents of an earlier block
+    pub duplicate_blocks: usize,
+    /// Duplicate samples dropped from each output
+    pub duplicates: HashMap<(Option<u64>, u16), usize>,
+    /// Samples of the resampled TDFs, by TDF ID
+    pub resample: HashMap<u16, resample::Stream>,
+    /// Samples written by the worker, when the decode collects them
+    pub batches: ArrowBatches,
+}
+
+/// Trackers of each decode worker, by worker index
+pub type WorkerTimelines = Arc<Mutex<HashMap<usize, WorkerTrackers>>>;
+
+/// Statistics, output files and continuity report of a decode run
+#[derive(Default)]
+pub struct RunResult {
+    pub block_stats: HashMap<blocks::BlockTypes, usize>,
+    /// Samples written for each TDF, by remote ID
+    pub tdf_stats: HashMap<Option<u64>, HashMap<u16, usize>>,
+    /// Selected samples dropped for being outside the time window
+    pub filtered_samples: usize,
+    /// Blocks dropped for repeating the contents of an earlier block
+    pub duplicate_blocks: usize,
+    /// Samples dropped for repeating an earlier sample
+    pub duplicate_samples: usize,
+    /// Earliest and latest time of the decoded samples
+    pub time_span: Option<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)>,
+    pub output_files: Vec<PathBuf>,
+    /// Data rows written to the outputs of this decode, without the files of earlier runs
+    pub output_rows: HashMap<PathBuf, usize>,
+    pub continuity: continuity::ContinuityReport,
+    /// Samples written by this decode, when [`RunArgs::collect_batches`] is set
+    pub batches: ArrowBatches,
+}
+
+/// Decoded record batches for each `(remote_id, tdf_id)` pair, in chronological order. Samples
+/// without a timestamp follow the sample before them, and samples with equal times keep the order
+/// they were logged in.
+pub type ArrowBatches = HashMap<(Option<u64>, u16), Vec<RecordBatch>>;
+
 pub struct DecodeWorkerArgsReporter<T: ProgressReporter> {
     pub decode_args: DecodeWorkerArgs,
     pub block_stats: Arc<Mutex<HashMap<blocks::BlockTypes, usize>>>,
-    pub tdf_stats: Arc<Mutex<HashMap<(Option<u64>, u16), HashMap<usize, TdfDecoderOutputs>>>>,
+    pub tdf_stats: TdfWorkerStats,
+    pub timelines: WorkerTimelines,
     pub reporter: T,
 }
 
-pub fn worker_run_decode<T: ProgressReporter, U: TdfOutput>(
+/// Decode the worker's range of blocks into `writer`, returning the writer so that it can be
+/// finished by the caller.
+///
+/// Malformed blocks are counted as [`blocks::BlockTypes::ERROR`], while failures to read the input
+/// or write the output stop the worker with an error naming the file and block.
+pub fn worker_run_decode<T: ProgressReporter, U: sessions::SessionIdOutput>(
     mut args: DecodeWorkerArgsReporter<T>,
-    mut writer: U,
-) {
+    writer: U,
+) -> io::Result<U> {
     let mut block_counter: HashMap<blocks::BlockTypes, usize> = HashMap::new();
-    // Open file
-    let file = File::open(args.decode_args.input_file.clone()).unwrap();
-    let mmap = unsafe { Mmap::map(&file).unwrap() };
-    let mmap_start = args.decode_args.block_size * args.decode_args.start_block;
-    let mmap_end =
-        args.decode_args.block_size * (args.decode_args.start_block + args.decode_args.num_blocks);
-
-    // Range of the file for this worker
-    let mmap_slice = &mmap[mmap_start..mmap_end];
-
-    // Iterate over the blocks
-    for (index, block) in mmap_slice
-        .chunks_exact(args.decode_args.block_size)
-        .enumerate()
-    {
-        match blocks::decode_block(&mut writer, block) {
-            Ok(block_type) => *block_counter.entry(block_type).or_default() += 1,
-            Err(_) => *block_counter.entry(blocks::BlockTypes::ERROR).or_default() += 1,
-        }
-
-        // Report every 10 blocks finished
-        if index % 10 == 9 {
-            args.reporter.increment(10);
-        }
+    let window = output_filter::TimeWindow::new(args.decode_args.since, args.decode_args.until)?;
+    let mut writer =
+        output_filter::FilteredOutput::new(writer, window, args.decode_args.selection.clone());
+    // Open files
+    let input = input::ConcatenatedInput::open(
+        &args.decode_args.input_files,
+        &args.decode_args.mapped_inputs,
+    )?
+    .with_hashes(args.decode_args.input_hashes.clone());
+    let block_size = args.decode_args.block_size;
+    // Rows are tagged with sessions that may have started in an earlier worker's blocks
+    let initial_session_id = if args.decode_args.session_id_column {
+        sessions::initial_session_id(
+            &input,
+            block_size,
+            args.decode_args.start_block,
+            &args.decode_args.cancel,
+        )?
+    } else {
+        None
+    };
+    writer.sessions = sessions::SessionTracker::new(initial_session_id);
+    if !args.decode_args.resample_tdfs.is_empty() {
+        writer.resample = Some(resample::ResampleTap::new(&args.decode_args.resample_tdfs));
     }
+    if args.decode_args.collect_batches {
+        writer.collected = Some(output_arrow::TdfArrowWriter::new());
+    }
+
+    // Iterate over the blocks in the range for this worker
+    let mut index = 0;
+    let mut duplicate_blocks = 0;
+    let mut failure = None;
+    let read = input.for_each_block(
+        block_size,
+        args.decode_args.start_block,
+        args.decode_args.num_blocks,
+        |block| {
+            if args.decode_args.cancel.is_cancelled() {
+                return ControlFlow::Break(());
+            }
+            let block_idx = args.decode_args.start_block + index;
+            let duplicate_samples = args.decode_args.duplicates.block_samples(block_idx);
+            writer.start_block(block_idx, duplicate_samples);
+
+            // Samples outside the time window are skipped by the writer without being decoded
+            if args.decode_args.duplicates.is_duplicate_block(block_idx) {
+                duplicate_blocks += 1;
+            } else {
+                match blocks::decode_block(&mut writer, block) {
+                    Ok(block_type) => {
+                        *block_counter.entry(block_type).or_default() += 1;
+                        writer.continuity.end_block(block_type);
+                    }
+                    Err(err) if is_malformed_data(&err) => {
+                        *block_counter.entry(blocks::BlockTypes::ERROR).or_default() += 1
+                    }
+                    Err(err) => {
+                        failure = Some(block_error(&input, block_idx, block_size, err));
+                        return ControlFlow::Break(());
+                    }
+                }
+            }
+
+            // Report every 10 blocks finished
+            if index % 10 == 9 {
+                args.reporter.increment(10);
+            }
+            index += 1;
+            ControlFlow::Continue(())
+        },
+    );
 
-    // Push TDF stats into the output hashmap
+    // Push TDF stats into the output hashmap, including for failed workers so that their
+    // partial outputs can be cleaned up
     let mut tdf_stats = args.tdf_stats.lock().unwrap();
 
     for ((remote_id, tdf_id), tdf_cnt) in writer.iter_written() {
-        let res = tdf_stats
-            .entry((*remote_id, *tdf_id))
-            .or_insert_with(|| HashMap::new());
-        let path = writer.output_path(*remote_id, *tdf_id).unwrap();
+        let Some(path) = writer.output_path(*remote_id, *tdf_id) else {
+            continue;
+        };
+        let res = tdf_stats.entry((*remote_id, *tdf_id)).or_default();
 
         res.insert(
             args.decode_args.decoder_idx,
             TdfDecoderOutputs {
-                output: path.clone(),
+                output: path,
                 num_output: *tdf_cnt,
+                time_range: writer.time_ranges.get(&(*remote_id, *tdf_id)).copied(),
             },
         );
     }
     drop(tdf_stats);
+
+    read?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
     // Update block stats
     let mut global_block_stats = args.block_stats.lock().unwrap();
 
     for (block_type, block_cnt) in block_counter.iter() {
         *global_block_stats.entry(*block_type).or_default() += block_cnt;
     }
+    drop(global_block_stats);
+
+    let resample = match writer.resample.take() {
+        Some(tap) => tap.finish()?,
+        None => HashMap::new(),
+    };
+    let batches = match writer.collected.take() {
+        Some(collected) => collected.finish()?,
+        None => ArrowBatches::new(),
+    };
+    args.timelines.lock().unwrap().insert(
+        args.decode_args.decoder_idx,
+        WorkerTrackers {
+            continuity: std::mem::take(&mut writer.continuity),
+            sessions: std::mem::take(&mut writer.sessions),
+            battery: std::mem::take(&mut writer.battery),
+            filtered: writer.filtered,
+            duplicate_blocks,
+            duplicates: std::mem::take(&mut writer.duplicates),
+            resample,
+            batches,
+        },
+    );
+
+    Ok(writer.inner)
+}
+
+/// Whether a decode error was caused by the contents of the block, rather than by output I/O
+fn is_malformed_data(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Add the input file and block that was being decoded to an error
+fn block_error(
+    input: &input::ConcatenatedInput,
+    block_idx: usize,
+    block_size: usize,
+    err: io::Error,
+) -> io::Error {
+    let location = match input.locate(block_idx * block_size) {
+        Some((path, offset)) => format!("block {block_idx} ('{}' offset {offset})", path.display()),
+        None => format!("block {block_idx}"),
+    };
+    io::Error::new(err.kind(), format!("Failed to decode {location}: {err}"))
 }
 
 pub struct RunArgs<T: ProgressReporter> {
     pub device_id: u64,
     pub block_size: usize,
     pub input_files: Vec<PathBuf>,
+    /// Input files read in place from an archive or disk image
+    pub mapped_inputs: input::MappedInputs,
     pub output_folder: PathBuf,
     pub output_prefix: String,
     pub output_unix_time: bool,
     pub output_format: args::OutputFormat,
+    pub csv_format: CsvFormat,
+    pub csv_utf8_bom: bool,
     pub merge_output_files: bool,
+    pub sort_by_time: bool,
+    /// Only decode samples at or after this time
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only decode samples before this time
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+    /// TDFs and remote IDs to decode
+    pub selection: args::TdfSelection,
     pub max_readings_per_output_file: usize,
-    pub copy_reporter: T,
+    /// Add a `session_id` column with the boot session of each row
+    pub session_id_column: bool,
+    /// Drop blocks and samples that repeat earlier data in the input
+    pub dedup: bool,
+    /// Thresholds for the continuity report
+    pub gap_config: continuity::GapConfig,
+    /// Also write the given TDFs joined onto a common time grid
+    pub resample: Option<resample::ResampleConfig>,
+    /// Only decode the blocks added since the last incremental decode into the same outputs
+    pub incremental: bool,
+    /// Write the boot sessions of the logging device, when the data contains their TDFs
+    pub sessions_output: bool,
+    /// Write the battery timeline, when the data contains battery TDFs
+    pub battery_output: bool,
+    /// Also return the samples written as Arrow batches, e.g. to plot them
+    pub collect_batches: bool,
+    /// Checked by the decode workers and the merge step, cancelling removes partial outputs and
+    /// restores the CSV files an incremental decode appended to
+    pub cancel: CancellationToken,
     pub decode_reporter: T,
     pub merge_reporter: T,
 }
 
 pub fn run<T: ProgressReporter + Clone + Send + 'static>(
     args: &mut RunArgs<T>,
-) -> io::Result<(
-    HashMap<blocks::BlockTypes, usize>,
-    HashMap<Option<u64>, HashMap<u16, usize>>,
-    Vec<PathBuf>,
-)> {
-    let stats_block = Arc::new(Mutex::new(HashMap::new()));
-    let stats_tdf = Arc::new(Mutex::new(HashMap::new()));
-    let mut output_files: Vec<PathBuf> = Vec::new();
+) -> io::Result<RunResult> {
+    let started = chrono::Utc::now();
+    let timer = Instant::now();
+    validate_run_args(args)?;
 
     // Ensure output folder exists
     std::fs::create_dir_all(args.output_folder.clone())?;
 
-    let (merged_file, size) = if args.input_files.len() == 1 {
-        let f: PathBuf = args.input_files.get(0).unwrap().clone();
-        if !f.exists() {
-            return io::Result::Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Input file does not exist",
-            ));
+    let mut prepared = prepare_inputs(args)?;
+    let decoded = decode_workers(args, &mut prepared)?;
+
+    // The outputs no longer match an existing manifest once they are written to, unless the files
+    // appended to are restored after a failure
+    let appended = AppendedOutputs::record(args, &prepared)?;
+    match std::fs::remove_file(&prepared.manifest_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+        _ => {}
+    }
+    let result = write_outputs(args, prepared, &decoded, started, timer);
+    if result.is_err() {
+        appended.restore();
+    }
+    result
+}
+
+/// Merge the decoded outputs and write the derived outputs, the summary and the manifest
+fn write_outputs<T: ProgressReporter>(
+    args: &mut RunArgs<T>,
+    mut prepared: PreparedInputs,
+    decoded: &DecodedBlocks,
+    started: chrono::DateTime<chrono::Utc>,
+    timer: Instant,
+) -> io::Result<RunResult> {
+    let mut output_files: Vec<PathBuf> = Vec::new();
+    let mut output_rows: HashMap<PathBuf, usize> = HashMap::new();
+    merge_outputs(
+        args,
+        decoded,
+        &mut prepared.merged_outputs,
+        &mut output_files,
+        &mut output_rows,
+    )?;
+
+    // Outputs written by an earlier incremental decode are still part of this one
+    let previous_files: Vec<PathBuf> = prepared
+        .merged_outputs
+        .values()
+        .flatten()
+        .filter(|file| !output_files.contains(file))
+        .cloned()
+        .collect();
+    output_files.extend(previous_files);
+
+    let batches = if args.collect_batches {
+        collect_worker_batches(decoded)?
+    } else {
+        ArrowBatches::new()
+    };
+    let (state, saved_state) = combine_worker_state(args, prepared.state, decoded);
+    let block = state.block_stats();
+    let mut tdf: HashMap<Option<u64>, HashMap<u16, usize>> = HashMap::new();
+    for ((remote_id, tdf_id), totals) in state.tdfs.iter() {
+        tdf.entry(*remote_id)
+            .or_default()
+            .insert(*tdf_id, totals.samples);
+    }
+    let filtered_samples = state.filtered_samples;
+    let duplicate_samples = state.tdfs.values().map(|totals| totals.duplicates).sum();
+    let continuity =
+        continuity::ContinuityReport::from_tracker(&state.continuity, &args.gap_config);
+
+    let (boot_sessions, battery) =
+        write_derived_outputs(args, &state, &mut output_files, &mut output_rows)?;
+
+    let input_summaries = summary::hash_input_files(
+        &args.input_files,
+        &args.mapped_inputs,
+        &decoded.input_hashes.lock().unwrap(),
+        &args.cancel,
+    )?;
+    let summary_path = summary::summary_path(&args.output_folder, &args.output_prefix);
+    output_files.push(summary_path.clone());
+    let summary = summary::DecodeSummary {
+        decoder_version: env!("CARGO_PKG_VERSION"),
+        device_id: format!("{:016x}", args.device_id),
+        started: summary::format_time(started),
+        duration_seconds: timer.elapsed().as_secs_f64(),
+        block_size: args.block_size,
+        input_files: input_summaries,
+        blocks: block
+            .iter()
+            .map(|(block_type, count)| (summary::block_key(*block_type), *count))
+            .collect(),
+        filtered_samples,
+        duplicate_blocks: state.duplicate_blocks,
+        duplicate_samples,
+        tdfs: summary::tdf_summaries(&state.tdfs),
+        output_files: output_files.iter().sorted().cloned().collect(),
+        continuity: continuity.clone(),
+        sessions: boot_sessions,
+        battery,
+        incremental: args.incremental.then_some(summary::IncrementalSummary {
+            previous_blocks: prepared.start_block,
+            restart_reason: prepared.restart_reason,
+        }),
+    };
+    summary.write(&summary_path)?;
+
+    if let Some(saved_state) = saved_state {
+        manifest::build(
+            args,
+            prepared.decoded_blocks,
+            &summary.input_files,
+            decoded.partial_sha256.as_deref(),
+            &prepared.merged_outputs,
+            saved_state,
+        )?
+        .write(&prepared.manifest_path)?;
+    }
+
+    Ok(RunResult {
+        block_stats: block,
+        tdf_stats: tdf,
+        filtered_samples,
+        duplicate_blocks: state.duplicate_blocks,
+        duplicate_samples,
+        time_span: summary::time_span(&state.tdfs),
+        output_files,
+        output_rows,
+        continuity,
+        batches,
+    })
+}
+
+/// Join the samples collected by the decode workers, sorted by time
+fn collect_worker_batches(decoded: &DecodedBlocks) -> io::Result<ArrowBatches> {
+    let mut batches = ArrowBatches::new();
+    let mut timelines = decoded.timelines.lock().unwrap();
+    for (_, trackers) in timelines.iter_mut().sorted_by_key(|(idx, _)| **idx) {
+        for (key, key_batches) in std::mem::take(&mut trackers.batches) {
+            batches.entry(key).or_default().extend(key_batches);
         }
-        let m = f.metadata()?;
-        let s = m.len() as usize;
-        (f, s)
+    }
+    sort_arrow_batches(batches)
+}
+
+/// Reject settings that can't be decoded before anything is written
+fn validate_run_args<T: ProgressReporter>(args: &RunArgs<T>) -> io::Result<()> {
+    if args.block_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Block size must be non-zero",
+        ));
+    }
+    if args.incremental && !args.merge_output_files {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Incremental decoding requires merged output files",
+        ));
+    }
+    if args.incremental && args.sort_by_time {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Incremental decoding can't sort by time, as the rows of earlier decodes aren't sorted again",
+        ));
+    }
+    if args.incremental && args.resample.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Incremental decoding can't write a resampled output, as it would need the blocks of earlier decodes",
+        ));
+    }
+
+    if args.output_format == args::OutputFormat::CSV {
+        args.csv_format.validate()?;
+    }
+    if let Some(config) = &args.resample {
+        config.validate()?;
+    }
+    args.gap_config.validate()?;
+    output_filter::TimeWindow::new(args.since, args.until)?;
+    Ok(())
+}
+
+/// Input of a decode, and where it continues an earlier incremental decode from
+struct PreparedInputs {
+    input: input::ConcatenatedInput,
+    num_blocks: usize,
+    /// Blocks before the trailing empty blocks of an incremental decode, or all of them
+    decoded_blocks: usize,
+    /// Input file that the decoded blocks end part way through, and the bytes of it before the end
+    partial_file: Option<(PathBuf, u64)>,
+    manifest_path: PathBuf,
+    /// First block that wasn't decoded by an earlier incremental decode
+    start_block: usize,
+    merged_outputs: output_common::MergedOutputs,
+    state: manifest::DecodeState,
+    known_hashes: manifest::InputHashes,
+    restart_reason: Option<String>,
+}
+
+/// Open the input files and find the blocks that an incremental decode continues from
+fn prepare_inputs<T: ProgressReporter>(args: &RunArgs<T>) -> io::Result<PreparedInputs> {
+    // Input files are decoded as a single logical stream, without copying them
+    let input = input::ConcatenatedInput::open(&args.input_files, &args.mapped_inputs)?;
+    let num_blocks = input.len() / args.block_size;
+
+    // Trailing empty blocks aren't recorded as decoded by an incremental decode, as they may be
+    // written to later. The decoded part of the file they start in is hashed for the manifest.
+    let decoded_blocks = if args.incremental {
+        manifest::end_of_data(&input, args.block_size, 0, num_blocks)?
     } else {
-        let (f, s) = merge_input_files(
-            &args.output_prefix,
-            &args.input_files,
-            &args.output_folder,
-            &mut args.copy_reporter,
-        )?;
-        output_files.push(f.clone());
-        (f, s)
+        num_blocks
     };
+    let partial_file: Option<(PathBuf, u64)> = input
+        .locate(decoded_blocks * args.block_size)
+        .filter(|(_, offset)| args.incremental && *offset > 0)
+        .map(|(path, offset)| (path.to_path_buf(), offset as u64));
 
-    let num_blocks = size / args.block_size;
-    let max_workers = (num_blocks / 100) + 1;
-    let num_workers = std::cmp::min(max_workers, num_cpus::get());
-    let blocks_per_worker = num_blocks / num_workers;
-    let trailing = num_blocks - (blocks_per_worker * num_workers);
+    let manifest_path = manifest::manifest_path(&args.output_folder, &args.output_prefix);
+    let resume = if args.incremental {
+        let partial = partial_file
+            .as_ref()
+            .map(|(path, bytes)| (path.as_path(), *bytes));
+        manifest::resume_point(args, partial)?
+    } else {
+        manifest::Resume::Restart(String::new())
+    };
+    let (start_block, merged_outputs, state, known_hashes, restart_reason) = match resume {
+        manifest::Resume::Continue {
+            start_block,
+            outputs,
+            state,
+            hashes,
+        } => (start_block, outputs, *state, hashes, None),
+        manifest::Resume::Restart(reason) => (
+            0,
+            HashMap::new(),
+            manifest::DecodeState::default(),
+            manifest::InputHashes::default(),
+            Some(reason),
+        ),
+    };
 
-    args.decode_reporter.start("Decoding blocks", num_blocks);
+    Ok(PreparedInputs {
+        input,
+        num_blocks,
+        decoded_blocks,
+        partial_file,
+        manifest_path,
+        start_block,
+        merged_outputs,
+        state,
+        known_hashes,
+        restart_reason,
+    })
+}
+
+/// CSV files of an earlier incremental decode that are appended to in place, with their lengths
+/// and the manifest describing them, to restore if the decode fails. Parquet files are copied
+/// rather than appended to, so they are left as they were without being recorded.
+#[derive(Default)]
+struct AppendedOutputs {
+    files: Vec<(PathBuf, u64)>,
+    manifest: Option<(PathBuf, Vec<u8>)>,
+}
 
-    // Construct arguments for decode workers
-    let mut worker_args = vec![];
-    for idx in 0..num_workers {
-        let mut num = blocks_per_worker;
-        if idx == num_workers - 1 {
-            num += trailing;
+impl AppendedOutputs {
+    fn record<T: ProgressReporter>(
+        args: &RunArgs<T>,
+        prepared: &PreparedInputs,
+    ) -> io::Result<Self> {
+        if args.output_format != args::OutputFormat::CSV || prepared.merged_outputs.is_empty() {
+            return Ok(Self::default());
         }
+        // Only the last file of each output is appended to, later files are new
+        let files = prepared
+            .merged_outputs
+            .values()
+            .filter_map(|files| files.last())
+            .map(|file| Ok((file.clone(), file.metadata()?.len())))
+            .collect::<io::Result<_>>()?;
+        let manifest = std::fs::read(&prepared.manifest_path)?;
+        Ok(Self {
+            files,
+            manifest: Some((prepared.manifest_path.clone(), manifest)),
+        })
+    }
 
-        worker_args.push(DecodeWorkerArgsReporter {
-            decode_args: DecodeWorkerArgs {
-                decoder_idx: idx,
-                input_file: merged_file.clone(),
-                output_folder: args.output_folder.clone(),
-                output_prefix: args.output_prefix.clone(),
-                output_unix_time: args.output_unix_time,
-                start_block: idx * blocks_per_worker,
-                num_blocks: num,
-                block_size: args.block_size,
-                output_format: args.output_format,
-            },
-            block_stats: stats_block.clone(),
-            tdf_stats: stats_tdf.clone(),
-            reporter: args.decode_reporter.clone(),
-        });
+    /// Truncate the files back to their earlier length, restoring the manifest if they all were
+    fn restore(&self) {
+        let mut restored = true;
+        for (path, len) in &self.files {
+            restored &= std::fs::OpenOptions::new()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_len(*len))
+                .is_ok();
+        }
+        if restored && let Some((path, manifest)) = &self.manifest {
+            let _ = std::fs::write(path, manifest);
+        }
     }
+}
+
+/// Statistics, outputs and timelines of the decode workers
+struct DecodedBlocks {
+    /// Block counts of the blocks before the trailing empty blocks
+    block_stats: Arc<Mutex<HashMap<blocks::BlockTypes, usize>>>,
+    tail_block_stats: Arc<Mutex<HashMap<blocks::BlockTypes, usize>>>,
+    tdf_stats: TdfWorkerStats,
+    timelines: WorkerTimelines,
+    /// Workers that decoded the blocks before the trailing empty blocks
+    num_decode_workers: usize,
+    /// Hashes of the input files for the summary
+    input_hashes: input::FileHashes,
+    /// Hash of the decoded bytes of the partial input file
+    partial_sha256: Option<String>,
+}
+
+/// Decode the blocks that weren't decoded by an earlier incremental decode on worker threads,
+/// removing the worker outputs if any worker fails or the decode is cancelled
+fn decode_workers<T: ProgressReporter + Clone + Send + 'static>(
+    args: &mut RunArgs<T>,
+    prepared: &mut PreparedInputs,
+) -> io::Result<DecodedBlocks> {
+    let input = &prepared.input;
+    let stats_block = Arc::new(Mutex::new(HashMap::new()));
+    let tail_block_stats = Arc::new(Mutex::new(HashMap::new()));
+    let stats_tdf: TdfWorkerStats = Arc::new(Mutex::new(HashMap::new()));
+    let timelines: WorkerTimelines = Arc::new(Mutex::new(HashMap::new()));
+
+    // Blocks decoded by an earlier run are continued from the state in its manifest. Trailing
+    // empty blocks have their own workers, so that they can be left out of the manifest.
+    let decode_ranges = worker_ranges(prepared.start_block, prepared.decoded_blocks);
+    let tail_ranges = if prepared.decoded_blocks < prepared.num_blocks {
+        worker_ranges(prepared.decoded_blocks, prepared.num_blocks)
+    } else {
+        Vec::new()
+    };
+
+    // Duplicates are found in order before decoding, so the first copy is kept whichever worker
+    // decodes it
+    let duplicates = Arc::new(if args.dedup {
+        dedup::find_duplicates(input, args.block_size, args.device_id, &args.cancel)?
+    } else {
+        dedup::Duplicates::default()
+    });
+
+    args.decode_reporter.start(
+        "Decoding blocks",
+        prepared.num_blocks - prepared.start_block,
+    );
+
+    // Input files are hashed for the summary as the workers read them, files that are split
+    // between workers or aren't decoded are hashed separately while the workers run. Files that
+    // were hashed while checking the manifest aren't read again.
+    let partial_path = prepared
+        .partial_file
+        .as_ref()
+        .map(|(path, _)| path.as_path());
+    let hashed_by_workers: Vec<&std::path::Path> = decode_ranges
+        .iter()
+        .chain(&tail_ranges)
+        .flat_map(|(start, num)| {
+            input.files_within(start * args.block_size, (start + num) * args.block_size)
+        })
+        .filter(|path| Some(*path) != partial_path)
+        .collect();
+    let manifest::InputHashes {
+        files: known_hashes,
+        partial: mut partial_sha256,
+    } = std::mem::take(&mut prepared.known_hashes);
+    let hasher = {
+        let partial = prepared
+            .partial_file
+            .clone()
+            .filter(|(path, _)| !known_hashes.contains_key(path));
+        let input_files: Vec<PathBuf> = args
+            .input_files
+            .iter()
+            .filter(|path| {
+                !hashed_by_workers.contains(&path.as_path())
+                    && !known_hashes.contains_key(*path)
+                    && Some(path.as_path()) != partial_path
+            })
+            .cloned()
+            .collect();
+        let mapped = args.mapped_inputs.clone();
+        let cancel = args.cancel.clone();
+        thread::spawn(move || {
+            let partial = match partial {
+                Some((path, bytes)) => {
+                    let (_, sha256, prefix_hashes) =
+                        summary::hash_file(&path, &mapped, &[bytes], &cancel)?;
+                    Some((path, sha256, prefix_hashes.into_iter().next().flatten()))
+                }
+                None => None,
+            };
+            let summaries =
+                summary::hash_input_files(&input_files, &mapped, &HashMap::new(), &cancel)?;
+            Ok::<_, io::Error>((summaries, partial))
+        })
+    };
+    let input_hashes: input::FileHashes = Arc::new(Mutex::new(known_hashes));
+
+    // Construct arguments for decode workers, trailing empty blocks are counted separately
+    let worker_args =
+        |decoder_idx: usize,
+         (start_block, num_blocks): (usize, usize),
+         block_stats: &Arc<Mutex<HashMap<blocks::BlockTypes, usize>>>| {
+            DecodeWorkerArgsReporter {
+                decode_args: DecodeWorkerArgs {
+                    decoder_idx,
+                    input_files: args.input_files.clone(),
+                    mapped_inputs: args.mapped_inputs.clone(),
+                    output_folder: args.output_folder.clone(),
+                    output_prefix: args.output_prefix.clone(),
+                    output_unix_time: args.output_unix_time,
+                    start_block,
+                    num_blocks,
+                    block_size: args.block_size,
+                    output_format: args.output_format,
+                    csv_format: args.csv_format,
+                    csv_utf8_bom: args.csv_utf8_bom,
+                    since: args.since,
+                    until: args.until,
+                    selection: args.selection.clone(),
+                    session_id_column: args.session_id_column,
+                    resample_tdfs: args
+                        .resample
+                        .as_ref()
+                        .map_or_else(Vec::new, |config| config.tdfs.clone()),
+                    collect_batches: args.collect_batches,
+                    duplicates: duplicates.clone(),
+                    input_hashes: input_hashes.clone(),
+                    cancel: args.cancel.clone(),
+                },
+                block_stats: block_stats.clone(),
+                tdf_stats: stats_tdf.clone(),
+                timelines: timelines.clone(),
+                reporter: args.decode_reporter.clone(),
+            }
+        };
 
     // Spin up decoder workers
     let mut workers = vec![];
-    for worker_arg in worker_args.into_iter() {
+    let ranges = decode_ranges
+        .iter()
+        .map(|range| (range, &stats_block))
+        .chain(tail_ranges.iter().map(|range| (range, &tail_block_stats)));
+    for (idx, (range, block_stats)) in ranges.enumerate() {
+        let worker_arg = worker_args(idx, *range, block_stats);
         workers.push(thread::spawn(move || {
             match worker_arg.decode_args.output_format {
                 args::OutputFormat::CSV => {
@@ -226,40 +856,100 @@ pub fn run<T: ProgressReporter + Clone + Send + 'static>(
                         worker_arg.decode_args.output_folder.clone(),
                         worker_arg.decode_args.output_prefix.clone(),
                         worker_arg.decode_args.output_unix_time,
-                    );
-                    worker_run_decode(worker_arg, writer);
+                        worker_arg.decode_args.csv_format,
+                        worker_arg.decode_args.csv_utf8_bom,
+                    )
+                    .with_session_ids(worker_arg.decode_args.session_id_column);
+                    worker_run_decode(worker_arg, writer)?.finish()
                 }
                 args::OutputFormat::PARQUET => {
                     let writer = output_parquet::TdfParquetWriter::new(
                         worker_arg.decode_args.decoder_idx,
                         worker_arg.decode_args.output_folder.clone(),
                         worker_arg.decode_args.output_prefix.clone(),
-                    );
-                    worker_run_decode(worker_arg, writer);
+                    )
+                    .with_session_ids(worker_arg.decode_args.session_id_column);
+                    worker_run_decode(worker_arg, writer)?.finish()
                 }
-            };
+            }
         }));
     }
 
-    // Wait for workers to terminate
-    for worker in workers.into_iter() {
-        worker.join().unwrap();
+    // Wait for workers to terminate, keeping every failure
+    let mut errors: Vec<io::Error> = Vec::new();
+    for (idx, worker) in workers.into_iter().enumerate() {
+        let result = worker
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other(format!("Decode worker {idx} panicked"))));
+        if let Err(err) = result {
+            errors.push(err);
+        }
     }
     args.decode_reporter.stop();
+    match hasher.join() {
+        Ok(Ok((summaries, partial))) => {
+            let mut hashes = input_hashes.lock().unwrap();
+            for summary in summaries {
+                hashes.insert(summary.path, summary.sha256);
+            }
+            if let Some((path, sha256, prefix_sha256)) = partial {
+                hashes.insert(path, sha256);
+                partial_sha256 = prefix_sha256;
+            }
+        }
+        Ok(Err(err)) => errors.push(err),
+        Err(_) => errors.push(io::Error::other("Input file hashing panicked")),
+    }
+
+    if let Err(err) = args.cancel.check() {
+        remove_partial_outputs(&stats_tdf, &[]);
+        return Err(err);
+    }
+    if let Some(err) = combine_errors(errors) {
+        remove_partial_outputs(&stats_tdf, &[]);
+        return Err(err);
+    }
 
+    Ok(DecodedBlocks {
+        block_stats: stats_block,
+        tail_block_stats,
+        tdf_stats: stats_tdf,
+        timelines,
+        num_decode_workers: decode_ranges.len(),
+        input_hashes,
+        partial_sha256,
+    })
+}
+
+/// Merge the worker outputs into the outputs of each TDF, or list them when they aren't merged,
+/// and write the resampled output. Partial outputs are removed if the merge is cancelled.
+fn merge_outputs<T: ProgressReporter>(
+    args: &mut RunArgs<T>,
+    decoded: &DecodedBlocks,
+    merged_outputs: &mut output_common::MergedOutputs,
+    output_files: &mut Vec<PathBuf>,
+    output_rows: &mut HashMap<PathBuf, usize>,
+) -> io::Result<()> {
+    let stats_tdf = &decoded.tdf_stats;
     if args.merge_output_files {
-        match args.output_format {
+        let merged = match args.output_format {
             args::OutputFormat::CSV => {
-                output_csv::merge(args, &mut output_files, &stats_tdf)?;
+                output_csv::merge(args, output_files, output_rows, merged_outputs, stats_tdf)
             }
-            args::OutputFormat::PARQUET => {
-                output_parquet::merge_with_threshold(
-                    args,
-                    &mut output_files,
-                    &stats_tdf,
-                    args.max_readings_per_output_file,
-                )?;
+            args::OutputFormat::PARQUET => output_parquet::merge_with_threshold(
+                args,
+                output_files,
+                output_rows,
+                merged_outputs,
+                stats_tdf,
+                args.max_readings_per_output_file,
+            ),
+        };
+        if let Err(err) = merged {
+            if err.kind() == io::ErrorKind::Interrupted {
+                remove_partial_outputs(stats_tdf, output_files);
             }
+            return Err(err);
         }
     } else {
         let results = stats_tdf.lock().unwrap();
@@ -267,23 +957,1133 @@ pub fn run<T: ProgressReporter + Clone + Send + 'static>(
             .values()
             .flat_map(|worker_outputs| worker_outputs.values().map(|output| output.output.clone()))
             .collect();
+        output_rows.extend(results.values().flat_map(|worker_outputs| {
+            worker_outputs
+                .values()
+                .map(|output| (output.output.clone(), output.num_output))
+        }));
         worker_output_files.sort();
         output_files.extend(worker_output_files);
     }
 
-    let block = stats_block.lock().unwrap().clone();
-    let mut tdf = HashMap::new();
+    if let Some(config) = &args.resample {
+        let path = output_common::derived_output_path(
+            &args.output_folder,
+            &args.output_prefix,
+            "resampled",
+            match args.output_format {
+                args::OutputFormat::CSV => "csv",
+                args::OutputFormat::PARQUET => "parquet",
+            },
+        );
+        let streams = resample::combine_streams(
+            decoded
+                .timelines
+                .lock()
+                .unwrap()
+                .iter_mut()
+                .sorted_by_key(|(idx, _)| **idx)
+                .map(|(_, trackers)| std::mem::take(&mut trackers.resample))
+                .collect::<Vec<_>>(),
+        );
+        match write_resampled(args, config, streams, &path) {
+            Ok(rows) => output_rows.insert(path.clone(), rows),
+            Err(err) => {
+                let _ = std::fs::remove_file(&path);
+                if err.kind() == io::ErrorKind::Interrupted {
+                    remove_partial_outputs(stats_tdf, output_files);
+                }
+                return Err(err);
+            }
+        };
+        output_files.push(path);
+    }
+    Ok(())
+}
+
+/// Add the statistics and trackers of the workers to those of the blocks decoded by earlier runs,
+/// along with the state to save for the next incremental decode
+fn combine_worker_state<T: ProgressReporter>(
+    args: &RunArgs<T>,
+    mut state: manifest::DecodeState,
+    decoded: &DecodedBlocks,
+) -> (manifest::DecodeState, Option<manifest::DecodeState>) {
+    state.add_blocks(&decoded.block_stats.lock().unwrap());
+    summary::add_worker_outputs(&mut state.tdfs, &decoded.tdf_stats.lock().unwrap());
+    let mut worker_trackers = std::mem::take(&mut *decoded.timelines.lock().unwrap())
+        .into_iter()
+        .sorted_by_key(|(idx, _)| *idx)
+        .map(|(_, trackers)| trackers);
+    for trackers in worker_trackers.by_ref().take(decoded.num_decode_workers) {
+        state.add_worker(trackers);
+    }
+    // Trailing empty blocks are decoded again by the next incremental decode
+    let saved_state = args.incremental.then(|| state.clone());
+    state.add_blocks(&decoded.tail_block_stats.lock().unwrap());
+    for trackers in worker_trackers {
+        state.add_worker(trackers);
+    }
+    (state, saved_state)
+}
+
+/// Write the requested boot session and battery outputs, returning the sessions and the battery
+/// report for the summary
+fn write_derived_outputs<T: ProgressReporter>(
+    args: &RunArgs<T>,
+    state: &manifest::DecodeState,
+    output_files: &mut Vec<PathBuf>,
+    output_rows: &mut HashMap<PathBuf, usize>,
+) -> io::Result<(Vec<sessions::Session>, Option<battery::BatteryReport>)> {
+    let boot_sessions = state.sessions.sessions();
+    if args.sessions_output && sessions::has_boot_records(&boot_sessions) {
+        let path = match args.output_format {
+            args::OutputFormat::CSV => {
+                let path = output_common::derived_output_path(
+                    &args.output_folder,
+                    &args.output_prefix,
+                    "sessions",
+                    "csv",
+                );
+                output_csv::write_sessions(
+                    &path,
+                    &boot_sessions,
+                    &args.csv_format,
+                    args.output_unix_time,
+                    args.csv_utf8_bom,
+                )
+                .map(|_| path)
+            }
+            args::OutputFormat::PARQUET => {
+                let path = output_common::derived_output_path(
+                    &args.output_folder,
+                    &args.output_prefix,
+                    "sessions",
+                    "parquet",
+                );
+                output_parquet::write_sessions(&path, &boot_sessions).map(|_| path)
+            }
+        }?;
+        output_rows.insert(path.clone(), boot_sessions.len());
+        output_files.push(path);
+    }
+
+    let battery = battery::battery_report(&state.battery);
+    if args.battery_output
+        && let Some((_, rows)) = &battery
+    {
+        let path = match args.output_format {
+            args::OutputFormat::CSV => {
+                let path = output_common::derived_output_path(
+                    &args.output_folder,
+                    &args.output_prefix,
+                    "battery",
+                    "csv",
+                );
+                output_csv::write_battery(
+                    &path,
+                    rows,
+                    &args.csv_format,
+                    args.output_unix_time,
+                    args.csv_utf8_bom,
+                )
+                .map(|_| path)
+            }
+            args::OutputFormat::PARQUET => {
+                let path = output_common::derived_output_path(
+                    &args.output_folder,
+                    &args.output_prefix,
+                    "battery",
+                    "parquet",
+                );
+                output_parquet::write_battery(&path, rows).map(|_| path)
+            }
+        }?;
+        output_rows.insert(path.clone(), rows.len());
+        output_files.push(path);
+    }
+    Ok((boot_sessions, battery.map(|(report, _)| report)))
+}
+
+/// Split the blocks in `start..end` into the `(start_block, num_blocks)` ranges of the workers
+fn worker_ranges(start: usize, end: usize) -> Vec<(usize, usize)> {
+    let num_blocks = end - start;
+    let max_workers = (num_blocks / 100) + 1;
+    let num_workers = std::cmp::min(max_workers, num_cpus::get());
+    let blocks_per_worker = num_blocks / num_workers;
+    let trailing = num_blocks - (blocks_per_worker * num_workers);
+
+    (0..num_workers)
+        .map(|idx| {
+            let mut num = blocks_per_worker;
+            if idx == num_workers - 1 {
+                num += trailing;
+            }
+            (start + idx * blocks_per_worker, num)
+        })
+        .collect()
+}
+
+/// Resample the streams collected by the decode workers and write the joined table
+fn write_resampled<T: ProgressReporter>(
+    args: &RunArgs<T>,
+    config: &resample::ResampleConfig,
+    streams: HashMap<u16, resample::Stream>,
+    path: &std::path::Path,
+) -> io::Result<usize> {
+    let resampler = resample::Resampler::from_streams(streams, config)?;
+    let rows = resampler.num_rows();
+    match args.output_format {
+        args::OutputFormat::CSV => output_csv::write_resampled(
+            path,
+            resampler,
+            &args.csv_format,
+            args.output_unix_time,
+            args.csv_utf8_bom,
+        ),
+        args::OutputFormat::PARQUET => output_parquet::write_resampled(path, resampler),
+    }?;
+    Ok(rows)
+}
+
+/// Combine the errors from several workers into one, keeping the kind of the first
+fn combine_errors(errors: Vec<io::Error>) -> Option<io::Error> {
+    match errors.len() {
+        0 => None,
+        1 => errors.into_iter().next(),
+        num => {
+            let kind = errors[0].kind();
+            let causes = errors.iter().map(|err| format!("  - {err}")).join("\n");
+            Some(io::Error::new(
+                kind,
+                format!("{num} decode workers failed:\n{causes}"),
+            ))
+        }
+    }
+}
+
+/// Remove worker outputs that haven't been merged yet and any merged outputs already written
+fn remove_partial_outputs(stats_tdf: &TdfWorkerStats, output_files: &[PathBuf]) {
     let results = stats_tdf.lock().unwrap();
+    let worker_files = results
+        .values()
+        .flat_map(|worker_outputs| worker_outputs.values().map(|output| &output.output));
+
+    for path in worker_files.chain(output_files) {
+        // Merged worker outputs have already been removed
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Decode blocks held in memory into Arrow record batches, without touching the filesystem.
+///
+/// Batches hold the samples sorted by time, trailing bytes that don't form a complete block are
+/// ignored.
+pub fn decode_to_arrow(
+    data: &[u8],
+    block_size: usize,
+) -> io::Result<(HashMap<blocks::BlockTypes, usize>, ArrowBatches)> {
+    let (block_stats, batches) = decode_to_arrow_in_block_order(data, block_size)?;
+    Ok((block_stats, sort_arrow_batches(batches)?))
+}
+
+fn decode_to_arrow_in_block_order(
+    data: &[u8],
+    block_size: usize,
+) -> io::Result<(HashMap<blocks::BlockTypes, usize>, ArrowBatches)> {
+    let num_blocks = data.len() / block_size.max(1);
+    let num_workers = std::cmp::min((num_blocks / 100) + 1, num_cpus::get());
+    decode_to_arrow_with_workers(data, block_size, num_workers)
+}
+
+fn sort_arrow_batches(batches: ArrowBatches) -> io::Result<ArrowBatches> {
+    batches
+        .into_iter()
+        .map(|(key, key_batches)| Ok((key, output_arrow::sort_by_time(key_batches)?)))
+        .collect()
+}
+
+/// Decode all blocks from `reader` with [`decode_to_arrow`], reading a chunk of blocks at a time
+pub fn decode_reader_to_arrow<R: Read>(
+    mut reader: R,
+    block_size: usize,
+) -> io::Result<(HashMap<blocks::BlockTypes, usize>, ArrowBatches)> {
+    if block_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Block size must be non-zero",
+        ));
+    }
+
+    let mut block_stats = HashMap::new();
+    let mut batches = ArrowBatches::new();
+    let mut chunk = vec![0; block_size * READER_CHUNK_BLOCKS];
+    loop {
+        let len = read_chunk(&mut reader, &mut chunk)?;
+        let (chunk_stats, chunk_batches) =
+            decode_to_arrow_in_block_order(&chunk[..len], block_size)?;
+        extend_arrow_results(&mut block_stats, &mut batches, chunk_stats, chunk_batches);
+        if len < chunk.len() {
+            return Ok((block_stats, sort_arrow_batches(batches)?));
+        }
+    }
+}
+
+/// Fill `buf` from `reader`, returning fewer bytes only at the end of the reader
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(read) => len += read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
+
+/// Append the results of decoding later blocks
+fn extend_arrow_results(
+    block_stats: &mut HashMap<blocks::BlockTypes, usize>,
+    batches: &mut ArrowBatches,
+    more_block_stats: HashMap<blocks::BlockTypes, usize>,
+    more_batches: ArrowBatches,
+) {
+    for (block_type, block_cnt) in more_block_stats {
+        *block_stats.entry(block_type).or_default() += block_cnt;
+    }
+    for (key, key_batches) in more_batches {
+        batches.entry(key).or_default().extend(key_batches);
+    }
+}
+
+fn decode_to_arrow_with_workers(
+    data: &[u8],
+    block_size: usize,
+    num_workers: usize,
+) -> io::Result<(HashMap<blocks::BlockTypes, usize>, ArrowBatches)> {
+    if block_size == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "Block size must be non-zero",
+        ));
+    }
+
+    let num_blocks = data.len() / block_size;
+    let blocks_per_worker = num_blocks.div_ceil(num_workers.max(1)).max(1);
+    let data = &data[..num_blocks * block_size];
+
+    // Workers decode contiguous ranges of blocks, so concatenating their outputs in
+    // worker order preserves the order of the input
+    let worker_results: Vec<io::Result<_>> = thread::scope(|scope| {
+        let workers: Vec<_> = data
+            .chunks(blocks_per_worker * block_size)
+            .map(|range| {
+                scope.spawn(move || {
+                    let mut block_counter: HashMap<blocks::BlockTypes, usize> = HashMap::new();
+                    let mut writer = output_arrow::TdfArrowWriter::new();
+                    for block in range.chunks_exact(block_size) {
+                        match blocks::decode_block(&mut writer, block) {
+                            Ok(block_type) => *block_counter.entry(block_type).or_default() += 1,
+                            Err(_) => {
+                                *block_counter.entry(blocks::BlockTypes::ERROR).or_default() += 1
+                            }
+                        }
+                    }
+                    Ok((block_counter, writer.finish()?))
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Arrow decode worker panicked")))
+            })
+            .collect()
+    });
+
+    let mut block_stats: HashMap<blocks::BlockTypes, usize> = HashMap::new();
+    let mut batches = ArrowBatches::new();
+    for result in worker_results {
+        let (block_counter, worker_batches) = result?;
+        extend_arrow_results(
+            &mut block_stats,
+            &mut batches,
+            block_counter,
+            worker_batches,
+        );
+    }
+
+    Ok((block_stats, batches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    const TEST_BLOCKS: &[u8] = include_bytes!("../data/tdf_test_blocks.bin");
+
+    /// Progress reporter that cancels the run when the given step starts
+    #[derive(Clone)]
+    struct CancelOnStart {
+        step: &'static str,
+        cancel: CancellationToken,
+    }
+
+    impl ProgressReporter for CancelOnStart {
+        fn start(&mut self, msg: &'static str, _total: usize) {
+            if msg == self.step {
+                self.cancel.cancel();
+            }
+        }
+
+        fn increment(&mut self, _value: usize) {}
+
+        fn stop(&mut self) {}
+    }
+
+    /// Arguments for a merged CSV decode of 512 byte blocks, for tests to override
+    fn run_args(input_files: Vec<PathBuf>, output_folder: &Path) -> RunArgs<CancelOnStart> {
+        let reporter = CancelOnStart {
+            step: "",
+            cancel: CancellationToken::new(),
+        };
+        RunArgs {
+            device_id: 0,
+            block_size: 512,
+            input_files,
+            mapped_inputs: input::MappedInputs::default(),
+            output_folder: output_folder.to_path_buf(),
+            output_prefix: "out".to_string(),
+            output_unix_time: true,
+            output_format: args::OutputFormat::CSV,
+            csv_format: CsvFormat::default(),
+            csv_utf8_bom: false,
+            merge_output_files: true,
+            sort_by_time: false,
+            since: None,
+            until: None,
+            selection: args::TdfSelection::default(),
+            max_readings_per_output_file: 0,
+            session_id_column: false,
+            dedup: false,
+            gap_config: continuity::GapConfig::default(),
+            resample: None,
+            incremental: false,
+            sessions_output: true,
+            battery_output: true,
+            collect_batches: false,
+            cancel: CancellationToken::new(),
+            decode_reporter: reporter.clone(),
+            merge_reporter: reporter,
+        }
+    }
+
+    fn cancelled_run(step: &'static str, sort_by_time: bool) {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("infuse_0123456789abcdef_0.bin");
+        std::fs::write(&input, TEST_BLOCKS).unwrap();
+        let output_folder = dir.join("output");
+
+        let cancel = CancellationToken::new();
+        let reporter = CancelOnStart {
+            step,
+            cancel: cancel.clone(),
+        };
+        let mut args = RunArgs {
+            device_id: 0x0123456789abcdef,
+            output_unix_time: false,
+            sort_by_time,
+            cancel,
+            decode_reporter: reporter.clone(),
+            merge_reporter: reporter,
+            ..run_args(vec![input], &output_folder)
+        };
+
+        let err = run(&mut args).err().expect("Cancelled run should fail");
+
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(std::fs::read_dir(&output_folder).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn cancelled_decode_removes_worker_outputs() {
+        cancelled_run("Decoding blocks", false);
+    }
+
+    #[test]
+    fn cancelled_merge_removes_partial_outputs() {
+        cancelled_run("Merging output files", false);
+        cancelled_run("Merging output files", true);
+    }
+
+    /// Write 512 byte blocks, each holding the given TDFs, to an input file in `dir`
+    fn write_blocks(dir: &std::path::Path, blocks: Vec<Vec<u8>>) -> PathBuf {
+        let mut data = Vec::new();
+        for tdfs in blocks {
+            let mut block = vec![0x01, 0x02];
+            block.extend(tdfs);
+            block.resize(512, 0);
+            data.extend(block);
+        }
+        let input = dir.join("input.bin");
+        std::fs::write(&input, data).unwrap();
+        input
+    }
+
+    /// Decode 512 byte blocks, each holding the given TDFs, to CSV files in a new folder
+    fn decode_blocks(blocks: Vec<Vec<u8>>, session_id_column: bool) -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let input = write_blocks(dir.path(), blocks);
+
+        let mut args = RunArgs {
+            session_id_column,
+            ..run_args(vec![input], dir.path())
+        };
+        run(&mut args).unwrap();
+        dir
+    }
+
+    #[test]
+    fn resampled_output_is_collected_by_the_workers() {
+        use sessions::tests::tdf;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let blocks = (0..250u32)
+            .map(|idx| tdf(4, 1000 + idx, &(idx as i32 * 10).to_le_bytes()))
+            .collect();
+        let input = write_blocks(dir, blocks);
+        let mut args = RunArgs {
+            selection: args::TdfSelection {
+                include_tdfs: vec![53],
+                ..Default::default()
+            },
+            resample: Some(resample::ResampleConfig {
+                tdfs: vec![4],
+                rate_hz: 1.0,
+                method: args::ResampleMethod::Nearest,
+                tolerance_seconds: 0.0,
+            }),
+            ..run_args(vec![input], dir)
+        };
+        run(&mut args).unwrap();
+
+        // The resampled TDFs don't have to be selected for their own outputs
+        assert!(!dir.join("out_AMBIENT_TEMPERATURE.csv").exists());
+        let resampled = std::fs::read_to_string(dir.join("out_resampled.csv")).unwrap();
+        let temperatures: Vec<f64> = resampled
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(temperatures.len(), 250);
+        assert!(temperatures.is_sorted_by(|a, b| a < b));
+    }
+
+    #[test]
+    fn rows_are_tagged_with_sessions_started_by_earlier_workers() {
+        use sessions::tests::{announce, reboot_info, tdf};
+
+        // Enough blocks for several workers, with a reboot part way through
+        let blocks = (0..250u32)
+            .map(|idx| {
+                let mut block = Vec::new();
+                if idx == 0 || idx == 150 {
+                    let count = if idx == 0 { 1 } else { 2 };
+                    block.extend(tdf(6, 1000 + idx, &reboot_info(4, count, 600)));
+                    block.extend(tdf(1, 1000 + idx, &announce(count as u16, 1)));
+                }
+                block.extend(tdf(4, 1000 + idx, &2500i32.to_le_bytes()));
+                block
+            })
+            .collect();
+        let temp = decode_blocks(blocks, true);
+        let dir = temp.path();
+
+        let temperature = std::fs::read_to_string(dir.join("out_AMBIENT_TEMPERATURE.csv")).unwrap();
+        let session_ids: Vec<&str> = temperature
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect();
+        assert!(temperature.starts_with("time,session_id,"));
+        assert_eq!(session_ids.len(), 250);
+        assert!(session_ids[..150].iter().all(|id| *id == "1"));
+        assert!(session_ids[150..].iter().all(|id| *id == "2"));
+
+        let sessions = std::fs::read_to_string(dir.join("out_sessions.csv")).unwrap();
+        let rows: Vec<&str> = sessions.lines().collect();
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1].starts_with("1,"));
+        assert!(rows[1].contains(",1.2.3+deadbeef,4,600,"));
+        assert!(rows[2].ends_with(",,,,,,"));
+    }
+
+    #[test]
+    fn battery_timeline_spans_workers() {
+        use sessions::tests::tdf;
+
+        // Discharging by 1% every 20 minutes, with voltage and state of charge in separate TDFs
+        let blocks = (0..200u32)
+            .map(|idx| {
+                let time = 1_000_000 + idx * 600;
+                let mut block = tdf(53, time, &(4100 - idx as u16).to_le_bytes());
+                block.extend(tdf(54, time, &[(100 - idx / 2) as u8]));
+                block
+            })
+            .collect();
+        let temp = decode_blocks(blocks, false);
+        let dir = temp.path();
 
-    for ((remote_id, tdf_id), worker_outputs) in results.iter() {
-        let values = tdf.entry(*remote_id).or_insert_with(|| HashMap::new());
+        let battery = std::fs::read_to_string(dir.join("out_battery.csv")).unwrap();
+        let rows: Vec<&str> = battery.lines().collect();
+        assert_eq!(
+            rows[0],
+            "time,voltage_mv,current_ua,soc,charge,state,cycle,abnormal_drop"
+        );
+        assert_eq!(rows.len(), 201);
+        assert!(rows[1].ends_with(",4100,,100,,discharging,0,false"));
+
+        let summary: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("out_summary.json")).unwrap())
+                .unwrap();
+        let report = &summary["battery"];
+        assert_eq!(report["samples"], 200);
+        assert_eq!(report["cycles"].as_array().unwrap().len(), 1);
+        assert_eq!(report["cycles"][0]["kind"], "discharge");
+        assert!(report["time_to_empty_hours"].as_f64().unwrap() > 0.0);
+    }
+
+    #[test]
+    fn summary_lists_itself_and_hashes_inputs_once() {
+        use sessions::tests::tdf;
+
+        // A single worker reads the whole input, while larger inputs are split between workers
+        for num_blocks in [50u32, 250] {
+            let blocks = (0..num_blocks)
+                .map(|idx| tdf(4, 1000 + idx, &2500i32.to_le_bytes()))
+                .collect();
+            let temp = decode_blocks(blocks, false);
+            let dir = temp.path();
+
+            let summary: serde_json::Value = serde_json::from_str(
+                &std::fs::read_to_string(dir.join("out_summary.json")).unwrap(),
+            )
+            .unwrap();
+            let (size, sha256, _) = summary::hash_file(
+                &dir.join("input.bin"),
+                &input::MappedInputs::default(),
+                &[],
+                &CancellationToken::new(),
+            )
+            .unwrap();
+            assert_eq!(summary["input_files"][0]["size"], size);
+            assert_eq!(summary["input_files"][0]["sha256"], sha256);
+            let output_files = summary["output_files"].as_array().unwrap();
+            assert!(output_files.contains(&serde_json::json!(dir.join("out_summary.json"))));
+        }
+    }
+
+    #[test]
+    fn output_rows_match_the_written_files() {
+        use sessions::tests::{announce, reboot_info, tdf};
+
+        let blocks: Vec<Vec<u8>> = (0..250u32)
+            .map(|idx| {
+                let mut block = Vec::new();
+                if idx == 0 {
+                    block.extend(tdf(6, 1000, &reboot_info(4, 1, 600)));
+                    block.extend(tdf(1, 1000, &announce(1, 1)));
+                }
+                block.extend(tdf(4, 1000 + idx, &2500i32.to_le_bytes()));
+                block
+            })
+            .collect();
+        for (output_format, merge_output_files) in [
+            (args::OutputFormat::CSV, true),
+            (args::OutputFormat::CSV, false),
+            (args::OutputFormat::PARQUET, true),
+        ] {
+            let temp = tempfile::tempdir().unwrap();
+            let dir = temp.path();
+            let input = write_blocks(dir, blocks.clone());
+            let mut args = RunArgs {
+                output_format,
+                merge_output_files,
+                resample: Some(resample::ResampleConfig {
+                    tdfs: vec![4],
+                    rate_hz: 1.0,
+                    method: args::ResampleMethod::Nearest,
+                    tolerance_seconds: 0.0,
+                }),
+                ..run_args(vec![input], dir)
+            };
+            let result = run(&mut args).unwrap();
 
-        let mut sum = 0;
-        for (_, output) in worker_outputs.iter() {
-            sum += output.num_output;
+            let mut data_files = 0;
+            for path in &result.output_files {
+                let read = output_preview::OutputFileInfo::read(path).unwrap().rows;
+                assert_eq!(result.output_rows.get(path).copied(), read, "{path:?}");
+                data_files += usize::from(read.is_some());
+            }
+            assert!(data_files > 3);
         }
-        values.insert(*tdf_id, sum);
     }
 
-    Ok((block, tdf, output_files))
+    #[test]
+    fn input_errors_keep_their_kind_and_path() {
+        let temp = tempfile::tempdir().unwrap();
+        let missing = temp.path().join("missing.bin");
+        let mut args = run_args(vec![missing.clone()], temp.path());
+
+        let err = run(&mut args).err().expect("Missing input should fail");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains(&missing.display().to_string()));
+    }
+
+    #[test]
+    fn disk_image_files_are_decoded_through_their_extents() {
+        let temp = tempfile::tempdir().unwrap();
+        let image = temp.path().join("card.img");
+        std::fs::write(
+            &image,
+            disk_image::tests::fat32_image(&[
+                ("infuse_0123456789abcdef_0.bin", &TEST_BLOCKS[..8192]),
+                ("infuse_0123456789abcdef_1.bin", &TEST_BLOCKS[8192..]),
+            ]),
+        )
+        .unwrap();
+        let extracted =
+            archive::extract_infuse_iot_files(&image, None, 512, &CancellationToken::new())
+                .unwrap();
+        let files = extracted.files()[&0x0123_4567_89ab_cdef].clone();
+
+        // The files only exist as extents of the image
+        let mut args = run_args(files.clone(), &temp.path().join("unmapped"));
+        let err = run(&mut args).err().expect("Unmapped files can't be read");
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        let plain = temp.path().join("plain.bin");
+        std::fs::write(&plain, TEST_BLOCKS).unwrap();
+        let mut expected = run_args(vec![plain], &temp.path().join("plain"));
+        let expected = run(&mut expected).unwrap();
+        let mut args = RunArgs {
+            mapped_inputs: extracted.mapped_inputs().clone(),
+            ..run_args(files, &temp.path().join("image"))
+        };
+        let result = run(&mut args).unwrap();
+        assert_eq!(result.tdf_stats, expected.tdf_stats);
+        assert_eq!(
+            std::fs::read_to_string(temp.path().join("image/out_AMBIENT_TEMPERATURE.csv")).unwrap(),
+            std::fs::read_to_string(temp.path().join("plain/out_AMBIENT_TEMPERATURE.csv")).unwrap()
+        );
+    }
+
+    #[test]
+    fn worker_errors_are_combined() {
+        let errors = vec![
+            io::Error::new(io::ErrorKind::NotFound, "first"),
+            io::Error::other("second"),
+        ];
+
+        let err = combine_errors(errors).unwrap();
+
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(
+            err.to_string(),
+            "2 decode workers failed:\n  - first\n  - second"
+        );
+        assert!(combine_errors(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn only_malformed_blocks_are_counted_as_errors() {
+        let eof = io::Error::new(io::ErrorKind::UnexpectedEof, "short");
+        let disk_full = io::Error::new(io::ErrorKind::StorageFull, "disk full");
+
+        assert!(is_malformed_data(&eof));
+        assert!(!is_malformed_data(&disk_full));
+    }
+
+    /// Blocks each holding one temperature sample, followed by `empty` unwritten blocks
+    fn temperature_blocks(first: u32, count: u32, empty: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        for idx in first..first + count {
+            let mut block = vec![0x01, 0x02];
+            block.extend(sessions::tests::tdf(
+                4,
+                1000 + idx,
+                &(idx as i32).to_le_bytes(),
+            ));
+            block.resize(512, 0);
+            data.extend(block);
+        }
+        data.resize(data.len() + empty * 512, 0);
+        data
+    }
+
+    #[test]
+    fn arrow_batches_are_sorted_by_time() {
+        // Samples logged with decreasing times, split over more than one chunk of the reader
+        let num_blocks = READER_CHUNK_BLOCKS as u32 + 10;
+        let mut data = Vec::new();
+        for block in temperature_blocks(0, num_blocks, 0).chunks_exact(512).rev() {
+            data.extend_from_slice(block);
+        }
+        data.extend_from_slice(&[0; 100]);
+
+        let (block_stats, batches) = decode_reader_to_arrow(data.as_slice(), 512).unwrap();
+        let (_, in_memory) = decode_to_arrow(&data, 512).unwrap();
+
+        assert_eq!(block_stats[&blocks::BlockTypes::TDF], num_blocks as usize);
+        let times: Vec<i64> = batches[&(None, 4)]
+            .iter()
+            .flat_map(|batch| {
+                batch["timestamp"]
+                    .as_any()
+                    .downcast_ref::<arrow_array::TimestampMicrosecondArray>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(times.len(), num_blocks as usize);
+        assert!(times.is_sorted_by(|a, b| a < b));
+        let rows =
+            |batches: &[RecordBatch]| batches.iter().map(RecordBatch::num_rows).sum::<usize>();
+        assert_eq!(rows(&in_memory[&(None, 4)]), times.len());
+        assert_eq!(in_memory[&(None, 4)], batches[&(None, 4)]);
+    }
+
+    /// Decode `inputs` to CSV in `output_folder` with incremental decoding or dedup enabled,
+    /// returning the summary
+    fn decode_to(
+        inputs: &[&Path],
+        output_folder: &Path,
+        incremental: bool,
+        dedup: bool,
+    ) -> serde_json::Value {
+        let mut args = RunArgs {
+            dedup,
+            incremental,
+            ..run_args(
+                inputs.iter().map(|input| input.to_path_buf()).collect(),
+                output_folder,
+            )
+        };
+        run(&mut args).unwrap();
+        let summary = std::fs::read_to_string(summary::summary_path(output_folder, "out")).unwrap();
+        serde_json::from_str(&summary).unwrap()
+    }
+
+    #[test]
+    fn incremental_decode_appends_new_blocks() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("input.bin");
+        let output = dir.join("incremental");
+        let full = dir.join("full");
+        let temperature = |folder: &Path| {
+            std::fs::read_to_string(folder.join("out_AMBIENT_TEMPERATURE.csv")).unwrap()
+        };
+
+        // Trailing empty blocks aren't recorded as decoded, as they are written to later
+        std::fs::write(&input, temperature_blocks(0, 150, 50)).unwrap();
+        let summary = decode_to(&[&input], &output, true, false);
+        assert_eq!(summary["incremental"]["previous_blocks"], 0);
+        let manifest = manifest::Manifest::read(&manifest::manifest_path(&output, "out")).unwrap();
+        assert_eq!(manifest.decoded_blocks, 150);
+
+        let mut grown = temperature_blocks(0, 150, 0);
+        grown.extend(temperature_blocks(150, 250, 10));
+        std::fs::write(&input, grown).unwrap();
+        let summary = decode_to(&[&input], &output, true, false);
+        assert_eq!(summary["incremental"]["previous_blocks"], 150);
+        assert!(summary["incremental"]["restart_reason"].is_null());
+        // The statistics and reports continue those of the earlier decode
+        let full_summary = decode_to(&[&input], &full, false, false);
+        assert_eq!(summary["tdfs"][0]["samples"], 400);
+        for key in ["blocks", "tdfs", "continuity", "input_files"] {
+            assert_eq!(summary[key], full_summary[key]);
+        }
+        assert_eq!(temperature(&output), temperature(&full));
+        assert_eq!(temperature(&output).lines().count(), 401);
+        assert!(!manifest::manifest_path(&full, "out").exists());
+
+        // The decode ends part way through a file that wasn't decoded before
+        let more = dir.join("more.bin");
+        std::fs::write(&more, temperature_blocks(400, 50, 5)).unwrap();
+        let summary = decode_to(&[&input, &more], &output, true, false);
+        assert_eq!(summary["incremental"]["previous_blocks"], 400);
+        let full_summary = decode_to(&[&input, &more], &full, false, false);
+        for key in ["blocks", "tdfs", "continuity", "input_files"] {
+            assert_eq!(summary[key], full_summary[key]);
+        }
+        assert_eq!(temperature(&output), temperature(&full));
+        let manifest = manifest::Manifest::read(&manifest::manifest_path(&output, "out")).unwrap();
+        assert_eq!(manifest.decoded_blocks, 460);
+        assert_eq!(manifest.inputs[1].decoded_bytes, 50 * 512);
+
+        // Changing blocks that were already decoded starts over
+        let mut changed = temperature_blocks(1, 399, 10);
+        changed.splice(0..0, temperature_blocks(1000, 1, 0));
+        std::fs::write(&input, &changed).unwrap();
+        let summary = decode_to(&[&input, &more], &output, true, false);
+        assert_eq!(summary["incremental"]["previous_blocks"], 0);
+        assert_eq!(
+            summary["incremental"]["restart_reason"],
+            "input.bin has changed since it was decoded"
+        );
+        decode_to(&[&input, &more], &full, false, false);
+        assert_eq!(temperature(&output), temperature(&full));
+    }
+
+    #[test]
+    fn failed_incremental_csv_decode_restores_earlier_outputs() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("input.bin");
+        let output = dir.join("out");
+        let temperature_path = output.join("out_AMBIENT_TEMPERATURE.csv");
+        let manifest_path = manifest::manifest_path(&output, "out");
+
+        std::fs::write(&input, temperature_blocks(0, 150, 0)).unwrap();
+        decode_to(&[&input], &output, true, false);
+        let temperature = std::fs::read_to_string(&temperature_path).unwrap();
+        let manifest = std::fs::read(&manifest_path).unwrap();
+        std::fs::write(&input, temperature_blocks(0, 250, 0)).unwrap();
+
+        // Cancelled while merging
+        let cancel = CancellationToken::new();
+        let reporter = CancelOnStart {
+            step: "Merging output files",
+            cancel: cancel.clone(),
+        };
+        let mut args = RunArgs {
+            incremental: true,
+            cancel,
+            decode_reporter: reporter.clone(),
+            merge_reporter: reporter,
+            ..run_args(vec![input.clone()], &output)
+        };
+        let err = run(&mut args).err().expect("Cancelled run should fail");
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(
+            std::fs::read_to_string(&temperature_path).unwrap(),
+            temperature
+        );
+        assert_eq!(std::fs::read(&manifest_path).unwrap(), manifest);
+
+        // Failing after the rows were appended
+        let summary_path = summary::summary_path(&output, "out");
+        std::fs::remove_file(&summary_path).unwrap();
+        std::fs::create_dir(&summary_path).unwrap();
+        let mut args = RunArgs {
+            incremental: true,
+            ..run_args(vec![input.clone()], &output)
+        };
+        assert!(run(&mut args).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&temperature_path).unwrap(),
+            temperature
+        );
+        assert_eq!(std::fs::read(&manifest_path).unwrap(), manifest);
+
+        // The next decode continues from the earlier one
+        std::fs::remove_dir(&summary_path).unwrap();
+        let summary = decode_to(&[&input], &output, true, false);
+        assert_eq!(summary["incremental"]["previous_blocks"], 150);
+        assert_eq!(
+            std::fs::read_to_string(&temperature_path)
+                .unwrap()
+                .lines()
+                .count(),
+            251
+        );
+    }
+
+    #[test]
+    fn derived_outputs_are_only_written_when_requested() {
+        use sessions::tests::tdf;
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let blocks = (0..10u32)
+            .map(|idx| {
+                let time = 1_000_000 + idx * 600;
+                let mut block = tdf(53, time, &(4100 - idx as u16).to_le_bytes());
+                block.extend(tdf(54, time, &[(100 - idx) as u8]));
+                block
+            })
+            .collect();
+        let input = write_blocks(dir, blocks);
+        let output = dir.join("out");
+
+        let mut args = RunArgs {
+            sessions_output: false,
+            battery_output: false,
+            ..run_args(vec![input.clone()], &output)
+        };
+        let result = run(&mut args).unwrap();
+        // The outputs of the two TDFs and the summary
+        assert_eq!(result.output_files.len(), 3);
+        assert!(!output.join("out_battery.csv").exists());
+
+        let mut args = run_args(vec![input.clone()], &output);
+        let result = run(&mut args).unwrap();
+        assert!(
+            result
+                .output_files
+                .contains(&output.join("out_battery.csv"))
+        );
+    }
+
+    #[test]
+    fn written_samples_are_returned_when_requested() {
+        let temp = tempfile::tempdir().unwrap();
+        // The second dump repeats the blocks of the first
+        let input = [
+            temp.path().join("first.bin"),
+            temp.path().join("second.bin"),
+        ];
+        for path in &input {
+            std::fs::write(path, temperature_blocks(0, 10, 0)).unwrap();
+        }
+        let output = temp.path().join("out");
+        let rows = |args: &mut RunArgs<CancelOnStart>| {
+            run(args).unwrap().batches[&(None, 4)]
+                .iter()
+                .map(RecordBatch::num_rows)
+                .sum::<usize>()
+        };
+
+        let mut args = run_args(input.to_vec(), &output);
+        assert!(run(&mut args).unwrap().batches.is_empty());
+        args.collect_batches = true;
+        assert_eq!(rows(&mut args), 20);
+        args.dedup = true;
+        assert_eq!(rows(&mut args), 10);
+        args.since = tdf::time::tdf_time_to_datetime(1005 << 16);
+        assert_eq!(rows(&mut args), 5);
+    }
+
+    #[test]
+    fn incremental_decode_conflicts_with_sorting_and_resampling() {
+        let temp = tempfile::tempdir().unwrap();
+        let input = temp.path().join("input.bin");
+        std::fs::write(&input, temperature_blocks(0, 10, 0)).unwrap();
+        let incremental_args = || RunArgs {
+            incremental: true,
+            ..run_args(vec![input.clone()], &temp.path().join("out"))
+        };
+
+        let mut sorted = RunArgs {
+            sort_by_time: true,
+            ..incremental_args()
+        };
+        let mut resampled = RunArgs {
+            resample: Some(resample::ResampleConfig {
+                tdfs: vec![4],
+                rate_hz: 1.0,
+                method: args::ResampleMethod::Nearest,
+                tolerance_seconds: 1.0,
+            }),
+            ..incremental_args()
+        };
+        for run_args in [&mut sorted, &mut resampled] {
+            let err = run(run_args).err().unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn overlapping_dumps_are_deduplicated() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let first = dir.join("first.bin");
+        let second = dir.join("second.bin");
+        let single = dir.join("single.bin");
+        std::fs::write(&first, temperature_blocks(0, 150, 0)).unwrap();
+        std::fs::write(&single, temperature_blocks(0, 250, 0)).unwrap();
+
+        // The second dump repeats the first 150 blocks, with one sample logged again in a block
+        // that differs by its wrap count
+        let mut overlap = temperature_blocks(0, 250, 0);
+        let mut repeated = temperature_blocks(10, 1, 0);
+        repeated[0] = 0x02;
+        overlap.extend(repeated);
+        std::fs::write(&second, overlap).unwrap();
+
+        let summary = decode_to(&[&first, &second], &dir.join("dedup"), false, true);
+        decode_to(&[&single], &dir.join("single"), false, false);
+        let temperature = |folder: &str| {
+            std::fs::read_to_string(dir.join(folder).join("out_AMBIENT_TEMPERATURE.csv")).unwrap()
+        };
+        assert_eq!(temperature("dedup"), temperature("single"));
+        assert_eq!(summary["duplicate_blocks"], 150);
+        assert_eq!(summary["duplicate_samples"], 1);
+        assert!(summary["blocks"].get("duplicate").is_none());
+        assert_eq!(summary["tdfs"][0]["samples"], 250);
+        assert_eq!(summary["tdfs"][0]["duplicates"], 1);
+    }
+
+    #[test]
+    fn overlapping_dumps_of_a_device_are_found_and_deduplicated() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let dump = |folder: &str, sub_files: [Vec<u8>; 2]| {
+            let folder = dir.join("card").join(folder);
+            std::fs::create_dir_all(&folder).unwrap();
+            for (num, data) in sub_files.iter().enumerate() {
+                std::fs::write(
+                    folder.join(format!("infuse_0123456789abcdef_{num}.bin")),
+                    data,
+                )
+                .unwrap();
+            }
+            folder
+        };
+        // The later dump repeats the blocks of the earlier one, and one of its own blocks
+        let earlier = dump(
+            "2024-01-01",
+            [
+                temperature_blocks(0, 100, 0),
+                temperature_blocks(100, 50, 0),
+            ],
+        );
+        let mut repeated = temperature_blocks(120, 130, 0);
+        repeated.extend(temperature_blocks(200, 1, 0));
+        let later = dump("2024-02-01", [temperature_blocks(0, 120, 0), repeated]);
+        let single = dir.join("single.bin");
+        std::fs::write(&single, temperature_blocks(0, 250, 0)).unwrap();
+
+        let files = fs_util::find_infuse_iot_files_recursive(&dir.join("card")).unwrap();
+        let mut roots = fs_util::find_infuse_iot_files_recursive(&earlier).unwrap();
+        fs_util::merge_device_files(
+            &mut roots,
+            fs_util::find_infuse_iot_files_recursive(&later).unwrap(),
+        );
+        assert_eq!(files, roots);
+        assert_eq!(files[&0x0123456789abcdef].len(), 4);
+
+        let output = dir.join("dedup");
+        let mut args = RunArgs {
+            device_id: 0x0123456789abcdef,
+            dedup: true,
+            ..run_args(files[&0x0123456789abcdef].clone(), &output)
+        };
+        let result = run(&mut args).unwrap();
+        decode_to(&[&single], &dir.join("single"), false, false);
+        let temperature = |folder: &Path| {
+            std::fs::read_to_string(folder.join("out_AMBIENT_TEMPERATURE.csv")).unwrap()
+        };
+        assert_eq!(temperature(&output), temperature(&dir.join("single")));
+        assert_eq!(result.duplicate_blocks, 151);
+        assert_eq!(result.tdf_stats[&None][&4], 250);
+    }
 }
@@ -1,15 +1,27 @@
-use clap::Parser;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
 use indicatif::{ProgressBar, ProgressStyle};
 use infuse_decoder::args;
-use std::collections::HashMap;
+use infuse_decoder::config::{Config, Profile};
+use infuse_decoder::input::MappedInputs;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
+use std::time::{Duration, Instant};
 
 #[macro_use]
 extern crate prettytable;
 use prettytable::{Table, format};
 
+const DEFAULT_WATCH_INTERVAL_SECONDS: f64 = 10.0;
+
+/// Exit code when some devices failed to decode, or had more error blocks than allowed, with
+/// `--error-threshold`
+const EXIT_PARTIAL: u8 = 3;
+
 #[derive(Clone)]
 pub struct IndicatifProgress {
     bar: Option<ProgressBar>,
@@ -21,6 +33,12 @@ impl IndicatifProgress {
     }
 }
 
+impl Default for IndicatifProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl infuse_decoder::ProgressReporter for IndicatifProgress {
     fn start(&mut self, msg: &'static str, total: usize) {
         let bar = ProgressBar::new(total as u64);
@@ -46,12 +64,14 @@ impl infuse_decoder::ProgressReporter for IndicatifProgress {
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    /// The path to the file/folder containing Infuse-IoT binary files
+    /// The path to the file/folder containing Infuse-IoT binary files. Can be repeated for
+    /// different devices, or with --dedup for dumps of the same devices, which also searches
+    /// the subfolders of each folder
     #[arg(short, long, required = true)]
-    path: std::path::PathBuf,
+    path: Vec<std::path::PathBuf>,
     /// Output path for decoded files
-    #[arg(short, long, required = true)]
-    output: std::path::PathBuf,
+    #[arg(short, long, required_unless_present = "profile")]
+    output: Option<std::path::PathBuf>,
     /// Prefix for output filenames
     #[arg(short, long)]
     name: Option<String>,
@@ -71,9 +91,524 @@ struct Cli {
     /// Keep decoder worker output files instead of merging them into linearized outputs
     #[arg(long = "no-linearize-output", alias = "no-merge-output-files")]
     no_linearize_output: bool,
+    /// Only decode samples at or after this UTC time (e.g. 2025-01-01T12:00:00Z or 2025-01-01)
+    #[arg(long, value_parser = args::parse_utc_time)]
+    since: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only decode samples before this UTC time
+    #[arg(long, value_parser = args::parse_utc_time)]
+    until: Option<chrono::DateTime<chrono::Utc>>,
+    /// Only decode these TDFs, by name or ID (e.g. BATTERY_STATE,11)
+    #[arg(long, value_delimiter = ',', value_parser = args::parse_tdf_id)]
+    include_tdf: Vec<u16>,
+    /// Never decode these TDFs, by name or ID
+    #[arg(long, value_delimiter = ',', value_parser = args::parse_tdf_id)]
+    exclude_tdf: Vec<u16>,
+    /// Only decode data from these remote IDs (hexadecimal, or 'local' for the logging device)
+    #[arg(long, value_delimiter = ',', value_parser = args::parse_remote_id)]
+    include_remote: Vec<Option<u64>>,
+    /// Never decode data from these remote IDs
+    #[arg(long, value_delimiter = ',', value_parser = args::parse_remote_id)]
+    exclude_remote: Vec<Option<u64>>,
+    /// Order linearized outputs by timestamp instead of by position in the input
+    #[arg(long, conflicts_with = "no_linearize_output")]
+    sort_by_time: bool,
+    /// Only decode blocks added since the last incremental decode, appending them to its outputs
+    #[arg(long, conflicts_with_all = ["no_linearize_output", "sort_by_time", "resample_tdf"])]
+    incremental: bool,
+    /// Field delimiter for CSV output
+    #[arg(long, default_value_t = args::CsvDelimiter::Comma)]
+    csv_delimiter: args::CsvDelimiter,
+    /// Decimal separator for non-integer values in CSV output
+    #[arg(long, default_value_t = args::DecimalSeparator::Point)]
+    csv_decimal_separator: args::DecimalSeparator,
+    /// Fixed number of decimal places for non-integer values in CSV output
+    #[arg(long)]
+    csv_float_precision: Option<usize>,
+    /// Start CSV output files with a UTF-8 byte order mark
+    #[arg(long)]
+    csv_bom: bool,
+    /// CSV representation of trailing variable length arrays (e.g. LTE neighbour cells)
+    #[arg(long, default_value_t = args::VlaMode::Rows)]
+    csv_vla_mode: args::VlaMode,
+    /// Number of array items given their own columns with `--csv-vla-mode wide`
+    #[arg(long, default_value_t = infuse_decoder::DEFAULT_CSV_VLA_MAX_ITEMS)]
+    csv_vla_max_items: usize,
+    /// Add a session_id column with the boot session (device reboot count) of each row
+    #[arg(long)]
+    session_id: bool,
+    /// Drop blocks and samples that repeat earlier data, e.g. from overlapping card dumps
+    #[arg(long)]
+    dedup: bool,
+    /// Also write the boot sessions of the device (<prefix>_sessions)
+    #[arg(long)]
+    sessions: bool,
+    /// Also write the battery timeline of the device (<prefix>_battery)
+    #[arg(long)]
+    battery: bool,
+    /// Report gaps between samples of a TDF longer than this multiple of its typical interval
+    #[arg(
+        long,
+        default_value_t = infuse_decoder::continuity::DEFAULT_GAP_INTERVAL_MULTIPLE,
+        value_parser = args::parse_positive_f64
+    )]
+    gap_multiple: f64,
+    /// Report runs of at least this many consecutive empty blocks
+    #[arg(long, default_value_t = infuse_decoder::continuity::DEFAULT_GAP_MIN_EMPTY_BLOCKS)]
+    gap_min_empty_blocks: usize,
+    /// Also write these TDFs joined onto a common time grid, by name or ID (e.g. ACC_4G,GYR_250DPS)
+    #[arg(long, value_delimiter = ',', value_parser = args::parse_tdf_id, requires = "resample_rate")]
+    resample_tdf: Vec<u16>,
+    /// Rows per second of the resampled output
+    #[arg(long, requires = "resample_tdf")]
+    resample_rate: Option<f64>,
+    /// How samples are placed on the resampled grid
+    #[arg(long, default_value_t = args::ResampleMethod::Linear)]
+    resample_method: args::ResampleMethod,
+    /// Furthest a sample can be from a resampled grid time, in seconds, and still be used for it
+    #[arg(long, default_value_t = infuse_decoder::resample::DEFAULT_RESAMPLE_TOLERANCE_SECONDS)]
+    resample_tolerance: f64,
+    /// Keep running, decoding new or changed Infuse-IoT file sets in the input folder and its
+    /// subfolders into an output subfolder for each device
+    #[arg(long)]
+    watch: bool,
+    /// Seconds between scans of the watched folder, files must be unchanged for a whole interval
+    #[arg(
+        long,
+        default_value_t = DEFAULT_WATCH_INTERVAL_SECONDS,
+        requires = "watch",
+        value_parser = args::parse_positive_f64
+    )]
+    watch_interval: f64,
+    /// Load settings from a saved profile, options given on the command line take precedence
+    #[arg(long)]
+    profile: Option<String>,
+    /// Save the settings of this run as a profile, replacing any profile with the same name
+    #[arg(long)]
+    save_profile: Option<String>,
+    /// Configuration file with the saved profiles, instead of the one in the user config folder
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Print the results of each device as JSON on stdout, instead of tables
+    #[arg(long, conflicts_with = "watch")]
+    json: bool,
+    /// Percentage of non-empty blocks that can fail to decode before the exit code reports a
+    /// partial decode. Without it, error blocks don't change the exit code.
+    #[arg(long, value_parser = args::parse_percent)]
+    error_threshold: Option<f64>,
+}
+
+impl Cli {
+    /// Output folder, which is given either on the command line or by a profile
+    fn output(&self) -> &Path {
+        self.output
+            .as_deref()
+            .expect("output folder is checked when the settings are loaded")
+    }
+
+    /// Use the settings of a profile that weren't given on the command line
+    fn apply_profile(&mut self, matches: &ArgMatches, profile: &Profile) {
+        let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+        macro_rules! apply {
+            ($arg:ident, $setting:ident, $convert:expr) => {
+                if !from_cli(stringify!($arg))
+                    && let Some(value) = profile.$setting.clone()
+                {
+                    self.$arg = $convert(value);
+                }
+            };
+            ($arg:ident, $setting:ident) => {
+                apply!($arg, $setting, |value| value)
+            };
+        }
+
+        apply!(output, output_folder, Some);
+        apply!(format, format);
+        apply!(unix, unix_time);
+        apply!(block_size, block_size);
+        apply!(max_readings_per_output_file, max_readings_per_output_file);
+        apply!(no_linearize_output, linearize_output, |value: bool| !value);
+        apply!(sort_by_time, sort_by_time);
+        apply!(incremental, incremental);
+        apply!(session_id, session_id);
+        apply!(dedup, dedup);
+        apply!(sessions, sessions);
+        apply!(battery, battery);
+        apply!(since, since, Some);
+        apply!(until, until, Some);
+        apply!(include_tdf, include_tdfs);
+        apply!(exclude_tdf, exclude_tdfs);
+        apply!(include_remote, include_remotes);
+        apply!(exclude_remote, exclude_remotes);
+        apply!(csv_delimiter, csv_delimiter);
+        apply!(csv_decimal_separator, csv_decimal_separator);
+        apply!(csv_float_precision, csv_float_precision, Some);
+        apply!(csv_bom, csv_bom);
+        apply!(csv_vla_mode, csv_vla_mode);
+        apply!(csv_vla_max_items, csv_vla_max_items);
+        apply!(gap_multiple, gap_multiple);
+        apply!(gap_min_empty_blocks, gap_min_empty_blocks);
+        apply!(resample_tdf, resample_tdfs);
+        apply!(resample_rate, resample_rate, Some);
+        apply!(resample_method, resample_method);
+        apply!(resample_tolerance, resample_tolerance);
+    }
+
+    /// Format of the CSV outputs
+    fn csv_format(&self) -> tdf::csv_format::CsvFormat {
+        tdf::csv_format::CsvFormat {
+            delimiter: self.csv_delimiter.as_char(),
+            decimal_separator: self.csv_decimal_separator.as_char(),
+            float_precision: self.csv_float_precision,
+            vla_mode: self.csv_vla_mode.csv_mode(self.csv_vla_max_items),
+        }
+    }
+
+    /// Reject a CSV format that every device would fail to decode with, before any is decoded
+    fn check_csv_format(&self) -> Result<(), String> {
+        if self.format != args::OutputFormat::CSV {
+            return Ok(());
+        }
+        self.csv_format().validate().map_err(|err| err.to_string())
+    }
+
+    /// Check the settings that clap can't, as it only sees the options given on the command line
+    /// and not those filled in from a profile
+    fn check_profile_settings(&self) -> Result<(), String> {
+        let conflicts = [
+            (
+                self.sort_by_time,
+                "--sort-by-time",
+                self.no_linearize_output,
+                "--no-linearize-output",
+            ),
+            (
+                self.incremental,
+                "--incremental",
+                self.no_linearize_output,
+                "--no-linearize-output",
+            ),
+            (
+                self.incremental,
+                "--incremental",
+                self.sort_by_time,
+                "--sort-by-time",
+            ),
+            (
+                self.incremental,
+                "--incremental",
+                !self.resample_tdf.is_empty(),
+                "--resample-tdf",
+            ),
+        ];
+        let name = self.profile.as_deref().unwrap_or_default();
+        if let Some((_, arg, _, other)) = conflicts
+            .into_iter()
+            .find(|(set, _, other_set, _)| *set && *other_set)
+        {
+            return Err(format!(
+                "The argument '{arg}' cannot be used with '{other}', check profile '{name}'"
+            ));
+        }
+        if !(self.gap_multiple.is_finite() && self.gap_multiple > 0.0) {
+            return Err(format!(
+                "Profile '{name}' has an invalid gap_multiple, expected a number greater than zero"
+            ));
+        }
+        if self.resample_tdf.is_empty() != self.resample_rate.is_none() {
+            return Err(format!(
+                "Resampling needs both '--resample-tdf' and '--resample-rate', check profile '{name}'"
+            ));
+        }
+        if let Some(rate) = self.resample_rate
+            && !(rate.is_finite() && rate > 0.0)
+        {
+            return Err(format!(
+                "Profile '{name}' has an invalid resample_rate, expected a number greater than zero"
+            ));
+        }
+        if !(self.resample_tolerance.is_finite() && self.resample_tolerance >= 0.0) {
+            return Err(format!(
+                "Profile '{name}' has an invalid resample_tolerance, expected a number that isn't negative"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Settings of this run, to save as a profile
+    fn profile(&self) -> Profile {
+        Profile {
+            output_folder: self.output.clone(),
+            format: Some(self.format),
+            unix_time: Some(self.unix),
+            block_size: Some(self.block_size),
+            max_readings_per_output_file: Some(self.max_readings_per_output_file),
+            linearize_output: Some(!self.no_linearize_output),
+            sort_by_time: Some(self.sort_by_time),
+            incremental: Some(self.incremental),
+            session_id: Some(self.session_id),
+            dedup: Some(self.dedup),
+            sessions: Some(self.sessions),
+            battery: Some(self.battery),
+            since: self.since,
+            until: self.until,
+            include_tdfs: Some(self.include_tdf.clone()),
+            exclude_tdfs: Some(self.exclude_tdf.clone()),
+            include_remotes: Some(self.include_remote.clone()),
+            exclude_remotes: Some(self.exclude_remote.clone()),
+            csv_delimiter: Some(self.csv_delimiter),
+            csv_decimal_separator: Some(self.csv_decimal_separator),
+            csv_float_precision: self.csv_float_precision,
+            csv_bom: Some(self.csv_bom),
+            csv_vla_mode: Some(self.csv_vla_mode),
+            csv_vla_max_items: Some(self.csv_vla_max_items),
+            gap_multiple: Some(self.gap_multiple),
+            gap_min_empty_blocks: Some(self.gap_min_empty_blocks),
+            resample_tdfs: Some(self.resample_tdf.clone()),
+            resample_rate: self.resample_rate,
+            resample_method: Some(self.resample_method),
+            resample_tolerance: Some(self.resample_tolerance),
+        }
+    }
+}
+
+/// Parse the command line, filling in settings from `--profile` and saving `--save-profile`
+fn load_settings() -> Result<Cli, String> {
+    let matches = Cli::command().get_matches();
+    let mut args = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if args.profile.is_none() && args.save_profile.is_none() {
+        args.check_csv_format()?;
+        return Ok(args);
+    }
+
+    let config_path = args
+        .config
+        .clone()
+        .or_else(infuse_decoder::config::default_config_path)
+        .ok_or("No user config folder to store profiles in, use --config")?;
+    let mut config = Config::load(&config_path).map_err(|err| err.to_string())?;
+    if let Some(name) = &args.profile {
+        let profile = config.profile(name).map_err(|err| err.to_string())?.clone();
+        args.apply_profile(&matches, &profile);
+        args.check_profile_settings()?;
+    }
+    if args.output.is_none() {
+        return Err(format!(
+            "Profile '{}' has no output folder, use --output",
+            args.profile.as_deref().unwrap_or_default()
+        ));
+    }
+    args.check_csv_format()?;
+    if let Some(name) = &args.save_profile {
+        config.profiles.insert(name.clone(), args.profile());
+        config.save(&config_path).map_err(|err| {
+            format!(
+                "Failed to save profile to '{}': {err}",
+                config_path.display()
+            )
+        })?;
+        status(
+            &args,
+            format!("Saved profile '{name}' to {:?}", config_path),
+        );
+    }
+    Ok(args)
+}
+
+/// How completely a device was decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum DecodeStatus {
+    Decoded,
+    /// Decoded, but more blocks failed to decode than the error threshold allows
+    Partial,
+    Failed,
+}
+
+#[derive(Serialize)]
+struct TdfCount {
+    /// Hexadecimal ID of the device that forwarded the data, `None` for the logging device
+    remote_id: Option<String>,
+    tdf_id: u16,
+    tdf_name: String,
+    samples: usize,
+}
+
+/// Results of decoding one device, for `--json`
+#[derive(Serialize)]
+struct DeviceReport {
+    device_id: String,
+    status: DecodeStatus,
+    error: Option<String>,
+    input_files: Vec<PathBuf>,
+    /// Percentage of the non-empty blocks that couldn't be decoded
+    error_blocks_percent: Option<f64>,
+    /// Number of blocks of each type
+    blocks: BTreeMap<&'static str, usize>,
+    /// Selected samples dropped for being outside the time window
+    filtered_samples: usize,
+    /// Blocks dropped for repeating the contents of an earlier block
+    duplicate_blocks: usize,
+    /// Samples dropped for repeating an earlier sample
+    duplicate_samples: usize,
+    tdfs: Vec<TdfCount>,
+    output_files: Vec<PathBuf>,
+    /// Summary report written next to the outputs
+    summary: Option<PathBuf>,
+    continuity: Option<infuse_decoder::continuity::ContinuityReport>,
+}
+
+impl DeviceReport {
+    fn new(
+        device_id: u64,
+        files: &[PathBuf],
+        summary: PathBuf,
+        result: &io::Result<infuse_decoder::RunResult>,
+        error_threshold: Option<f64>,
+    ) -> Self {
+        let mut report = Self {
+            device_id: format!("{device_id:016x}"),
+            status: DecodeStatus::Failed,
+            error: None,
+            input_files: files.to_vec(),
+            error_blocks_percent: None,
+            blocks: BTreeMap::new(),
+            filtered_samples: 0,
+            duplicate_blocks: 0,
+            duplicate_samples: 0,
+            tdfs: Vec::new(),
+            output_files: Vec::new(),
+            summary: None,
+            continuity: None,
+        };
+        let result = match result {
+            Ok(result) => result,
+            Err(err) => {
+                report.error = Some(err.to_string());
+                return report;
+            }
+        };
+
+        // Error blocks only change the status when a threshold is given, as they didn't before
+        // the threshold was added
+        let percent = infuse_decoder::summary::error_block_percent(&result.block_stats);
+        report.status = match (percent, error_threshold) {
+            (Some(percent), Some(_)) if percent >= 100.0 => {
+                report.error = Some("No blocks could be decoded".to_string());
+                DecodeStatus::Failed
+            }
+            (Some(percent), Some(threshold)) if percent > threshold => DecodeStatus::Partial,
+            _ => DecodeStatus::Decoded,
+        };
+        report.error_blocks_percent = percent;
+        report.blocks = result
+            .block_stats
+            .iter()
+            .map(|(block_type, count)| (infuse_decoder::summary::block_key(*block_type), *count))
+            .collect();
+        report.filtered_samples = result.filtered_samples;
+        report.duplicate_blocks = result.duplicate_blocks;
+        report.duplicate_samples = result.duplicate_samples;
+        for (remote_id, tdfs) in result.tdf_stats.iter() {
+            for (tdf_id, samples) in tdfs {
+                report.tdfs.push(TdfCount {
+                    remote_id: remote_id.map(|id| format!("{id:016x}")),
+                    tdf_id: *tdf_id,
+                    tdf_name: tdf::decoders::tdf_name(tdf_id),
+                    samples: *samples,
+                });
+            }
+        }
+        report
+            .tdfs
+            .sort_by(|a, b| (&a.remote_id, a.tdf_id).cmp(&(&b.remote_id, b.tdf_id)));
+        report.output_files = result.output_files.clone();
+        report.output_files.sort();
+        report.summary = summary.exists().then_some(summary);
+        report.continuity = Some(result.continuity.clone());
+        report
+    }
+}
+
+/// Results of all devices, printed with `--json`
+#[derive(Serialize)]
+struct CliReport {
+    decoder_version: &'static str,
+    status: DecodeStatus,
+    exit_code: u8,
+    error_threshold_percent: Option<f64>,
+    devices: Vec<DeviceReport>,
+}
+
+/// Overall status and exit code: success if every device decoded cleanly, failure if none could
+/// be decoded, and a partial decode otherwise. No devices is a success, as it was before exit codes
+/// were reported.
+fn overall_status(devices: &[DeviceReport]) -> (DecodeStatus, u8) {
+    if devices.iter().all(|d| d.status == DecodeStatus::Decoded) {
+        (DecodeStatus::Decoded, 0)
+    } else if devices.iter().all(|d| d.status == DecodeStatus::Failed) {
+        (DecodeStatus::Failed, 1)
+    } else {
+        (DecodeStatus::Partial, EXIT_PARTIAL)
+    }
+}
+
+/// Print a progress message, on stderr when stdout is reserved for `--json` results
+fn status(args: &Cli, msg: String) {
+    if args.json {
+        eprintln!("{msg}");
+    } else {
+        println!("{msg}");
+    }
+}
+
+fn format_event_time(time: Option<chrono::DateTime<chrono::Utc>>) -> String {
+    match time {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn print_continuity_report(report: &infuse_decoder::continuity::ContinuityReport) {
+    use infuse_decoder::continuity::ContinuityEventKind;
+
+    println!(
+        "Continuity: {} gaps, {} empty block runs, {} time regressions{}",
+        report.count(ContinuityEventKind::Gap),
+        report.count(ContinuityEventKind::EmptyBlocks),
+        report.count(ContinuityEventKind::Regression),
+        if report.truncated { " (truncated)" } else { "" }
+    );
+    if report.events.is_empty() {
+        return;
+    }
+
+    let mut table = Table::new();
+    table.set_titles(row![
+        "Type",
+        "TDF",
+        "Start",
+        "End",
+        "Duration (s)",
+        "Blocks"
+    ]);
+    for event in report.events.iter() {
+        table.add_row(row![
+            event.kind,
+            event.tdf_label(),
+            format_event_time(event.start_time()),
+            format_event_time(event.end_time()),
+            event
+                .duration_seconds()
+                .map_or("-".to_string(), |duration| format!("{duration:.3}")),
+            format!("{}-{}", event.first_block, event.last_block)
+        ]);
+    }
+    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+    table.printstd();
+    println!();
 }
 
-fn print_run_error(err: &io::Error, device_id: u64, files: &[PathBuf], output_folder: &PathBuf) {
+fn print_run_error(err: &io::Error, device_id: u64, files: &[PathBuf], output_folder: &Path) {
     eprintln!();
     eprintln!("Decode failed");
     eprintln!("=============");
@@ -87,30 +622,346 @@ fn print_run_error(err: &io::Error, device_id: u64, files: &[PathBuf], output_fo
     eprintln!("Cause         : {err}");
 }
 
-fn main() -> ExitCode {
-    let args = Cli::parse();
+/// Decode the input files of one device into `output_folder`, printing its statistics unless
+/// the results are printed as JSON
+fn decode_device(
+    args: &Cli,
+    device_id: u64,
+    files: &[PathBuf],
+    mapped_inputs: &MappedInputs,
+    output_folder: &Path,
+    output_prefix: String,
+    cancel: &infuse_decoder::CancellationToken,
+) -> io::Result<infuse_decoder::RunResult> {
+    let mut run_args = infuse_decoder::RunArgs {
+        device_id,
+        block_size: args.block_size as usize,
+        input_files: files.to_vec(),
+        mapped_inputs: mapped_inputs.clone(),
+        output_folder: output_folder.to_path_buf(),
+        output_prefix,
+        output_unix_time: args.unix,
+        output_format: args.format,
+        csv_format: args.csv_format(),
+        csv_utf8_bom: args.csv_bom,
+        merge_output_files: !args.no_linearize_output,
+        sort_by_time: args.sort_by_time,
+        since: args.since,
+        until: args.until,
+        selection: args::TdfSelection {
+            include_tdfs: args.include_tdf.clone(),
+            exclude_tdfs: args.exclude_tdf.clone(),
+            include_remotes: args.include_remote.clone(),
+            exclude_remotes: args.exclude_remote.clone(),
+        },
+        max_readings_per_output_file: args.max_readings_per_output_file,
+        session_id_column: args.session_id,
+        dedup: args.dedup,
+        gap_config: infuse_decoder::continuity::GapConfig {
+            interval_multiple: args.gap_multiple,
+            min_empty_blocks: args.gap_min_empty_blocks,
+        },
+        resample: args
+            .resample_rate
+            .map(|rate_hz| infuse_decoder::resample::ResampleConfig {
+                tdfs: args.resample_tdf.clone(),
+                rate_hz,
+                method: args.resample_method,
+                tolerance_seconds: args.resample_tolerance,
+            }),
+        incremental: args.incremental,
+        sessions_output: args.sessions,
+        battery_output: args.battery,
+        collect_batches: false,
+        cancel: cancel.clone(),
+        decode_reporter: IndicatifProgress::new(),
+        merge_reporter: IndicatifProgress::new(),
+    };
+
+    let result = infuse_decoder::run(&mut run_args)?;
+    if args.json {
+        return Ok(result);
+    }
+    if args.verbose {
+        for (remote_id, tdfs) in result.tdf_stats.iter() {
+            let mut table = Table::new();
 
-    // Handle single file supplied
-    let iot_bin_files: HashMap<u64, Vec<PathBuf>> = if args.path.is_dir() {
-        match infuse_decoder::fs_util::find_infuse_iot_files(&args.path) {
-            Ok(files) => files,
+            for (tdf_id, count) in tdfs.iter() {
+                table.add_row(row![tdf::decoders::tdf_name(tdf_id), count]);
+            }
+            table.set_titles(row!["TDF", "Count"]);
+            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+
+            if let Some(x) = remote_id {
+                println!("Remote ID: {:016x}", x);
+            }
+            table.printstd();
+            println!();
+        }
+
+        // Output Block statistics
+        let mut sorted: Vec<(&blocks::BlockTypes, &usize)> = result.block_stats.iter().collect();
+        sorted.sort_by(|a, b| b.1.cmp(a.1));
+
+        let mut table = Table::new();
+        table.set_titles(row!["Block Type", "Count"]);
+        for (block_type, block_cnt) in sorted {
+            table.add_row(row![format!("{}", block_type), block_cnt]);
+        }
+        table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+        table.printstd();
+        println!();
+        if result.filtered_samples > 0 {
+            println!("Samples outside time range: {}\n", result.filtered_samples);
+        }
+        if result.duplicate_blocks > 0 || result.duplicate_samples > 0 {
+            println!(
+                "Duplicates removed: {} blocks, {} samples\n",
+                result.duplicate_blocks, result.duplicate_samples
+            );
+        }
+        print_continuity_report(&result.continuity);
+    }
+    Ok(result)
+}
+
+/// Decode new and changed input sets in the watched folder until cancelled
+fn watch(args: &Cli, cancel: &infuse_decoder::CancellationToken) -> ExitCode {
+    let [path] = args.path.as_slice() else {
+        eprintln!("Only one folder can be watched");
+        return ExitCode::FAILURE;
+    };
+    if !path.is_dir() {
+        eprintln!("Watched path '{}' is not a folder", path.display());
+        return ExitCode::FAILURE;
+    }
+    let mut watcher = match infuse_decoder::watch::Watcher::open(path, args.output()) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            eprintln!(
+                "Failed to open watch log in '{}': {err}",
+                args.output().display()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    // Intervals too long to represent wait forever between scans
+    let interval = Duration::try_from_secs_f64(args.watch_interval).unwrap_or(Duration::MAX);
+
+    println!(
+        "Watching {:?} for Infuse-IoT files, press Ctrl-C to stop...",
+        path
+    );
+    while !cancel.is_cancelled() {
+        // The watched folder may be a network share that is briefly unavailable
+        let ready = match watcher.scan() {
+            Ok((ready, warnings)) => {
+                for warning in warnings {
+                    eprintln!("Warning: {warning}");
+                }
+                ready
+            }
             Err(err) => {
-                eprintln!("Failed to scan input path '{}': {err}", args.path.display());
+                eprintln!("Failed to scan '{}': {err}", path.display());
+                Vec::new()
+            }
+        };
+
+        for set in ready {
+            let files = set.paths();
+            let output_folder = set.output_folder(args.output());
+            println!(
+                "Handling Infuse-IoT device ID: {:016x} in {:?}...",
+                set.device_id,
+                path.join(&set.folder)
+            );
+            for warning in infuse_decoder::fs_util::input_file_warnings(
+                &files,
+                &MappedInputs::default(),
+                args.block_size as usize,
+            ) {
+                eprintln!("Warning: {warning}");
+            }
+
+            let output_prefix = args
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{:016x}", set.device_id));
+            let result = decode_device(
+                args,
+                set.device_id,
+                &files,
+                &MappedInputs::default(),
+                &output_folder,
+                output_prefix,
+                cancel,
+            )
+            .map(|_| ());
+            match &result {
+                Ok(()) => {}
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    eprintln!("Decode cancelled, partial output files removed");
+                    return ExitCode::from(130);
+                }
+                Err(err) => print_run_error(err, set.device_id, &files, &output_folder),
+            }
+            if let Err(err) = watcher.record(&set, result.map_err(|err| err.to_string())) {
+                eprintln!("Failed to update watch log: {err}");
                 return ExitCode::FAILURE;
             }
         }
-    } else {
-        let mut f: HashMap<u64, Vec<PathBuf>> = HashMap::new();
-        f.insert(0, vec![args.path.clone()]);
-        f
+
+        // Sleep in short steps so that Ctrl-C stops the watch promptly
+        let started = Instant::now();
+        while !cancel.is_cancelled() && started.elapsed() < interval {
+            std::thread::sleep(
+                interval
+                    .saturating_sub(started.elapsed())
+                    .min(Duration::from_millis(200)),
+            );
+        }
+    }
+
+    println!("Stopped watching {:?}", path);
+    ExitCode::SUCCESS
+}
+
+fn main() -> ExitCode {
+    let args = match load_settings() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
     };
+
+    // The first Ctrl-C stops the decode and removes partial outputs, a second exits immediately
+    let cancel = infuse_decoder::CancellationToken::new();
+    let handler_cancel = cancel.clone();
+    if let Err(err) = ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            std::process::exit(130);
+        }
+        eprintln!();
+        eprintln!("Cancelling decode, press Ctrl-C again to exit immediately...");
+        handler_cancel.cancel();
+    }) {
+        eprintln!("Warning: Failed to install Ctrl-C handler: {err}");
+    }
+
+    if args.watch {
+        return watch(&args, &cancel);
+    }
+
+    // Archives and disk images are decoded like a folder of the Infuse-IoT files inside them. The
+    // files of a device in several inputs are only decoded together when they are deduplicated.
+    let mut extracted = Vec::new();
+    let mut mapped_inputs = infuse_decoder::input::MappedInputs::default();
+    let mut iot_bin_files: HashMap<u64, Vec<PathBuf>> = HashMap::new();
+    for path in &args.path {
+        let files = if infuse_decoder::archive::is_archive(path) {
+            status(
+                &args,
+                format!("Extracting Infuse-IoT files from {:?}...", path),
+            );
+            match infuse_decoder::archive::extract_infuse_iot_files(
+                path,
+                None,
+                args.block_size as usize,
+                &cancel,
+            ) {
+                Ok(archive) => {
+                    for warning in archive.warnings() {
+                        eprintln!("Warning: {warning}");
+                    }
+                    mapped_inputs.extend(archive.mapped_inputs().clone());
+                    let files = archive.files().clone();
+                    extracted.push(archive);
+                    files
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                    eprintln!("Extraction cancelled");
+                    return ExitCode::from(130);
+                }
+                Err(err) => {
+                    eprintln!("{err}");
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else if path.is_dir() {
+            match infuse_decoder::fs_util::find_input_folder_files(path, args.dedup) {
+                Ok(files) => files,
+                Err(err) => {
+                    eprintln!("Failed to scan input path '{}': {err}", path.display());
+                    return ExitCode::FAILURE;
+                }
+            }
+        } else {
+            let name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default();
+            let device_id = infuse_decoder::fs_util::infuse_iot_device_id(name)
+                .or_else(|| infuse_decoder::fs_util::fallback_device_id(name));
+            match device_id {
+                Some(device_id) => HashMap::from([(device_id, vec![path.clone()])]),
+                // A single file supplied on its own doesn't need a device ID
+                None if args.path.len() == 1 => HashMap::from([(0, vec![path.clone()])]),
+                None => {
+                    eprintln!(
+                        "The device ID of '{}' isn't part of its name, decode it on its own",
+                        path.display()
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+        };
+        if !args.dedup
+            && let Some(device_id) = files.keys().find(|id| iot_bin_files.contains_key(id))
+        {
+            eprintln!(
+                "Files of device ID {device_id:016x} are in several inputs, use --dedup to decode them together"
+            );
+            return ExitCode::FAILURE;
+        }
+        infuse_decoder::fs_util::merge_device_files(&mut iot_bin_files, files);
+    }
     let num_devices = iot_bin_files.len();
+    if num_devices == 0 {
+        eprintln!(
+            "No Infuse-IoT files found in {}",
+            args.path
+                .iter()
+                .map(|path| format!("'{}'", path.display()))
+                .join(", ")
+        );
+    }
 
-    for (device_id, files) in iot_bin_files.iter() {
-        if args.path.is_dir() {
-            println!("Handling Infuse-IoT device ID: {:016x}...", device_id);
+    let mut devices = Vec::new();
+    for (device_id, files) in iot_bin_files
+        .iter()
+        .sorted_by_key(|(device_id, _)| **device_id)
+    {
+        // Files given on their own are named after the file rather than the device
+        let single_file = matches!(files.as_slice(), [file] if args.path.contains(file));
+        if !single_file {
+            status(
+                &args,
+                format!("Handling Infuse-IoT device ID: {:016x}...", device_id),
+            );
         } else {
-            println!("Handling Infuse-IoT file: {:?}...", args.path);
+            status(
+                &args,
+                format!("Handling Infuse-IoT file: {:?}...", files[0]),
+            );
+        }
+
+        for warning in infuse_decoder::fs_util::input_file_warnings(
+            files,
+            &mapped_inputs,
+            args.block_size as usize,
+        ) {
+            eprintln!("Warning: {warning}");
         }
 
         let output_prefix = match args.name.as_ref() {
@@ -118,17 +969,17 @@ fn main() -> ExitCode {
                 if num_devices > 1 {
                     format!("{name}_{device_id:016x}")
                 } else {
-                    format!("{name}")
+                    name.clone()
                 }
             }
             None => {
-                if args.path.is_file() {
-                    match args.path.file_stem().and_then(|stem| stem.to_str()) {
+                if single_file {
+                    match files[0].file_stem().and_then(|stem| stem.to_str()) {
                         Some(stem) => stem.to_string(),
                         None => {
                             eprintln!(
                                 "Failed to derive output name from input path '{}'",
-                                args.path.display()
+                                files[0].display()
                             );
                             return ExitCode::FAILURE;
                         }
@@ -139,58 +990,58 @@ fn main() -> ExitCode {
             }
         };
 
-        let mut run_args = infuse_decoder::RunArgs {
-            device_id: *device_id,
-            block_size: args.block_size as usize,
-            input_files: files.clone(),
-            output_folder: args.output.clone(),
-            output_prefix: output_prefix,
-            output_unix_time: args.unix,
-            output_format: args.format,
-            merge_output_files: !args.no_linearize_output,
-            max_readings_per_output_file: args.max_readings_per_output_file,
-            copy_reporter: IndicatifProgress::new(),
-            decode_reporter: IndicatifProgress::new(),
-            merge_reporter: IndicatifProgress::new(),
-        };
-
-        let (block_stats, tdf_stats, _output_files) = match infuse_decoder::run(&mut run_args) {
-            Ok(result) => result,
-            Err(err) => {
-                print_run_error(&err, *device_id, files, &args.output);
-                return ExitCode::FAILURE;
+        let summary = infuse_decoder::summary::summary_path(args.output(), &output_prefix);
+        let result = decode_device(
+            &args,
+            *device_id,
+            files,
+            &mapped_inputs,
+            args.output(),
+            output_prefix,
+            &cancel,
+        );
+        match &result {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                eprintln!("Decode cancelled, partial output files removed");
+                return ExitCode::from(130);
             }
-        };
-
-        if args.verbose {
-            for (remote_id, tdfs) in tdf_stats.iter() {
-                let mut table = Table::new();
-
-                for (tdf_id, count) in tdfs.iter() {
-                    table.add_row(row![tdf::decoders::tdf_name(tdf_id), count]);
-                }
-                table.set_titles(row!["TDF", "Count"]);
-                table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+            Err(err) if !args.json => print_run_error(err, *device_id, files, args.output()),
+            _ => {}
+        }
 
-                if let Some(x) = remote_id {
-                    println!("Remote ID: {:016x}", x);
+        // Other devices are still decoded after a failure, which is reported by the exit code
+        let report = DeviceReport::new(*device_id, files, summary, &result, args.error_threshold);
+        if !args.json {
+            match (report.status, report.error_blocks_percent, &report.error) {
+                (DecodeStatus::Partial, Some(percent), _) => eprintln!(
+                    "Warning: {percent:.2}% of blocks could not be decoded (threshold {}%)",
+                    args.error_threshold.unwrap_or_default()
+                ),
+                (DecodeStatus::Failed, _, Some(error)) if result.is_ok() => {
+                    eprintln!("Error: {error}")
                 }
-                table.printstd();
-                println!("");
+                _ => {}
             }
+        }
+        devices.push(report);
+    }
 
-            // Output Block statistics
-            let mut sorted: Vec<(&blocks::BlockTypes, &usize)> = block_stats.iter().collect();
-            sorted.sort_by(|a, b| b.1.cmp(a.1));
-
-            let mut table = Table::new();
-            table.set_titles(row!["Block Type", "Count"]);
-            for (block_type, block_cnt) in sorted {
-                table.add_row(row![format!("{}", block_type), block_cnt]);
+    let (status, exit_code) = overall_status(&devices);
+    if args.json {
+        let report = CliReport {
+            decoder_version: env!("CARGO_PKG_VERSION"),
+            status,
+            exit_code,
+            error_threshold_percent: args.error_threshold,
+            devices,
+        };
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{json}"),
+            Err(err) => {
+                eprintln!("Failed to write JSON results: {err}");
+                return ExitCode::FAILURE;
             }
-            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.printstd();
         }
     }
-    ExitCode::SUCCESS
+    ExitCode::from(exit_code)
 }
@@ -1,24 +1,33 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+use std::collections::HashMap;
 use std::env;
 use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::{collections::HashMap, path::PathBuf};
 
-use chrono::{Datelike, Utc};
+use chrono::{DateTime, Datelike, Utc};
 use eframe::egui::{self, IconData};
 use egui_extras::{Column, TableBuilder};
 use image::GenericImageView;
+use infuse_decoder::archive::ExtractedArchive;
 use infuse_decoder::args::OutputFormat;
+use infuse_decoder::config::{Config, Profile};
+use infuse_decoder::continuity::{self, ContinuityEventKind, ContinuityReport};
+use infuse_decoder::output_preview::{OutputFileInfo, Preview, read_preview};
+use infuse_decoder::plot::{PlotSeries, plot_fields, plot_series};
 use rfd::FileDialog;
 
-use infuse_decoder::args::BlockSizeOptions;
+use infuse_decoder::args::{
+    BlockSizeOptions, CsvDelimiter, DecimalSeparator, TdfSelection, VlaMode, known_tdfs,
+    parse_remote_id, parse_utc_time,
+};
 
 #[derive(PartialEq)]
 enum TimeOutput {
-    UNIX,
-    UTC,
+    Unix,
+    Utc,
 }
 
 struct SliderInternalState {
@@ -35,7 +44,7 @@ struct SliderState {
 impl SliderState {
     pub fn new(label: &'static str) -> Self {
         Self {
-            label: label,
+            label,
             state: Arc::new(Mutex::new(SliderInternalState {
                 total: 1,
                 current: 0,
@@ -43,13 +52,13 @@ impl SliderState {
             })),
         }
     }
-    pub fn reset(self: &mut Self) {
+    pub fn reset(&mut self) {
         let mut s = self.state.lock().unwrap();
         s.current = 0;
         s.enabled = false;
     }
 
-    pub fn draw(self: &mut Self, ui: &mut egui::Ui) {
+    pub fn draw(&mut self, ui: &mut egui::Ui) {
         ui.label(self.label);
 
         let s = self.state.lock().unwrap();
@@ -58,7 +67,7 @@ impl SliderState {
         ui.add_enabled(s.enabled, progress_bar);
     }
 
-    pub fn draw_count(self: &mut Self, ui: &mut egui::Ui) {
+    pub fn draw_count(&mut self, ui: &mut egui::Ui) {
         ui.label(self.label);
 
         let s = self.state.lock().unwrap();
@@ -89,39 +98,236 @@ impl infuse_decoder::ProgressReporter for SliderState {
     }
 }
 
+/// Results of decoding one device
+struct DeviceResult {
+    device_id: u64,
+    block_stats: HashMap<blocks::BlockTypes, usize>,
+    tdf_stats: HashMap<Option<u64>, HashMap<u16, usize>>,
+    /// Selected samples dropped for being outside the time window
+    filtered_samples: usize,
+    /// Blocks and samples dropped for repeating earlier data
+    duplicate_blocks: usize,
+    duplicate_samples: usize,
+    output_files: Vec<OutputFile>,
+    continuity: ContinuityReport,
+    /// Earliest and latest sample time
+    time_span: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// Samples written by the decode, for the plot
+    batches: infuse_decoder::ArrowBatches,
+}
+
+impl DeviceResult {
+    /// Blocks that held data, whether or not they could be decoded
+    fn num_blocks(&self) -> usize {
+        [
+            blocks::BlockTypes::TDF,
+            blocks::BlockTypes::REMOTE,
+            blocks::BlockTypes::OTHER,
+            blocks::BlockTypes::ERROR,
+        ]
+        .iter()
+        .filter_map(|block_type| self.block_stats.get(block_type))
+        .sum()
+    }
+
+    fn num_errors(&self) -> usize {
+        self.block_stats
+            .get(&blocks::BlockTypes::ERROR)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn num_samples(&self) -> usize {
+        self.tdf_stats.values().flat_map(HashMap::values).sum()
+    }
+}
+
+/// Results of each decoded device, in device ID order
+type GuiRunResult = Vec<DeviceResult>;
+
+/// Archive or disk image being opened on a background thread
+struct ArchiveLoader {
+    path: PathBuf,
+    handle: std::thread::JoinHandle<io::Result<ExtractedArchive>>,
+    cancel: infuse_decoder::CancellationToken,
+}
+
 struct MyApp {
     doc_capture: Option<DocCapture>,
     time_mode: TimeOutput,
     output_format: OutputFormat,
     linearize_output_files: bool,
+    sort_by_time: bool,
+    incremental: bool,
+    session_id_column: bool,
+    dedup: bool,
+    sessions_output: bool,
+    battery_output: bool,
+    time_since: String,
+    time_until: String,
+    tdf_enabled: Vec<(u16, String, bool)>,
+    show_tdf_selection: bool,
+    only_remote_ids: String,
+    skip_remote_ids: String,
     decode_all_devices: bool,
     device_id: u64,
     block_size: BlockSizeOptions,
     max_readings_per_output_file: usize,
+    csv_delimiter: CsvDelimiter,
+    csv_decimal_separator: DecimalSeparator,
+    csv_float_precision: Option<usize>,
+    csv_bom: bool,
+    csv_vla_mode: VlaMode,
+    csv_vla_max_items: usize,
+    gap_multiple: f64,
+    gap_min_empty_blocks: usize,
+    /// Resample settings of the applied profile, which the GUI doesn't use but keeps when saving it
+    profile_resample: Profile,
     error_msg: Option<String>,
+    input_warnings: Option<Vec<String>>,
+    input_warnings_accepted: bool,
     input_path: Option<PathBuf>,
     input_files: Option<HashMap<u64, Vec<PathBuf>>>,
+    /// Files of the input archive, extracted once when it is opened and kept until another input
+    /// is picked
+    archive: Option<Arc<ExtractedArchive>>,
+    archive_loader: Option<ArchiveLoader>,
     output_folder: PathBuf,
     output_prefix: String,
-    progress_copy: SliderState,
     progress_devices: SliderState,
     progress_decode: SliderState,
     progress_merge: SliderState,
     block_stats: Option<Vec<(blocks::BlockTypes, usize)>>,
+    /// Samples outside the time window, of the devices whose tables are shown
+    filtered_samples: usize,
+    /// Duplicate blocks and samples, of the devices whose tables are shown
+    duplicate_blocks: usize,
+    duplicate_samples: usize,
     tdf_stats: Option<HashMap<Option<u64>, HashMap<u16, usize>>>,
-    output_files: Option<Vec<PathBuf>>,
-    runner_thread: Option<
-        std::thread::JoinHandle<
-            Result<
-                (
-                    HashMap<blocks::BlockTypes, usize>,
-                    HashMap<Option<u64>, HashMap<u16, usize>>,
-                    Vec<PathBuf>,
-                ),
-                std::io::Error,
-            >,
-        >,
-    >,
+    output_files: Option<Vec<OutputFile>>,
+    /// Text the output file list is filtered by
+    output_filter: String,
+    preview: Option<FilePreview>,
+    device_results: Option<Vec<DeviceResult>>,
+    /// Device whose tables are shown, `None` for all devices combined
+    expanded_device: Option<u64>,
+    runner_thread: Option<std::thread::JoinHandle<io::Result<GuiRunResult>>>,
+    cancel: infuse_decoder::CancellationToken,
+    /// Configuration file with the saved profiles, `None` if there is no user config folder
+    config_path: Option<PathBuf>,
+    config: Config,
+    profile_name: String,
+    plot: PlotPanel,
+}
+
+/// Rows shown by the output file preview
+const PREVIEW_ROWS: usize = 100;
+
+/// First rows of the selected output file
+struct FilePreview {
+    open: bool,
+    path: PathBuf,
+    /// Rows of the file, `None` while they are read
+    preview: Option<Result<Preview, String>>,
+    loader: Option<std::thread::JoinHandle<io::Result<Preview>>>,
+}
+
+impl FilePreview {
+    /// Read the first rows of a file on a background thread
+    fn load(path: &Path) -> Self {
+        let loader_path = path.to_path_buf();
+        Self {
+            open: true,
+            path: path.to_path_buf(),
+            preview: None,
+            loader: Some(thread::spawn(move || {
+                read_preview(&loader_path, PREVIEW_ROWS)
+            })),
+        }
+    }
+
+    /// Collect the rows once they are read
+    fn poll(&mut self) {
+        if !self
+            .loader
+            .as_ref()
+            .is_some_and(|handle| handle.is_finished())
+        {
+            return;
+        }
+        let result = self
+            .loader
+            .take()
+            .unwrap()
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Reading the file stopped unexpectedly")));
+        self.preview = Some(result.map_err(|err| err.to_string()));
+    }
+}
+
+/// Output file of a decode, with its size and rows or the error reading them
+#[derive(Clone)]
+struct OutputFile {
+    path: PathBuf,
+    info: Result<OutputFileInfo, String>,
+}
+
+impl OutputFile {
+    fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// Time series plot of the numeric fields of a decoded TDF
+#[derive(Default)]
+struct PlotPanel {
+    open: bool,
+    device_id: u64,
+    tdf: Option<(Option<u64>, u16)>,
+    fields: Vec<(String, bool)>,
+    series: Vec<PlotSeries>,
+    /// Fit the plot to the series on the next frame
+    reset_view: bool,
+}
+
+impl PlotPanel {
+    /// Forget the plotted data, ready to plot the results of a new decode
+    fn reset(&mut self, device_id: u64) {
+        self.device_id = device_id;
+        self.tdf = None;
+        self.fields.clear();
+        self.series.clear();
+    }
+
+    fn select_tdf(&mut self, key: (Option<u64>, u16)) {
+        self.tdf = Some(key);
+        self.fields = plot_fields(key.1)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, name)| (name, idx == 0))
+            .collect();
+    }
+
+    /// Build the series of the selected fields from the samples of the decode
+    fn load(&mut self, batches: &infuse_decoder::ArrowBatches) {
+        let Some((remote_id, tdf_id)) = self.tdf else {
+            return;
+        };
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .filter(|(_, on)| *on)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let tdf_batches = batches
+            .get(&(remote_id, tdf_id))
+            .map_or(&[][..], Vec::as_slice);
+        self.series = plot_series(tdf_id, tdf_batches, &fields);
+        self.reset_view = true;
+    }
 }
 
 struct DocCapture {
@@ -159,10 +365,7 @@ impl Default for MyApp {
                 .document_dir()
                 .map(|x| x.to_owned().join("infuse_iot"))
         } else {
-            match env::current_dir() {
-                Ok(dir) => Some(dir),
-                _ => None,
-            }
+            env::current_dir().ok()
         };
         if default_out.is_none() {
             default_out = Some(PathBuf::from("."));
@@ -187,26 +390,65 @@ impl Default for MyApp {
 
         Self {
             doc_capture,
-            time_mode: TimeOutput::UTC,
+            time_mode: TimeOutput::Utc,
             output_format: OutputFormat::CSV,
             linearize_output_files: true,
+            sort_by_time: false,
+            incremental: false,
+            session_id_column: false,
+            dedup: false,
+            sessions_output: false,
+            battery_output: false,
+            time_since: String::new(),
+            time_until: String::new(),
+            tdf_enabled: known_tdfs()
+                .into_iter()
+                .map(|(tdf_id, name)| (tdf_id, name, true))
+                .collect(),
+            show_tdf_selection: false,
+            only_remote_ids: String::new(),
+            skip_remote_ids: String::new(),
             decode_all_devices: false,
             device_id,
             block_size: BlockSizeOptions::B512,
             max_readings_per_output_file: infuse_decoder::DEFAULT_MAX_READINGS_PER_OUTPUT_FILE,
+            csv_delimiter: CsvDelimiter::Comma,
+            csv_decimal_separator: DecimalSeparator::Point,
+            csv_float_precision: None,
+            csv_bom: false,
+            csv_vla_mode: VlaMode::Rows,
+            csv_vla_max_items: infuse_decoder::DEFAULT_CSV_VLA_MAX_ITEMS,
+            gap_multiple: continuity::DEFAULT_GAP_INTERVAL_MULTIPLE,
+            gap_min_empty_blocks: continuity::DEFAULT_GAP_MIN_EMPTY_BLOCKS,
+            profile_resample: Profile::default(),
             error_msg: None,
+            input_warnings: None,
+            input_warnings_accepted: false,
             input_path,
             input_files,
+            archive: None,
+            archive_loader: None,
             output_folder: default_out.unwrap(),
             output_prefix,
-            progress_copy: SliderState::new("Copying files"),
             progress_devices: SliderState::new("Devices decoded"),
             progress_decode: SliderState::new("Decoding files"),
             progress_merge: SliderState::new("Merging output"),
             block_stats: None,
+            filtered_samples: 0,
+            duplicate_blocks: 0,
+            duplicate_samples: 0,
             tdf_stats: None,
             output_files: None,
+            output_filter: String::new(),
+            preview: None,
+            device_results: None,
+            expanded_device: None,
             runner_thread: None,
+            cancel: infuse_decoder::CancellationToken::new(),
+            config_path: None,
+            config: Config::default(),
+            profile_name: String::new(),
+            plot: PlotPanel::default(),
         }
     }
 }
@@ -217,6 +459,244 @@ fn doc_capture_enabled() -> bool {
 }
 
 impl MyApp {
+    /// Extract the Infuse-IoT files of an archive on a background thread, replacing the input
+    /// once they are extracted
+    fn open_archive(&mut self, path: PathBuf) {
+        self.set_archive(None);
+        let cancel = infuse_decoder::CancellationToken::new();
+        let block_size = self.block_size as usize;
+        let handle = {
+            let path = path.clone();
+            let cancel = cancel.clone();
+            thread::spawn(move || {
+                infuse_decoder::archive::extract_infuse_iot_files(&path, None, block_size, &cancel)
+            })
+        };
+        self.archive_loader = Some(ArchiveLoader {
+            path,
+            handle,
+            cancel,
+        });
+    }
+
+    /// Replace the opened archive, stopping one that is still being opened
+    fn set_archive(&mut self, archive: Option<ExtractedArchive>) {
+        if let Some(loader) = self.archive_loader.take() {
+            loader.cancel.cancel();
+        }
+        self.archive = archive.map(Arc::new);
+    }
+
+    /// Use the archive as the input once it has been extracted
+    fn poll_archive(&mut self) {
+        let Some(loader) = self
+            .archive_loader
+            .take_if(|loader| loader.handle.is_finished())
+        else {
+            return;
+        };
+        let extracted = loader
+            .handle
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::other("Opening the archive stopped unexpectedly")));
+        match extracted {
+            Ok(extracted) => {
+                let files = extracted.files().clone();
+                self.device_id = files.keys().copied().min().unwrap_or(0);
+                self.output_prefix = format!("{:016x}", self.device_id);
+                self.input_path = Some(loader.path);
+                self.input_files = Some(files);
+                self.decode_all_devices = false;
+                self.set_archive(Some(extracted));
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => self.error_msg = Some(err.to_string()),
+        }
+    }
+
+    /// Start with the settings the GUI was last used with
+    fn with_saved_settings() -> Self {
+        let mut app = Self::default();
+        // Documentation screenshots always show the default settings
+        if app.is_doc_capture() {
+            return app;
+        }
+        app.config_path = infuse_decoder::config::default_config_path();
+        if let Some(path) = &app.config_path {
+            match Config::load(path) {
+                Ok(config) => app.config = config,
+                Err(err) => app.error_msg = Some(err.to_string()),
+            }
+        }
+        if let Some(profile) = app.config.last_used.clone() {
+            app.apply_profile(&profile);
+        }
+        app
+    }
+
+    /// Current settings, as a profile
+    fn profile(&self) -> Profile {
+        let parse_bound = |value: &str| parse_utc_time(value).ok();
+        Profile {
+            output_folder: Some(self.output_folder.clone()),
+            format: Some(self.output_format),
+            unix_time: Some(self.time_mode == TimeOutput::Unix),
+            block_size: Some(self.block_size),
+            max_readings_per_output_file: Some(self.max_readings_per_output_file),
+            linearize_output: Some(self.linearize_output_files),
+            sort_by_time: Some(self.sort_by_time),
+            incremental: Some(self.incremental),
+            session_id: Some(self.session_id_column),
+            dedup: Some(self.dedup),
+            sessions: Some(self.sessions_output),
+            battery: Some(self.battery_output),
+            since: parse_bound(&self.time_since),
+            until: parse_bound(&self.time_until),
+            include_tdfs: Some(Vec::new()),
+            exclude_tdfs: Some(
+                self.tdf_enabled
+                    .iter()
+                    .filter(|(_, _, on)| !on)
+                    .map(|(tdf_id, _, _)| *tdf_id)
+                    .collect(),
+            ),
+            include_remotes: parse_remote_ids(&self.only_remote_ids).ok(),
+            exclude_remotes: parse_remote_ids(&self.skip_remote_ids).ok(),
+            csv_delimiter: Some(self.csv_delimiter),
+            csv_decimal_separator: Some(self.csv_decimal_separator),
+            csv_float_precision: self.csv_float_precision,
+            csv_bom: Some(self.csv_bom),
+            csv_vla_mode: Some(self.csv_vla_mode),
+            csv_vla_max_items: Some(self.csv_vla_max_items),
+            gap_multiple: Some(self.gap_multiple),
+            gap_min_empty_blocks: Some(self.gap_min_empty_blocks),
+            resample_tdfs: self.profile_resample.resample_tdfs.clone(),
+            resample_rate: self.profile_resample.resample_rate,
+            resample_method: self.profile_resample.resample_method,
+            resample_tolerance: self.profile_resample.resample_tolerance,
+        }
+    }
+
+    /// Use the settings of a profile
+    fn apply_profile(&mut self, profile: &Profile) {
+        macro_rules! apply {
+            ($field:ident, $setting:ident) => {
+                if let Some(value) = profile.$setting.clone() {
+                    self.$field = value;
+                }
+            };
+        }
+        apply!(output_folder, output_folder);
+        apply!(output_format, format);
+        if let Some(unix_time) = profile.unix_time {
+            self.time_mode = if unix_time {
+                TimeOutput::Unix
+            } else {
+                TimeOutput::Utc
+            };
+        }
+        apply!(block_size, block_size);
+        apply!(max_readings_per_output_file, max_readings_per_output_file);
+        apply!(linearize_output_files, linearize_output);
+        apply!(sort_by_time, sort_by_time);
+        apply!(incremental, incremental);
+        apply!(session_id_column, session_id);
+        apply!(dedup, dedup);
+        apply!(sessions_output, sessions);
+        apply!(battery_output, battery);
+        let format_bound =
+            |time: Option<DateTime<Utc>>| time.map(|time| time.to_rfc3339()).unwrap_or_default();
+        self.time_since = format_bound(profile.since);
+        self.time_until = format_bound(profile.until);
+        if profile.include_tdfs.is_some() || profile.exclude_tdfs.is_some() {
+            let selection = TdfSelection {
+                include_tdfs: profile.include_tdfs.clone().unwrap_or_default(),
+                exclude_tdfs: profile.exclude_tdfs.clone().unwrap_or_default(),
+                ..TdfSelection::default()
+            };
+            for (tdf_id, _, on) in self.tdf_enabled.iter_mut() {
+                *on = selection.allows_tdf(*tdf_id);
+            }
+        }
+        if let Some(remotes) = &profile.include_remotes {
+            self.only_remote_ids = format_remote_ids(remotes);
+        }
+        if let Some(remotes) = &profile.exclude_remotes {
+            self.skip_remote_ids = format_remote_ids(remotes);
+        }
+        apply!(csv_delimiter, csv_delimiter);
+        apply!(csv_decimal_separator, csv_decimal_separator);
+        self.csv_float_precision = profile.csv_float_precision;
+        apply!(csv_bom, csv_bom);
+        apply!(csv_vla_mode, csv_vla_mode);
+        apply!(csv_vla_max_items, csv_vla_max_items);
+        apply!(gap_multiple, gap_multiple);
+        apply!(gap_min_empty_blocks, gap_min_empty_blocks);
+        self.profile_resample = Profile {
+            resample_tdfs: profile.resample_tdfs.clone(),
+            resample_rate: profile.resample_rate,
+            resample_method: profile.resample_method,
+            resample_tolerance: profile.resample_tolerance,
+            ..Profile::default()
+        };
+    }
+
+    /// Change the configuration file, reading it again first so that profiles saved by the CLI
+    /// since the GUI started are kept
+    fn update_config(&mut self, update: impl FnOnce(&mut Config)) -> io::Result<()> {
+        let Some(path) = &self.config_path else {
+            return Ok(());
+        };
+        let mut config = Config::load(path)?;
+        update(&mut config);
+        config.save(path)?;
+        self.config = config;
+        Ok(())
+    }
+
+    /// Save the current settings as the named profile
+    fn save_profile(&mut self) {
+        let name = self.profile_name.trim().to_string();
+        let profile = self.profile();
+        if let Err(err) = self.update_config(|config| {
+            config.profiles.insert(name, profile);
+        }) {
+            self.error_msg = Some(format!("Failed to save profile: {err}"));
+        }
+    }
+
+    /// Show the block, TDF and output file tables of one device, or of all devices combined
+    fn expand_device(&mut self, device_id: Option<u64>) {
+        let Some(results) = self.device_results.as_ref() else {
+            return;
+        };
+        let mut block_stats = HashMap::new();
+        let mut tdf_stats = HashMap::new();
+        let mut filtered_samples = 0;
+        let mut duplicate_blocks = 0;
+        let mut duplicate_samples = 0;
+        let mut output_files = Vec::new();
+        for result in results
+            .iter()
+            .filter(|result| device_id.is_none_or(|id| id == result.device_id))
+        {
+            merge_block_stats(&mut block_stats, result.block_stats.clone());
+            merge_tdf_stats(&mut tdf_stats, result.tdf_stats.clone());
+            filtered_samples += result.filtered_samples;
+            duplicate_blocks += result.duplicate_blocks;
+            duplicate_samples += result.duplicate_samples;
+            output_files.extend(result.output_files.iter().cloned());
+        }
+        output_files.sort_by(|a: &OutputFile, b| a.path.cmp(&b.path));
+        self.block_stats = Some(hashmap_sort(block_stats));
+        self.filtered_samples = filtered_samples;
+        self.duplicate_blocks = duplicate_blocks;
+        self.duplicate_samples = duplicate_samples;
+        self.tdf_stats = Some(tdf_stats);
+        self.output_files = Some(output_files);
+        self.expanded_device = device_id;
+    }
+
     fn is_doc_capture(&self) -> bool {
         self.doc_capture.is_some()
     }
@@ -302,12 +782,12 @@ fn save_color_image(path: &std::path::Path, image: &egui::ColorImage) -> image::
     )
 }
 
-fn trimmed_label(label: &String, max_len: usize) -> String {
+fn trimmed_label(label: &str, max_len: usize) -> String {
     if label.len() > max_len {
         let idx = label.len() - (max_len - 3);
         format!("...{}", &label[idx..])
     } else {
-        label.clone()
+        label.to_string()
     }
 }
 
@@ -318,31 +798,50 @@ pub fn open_in_native_browser(path: &std::path::Path) -> Result<(), String> {
     if !path.is_dir() {
         return Err(format!("Path is not a directory: {}", path.display()));
     }
+    open_with_system(&[path])
+}
 
-    #[cfg(target_os = "windows")]
-    {
-        std::process::Command::new("explorer")
-            .arg(path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
+/// Open a file in the application the system associates with its type
+pub fn open_in_default_app(path: &std::path::Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!("File does not exist: {}", path.display()));
     }
+    open_with_system(&[path])
+}
 
-    #[cfg(target_os = "macos")]
-    {
-        std::process::Command::new("open")
-            .arg(path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
+/// Show a file in the native file browser, selected where the browser supports it
+pub fn reveal_in_folder(path: &std::path::Path) -> Result<(), String> {
+    if !path.is_file() {
+        return Err(format!("File does not exist: {}", path.display()));
     }
 
-    #[cfg(target_os = "linux")]
-    {
-        std::process::Command::new("xdg-open")
-            .arg(path)
-            .spawn()
-            .map_err(|e| e.to_string())?;
-    }
+    #[cfg(target_os = "windows")]
+    let args = {
+        let mut select = std::ffi::OsString::from("/select,");
+        select.push(path);
+        [select]
+    };
+    #[cfg(target_os = "macos")]
+    let args = [std::ffi::OsStr::new("-R"), path.as_os_str()];
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let args = [path.parent().unwrap_or(path)];
 
+    open_with_system(&args)
+}
+
+/// Run the system's file browser or opener with the given arguments
+fn open_with_system<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Result<(), String> {
+    #[cfg(target_os = "windows")]
+    let program = "explorer";
+    #[cfg(target_os = "macos")]
+    let program = "open";
+    #[cfg(not(any(target_os = "windows", target_os = "macos")))]
+    let program = "xdg-open";
+
+    std::process::Command::new(program)
+        .args(args)
+        .spawn()
+        .map_err(|e| e.to_string())?;
     Ok(())
 }
 
@@ -356,13 +855,12 @@ fn core_options(app: &mut MyApp, ui: &mut egui::Ui) {
             ui.label(egui::RichText::new(trimmed_label(&folder_str, 48)).code());
             ui.horizontal(|ui| {
                 let folder_button = ui.button("Folder");
-                if folder_button.clicked() {
-                    if let Some(folder) = FileDialog::new()
+                if folder_button.clicked()
+                    && let Some(folder) = FileDialog::new()
                         .set_directory(app.output_folder.as_path())
                         .pick_folder()
-                    {
-                        app.output_folder = folder;
-                    }
+                {
+                    app.output_folder = folder;
                 }
                 let open_button = ui.button("Open");
                 if open_button.clicked() {
@@ -371,9 +869,10 @@ fn core_options(app: &mut MyApp, ui: &mut egui::Ui) {
             });
             ui.end_row();
 
-            let folder_str = match app.input_path.as_ref() {
-                Some(folder) => folder.display().to_string(),
-                None => String::from("N/A"),
+            let folder_str = match (&app.archive_loader, app.input_path.as_ref()) {
+                (Some(loader), _) => format!("Opening {}...", loader.path.display()),
+                (None, Some(folder)) => folder.display().to_string(),
+                (None, None) => String::from("N/A"),
             };
 
             let input_label = ui.label("Input folder/file");
@@ -382,47 +881,54 @@ fn core_options(app: &mut MyApp, ui: &mut egui::Ui) {
 
             ui.horizontal(|ui| {
                 let folder_button = ui.button("Folder");
-                if folder_button.clicked() {
-                    if let Some(folder) = FileDialog::new().pick_folder() {
-                        match infuse_decoder::fs_util::find_infuse_iot_files(&folder) {
-                            Ok(files) => {
-                                app.device_id = *files.keys().next().unwrap_or(&0);
-                                app.output_prefix = format!("{:016x}", app.device_id);
-                                app.input_path = Some(folder);
-                                app.input_files = Some(files);
-                                // Reset the 'decode all' option when the folder changes
-                                app.decode_all_devices = false;
-                            }
-                            _ => {}
-                        }
-                    }
+                if folder_button.clicked()
+                    && let Some(folder) = FileDialog::new().pick_folder()
+                    && let Ok(files) =
+                        infuse_decoder::fs_util::find_input_folder_files(&folder, app.dedup)
+                {
+                    app.device_id = *files.keys().next().unwrap_or(&0);
+                    app.output_prefix = format!("{:016x}", app.device_id);
+                    app.set_archive(None);
+                    app.input_path = Some(folder);
+                    app.input_files = Some(files);
+                    // Reset the 'decode all' option when the folder changes
+                    app.decode_all_devices = false;
                 }
                 let file_button = ui.button("File");
-                if file_button.clicked() {
-                    if let Some(file) = FileDialog::new().pick_file() {
-                        let mut h = HashMap::new();
-                        h.insert(0, vec![file.clone()]);
-                        let prefix = match file.file_stem() {
-                            Some(name) => name.to_str().unwrap_or("0"),
-                            None => "0",
-                        };
-
-                        app.device_id = 0;
-                        app.decode_all_devices = false;
-                        app.output_prefix = prefix.to_string();
-                        app.input_path = Some(file);
-                        app.input_files = Some(h);
+                if file_button.clicked()
+                    && let Some(file) = FileDialog::new().pick_file()
+                {
+                    // Archives are listed like a folder once they are extracted, which can take a
+                    // while, so it's done on a background thread
+                    if infuse_decoder::archive::is_archive(&file) {
+                        app.open_archive(file);
+                        return;
                     }
+                    app.set_archive(None);
+                    let mut h = HashMap::new();
+                    h.insert(0, vec![file.clone()]);
+                    let prefix = match file.file_stem() {
+                        Some(name) => name.to_str().unwrap_or("0"),
+                        None => "0",
+                    };
+
+                    app.device_id = 0;
+                    app.decode_all_devices = false;
+                    app.output_prefix = prefix.to_string();
+                    app.input_path = Some(file);
+                    app.input_files = Some(h);
                 }
             });
             ui.end_row();
 
             // Clear the selected paths if they no longer exist (SD card removed)
-            if let Some(input) = &app.input_path {
-                if !app.is_doc_capture() && !input.exists() {
-                    app.input_path = None;
-                    app.input_files = None;
-                }
+            if let Some(input) = &app.input_path
+                && !app.is_doc_capture()
+                && !input.exists()
+            {
+                app.set_archive(None);
+                app.input_path = None;
+                app.input_files = None;
             }
 
             let device_label = ui.label("Device ID");
@@ -474,6 +980,41 @@ fn core_options(app: &mut MyApp, ui: &mut egui::Ui) {
                 example_prefix
             ));
             ui.end_row();
+
+            ui.label("Profile");
+            ui.add_enabled_ui(app.config_path.is_some(), |ui| {
+                ui.horizontal(|ui| {
+                    let mut load = None;
+                    egui::ComboBox::from_id_salt("Profile")
+                        .selected_text("Load")
+                        .show_ui(ui, |ui| {
+                            for name in app.config.profiles.keys() {
+                                if ui.selectable_label(false, name).clicked() {
+                                    load = Some(name.clone());
+                                }
+                            }
+                        });
+                    if let Some(name) = load {
+                        let profile = app.config.profiles[&name].clone();
+                        app.apply_profile(&profile);
+                        app.profile_name = name;
+                    }
+                    ui.add(
+                        egui::TextEdit::singleline(&mut app.profile_name)
+                            .hint_text("Profile name")
+                            .desired_width(140.0),
+                    );
+                });
+            });
+            let can_save = app.config_path.is_some() && !app.profile_name.trim().is_empty();
+            if ui
+                .add_enabled(can_save, egui::Button::new("Save"))
+                .on_hover_text("Save the current settings as this profile")
+                .clicked()
+            {
+                app.save_profile();
+            }
+            ui.end_row();
         });
 }
 
@@ -493,6 +1034,26 @@ fn decode_options(app: &mut MyApp, ui: &mut egui::Ui) {
             let file_output_control = ui.label("File Output Control");
             app.mark_doc("6", file_output_control.rect);
             ui.checkbox(&mut app.linearize_output_files, "Linearize Output");
+            ui.add_enabled_ui(app.linearize_output_files, |ui| {
+                // Rows appended by an incremental decode aren't sorted with the earlier rows
+                ui.add_enabled(
+                    !app.incremental,
+                    egui::Checkbox::new(&mut app.sort_by_time, "Sort by Time"),
+                );
+                ui.add_enabled(
+                    !app.sort_by_time,
+                    egui::Checkbox::new(&mut app.incremental, "Incremental"),
+                )
+                .on_hover_text("Only decode blocks added since the last incremental decode");
+            });
+            ui.checkbox(&mut app.session_id_column, "Session ID Column")
+                .on_hover_text("Add the boot session (device reboot count) to each row");
+            ui.checkbox(&mut app.dedup, "Remove Duplicates")
+                .on_hover_text("Drop blocks and samples that repeat earlier data in the input");
+            ui.checkbox(&mut app.sessions_output, "Sessions Output")
+                .on_hover_text("Also write the boot sessions of each device");
+            ui.checkbox(&mut app.battery_output, "Battery Output")
+                .on_hover_text("Also write the battery timeline of each device");
             ui.label("Max Readings Per File");
             ui.add_enabled_ui(app.linearize_output_files, |ui| {
                 ui.add(
@@ -509,17 +1070,181 @@ fn decode_options(app: &mut MyApp, ui: &mut egui::Ui) {
             ui.add_enabled_ui(app.output_format == OutputFormat::CSV, |ui| {
                 ui.radio_value(
                     &mut app.time_mode,
-                    TimeOutput::UTC,
+                    TimeOutput::Utc,
                     "UTC  (2020-01-01T00:00:00.000000Z)",
                 );
                 ui.radio_value(
                     &mut app.time_mode,
-                    TimeOutput::UNIX,
+                    TimeOutput::Unix,
                     "UNIX (1577800800.000000)",
                 );
             });
         });
         ui.separator();
+        ui.vertical(|ui| {
+            ui.label("CSV Format");
+            ui.add_enabled_ui(app.output_format == OutputFormat::CSV, |ui| {
+                egui::Grid::new("csv_format").num_columns(2).show(ui, |ui| {
+                    ui.label("Delimiter");
+                    egui::ComboBox::from_id_salt("CSV Delimiter")
+                        .selected_text(format!("{}", app.csv_delimiter))
+                        .show_ui(ui, |ui| {
+                            for delimiter in [
+                                CsvDelimiter::Comma,
+                                CsvDelimiter::Semicolon,
+                                CsvDelimiter::Tab,
+                            ] {
+                                ui.selectable_value(
+                                    &mut app.csv_delimiter,
+                                    delimiter,
+                                    format!("{delimiter}"),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Decimal");
+                    egui::ComboBox::from_id_salt("CSV Decimal Separator")
+                        .selected_text(format!("{}", app.csv_decimal_separator))
+                        .show_ui(ui, |ui| {
+                            for separator in [DecimalSeparator::Point, DecimalSeparator::Comma] {
+                                ui.selectable_value(
+                                    &mut app.csv_decimal_separator,
+                                    separator,
+                                    format!("{separator}"),
+                                );
+                            }
+                        });
+                    ui.end_row();
+
+                    let mut fixed_precision = app.csv_float_precision.is_some();
+                    let mut precision = app.csv_float_precision.unwrap_or(3);
+                    ui.checkbox(&mut fixed_precision, "Places");
+                    ui.add_enabled(
+                        fixed_precision,
+                        egui::DragValue::new(&mut precision).range(0..=9),
+                    );
+                    app.csv_float_precision = fixed_precision.then_some(precision);
+                    ui.end_row();
+
+                    ui.label("Arrays");
+                    egui::ComboBox::from_id_salt("CSV VLA Mode")
+                        .selected_text(format!("{}", app.csv_vla_mode))
+                        .show_ui(ui, |ui| {
+                            for mode in [VlaMode::Rows, VlaMode::Json, VlaMode::Wide] {
+                                ui.selectable_value(&mut app.csv_vla_mode, mode, format!("{mode}"));
+                            }
+                        });
+                    ui.end_row();
+
+                    ui.label("Max Items");
+                    ui.add_enabled(
+                        app.csv_vla_mode == VlaMode::Wide,
+                        egui::DragValue::new(&mut app.csv_vla_max_items).range(1..=64),
+                    );
+                    ui.end_row();
+                });
+                ui.checkbox(&mut app.csv_bom, "UTF-8 BOM");
+            });
+        });
+        ui.separator();
+        ui.vertical(|ui| {
+            ui.label("Time Range (UTC)");
+            egui::Grid::new("time_range").num_columns(2).show(ui, |ui| {
+                for (label, value) in [
+                    ("Since", &mut app.time_since),
+                    ("Until", &mut app.time_until),
+                ] {
+                    ui.label(label);
+                    let valid = value.trim().is_empty() || parse_utc_time(value).is_ok();
+                    let mut edit = egui::TextEdit::singleline(value)
+                        .hint_text("2025-01-01")
+                        .desired_width(140.0);
+                    if !valid {
+                        edit = edit.text_color(egui::Color32::RED);
+                    }
+                    ui.add(edit);
+                    ui.end_row();
+                }
+            });
+        });
+        ui.separator();
+        ui.vertical(|ui| {
+            ui.label("Data Selection");
+            let num_enabled = app.tdf_enabled.iter().filter(|(_, _, on)| *on).count();
+            if ui
+                .button(format!("TDFs ({num_enabled}/{})", app.tdf_enabled.len()))
+                .clicked()
+            {
+                app.show_tdf_selection = !app.show_tdf_selection;
+            }
+            for (label, remote_ids, hint) in [
+                ("Only Remote IDs", &mut app.only_remote_ids, "All"),
+                (
+                    "Skip Remote IDs",
+                    &mut app.skip_remote_ids,
+                    "0123456789abcdef, local",
+                ),
+            ] {
+                ui.label(label);
+                let valid = parse_remote_ids(remote_ids).is_ok();
+                let mut edit = egui::TextEdit::singleline(remote_ids)
+                    .hint_text(hint)
+                    .desired_width(140.0);
+                if !valid {
+                    edit = edit.text_color(egui::Color32::RED);
+                }
+                ui.add(edit);
+            }
+        });
+        ui.separator();
+        ui.vertical(|ui| {
+            ui.label("Continuity Report");
+            egui::Grid::new("continuity_options")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Gap Multiple");
+                    ui.add(
+                        egui::DragValue::new(&mut app.gap_multiple)
+                            .range(1.0..=1000.0)
+                            .speed(0.1),
+                    )
+                    .on_hover_text(
+                        "Report gaps longer than this multiple of a TDF's typical interval",
+                    );
+                    ui.end_row();
+                    ui.label("Empty Blocks");
+                    ui.add(
+                        egui::DragValue::new(&mut app.gap_min_empty_blocks).range(1..=usize::MAX),
+                    )
+                    .on_hover_text("Report runs of at least this many empty blocks");
+                    ui.end_row();
+                });
+        });
+        egui::Window::new("TDF Selection")
+            .open(&mut app.show_tdf_selection)
+            .resizable(true)
+            .show(ui.ctx(), |ui| {
+                ui.horizontal(|ui| {
+                    if ui.button("All").clicked() {
+                        app.tdf_enabled.iter_mut().for_each(|(_, _, on)| *on = true);
+                    }
+                    if ui.button("None").clicked() {
+                        app.tdf_enabled
+                            .iter_mut()
+                            .for_each(|(_, _, on)| *on = false);
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .max_height(400.0)
+                    .show(ui, |ui| {
+                        for (tdf_id, name, on) in app.tdf_enabled.iter_mut() {
+                            ui.checkbox(on, format!("{name} ({tdf_id})"));
+                        }
+                    });
+            });
+        ui.separator();
         ui.vertical(|ui| {
             let block_size_label = ui.label("Input Block Size");
             app.mark_doc("8", block_size_label.rect);
@@ -534,6 +1259,27 @@ fn decode_options(app: &mut MyApp, ui: &mut egui::Ui) {
     });
 }
 
+/// Parse a comma separated list of remote IDs
+fn parse_remote_ids(value: &str) -> Result<Vec<Option<u64>>, String> {
+    value
+        .split(',')
+        .filter(|id| !id.trim().is_empty())
+        .map(parse_remote_id)
+        .collect()
+}
+
+/// Format remote IDs as a comma separated list that [`parse_remote_ids`] accepts
+fn format_remote_ids(remote_ids: &[Option<u64>]) -> String {
+    remote_ids
+        .iter()
+        .map(|remote_id| match remote_id {
+            Some(id) => format!("{id:016x}"),
+            None => "local".to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 fn output_prefix_for_device(
     base_prefix: &str,
     selected_device_id: u64,
@@ -575,10 +1321,24 @@ fn merge_tdf_stats(
 }
 
 fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
+    ui.add_space(ui.available_width() - 100.0);
+    if app.runner_thread.is_some() {
+        let cancel_button = egui::Button::new("CANCEL")
+            .fill(egui::Color32::from_rgb(0xb0, 0x20, 0x20))
+            .min_size((100.0, ui.available_height()).into());
+        let response = ui
+            .add_enabled(!app.cancel.is_cancelled(), cancel_button)
+            .on_hover_text("Stop decoding and remove partial output files");
+        app.mark_doc("9", response.rect);
+        if response.clicked() {
+            app.cancel.cancel();
+        }
+        return;
+    }
+
     let start_button = egui::Button::new("DECODE")
         .fill(egui::Color32::from_rgb(0, 0x89, 0x47))
         .min_size((100.0, ui.available_height()).into());
-    ui.add_space(ui.available_width() - 100.0);
     let response = ui
         .add_enabled(
             app.runner_thread.is_none() && app.input_path.is_some(),
@@ -586,28 +1346,77 @@ fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
         )
         .on_hover_text("Decode");
     app.mark_doc("9", response.rect);
-    if response.clicked() {
+    if response.clicked() || (app.input_warnings_accepted && app.runner_thread.is_none()) {
         // Reset progress bars
-        app.progress_copy.reset();
         app.progress_devices.reset();
         app.progress_decode.reset();
         app.progress_merge.reset();
         app.block_stats = None;
+        app.filtered_samples = 0;
+        app.duplicate_blocks = 0;
+        app.duplicate_samples = 0;
         app.tdf_stats = None;
         app.output_files = None;
+        app.preview = None;
+        app.device_results = None;
+        app.expanded_device = None;
 
-        let input_path = app.input_path.as_ref().unwrap();
-        let device_jobs = if input_path.is_dir() {
-            let iot_bin_files: HashMap<u64, Vec<PathBuf>> =
-                infuse_decoder::fs_util::find_infuse_iot_files(input_path).unwrap();
+        let parse_bound = |value: &str| match value.trim() {
+            "" => Ok(None),
+            value => parse_utc_time(value).map(Some),
+        };
+        let (since, until) = match (parse_bound(&app.time_since), parse_bound(&app.time_until)) {
+            (Ok(since), Ok(until)) => (since, until),
+            (Err(err), _) | (_, Err(err)) => {
+                app.error_msg = Some(err);
+                return;
+            }
+        };
 
+        let remotes = parse_remote_ids(&app.only_remote_ids)
+            .and_then(|include| Ok((include, parse_remote_ids(&app.skip_remote_ids)?)));
+        let selection = match remotes {
+            Ok((include_remotes, exclude_remotes)) => TdfSelection {
+                exclude_tdfs: app
+                    .tdf_enabled
+                    .iter()
+                    .filter(|(_, _, on)| !on)
+                    .map(|(tdf_id, _, _)| *tdf_id)
+                    .collect(),
+                include_remotes,
+                exclude_remotes,
+                ..TdfSelection::default()
+            },
+            Err(err) => {
+                app.error_msg = Some(err);
+                return;
+            }
+        };
+
+        let input_path = app.input_path.as_ref().unwrap();
+        // Archives are decoded like a folder of the files extracted when they were opened
+        let listed_files = if input_path.is_dir() {
+            match infuse_decoder::fs_util::find_input_folder_files(input_path, app.dedup) {
+                Ok(files) => Some(files),
+                Err(err) => {
+                    app.error_msg = Some(format!(
+                        "Failed to scan input folder '{}': {err}",
+                        input_path.display()
+                    ));
+                    return;
+                }
+            }
+        } else {
+            app.archive.as_ref().map(|archive| archive.files().clone())
+        };
+        let device_jobs = if let Some(iot_bin_files) = listed_files {
             if iot_bin_files.is_empty() {
                 let input_folder = input_path.display().to_string();
                 app.runner_thread = Some(thread::spawn(move || {
-                    return std::result::Result::Err(std::io::Error::new(
+                    std::result::Result::Err(std::io::Error::new(
                         std::io::ErrorKind::NotFound,
                         format!("No valid files found in '{}'", input_folder),
-                    ));
+                    ))
                 }));
                 return;
             }
@@ -620,10 +1429,10 @@ fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
                     None => {
                         let device_id = app.device_id;
                         app.runner_thread = Some(thread::spawn(move || {
-                            return std::result::Result::Err(std::io::Error::new(
+                            std::result::Result::Err(std::io::Error::new(
                                 std::io::ErrorKind::NotFound,
                                 format!("No files found for device ID {device_id:016x}"),
-                            ));
+                            ))
                         }));
                         return;
                     }
@@ -634,6 +1443,38 @@ fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
         } else {
             vec![(app.device_id, vec![input_path.clone()])]
         };
+
+        let mapped_inputs = app
+            .archive
+            .as_ref()
+            .map(|archive| archive.mapped_inputs().clone())
+            .unwrap_or_default();
+
+        // Confirm before decoding input files that look incomplete, or that were recovered from a
+        // damaged disk image
+        if !app.input_warnings_accepted {
+            let warnings: Vec<String> = app
+                .archive
+                .iter()
+                .flat_map(|archive| archive.warnings().iter().cloned())
+                .chain(device_jobs.iter().flat_map(|(_, files)| {
+                    infuse_decoder::fs_util::input_file_warnings(
+                        files,
+                        &mapped_inputs,
+                        app.block_size as usize,
+                    )
+                }))
+                .collect();
+            if !warnings.is_empty() {
+                app.input_warnings = Some(warnings);
+                return;
+            }
+        }
+        app.input_warnings_accepted = false;
+        app.cancel = infuse_decoder::CancellationToken::new();
+        app.plot
+            .reset(device_jobs.first().map_or(0, |(device_id, _)| *device_id));
+
         let num_devices = device_jobs.len();
         infuse_decoder::ProgressReporter::start(
             &mut app.progress_devices,
@@ -646,6 +1487,7 @@ fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
                 device_id,
                 block_size: app.block_size as usize,
                 input_files,
+                mapped_inputs: mapped_inputs.clone(),
                 output_folder: app.output_folder.clone(),
                 output_prefix: output_prefix_for_device(
                     &app.output_prefix,
@@ -654,36 +1496,76 @@ fn start_button(app: &mut MyApp, ui: &mut egui::Ui) {
                     num_devices,
                     app.decode_all_devices,
                 ),
-                output_unix_time: app.time_mode == TimeOutput::UNIX,
+                output_unix_time: app.time_mode == TimeOutput::Unix,
                 output_format: app.output_format,
+                csv_format: tdf::csv_format::CsvFormat {
+                    delimiter: app.csv_delimiter.as_char(),
+                    decimal_separator: app.csv_decimal_separator.as_char(),
+                    float_precision: app.csv_float_precision,
+                    vla_mode: app.csv_vla_mode.csv_mode(app.csv_vla_max_items),
+                },
+                csv_utf8_bom: app.csv_bom,
                 merge_output_files: app.linearize_output_files,
+                incremental: app.linearize_output_files && app.incremental && !app.sort_by_time,
+                sort_by_time: app.sort_by_time,
+                since,
+                until,
+                selection: selection.clone(),
                 max_readings_per_output_file: app.max_readings_per_output_file,
-                copy_reporter: app.progress_copy.clone(),
+                session_id_column: app.session_id_column,
+                dedup: app.dedup,
+                gap_config: continuity::GapConfig {
+                    interval_multiple: app.gap_multiple,
+                    min_empty_blocks: app.gap_min_empty_blocks,
+                },
+                resample: None,
+                sessions_output: app.sessions_output,
+                battery_output: app.battery_output,
+                collect_batches: true,
+                cancel: app.cancel.clone(),
                 decode_reporter: app.progress_decode.clone(),
                 merge_reporter: app.progress_merge.clone(),
             })
             .collect::<Vec<_>>();
         let mut device_reporter = app.progress_devices.clone();
+        // Keeps the extracted files of an archive until the decode has finished with them
+        let archive = app.archive.clone();
 
         app.runner_thread = Some(thread::spawn(move || {
-            let mut combined_block_stats = HashMap::new();
-            let mut combined_tdf_stats = HashMap::new();
-            let mut combined_output_files = Vec::new();
+            let _archive = archive;
+            let mut results = Vec::new();
 
             for mut run_args in run_args {
-                let (block_stats, tdf_stats, mut output_files) =
-                    infuse_decoder::run(&mut run_args)?;
-                merge_block_stats(&mut combined_block_stats, block_stats);
-                merge_tdf_stats(&mut combined_tdf_stats, tdf_stats);
-                combined_output_files.append(&mut output_files);
+                let mut result = infuse_decoder::run(&mut run_args)?;
+                result.output_files.sort();
+                let output_files = result
+                    .output_files
+                    .into_iter()
+                    .map(|path| OutputFile {
+                        info: OutputFileInfo::with_rows(
+                            &path,
+                            result.output_rows.get(&path).copied(),
+                        )
+                        .map_err(|err| err.to_string()),
+                        path,
+                    })
+                    .collect();
+                results.push(DeviceResult {
+                    device_id: run_args.device_id,
+                    block_stats: result.block_stats,
+                    tdf_stats: result.tdf_stats,
+                    filtered_samples: result.filtered_samples,
+                    duplicate_blocks: result.duplicate_blocks,
+                    duplicate_samples: result.duplicate_samples,
+                    output_files,
+                    continuity: result.continuity,
+                    time_span: result.time_span,
+                    batches: result.batches,
+                });
                 infuse_decoder::ProgressReporter::increment(&mut device_reporter, 1);
             }
 
-            Ok::<_, io::Error>((
-                combined_block_stats,
-                combined_tdf_stats,
-                combined_output_files,
-            ))
+            Ok::<_, io::Error>(results)
         }));
     };
 }
@@ -708,7 +1590,7 @@ fn copyright_bar(ui: &mut egui::Ui) {
 
 fn hashmap_sort<T>(hashmap: HashMap<T, usize>) -> Vec<(T, usize)> {
     let mut sorted: Vec<(T, usize)> = hashmap.into_iter().collect();
-    sorted.sort_by(|a, b| b.1.cmp(&a.1));
+    sorted.sort_by_key(|b| std::cmp::Reverse(b.1));
     sorted
 }
 
@@ -763,7 +1645,472 @@ fn draw_tdf_table(ui: &mut egui::Ui, id: Option<u64>, tdfs: &HashMap<u16, usize>
         });
 }
 
+fn format_time_span(time_span: Option<(DateTime<Utc>, DateTime<Utc>)>) -> String {
+    match time_span {
+        Some((first, last)) => format!(
+            "{} to {}",
+            first.format("%Y-%m-%d %H:%M:%S"),
+            last.format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => "-".to_string(),
+    }
+}
+
+/// Summary row of each device, clicking a row shows the tables of that device.
+///
+/// With more than one device a final row combines all of them, which is shown by default.
+fn draw_device_table(ui: &mut egui::Ui, results: &[DeviceResult], expanded: &mut Option<u64>) {
+    let summary_row = |body: &mut egui_extras::TableBody,
+                       expanded: &mut Option<u64>,
+                       device_id: Option<u64>,
+                       results: &[&DeviceResult]| {
+        let num_blocks: usize = results.iter().map(|result| result.num_blocks()).sum();
+        let num_errors: usize = results.iter().map(|result| result.num_errors()).sum();
+        let num_samples: usize = results.iter().map(|result| result.num_samples()).sum();
+        let num_files: usize = results.iter().map(|result| result.output_files.len()).sum();
+        let time_span = results.iter().filter_map(|result| result.time_span).reduce(
+            |(first, last), (device_first, device_last)| {
+                (first.min(device_first), last.max(device_last))
+            },
+        );
+        let is_expanded = *expanded == device_id;
+
+        body.row(18.0, |mut row| {
+            row.col(|ui| {
+                let label = format!(
+                    "{} {}",
+                    if is_expanded { "⏷" } else { "⏵" },
+                    device_id.map_or("All devices".to_string(), |id| format!("{id:016x}"))
+                );
+                if ui.selectable_label(is_expanded, label).clicked() {
+                    *expanded = device_id;
+                }
+            });
+            row.col(|ui| {
+                ui.label(format!("{num_blocks}"));
+            });
+            row.col(|ui| {
+                let text = if num_blocks > 0 {
+                    format!(
+                        "{num_errors} ({:.1}%)",
+                        100.0 * num_errors as f64 / num_blocks as f64
+                    )
+                } else {
+                    format!("{num_errors}")
+                };
+                if num_errors > 0 {
+                    ui.colored_label(ui.visuals().warn_fg_color, text);
+                } else {
+                    ui.label(text);
+                }
+            });
+            row.col(|ui| {
+                ui.label(format_time_span(time_span));
+            });
+            row.col(|ui| {
+                ui.label(format!("{num_samples}"));
+            });
+            row.col(|ui| {
+                ui.label(format!("{num_files}"));
+            });
+        });
+    };
+
+    TableBuilder::new(ui)
+        .id_salt("devices")
+        .striped(true)
+        .max_scroll_height(150.0)
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::auto())
+        .column(Column::remainder())
+        .header(5.0, |mut header| {
+            for title in [
+                "Device",
+                "Blocks",
+                "Errors",
+                "Time Span",
+                "Samples",
+                "Output Files",
+            ] {
+                header.col(|ui| {
+                    ui.strong(title);
+                });
+            }
+        })
+        .body(|mut body| {
+            for result in results {
+                summary_row(&mut body, expanded, Some(result.device_id), &[result]);
+            }
+            if results.len() > 1 {
+                let all: Vec<&DeviceResult> = results.iter().collect();
+                summary_row(&mut body, expanded, None, &all);
+            }
+        });
+}
+
+fn format_event_time(time: Option<DateTime<Utc>>) -> String {
+    match time {
+        Some(time) => time.format("%Y-%m-%d %H:%M:%S%.3f").to_string(),
+        None => "-".to_string(),
+    }
+}
+
+fn draw_continuity_table(ui: &mut egui::Ui, results: &[DeviceResult]) {
+    let count = |kind| {
+        results
+            .iter()
+            .map(|result| result.continuity.count(kind))
+            .sum::<usize>()
+    };
+    let truncated = results.iter().any(|result| result.continuity.truncated);
+    let title = format!(
+        "Continuity: {} gaps, {} empty block runs, {} time regressions{}",
+        count(ContinuityEventKind::Gap),
+        count(ContinuityEventKind::EmptyBlocks),
+        count(ContinuityEventKind::Regression),
+        if truncated { " (truncated)" } else { "" }
+    );
+    let show_device = results.len() > 1;
+
+    egui::CollapsingHeader::new(title)
+        .id_salt("continuity")
+        .show(ui, |ui| {
+            let mut table = TableBuilder::new(ui)
+                .striped(true)
+                .max_scroll_height(200.0)
+                .column(Column::auto());
+            if show_device {
+                table = table.column(Column::auto());
+            }
+            table
+                .column(Column::auto())
+                .column(Column::auto())
+                .column(Column::auto())
+                .column(Column::remainder())
+                .header(5.0, |mut header| {
+                    if show_device {
+                        header.col(|ui| {
+                            ui.strong("Device");
+                        });
+                    }
+                    for title in ["Type", "TDF", "Start", "End", "Duration (s)"] {
+                        header.col(|ui| {
+                            ui.strong(title);
+                        });
+                    }
+                })
+                .body(|mut body| {
+                    for result in results {
+                        for event in result.continuity.events.iter() {
+                            body.row(5.0, |mut row| {
+                                if show_device {
+                                    row.col(|ui| {
+                                        ui.label(format!("{:016x}", result.device_id));
+                                    });
+                                }
+                                row.col(|ui| {
+                                    ui.label(event.kind.to_string());
+                                });
+                                row.col(|ui| {
+                                    ui.add(
+                                        egui::Label::new(event.tdf_label())
+                                            .wrap_mode(egui::TextWrapMode::Truncate),
+                                    );
+                                });
+                                row.col(|ui| {
+                                    ui.label(format_event_time(event.start_time()));
+                                });
+                                row.col(|ui| {
+                                    ui.label(format_event_time(event.end_time()));
+                                });
+                                row.col(|ui| {
+                                    ui.label(
+                                        event
+                                            .duration_seconds()
+                                            .map_or("-".to_string(), |d| format!("{d:.3}")),
+                                    );
+                                });
+                            });
+                        }
+                    }
+                });
+        });
+}
+
+fn plot_tdf_label((remote_id, tdf_id): (Option<u64>, u16)) -> String {
+    match remote_id {
+        Some(remote_id) => format!("{} ({remote_id:016x})", tdf::decoders::tdf_name(&tdf_id)),
+        None => tdf::decoders::tdf_name(&tdf_id),
+    }
+}
+
+fn format_plot_time(time: f64, step: f64) -> String {
+    let Some(time) = DateTime::<Utc>::from_timestamp_micros((time * 1e6).round() as i64) else {
+        return String::new();
+    };
+    let format = if step < 1.0 {
+        "%H:%M:%S%.3f"
+    } else if step < 60.0 {
+        "%H:%M:%S"
+    } else if step < 86400.0 {
+        "%m-%d %H:%M"
+    } else {
+        "%Y-%m-%d"
+    };
+    time.format(format).to_string()
+}
+
+fn plot_window(app: &mut MyApp, ui: &mut egui::Ui) {
+    let plot = &mut app.plot;
+    let results = app.device_results.as_deref().unwrap_or_default();
+    let mut open = plot.open;
+    egui::Window::new("Time Series")
+        .open(&mut open)
+        .resizable(true)
+        .default_size([800.0, 450.0])
+        .show(ui.ctx(), |ui| {
+            ui.horizontal_wrapped(|ui| {
+                if results.len() > 1 {
+                    egui::ComboBox::from_id_salt("Plot Device")
+                        .selected_text(format!("{:016x}", plot.device_id))
+                        .show_ui(ui, |ui| {
+                            for result in results {
+                                ui.selectable_value(
+                                    &mut plot.device_id,
+                                    result.device_id,
+                                    format!("{:016x}", result.device_id),
+                                );
+                            }
+                        });
+                }
+                let result = results
+                    .iter()
+                    .find(|result| result.device_id == plot.device_id);
+
+                let mut tdfs: Vec<(Option<u64>, u16)> = result
+                    .iter()
+                    .flat_map(|result| result.batches.keys().copied())
+                    .filter(|(_, tdf_id)| !plot_fields(*tdf_id).is_empty())
+                    .collect();
+                tdfs.sort();
+                let mut selected = plot.tdf;
+                egui::ComboBox::from_id_salt("Plot TDF")
+                    .selected_text(selected.map_or("TDF".to_string(), plot_tdf_label))
+                    .show_ui(ui, |ui| {
+                        for key in tdfs {
+                            ui.selectable_value(&mut selected, Some(key), plot_tdf_label(key));
+                        }
+                    });
+                if selected != plot.tdf
+                    && let Some(key) = selected
+                {
+                    plot.select_tdf(key);
+                }
+
+                for (name, on) in plot.fields.iter_mut() {
+                    ui.checkbox(on, name.as_str());
+                }
+                let can_plot = plot.tdf.is_some() && plot.fields.iter().any(|(_, on)| *on);
+                if ui
+                    .add_enabled(can_plot, egui::Button::new("Plot"))
+                    .clicked()
+                    && let Some(result) = result
+                {
+                    plot.load(&result.batches);
+                }
+            });
+            ui.separator();
+
+            if plot.series.is_empty() {
+                ui.label("Pick a TDF and the fields to plot");
+                return;
+            }
+            // Zoomed out views draw the peaks of runs of samples rather than every sample
+            let max_points = ui.available_width() as usize * 2;
+            let mut time_series = egui_plot::Plot::new("Time Series Plot")
+                .legend(egui_plot::Legend::default())
+                .x_axis_formatter(|mark, _| format_plot_time(mark.value, mark.step_size))
+                .label_formatter(|name, point| {
+                    format!("{name}\n{}\n{:.3}", format_plot_time(point.x, 0.0), point.y)
+                });
+            if std::mem::take(&mut plot.reset_view) {
+                time_series = time_series.reset();
+            }
+            time_series.show(ui, |plot_ui| {
+                let bounds = plot_ui.plot_bounds();
+                let (start, end) = if plot_ui.auto_bounds().x {
+                    (f64::NEG_INFINITY, f64::INFINITY)
+                } else {
+                    (bounds.min()[0], bounds.max()[0])
+                };
+                for series in &plot.series {
+                    plot_ui.line(egui_plot::Line::new(
+                        series.name.clone(),
+                        series.points(start, end, max_points),
+                    ));
+                }
+            });
+        });
+    plot.open = open;
+}
+
+/// What was done to an entry of the output file list
+enum FileAction {
+    Preview,
+    Open,
+    Reveal,
+}
+
+fn format_file_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["kB", "MB", "GB", "TB"];
+    if size < 1000 {
+        return format!("{size} B");
+    }
+    let mut value = size as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 1000.0 && unit + 1 < UNITS.len() {
+        value /= 1000.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", UNITS[unit])
+}
+
+/// Entry of the output file list: the file name, with its TDF, row count and size underneath
+fn draw_output_file(ui: &mut egui::Ui, file: &OutputFile, selected: bool) -> Option<FileAction> {
+    let details = match &file.info {
+        Ok(info) => {
+            let mut details = Vec::new();
+            if let Some(tdf_name) = &info.tdf_name {
+                details.push(tdf_name.clone());
+            }
+            if let Some(rows) = info.rows {
+                details.push(format!("{rows} rows"));
+            }
+            details.push(format_file_size(info.size));
+            egui::RichText::new(details.join(" · ")).weak()
+        }
+        Err(err) => egui::RichText::new(format!("Failed to read the file: {err}"))
+            .color(ui.visuals().error_fg_color),
+    };
+
+    let mut action = None;
+    let response = ui
+        .vertical(|ui| {
+            let response = ui.add(
+                egui::Button::selectable(selected, file.file_name())
+                    .truncate()
+                    .frame_when_inactive(false),
+            );
+            ui.add(egui::Label::new(details.small()).wrap_mode(egui::TextWrapMode::Truncate));
+            response
+        })
+        .inner
+        .on_hover_text(format!(
+            "{}\nClick to preview, double click to open, right click for more",
+            file.path.display()
+        ));
+    if response.clicked() {
+        action = Some(FileAction::Preview);
+    }
+    if response.double_clicked() {
+        action = Some(FileAction::Open);
+    }
+    response.context_menu(|ui| {
+        if ui.button("Preview").clicked() {
+            action = Some(FileAction::Preview);
+        }
+        if ui.button("Open").clicked() {
+            action = Some(FileAction::Open);
+        }
+        if ui.button("Show in Folder").clicked() {
+            action = Some(FileAction::Reveal);
+        }
+    });
+    action
+}
+
+/// First rows of the selected output file, read back from the file
+fn preview_window(app: &mut MyApp, ui: &mut egui::Ui) {
+    let Some(preview) = app.preview.as_mut() else {
+        return;
+    };
+    preview.poll();
+    if preview.loader.is_some() {
+        ui.request_repaint_after(core::time::Duration::from_millis(100));
+    }
+    let title = preview
+        .path
+        .file_name()
+        .map_or("Preview".to_string(), |name| {
+            name.to_string_lossy().into_owned()
+        });
+
+    egui::Window::new(title)
+        .id(egui::Id::new("Output Preview"))
+        .open(&mut preview.open)
+        .resizable(true)
+        .default_size([800.0, 400.0])
+        .show(ui.ctx(), |ui| match &preview.preview {
+            None => {
+                ui.horizontal(|ui| {
+                    ui.spinner();
+                    ui.label("Reading the file...");
+                });
+            }
+            Some(Err(err)) => {
+                ui.label(format!("Failed to read the file: {err}"));
+            }
+            Some(Ok(rows)) if rows.columns.is_empty() => {
+                ui.label("The file is empty");
+            }
+            Some(Ok(rows)) => {
+                ui.label(format!("First {} rows", rows.rows.len()));
+                egui::ScrollArea::horizontal().show(ui, |ui| {
+                    TableBuilder::new(ui)
+                        .id_salt("preview")
+                        .striped(true)
+                        .columns(Column::auto().resizable(true), rows.columns.len())
+                        .header(18.0, |mut header| {
+                            for column in &rows.columns {
+                                header.col(|ui| {
+                                    ui.strong(column);
+                                });
+                            }
+                        })
+                        .body(|body| {
+                            body.rows(18.0, rows.rows.len(), |mut row| {
+                                let values = &rows.rows[row.index()];
+                                for idx in 0..rows.columns.len() {
+                                    row.col(|ui| {
+                                        ui.label(values.get(idx).map_or("", String::as_str));
+                                    });
+                                }
+                            });
+                        });
+                });
+            }
+        });
+    if !preview.open {
+        app.preview = None;
+    }
+}
+
 fn gui_stats(app: &mut MyApp, ui: &mut egui::Ui) {
+    if let Some(results) = app.device_results.as_ref() {
+        egui::Panel::bottom("continuity_panel").show_inside(ui, |ui| {
+            draw_continuity_table(ui, results);
+        });
+        let mut expanded = app.expanded_device;
+        egui::Panel::top("devices_panel").show_inside(ui, |ui| {
+            draw_device_table(ui, results, &mut expanded);
+        });
+        if expanded != app.expanded_device {
+            app.expand_device(expanded);
+        }
+    }
     egui::CentralPanel::default().show_inside(ui, |ui| {
         ui.columns_const(|[col_blocks, col_tdfs, col_files]| {
             col_blocks.push_id(0, |ui| {
@@ -805,11 +2152,36 @@ fn gui_stats(app: &mut MyApp, ui: &mut egui::Ui) {
                             }
                         }
                     });
+
+                if app.filtered_samples > 0 {
+                    ui.separator();
+                    ui.label(format!(
+                        "{} samples outside the time range",
+                        app.filtered_samples
+                    ));
+                }
+                if app.duplicate_blocks > 0 || app.duplicate_samples > 0 {
+                    ui.separator();
+                    ui.label(format!(
+                        "{} duplicate blocks and {} duplicate samples removed",
+                        app.duplicate_blocks, app.duplicate_samples
+                    ));
+                }
             });
 
             col_tdfs.push_id(1, |ui| {
                 draw_right_edge(ui, 1.0, egui::Color32::GRAY);
 
+                if app.tdf_stats.is_some()
+                    && app.device_results.is_some()
+                    && ui
+                        .button("Plot")
+                        .on_hover_text("Plot decoded TDF fields over time")
+                        .clicked()
+                {
+                    app.plot.open = true;
+                }
+
                 if let Some(tdf_per_id) = app.tdf_stats.as_ref() {
                     // Show the loval TDFs first
                     if let Some(tdfs) = tdf_per_id.get(&None) {
@@ -826,8 +2198,15 @@ fn gui_stats(app: &mut MyApp, ui: &mut egui::Ui) {
 
             col_files.push_id(2, |ui| {
                 ui.heading("Output Files");
+                ui.add(
+                    egui::TextEdit::singleline(&mut app.output_filter)
+                        .hint_text("Filter by file or TDF name")
+                        .desired_width(f32::INFINITY),
+                );
 
                 let scroll_height = (ui.clip_rect().bottom() - ui.cursor().top()).max(0.0);
+                let mut selected = None;
+                let mut action_error = None;
                 egui::ScrollArea::vertical()
                     .id_salt("OutputFiles")
                     .auto_shrink([false, false])
@@ -835,37 +2214,85 @@ fn gui_stats(app: &mut MyApp, ui: &mut egui::Ui) {
                     .show(ui, |ui| {
                         ui.set_width(ui.available_width());
 
-                        if let Some(files) = app.output_files.as_ref() {
-                            for file in files {
-                                let name = file.file_name().unwrap().to_str().unwrap();
-                                ui.add(
-                                    egui::Label::new(name).wrap_mode(egui::TextWrapMode::Truncate),
-                                );
+                        let filter = app.output_filter.trim().to_lowercase();
+                        let preview_path = app.preview.as_ref().map(|preview| &preview.path);
+                        for file in app.output_files.iter().flatten() {
+                            let name = file.file_name();
+                            let tdf_name = file
+                                .info
+                                .as_ref()
+                                .ok()
+                                .and_then(|info| info.tdf_name.as_deref())
+                                .unwrap_or_default();
+                            if !name.to_lowercase().contains(&filter)
+                                && !tdf_name.to_lowercase().contains(&filter)
+                            {
+                                continue;
+                            }
+                            match draw_output_file(ui, file, preview_path == Some(&file.path)) {
+                                Some(FileAction::Preview) => selected = Some(file.path.clone()),
+                                Some(FileAction::Open) => {
+                                    action_error = open_in_default_app(&file.path).err()
+                                }
+                                Some(FileAction::Reveal) => {
+                                    action_error = reveal_in_folder(&file.path).err()
+                                }
+                                None => {}
                             }
                         }
                     });
+                if let Some(path) = selected {
+                    app.preview = Some(FilePreview::load(&path));
+                }
+                if let Some(err) = action_error {
+                    app.error_msg = Some(err);
+                }
             });
         });
     });
 }
 
 impl eframe::App for MyApp {
+    fn on_exit(&mut self) {
+        let profile = self.profile();
+        if let Err(err) = self.update_config(|config| config.last_used = Some(profile)) {
+            eprintln!("Failed to save settings: {err}");
+        }
+    }
+
     fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
         if let Some(doc_capture) = &mut self.doc_capture {
             doc_capture.reset_markers();
         }
 
+        self.poll_archive();
+        if self.archive_loader.is_some() {
+            ui.request_repaint_after(core::time::Duration::from_millis(100));
+        }
+
         // Check if executing work has completed
         if let Some(handle) = self.runner_thread.as_ref() {
             if handle.is_finished() {
-                let res = self.runner_thread.take().unwrap().join().unwrap();
+                let res = self
+                    .runner_thread
+                    .take()
+                    .unwrap()
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Decoding stopped unexpectedly")));
                 match res {
-                    Ok((block_stats, tdf_stats, output_files)) => {
-                        let mut files = output_files.clone();
-                        files.sort();
-                        self.block_stats = Some(hashmap_sort(block_stats));
-                        self.tdf_stats = Some(tdf_stats);
-                        self.output_files = Some(files);
+                    Ok(results) => {
+                        let single_device = match results.as_slice() {
+                            [result] => Some(result.device_id),
+                            _ => None,
+                        };
+                        self.device_results = Some(results);
+                        self.expand_device(single_device);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {
+                        // Cancelled by the user, partial outputs have already been removed
+                        self.progress_devices.reset();
+                        self.progress_decode.reset();
+                        self.progress_merge.reset();
                     }
                     Err(e) => {
                         self.error_msg = Some(if e.kind() == std::io::ErrorKind::NotFound {
@@ -880,6 +2307,29 @@ impl eframe::App for MyApp {
             ui.request_repaint_after(core::time::Duration::from_millis(100));
         }
 
+        if let Some(warnings) = &self.input_warnings.clone() {
+            egui::Window::new("Input Warnings")
+                .collapsible(false)
+                .resizable(false)
+                .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+                .show(ui.ctx(), |ui| {
+                    for warning in warnings {
+                        ui.label(warning);
+                    }
+                    ui.add_space(8.0);
+                    ui.horizontal(|ui| {
+                        if ui.button("Decode Anyway").clicked() {
+                            self.input_warnings = None;
+                            self.input_warnings_accepted = true;
+                            ui.ctx().request_repaint();
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.input_warnings = None;
+                        }
+                    });
+                });
+        }
+
         if let Some(msg) = &self.error_msg.clone() {
             egui::Window::new("Decoding Error")
                 .collapsible(false)
@@ -916,8 +2366,6 @@ impl eframe::App for MyApp {
             egui::Grid::new("progress_bars")
                 .num_columns(2)
                 .show(ui, |ui| {
-                    self.progress_copy.draw(ui);
-                    ui.end_row();
                     if self.decode_all_devices {
                         self.progress_devices.draw_count(ui);
                         ui.end_row();
@@ -930,6 +2378,8 @@ impl eframe::App for MyApp {
             ui.add_space(5.0);
         });
         gui_stats(self, ui);
+        plot_window(self, ui);
+        preview_window(self, ui);
         self.handle_doc_capture(ui.ctx());
     }
 }
@@ -962,7 +2412,7 @@ fn main() -> eframe::Result {
     eframe::run_native(
         "Infuse-IoT Data Decoder",
         options,
-        Box::new(|_cc| Ok(Box::new(MyApp::default()))),
+        Box::new(|_cc| Ok(Box::new(MyApp::with_saved_settings()))),
     )?;
     Ok(())
 }
@@ -0,0 +1,420 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+
+use crate::battery::BatteryTracker;
+use crate::continuity::ContinuityTracker;
+use crate::input::ConcatenatedInput;
+use crate::output_common::{MergedOutputs, derived_output_path, key_map};
+use crate::sessions::SessionTracker;
+use crate::summary::{self, InputFileSummary, TdfTotals, hash_file};
+use crate::{ProgressReporter, RunArgs, WorkerTrackers};
+
+/// Blocks read at a time while searching backwards for the last block with data
+const SCAN_CHUNK_BLOCKS: usize = 2048;
+
+/// Settings that change the contents of the outputs, which must match to append to them
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct ManifestOptions {
+    pub device_id: String,
+    pub block_size: usize,
+    pub output_format: String,
+    pub output_unix_time: bool,
+    pub csv_delimiter: char,
+    pub csv_decimal_separator: char,
+    pub csv_float_precision: Option<usize>,
+    pub csv_vla_mode: String,
+    pub csv_utf8_bom: bool,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    pub include_tdfs: Vec<u16>,
+    pub exclude_tdfs: Vec<u16>,
+    pub include_remotes: Vec<Option<u64>>,
+    pub exclude_remotes: Vec<Option<u64>>,
+    pub max_readings_per_output_file: usize,
+    pub session_id_column: bool,
+    pub dedup: bool,
+}
+
+impl ManifestOptions {
+    fn new<T: ProgressReporter>(args: &RunArgs<T>) -> Self {
+        Self {
+            device_id: format!("{:016x}", args.device_id),
+            block_size: args.block_size,
+            output_format: args.output_format.to_string(),
+            output_unix_time: args.output_unix_time,
+            csv_delimiter: args.csv_format.delimiter,
+            csv_decimal_separator: args.csv_format.decimal_separator,
+            csv_float_precision: args.csv_format.float_precision,
+            csv_vla_mode: format!("{:?}", args.csv_format.vla_mode),
+            csv_utf8_bom: args.csv_utf8_bom,
+            since: args.since.map(crate::summary::format_time),
+            until: args.until.map(crate::summary::format_time),
+            include_tdfs: args.selection.include_tdfs.clone(),
+            exclude_tdfs: args.selection.exclude_tdfs.clone(),
+            include_remotes: args.selection.include_remotes.clone(),
+            exclude_remotes: args.selection.exclude_remotes.clone(),
+            max_readings_per_output_file: args.max_readings_per_output_file,
+            session_id_column: args.session_id_column,
+            dedup: args.dedup,
+        }
+    }
+}
+
+/// Part of an input file that has been decoded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestInput {
+    /// File name, without the folder so that the input can be copied elsewhere
+    pub file: String,
+    /// Offset of the file in the concatenated input
+    pub offset: u64,
+    /// Bytes from the start of the file that have been decoded
+    pub decoded_bytes: u64,
+    /// SHA-256 hash of the decoded bytes
+    pub sha256: String,
+}
+
+/// Linearized files of an output
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ManifestOutput {
+    /// Hexadecimal ID of the device that forwarded the data, `None` for the logging device
+    pub remote_id: Option<String>,
+    pub tdf_id: u16,
+    /// File names in the output folder, in order
+    pub files: Vec<String>,
+}
+
+/// Statistics and report trackers of the decoded blocks, so that the next decode can continue them
+/// without reading the blocks again
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DecodeState {
+    /// Count of each block type, by [`summary::block_key`]
+    pub blocks: BTreeMap<String, usize>,
+    #[serde(with = "key_map")]
+    pub tdfs: HashMap<(Option<u64>, u16), TdfTotals>,
+    /// Selected samples dropped for being outside the time window
+    pub filtered_samples: usize,
+    /// Blocks dropped for repeating the contents of an earlier block
+    pub duplicate_blocks: usize,
+    pub continuity: ContinuityTracker,
+    pub sessions: SessionTracker,
+    pub battery: BatteryTracker,
+}
+
+impl DecodeState {
+    pub(crate) fn add_blocks(&mut self, block_stats: &HashMap<blocks::BlockTypes, usize>) {
+        for (block_type, count) in block_stats {
+            *self
+                .blocks
+                .entry(summary::block_key(*block_type).to_string())
+                .or_default() += count;
+        }
+    }
+
+    /// Add the trackers of the worker that decoded the following range of blocks
+    pub(crate) fn add_worker(&mut self, trackers: WorkerTrackers) {
+        self.filtered_samples += trackers.filtered;
+        self.duplicate_blocks += trackers.duplicate_blocks;
+        for (key, count) in trackers.duplicates {
+            self.tdfs.entry(key).or_default().duplicates += count;
+        }
+        self.continuity.append(trackers.continuity);
+        self.sessions.append(trackers.sessions);
+        self.battery.append(trackers.battery);
+    }
+
+    pub fn block_stats(&self) -> HashMap<blocks::BlockTypes, usize> {
+        self.blocks
+            .iter()
+            .filter_map(|(key, count)| Some((summary::block_type(key)?, *count)))
+            .collect()
+    }
+}
+
+/// Record of the input blocks already decoded into the outputs of a folder, so that a later decode
+/// of the same input after more data has been logged only has to decode the new blocks
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub decoder_version: String,
+    pub options: ManifestOptions,
+    /// Blocks from the start of the concatenated input that have been decoded
+    pub decoded_blocks: usize,
+    pub inputs: Vec<ManifestInput>,
+    pub outputs: Vec<ManifestOutput>,
+    pub state: DecodeState,
+}
+
+/// Hashes of the input files read while checking a manifest, so that they aren't read again
+#[derive(Default)]
+pub(crate) struct InputHashes {
+    /// SHA-256 hash of each whole file
+    pub(crate) files: HashMap<PathBuf, String>,
+    /// SHA-256 hash of the decoded part of the file that the decoded blocks end in
+    pub(crate) partial: Option<String>,
+}
+
+/// Where a decode starts
+pub(crate) enum Resume {
+    /// Append the blocks after `start_block` to the outputs of an earlier decode
+    Continue {
+        start_block: usize,
+        outputs: MergedOutputs,
+        state: Box<DecodeState>,
+        hashes: InputHashes,
+    },
+    /// Decode everything, with the reason the earlier outputs can't be appended to
+    Restart(String),
+}
+
+/// Path of the manifest for a decode with the given output prefix
+pub fn manifest_path(output_folder: &Path, output_prefix: &str) -> PathBuf {
+    derived_output_path(output_folder, output_prefix, "manifest", "json")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+impl Manifest {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file)).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid manifest '{}': {err}", path.display()),
+            )
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+
+    /// Check that the inputs and settings of `args` extend the decode recorded by this manifest.
+    ///
+    /// `partial` is the file that the blocks decoded by this decode end in and the length of its
+    /// decoded part, which is hashed along with the decoded files.
+    fn resume<T: ProgressReporter>(
+        self,
+        args: &RunArgs<T>,
+        partial: Option<(&Path, u64)>,
+    ) -> io::Result<Resume> {
+        if self.decoder_version != env!("CARGO_PKG_VERSION") {
+            return Ok(Resume::Restart(format!(
+                "Outputs were decoded by version {}",
+                self.decoder_version
+            )));
+        }
+        if self.options != ManifestOptions::new(args) {
+            return Ok(Resume::Restart("Decode settings have changed".to_string()));
+        }
+        if self.inputs.len() > args.input_files.len() {
+            return Ok(Resume::Restart(format!(
+                "{} input files were decoded, but only {} were provided",
+                self.inputs.len(),
+                args.input_files.len()
+            )));
+        }
+
+        let mut hashes = InputHashes::default();
+        let mut offset = 0;
+        for (idx, (input, path)) in self.inputs.iter().zip(&args.input_files).enumerate() {
+            let name = file_name(path);
+            if input.file != name {
+                return Ok(Resume::Restart(format!(
+                    "Input file {idx} is now {name} instead of {}",
+                    input.file
+                )));
+            }
+            let size = args.mapped_inputs.input_len(path)?;
+            let last = idx + 1 == self.inputs.len();
+            if input.offset != offset
+                || size < input.decoded_bytes
+                || (!last && size != input.decoded_bytes)
+            {
+                return Ok(Resume::Restart(format!(
+                    "{name} has changed size since it was decoded"
+                )));
+            }
+            let partial_bytes = partial
+                .filter(|(partial, _)| *partial == path.as_path())
+                .map(|(_, bytes)| bytes);
+            let prefixes: Vec<u64> = std::iter::once(input.decoded_bytes)
+                .chain(partial_bytes)
+                .collect();
+            let (_, sha256, prefix_hashes) =
+                hash_file(path, &args.mapped_inputs, &prefixes, &args.cancel)?;
+            if prefix_hashes[0].as_ref() != Some(&input.sha256) {
+                return Ok(Resume::Restart(format!(
+                    "{name} has changed since it was decoded"
+                )));
+            }
+            if let Some(partial) = prefix_hashes.get(1) {
+                hashes.partial = partial.clone();
+            }
+            hashes.files.insert(path.clone(), sha256);
+            offset += size;
+        }
+        let decoded_bytes = self
+            .inputs
+            .last()
+            .map_or(0, |input| input.offset + input.decoded_bytes);
+        if decoded_bytes != (self.decoded_blocks * args.block_size) as u64 {
+            return Ok(Resume::Restart(
+                "Manifest input ranges don't match its decoded blocks".to_string(),
+            ));
+        }
+
+        let mut outputs = HashMap::new();
+        for output in &self.outputs {
+            let remote_id = match &output.remote_id {
+                Some(id) => match u64::from_str_radix(id, 16) {
+                    Ok(id) => Some(id),
+                    Err(_) => {
+                        return Ok(Resume::Restart(format!(
+                            "Manifest has an invalid remote ID '{id}'"
+                        )));
+                    }
+                },
+                None => None,
+            };
+            let files: Vec<PathBuf> = output
+                .files
+                .iter()
+                .map(|file| args.output_folder.join(file))
+                .collect();
+            if let Some(missing) = files.iter().find(|file| !file.exists()) {
+                return Ok(Resume::Restart(format!(
+                    "Output {} is missing",
+                    missing.display()
+                )));
+            }
+            outputs.insert((remote_id, output.tdf_id), files);
+        }
+
+        Ok(Resume::Continue {
+            start_block: self.decoded_blocks,
+            outputs,
+            state: Box::new(self.state),
+            hashes,
+        })
+    }
+}
+
+/// Where the decode described by `args` can start, from the manifest in its output folder.
+///
+/// `partial` is passed on to [`Manifest::resume`].
+pub(crate) fn resume_point<T: ProgressReporter>(
+    args: &RunArgs<T>,
+    partial: Option<(&Path, u64)>,
+) -> io::Result<Resume> {
+    let path = manifest_path(&args.output_folder, &args.output_prefix);
+    match Manifest::read(&path) {
+        Ok(manifest) => manifest.resume(args, partial),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            Ok(Resume::Restart("No earlier decode".to_string()))
+        }
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            Ok(Resume::Restart(err.to_string()))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Index after the last block in `start_block..end_block` that isn't empty, or `start_block`.
+///
+/// Trailing empty blocks aren't recorded as decoded, as they may be written to later.
+pub(crate) fn end_of_data(
+    input: &ConcatenatedInput,
+    block_size: usize,
+    start_block: usize,
+    end_block: usize,
+) -> io::Result<usize> {
+    let mut chunk_end = end_block;
+    while chunk_end > start_block {
+        let chunk_start = chunk_end.saturating_sub(SCAN_CHUNK_BLOCKS).max(start_block);
+        let mut last_data = None;
+        let mut block_idx = chunk_start;
+        input.for_each_block(block_size, chunk_start, chunk_end - chunk_start, |block| {
+            if !blocks::is_empty_block(block) {
+                last_data = Some(block_idx);
+            }
+            block_idx += 1;
+            ControlFlow::Continue(())
+        })?;
+        if let Some(block_idx) = last_data {
+            return Ok(block_idx + 1);
+        }
+        chunk_end = chunk_start;
+    }
+    Ok(start_block)
+}
+
+/// Manifest for a decode of `args` that has decoded the first `decoded_blocks` of its input, with
+/// the statistics and trackers of those blocks in `state`.
+///
+/// The hashes of fully decoded files are taken from `input_summaries`, and the hash of the decoded
+/// part of the file that the decoded blocks end in from `partial_sha256`.
+pub(crate) fn build<T: ProgressReporter>(
+    args: &RunArgs<T>,
+    decoded_blocks: usize,
+    input_summaries: &[InputFileSummary],
+    partial_sha256: Option<&str>,
+    outputs: &MergedOutputs,
+    state: DecodeState,
+) -> io::Result<Manifest> {
+    let decoded_end = (decoded_blocks * args.block_size) as u64;
+
+    let mut inputs = Vec::new();
+    let mut offset = 0;
+    for summary in input_summaries {
+        if offset >= decoded_end {
+            break;
+        }
+        let decoded_bytes = summary.size.min(decoded_end - offset);
+        let sha256 = if decoded_bytes == summary.size {
+            summary.sha256.clone()
+        } else {
+            partial_sha256.map(str::to_string).ok_or_else(|| {
+                io::Error::other(format!(
+                    "Decoded part of '{}' wasn't hashed",
+                    summary.path.display()
+                ))
+            })?
+        };
+        inputs.push(ManifestInput {
+            file: file_name(&summary.path),
+            offset,
+            decoded_bytes,
+            sha256,
+        });
+        offset += summary.size;
+    }
+
+    let mut outputs: Vec<ManifestOutput> = outputs
+        .iter()
+        .map(|((remote_id, tdf_id), files)| ManifestOutput {
+            remote_id: remote_id.map(|id| format!("{id:016x}")),
+            tdf_id: *tdf_id,
+            files: files.iter().map(|file| file_name(file)).collect(),
+        })
+        .collect();
+    outputs.sort_by(|a, b| (&a.remote_id, a.tdf_id).cmp(&(&b.remote_id, b.tdf_id)));
+
+    Ok(Manifest {
+        decoder_version: env!("CARGO_PKG_VERSION").to_string(),
+        options: ManifestOptions::new(args),
+        decoded_blocks,
+        inputs,
+        outputs,
+        state,
+    })
+}
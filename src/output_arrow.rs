@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+
+use arrow_array::{RecordBatch, TimestampMicrosecondArray};
+use tdf::TdfOutput;
+use tdf::decoders_parquet::TdfParquetBatchBuilder;
+
+use crate::output_common::{OutputKey, increment_output_count, touch_output_count, written};
+use crate::output_parquet::{batch_row_time, gather_rows, row_meta, to_io_error};
+use crate::output_sort::{SortKey, SortKeys};
+use crate::sessions::SessionIdOutput;
+
+const DEFAULT_BATCH_ROWS: usize = 65536;
+
+struct TdfArrowOutput {
+    tdf_id: u16,
+    builder: TdfParquetBatchBuilder,
+    batches: Vec<RecordBatch>,
+}
+
+impl TdfArrowOutput {
+    fn new(tdf_id: u16, batch_rows: usize) -> io::Result<Self> {
+        Ok(Self {
+            tdf_id,
+            builder: new_builder(tdf_id, batch_rows)?,
+            batches: Vec::new(),
+        })
+    }
+
+    fn flush_batch(&mut self, batch_rows: usize) -> io::Result<()> {
+        if self.builder.rows() == 0 {
+            return Ok(());
+        }
+
+        self.batches
+            .push(self.builder.finish_batch().map_err(to_io_error)?);
+        self.builder = new_builder(self.tdf_id, batch_rows)?;
+        Ok(())
+    }
+}
+
+fn new_builder(tdf_id: u16, batch_rows: usize) -> io::Result<TdfParquetBatchBuilder> {
+    tdf::decoders_parquet::tdf_parquet_builder(tdf_id, batch_rows)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown TDF ID"))
+}
+
+/// TDF output that keeps decoded samples in memory as Arrow record batches
+pub struct TdfArrowWriter {
+    batch_rows: usize,
+    outputs: HashMap<OutputKey, TdfArrowOutput>,
+    output_cnt: HashMap<OutputKey, usize>,
+}
+
+impl TdfArrowWriter {
+    pub fn new() -> Self {
+        Self::new_with_batch_rows(DEFAULT_BATCH_ROWS)
+    }
+
+    pub fn new_with_batch_rows(batch_rows: usize) -> Self {
+        Self {
+            batch_rows: batch_rows.max(1),
+            outputs: HashMap::new(),
+            output_cnt: HashMap::new(),
+        }
+    }
+
+    /// Flush any partially filled batches and return all batches, in the order they were written
+    pub fn finish(mut self) -> io::Result<HashMap<OutputKey, Vec<RecordBatch>>> {
+        let mut batches = HashMap::new();
+        for (key, mut output) in self.outputs.drain() {
+            output.flush_batch(self.batch_rows)?;
+            batches.insert(key, output.batches);
+        }
+        Ok(batches)
+    }
+}
+
+/// Sort the batches of one output into chronological order.
+///
+/// Rows are keyed like the sorted merge, so rows without a timestamp stay with the sample before
+/// them and rows with equal times keep their block order.
+pub(crate) fn sort_by_time(batches: Vec<RecordBatch>) -> io::Result<Vec<RecordBatch>> {
+    let mut keys = SortKeys::new();
+    let mut rows: Vec<(SortKey, usize, usize)> = Vec::new();
+    for (idx, batch) in batches.iter().enumerate() {
+        let times = batch
+            .column_by_name("timestamp")
+            .and_then(|column| column.as_any().downcast_ref::<TimestampMicrosecondArray>());
+        for row in 0..batch.num_rows() {
+            rows.push((keys.key(batch_row_time(times, row)), idx, row));
+        }
+    }
+    if rows.is_sorted_by_key(|(key, _, _)| *key) {
+        return Ok(batches);
+    }
+
+    rows.sort_unstable_by_key(|(key, _, _)| *key);
+    let batch_refs: Vec<&RecordBatch> = batches.iter().collect();
+    rows.chunks(DEFAULT_BATCH_ROWS)
+        .map(|chunk| {
+            let indices: Vec<(usize, usize)> = chunk.iter().map(|(_, b, r)| (*b, *r)).collect();
+            gather_rows(&indices, &batch_refs)
+        })
+        .collect()
+}
+
+impl Default for TdfArrowWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionIdOutput for TdfArrowWriter {
+    fn set_session_id(&mut self, _session_id: Option<u32>) {}
+}
+
+impl TdfOutput for TdfArrowWriter {
+    fn output_path(&self, _remote_id: Option<u64>, _tdf_id: u16) -> Option<PathBuf> {
+        None
+    }
+
+    fn write(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        tdf_time: i64,
+        tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        if !tdf::decoders_parquet::tdf_parquet_has_schema(tdf_id) {
+            let mut buf = vec![0; size as usize];
+            cursor.read_exact(&mut buf)?;
+            return Ok(());
+        }
+
+        let output = match self.outputs.entry((remote_id, tdf_id)) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let output = TdfArrowOutput::new(tdf_id, self.batch_rows)?;
+                touch_output_count(&mut self.output_cnt, (remote_id, tdf_id));
+                entry.insert(output)
+            }
+        };
+
+        output
+            .builder
+            .append(row_meta(tdf_time, tdf_idx), size, cursor)?;
+
+        if output.builder.rows() >= self.batch_rows {
+            output.flush_batch(self.batch_rows)?;
+        }
+
+        increment_output_count(&mut self.output_cnt, (remote_id, tdf_id));
+
+        Ok(())
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        self.output_cnt.iter()
+    }
+
+    fn written(&self, remote_id: Option<u64>, tdf_id: u16) -> usize {
+        written(&self.output_cnt, (remote_id, tdf_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_BLOCKS: &[u8] = include_bytes!("../data/tdf_test_blocks.bin");
+
+    #[test]
+    fn batches_hold_every_written_sample() {
+        let mut writer = TdfArrowWriter::new_with_batch_rows(7);
+        for block in TEST_BLOCKS.chunks_exact(512) {
+            let _ = blocks::decode_block(&mut writer, block);
+        }
+        let counts: HashMap<OutputKey, usize> = writer
+            .iter_written()
+            .map(|(key, cnt)| (*key, *cnt))
+            .collect();
+
+        let batches = writer.finish().unwrap();
+
+        assert!(!batches.is_empty());
+        assert_eq!(batches.len(), counts.len());
+        for (key, key_batches) in batches.iter() {
+            let rows: usize = key_batches.iter().map(|batch| batch.num_rows()).sum();
+            assert_eq!(rows, counts[key]);
+            assert!(key_batches.iter().all(|batch| batch.num_rows() <= 7));
+        }
+    }
+
+    #[test]
+    fn parallel_decode_matches_sequential_decode() {
+        let (_, sequential) = crate::decode_to_arrow_with_workers(TEST_BLOCKS, 512, 1).unwrap();
+        let (block_stats, parallel) =
+            crate::decode_to_arrow_with_workers(TEST_BLOCKS, 512, 4).unwrap();
+
+        assert_eq!(block_stats.values().sum::<usize>(), TEST_BLOCKS.len() / 512);
+        assert_eq!(sequential.len(), parallel.len());
+        for (key, batches) in sequential.iter() {
+            let expected = rows_debug(batches);
+            let actual = rows_debug(&parallel[key]);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn untimed_and_equal_rows_keep_their_block_order() {
+        use arrow_array::{ArrayRef, Int32Array};
+        use std::sync::Arc;
+
+        let batch = |times: Vec<Option<i64>>, values: Vec<i32>| {
+            RecordBatch::try_from_iter([
+                (
+                    "timestamp",
+                    Arc::new(TimestampMicrosecondArray::from(times)) as ArrayRef,
+                ),
+                ("value", Arc::new(Int32Array::from(values)) as ArrayRef),
+            ])
+            .unwrap()
+        };
+        let batches = vec![
+            batch(vec![Some(30), None], vec![1, 2]),
+            batch(vec![Some(10), Some(10), None], vec![3, 4, 5]),
+        ];
+
+        let sorted = sort_by_time(batches).unwrap();
+        let values: Vec<i32> = sorted
+            .iter()
+            .flat_map(|batch| {
+                batch["value"]
+                    .as_any()
+                    .downcast_ref::<Int32Array>()
+                    .unwrap()
+                    .values()
+                    .to_vec()
+            })
+            .collect();
+        assert_eq!(values, vec![3, 4, 5, 1, 2]);
+    }
+
+    fn rows_debug(batches: &[RecordBatch]) -> Vec<String> {
+        batches
+            .iter()
+            .flat_map(|batch| {
+                (0..batch.num_rows()).map(move |row| format!("{:?}", batch.slice(row, 1)))
+            })
+            .collect()
+    }
+}
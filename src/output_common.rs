@@ -5,6 +5,9 @@ use std::path::{Path, PathBuf};
 
 pub(crate) type OutputKey = (Option<u64>, u16);
 
+/// Linearized output files of each output, in order
+pub(crate) type MergedOutputs = HashMap<OutputKey, Vec<PathBuf>>;
+
 pub(crate) fn worker_output_path(
     output_folder: &Path,
     output_prefix: &str,
@@ -56,6 +59,20 @@ pub(crate) fn merged_output_path(
     }
 }
 
+/// Path of an output derived from the whole decode, such as the boot sessions
+pub(crate) fn derived_output_path(
+    output_folder: &Path,
+    output_prefix: &str,
+    name: &str,
+    extension: &str,
+) -> PathBuf {
+    if output_prefix.is_empty() {
+        output_folder.join(format!("{name}.{extension}"))
+    } else {
+        output_folder.join(format!("{output_prefix}_{name}.{extension}"))
+    }
+}
+
 pub(crate) fn rename_first_file_if_splitting(
     part_idx: usize,
     output_files: &mut [PathBuf],
@@ -93,3 +110,26 @@ pub(crate) fn increment_output_count(output_cnt: &mut HashMap<OutputKey, usize>,
 pub(crate) fn written(output_cnt: &HashMap<OutputKey, usize>, key: OutputKey) -> usize {
     output_cnt.get(&key).copied().unwrap_or_default()
 }
+
+/// Serializes a map keyed by output as a list of `(key, value)` pairs, as JSON objects can only
+/// have string keys. Used with `#[serde(with = "key_map")]`.
+pub(crate) mod key_map {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+
+    use super::OutputKey;
+
+    pub(crate) fn serialize<S: Serializer, V: Serialize>(
+        map: &HashMap<OutputKey, V>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(map.iter())
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>, V: Deserialize<'de>>(
+        deserializer: D,
+    ) -> Result<HashMap<OutputKey, V>, D::Error> {
+        let pairs = Vec::<(OutputKey, V)>::deserialize(deserializer)?;
+        Ok(pairs.into_iter().collect())
+    }
+}
@@ -1,49 +1,90 @@
+use arrow_array::cast::AsArray;
+use arrow_array::types::{Float64Type, TimestampMicrosecondType};
+use arrow_array::{Array, Float64Array};
 use chrono::SecondsFormat;
 use itertools::Itertools;
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::fs::{self, File};
 use std::io::Cursor;
-use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::io::{self, BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+
 use tdf::TdfOutput;
+use tdf::csv_format::{CsvFormat, CsvRow};
 
+use crate::battery::BatteryRow;
 use crate::output_common::{
-    OutputKey, increment_output_count, merged_output_path, rename_first_file_if_splitting,
-    touch_output_count, worker_output_path, written,
+    MergedOutputs, OutputKey, increment_output_count, merged_output_path,
+    rename_first_file_if_splitting, touch_output_count, worker_output_path, written,
 };
-use crate::{ProgressReporter, RunArgs, TdfDecoderOutputs};
+use crate::output_sort::{SORT_RUN_ROWS, SortKey, SortKeys, SortRuns, SortedSource, merge_sorted};
+use crate::resample::Resampler;
+use crate::sessions::{Session, SessionIdOutput};
+use crate::{CancellationToken, ProgressReporter, RunArgs, TdfWorkerStats};
 
 pub struct TdfCsvWriter {
     decoder_idx: usize,
     output_folder: std::path::PathBuf,
     output_prefix: String,
     output_unix: bool,
+    format: CsvFormat,
+    utf8_bom: bool,
+    /// Add a `session_id` column after the time
+    session_ids: bool,
+    session_id: Option<u32>,
     pub outputs:
         HashMap<(Option<u64>, u16), (std::path::PathBuf, std::io::BufWriter<std::fs::File>)>,
     output_cnt: HashMap<OutputKey, usize>,
 }
+const UTF8_BOM: &str = "\u{feff}";
+
 impl TdfCsvWriter {
     pub fn new(
         decoder_idx: usize,
         output_folder: std::path::PathBuf,
         output_prefix: String,
         output_unix_time: bool,
+        format: CsvFormat,
+        utf8_bom: bool,
     ) -> Self {
         Self {
-            decoder_idx: decoder_idx,
-            output_folder: output_folder,
+            decoder_idx,
+            output_folder,
             output_prefix,
             output_unix: output_unix_time,
+            format,
+            utf8_bom,
+            session_ids: false,
+            session_id: None,
             outputs: HashMap::new(),
             output_cnt: HashMap::new(),
         }
     }
+
+    /// Tag each row with the boot session it was logged in
+    pub fn with_session_ids(mut self, enabled: bool) -> Self {
+        self.session_ids = enabled;
+        self
+    }
+
+    /// Flush all output files, reporting any write errors
+    pub fn finish(&mut self) -> io::Result<()> {
+        for (_, writer) in self.outputs.values_mut() {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+impl SessionIdOutput for TdfCsvWriter {
+    fn set_session_id(&mut self, session_id: Option<u32>) {
+        self.session_id = session_id;
+    }
 }
 
 impl TdfOutput for TdfCsvWriter {
-    fn output_path(self: &Self, remote_id: Option<u64>, tdf_id: u16) -> Option<PathBuf> {
+    fn output_path(&self, remote_id: Option<u64>, tdf_id: u16) -> Option<PathBuf> {
         self.outputs
             .get(&(remote_id, tdf_id))
             .map(|(pathbuf, _)| pathbuf.clone())
@@ -73,8 +114,18 @@ impl TdfOutput for TdfCsvWriter {
                 let mut writer = std::io::BufWriter::new(std::fs::File::create(path.clone())?);
 
                 // Write header into file
-                let heading = tdf::decoders_csv::tdf_fields(&tdf_id).join(",");
-                writer.write_all(format!("time,{}\n", heading).as_bytes())?;
+                if self.utf8_bom {
+                    writer.write_all(UTF8_BOM.as_bytes())?;
+                }
+                let mut heading = vec![String::from("time")];
+                if self.session_ids {
+                    heading.push(String::from("session_id"));
+                }
+                heading.extend(tdf::decoders_csv::tdf_csv_fields(
+                    &tdf_id,
+                    self.format.vla_mode,
+                ));
+                writer.write_all(format!("{}\n", self.format.join(&heading)).as_bytes())?;
 
                 // Touch the count variable in case the decoding fails
                 touch_output_count(&mut self.output_cnt, (remote_id, tdf_id));
@@ -85,26 +136,26 @@ impl TdfOutput for TdfCsvWriter {
         };
 
         // Construct CSV line
-        let reading = tdf::decoders_csv::tdf_read_into_str(&tdf_id, size, cursor)?;
+        let mut reading =
+            tdf::decoders_csv::tdf_read_into_str(&tdf_id, size, cursor, &self.format)?;
         let time = match tdf_idx {
             Some(idx) => {
                 // Use the index directly if provided
                 format!("{idx}")
             }
-            None => match self.output_unix {
-                // Otherwise, format the time to a string
-                true => {
-                    let (unix_seconds, unix_nano) = tdf::time::tdf_time_to_unix(tdf_time);
-                    format!("{}.{:06}", unix_seconds, unix_nano / 1000)
-                }
-                false => {
-                    let datetime = tdf::time::tdf_time_to_datetime(tdf_time).expect("Invalid time");
-                    datetime.to_rfc3339_opts(SecondsFormat::Micros, true)
-                }
-            },
+            None => format_time(tdf_time, self.output_unix, &self.format),
         };
+        if self.session_ids {
+            let session_id = self.session_id.map(|id| id.to_string()).unwrap_or_default();
+            // Continuation lines of variable length arrays also need the extra column
+            let delimiter = self.format.delimiter;
+            reading = format!(
+                "{session_id}{delimiter}{}",
+                reading.replace('\n', &format!("\n{delimiter}"))
+            );
+        }
 
-        let line: String = format!("{},{}\n", time, reading);
+        let line: String = format!("{}{}{}\n", time, self.format.delimiter, reading);
 
         // Write line to output
         writer.write_all(line.as_bytes())?;
@@ -123,13 +174,205 @@ impl TdfOutput for TdfCsvWriter {
     }
 }
 
+/// Written in place of times that can't be represented as RFC3339. The time cell isn't left empty,
+/// as lines starting with the delimiter continue the array of the reading before them.
+const INVALID_TIME: &str = "invalid";
+
+/// Format a TDF time as an RFC3339 string or Unix timestamp
+fn format_time(tdf_time: i64, output_unix: bool, format: &CsvFormat) -> String {
+    match output_unix {
+        true => {
+            let (unix_seconds, unix_nano) = tdf::time::tdf_time_to_unix(tdf_time);
+            format.decimal(format!("{}.{:06}", unix_seconds, unix_nano / 1000))
+        }
+        false => tdf::time::tdf_time_to_datetime(tdf_time)
+            .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Micros, true))
+            .unwrap_or_else(|| INVALID_TIME.to_string()),
+    }
+}
+
+/// Write the boot sessions as a CSV file
+pub fn write_sessions(
+    path: &Path,
+    sessions: &[Session],
+    format: &CsvFormat,
+    output_unix: bool,
+    utf8_bom: bool,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if utf8_bom {
+        writer.write_all(UTF8_BOM.as_bytes())?;
+    }
+    let heading = [
+        "session_id",
+        "start",
+        "end",
+        "application",
+        "firmware_version",
+        "reset_reason",
+        "reset_uptime",
+        "reset_hardware_flags",
+        "reset_param_1",
+        "reset_param_2",
+        "reset_thread",
+    ];
+    writeln!(writer, "{}", format.join(&heading))?;
+
+    for session in sessions {
+        let mut row = CsvRow::new(format);
+        let time = |time: Option<i64>| {
+            time.map(|time| format_time(time, output_unix, format))
+                .unwrap_or_default()
+        };
+        match session.session_id {
+            Some(id) => row.display(id),
+            None => row.empty(),
+        }
+        row.text(&time(session.start));
+        row.text(&time(session.end));
+        match session.firmware {
+            Some(firmware) => {
+                row.hex(firmware.application, 8);
+                row.text(&firmware.to_string());
+            }
+            None => {
+                row.empty();
+                row.empty();
+            }
+        }
+        match &session.reset {
+            Some(reset) => {
+                row.display(reset.reason);
+                row.display(reset.uptime);
+                row.hex(reset.hardware_flags, 8);
+                row.hex(reset.param_1, 8);
+                row.hex(reset.param_2, 8);
+                row.quoted(&reset.thread);
+            }
+            None => (0..6).for_each(|_| row.empty()),
+        }
+        writeln!(writer, "{}", row.finish())?;
+    }
+    writer.flush()
+}
+
+/// Write the merged battery timeline as a CSV file
+pub fn write_battery(
+    path: &Path,
+    rows: &[BatteryRow],
+    format: &CsvFormat,
+    output_unix: bool,
+    utf8_bom: bool,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if utf8_bom {
+        writer.write_all(UTF8_BOM.as_bytes())?;
+    }
+    let heading = [
+        "time",
+        "voltage_mv",
+        "current_ua",
+        "soc",
+        "charge",
+        "state",
+        "cycle",
+        "abnormal_drop",
+    ];
+    writeln!(writer, "{}", format.join(&heading))?;
+
+    for battery in rows {
+        let mut row = CsvRow::new(format);
+        let point = &battery.point;
+        row.text(&format_time(point.time, output_unix, format));
+        optional(&mut row, point.voltage_mv);
+        optional(&mut row, point.current_ua);
+        optional(&mut row, point.soc);
+        optional(&mut row, point.charge);
+        optional(&mut row, battery.state);
+        optional(&mut row, battery.cycle);
+        row.display(battery.abnormal_drop);
+        writeln!(writer, "{}", row.finish())?;
+    }
+    writer.flush()
+}
+
+fn optional(row: &mut CsvRow, value: Option<impl std::fmt::Display>) {
+    match value {
+        Some(value) => row.display(value),
+        None => row.empty(),
+    }
+}
+
+/// Write the streams of a resampled output as a CSV file
+pub fn write_resampled(
+    path: &Path,
+    resampler: Resampler,
+    format: &CsvFormat,
+    output_unix: bool,
+    utf8_bom: bool,
+) -> io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    if utf8_bom {
+        writer.write_all(UTF8_BOM.as_bytes())?;
+    }
+    let schema = resampler.schema();
+    // The time column is named as in the other CSV outputs
+    let heading: Vec<&str> = std::iter::once("time")
+        .chain(
+            schema.fields()[1..]
+                .iter()
+                .map(|field| field.name().as_str()),
+        )
+        .collect();
+    writeln!(writer, "{}", format.join(&heading))?;
+
+    for batch in resampler {
+        let batch = batch?;
+        let times = batch.column(0).as_primitive::<TimestampMicrosecondType>();
+        let values: Vec<&Float64Array> = batch.columns()[1..]
+            .iter()
+            .map(|column| column.as_primitive::<Float64Type>())
+            .collect();
+
+        for row_idx in 0..batch.num_rows() {
+            let mut row = CsvRow::new(format);
+            let time = times.value(row_idx);
+            match output_unix {
+                true => row.text(&format.decimal(format!(
+                    "{}.{:06}",
+                    time.div_euclid(1_000_000),
+                    time.rem_euclid(1_000_000)
+                ))),
+                false => row.text(
+                    &chrono::DateTime::from_timestamp_micros(time)
+                        .map(|datetime| datetime.to_rfc3339_opts(SecondsFormat::Micros, true))
+                        .unwrap_or_default(),
+                ),
+            }
+            for column in values.iter() {
+                match column.is_valid(row_idx) {
+                    true => row.float(column.value(row_idx)),
+                    false => row.empty(),
+                }
+            }
+            writeln!(writer, "{}", row.finish())?;
+        }
+    }
+    writer.flush()
+}
+
 struct TdfCsvMergedOutput {
     output_folder: PathBuf,
     output_prefix: String,
     remote_id: Option<u64>,
     tdf_id: u16,
     max_readings_per_file: Option<usize>,
+    utf8_bom: bool,
+    /// Field delimiter, which starts the continuation lines of variable length arrays
+    delimiter: char,
     output_files: Vec<PathBuf>,
+    /// Readings in each of `output_files`, `None` until the file is finished
+    file_readings: Vec<Option<usize>>,
     writer: Option<BufWriter<File>>,
     header: Option<String>,
     readings_in_file: usize,
@@ -143,6 +386,8 @@ impl TdfCsvMergedOutput {
         remote_id: Option<u64>,
         tdf_id: u16,
         max_readings_per_file: usize,
+        utf8_bom: bool,
+        delimiter: char,
     ) -> Self {
         Self {
             output_folder,
@@ -153,7 +398,10 @@ impl TdfCsvMergedOutput {
                 0 => None,
                 value => Some(value),
             },
+            utf8_bom,
+            delimiter,
             output_files: Vec::new(),
+            file_readings: Vec::new(),
             writer: None,
             header: None,
             readings_in_file: 0,
@@ -161,6 +409,29 @@ impl TdfCsvMergedOutput {
         }
     }
 
+    /// Continue appending to the output files of an earlier decode
+    fn resume(&mut self, files: Vec<PathBuf>) -> io::Result<()> {
+        let Some(last) = files.last() else {
+            return Ok(());
+        };
+        let mut lines = BufReader::new(File::open(last)?).lines();
+        let header = lines.next().transpose()?.unwrap_or_default();
+        self.header = Some(header.trim_start_matches(UTF8_BOM).to_string());
+        self.readings_in_file = 0;
+        for line in lines {
+            if !self.is_continuation(&line?) {
+                self.readings_in_file += 1;
+            }
+        }
+
+        let file = fs::OpenOptions::new().append(true).open(last)?;
+        self.writer = Some(BufWriter::new(file));
+        self.part_idx = files.len();
+        self.file_readings = vec![None; files.len()];
+        self.output_files = files;
+        Ok(())
+    }
+
     fn set_header(&mut self, header: String) -> io::Result<()> {
         if self.header.is_none() {
             self.header = Some(header);
@@ -169,14 +440,22 @@ impl TdfCsvMergedOutput {
         Ok(())
     }
 
+    /// Whether a line continues the variable length array of the reading before it
+    fn is_continuation(&self, line: &str) -> bool {
+        line.starts_with(self.delimiter)
+    }
+
     fn append_line(&mut self, line: &str) -> io::Result<()> {
         if self.writer.is_none() {
             self.start_next_file()?;
         }
 
-        if self
-            .max_readings_per_file
-            .is_some_and(|max_readings| self.readings_in_file >= max_readings)
+        // Continuation lines stay in the file of their reading and aren't counted as readings
+        let continuation = self.is_continuation(line);
+        if !continuation
+            && self
+                .max_readings_per_file
+                .is_some_and(|max_readings| self.readings_in_file >= max_readings)
         {
             self.start_next_file()?;
         }
@@ -189,7 +468,9 @@ impl TdfCsvMergedOutput {
             .as_mut()
             .expect("CSV writer should be open")
             .write_all(b"\n")?;
-        self.readings_in_file += 1;
+        if !continuation {
+            self.readings_in_file += 1;
+        }
         Ok(())
     }
 
@@ -216,6 +497,9 @@ impl TdfCsvMergedOutput {
         })?;
         let mut writer = BufWriter::new(file);
 
+        if self.utf8_bom {
+            writer.write_all(UTF8_BOM.as_bytes())?;
+        }
         if let Some(header) = &self.header {
             writer.write_all(header.as_bytes())?;
             writer.write_all(b"\n")?;
@@ -225,6 +509,7 @@ impl TdfCsvMergedOutput {
         }
 
         self.output_files.push(path);
+        self.file_readings.push(None);
         self.writer = Some(writer);
         self.part_idx += 1;
         Ok(())
@@ -245,6 +530,9 @@ impl TdfCsvMergedOutput {
     fn finish_current_file(&mut self) -> io::Result<()> {
         if let Some(mut writer) = self.writer.take() {
             writer.flush()?;
+            if let Some(readings) = self.file_readings.last_mut() {
+                *readings = Some(self.readings_in_file);
+            }
         }
         Ok(())
     }
@@ -278,12 +566,187 @@ impl TdfCsvMergedOutput {
             "csv",
         )
     }
+
+    fn sort_run_path(&self, run_idx: usize) -> PathBuf {
+        merged_output_path(
+            &self.output_folder,
+            &self.output_prefix,
+            self.remote_id,
+            self.tdf_id,
+            None,
+            &format!("sort{run_idx:05}.csv"),
+        )
+    }
+}
+
+/// Parse the timestamp at the start of a CSV line, `None` for sample indices
+fn csv_line_time(line: &str, format: &CsvFormat, output_unix: bool) -> Option<i64> {
+    let field = line.split(format.delimiter).next()?;
+    if output_unix {
+        let (seconds, micros) = field.split_once(format.decimal_separator)?;
+        Some(seconds.parse::<i64>().ok()? * 1_000_000 + micros.parse::<i64>().ok()?)
+    } else {
+        chrono::DateTime::parse_from_rfc3339(field)
+            .ok()
+            .map(|datetime| datetime.timestamp_micros())
+    }
+}
+
+/// Sort the buffered records and write them to a temporary run file
+fn write_csv_run(path: PathBuf, records: &mut Vec<(SortKey, String)>) -> io::Result<PathBuf> {
+    records.sort_unstable_by_key(|(key, _)| *key);
+
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for ((time, seq), record) in records.drain(..) {
+        let num_lines = record.split('\n').count();
+        writeln!(writer, "{time} {seq} {num_lines}")?;
+        writeln!(writer, "{record}")?;
+    }
+    writer.flush()?;
+    Ok(path)
+}
+
+/// Reads records back from a run file created by `write_csv_run`
+struct CsvRunReader {
+    lines: Lines<BufReader<File>>,
+}
+
+impl CsvRunReader {
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            lines: BufReader::new(File::open(path)?).lines(),
+        })
+    }
+
+    fn read_record(&mut self, header: &str) -> io::Result<(SortKey, String)> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid CSV sort run");
+        let mut parts = header.split(' ').map(|part| part.parse::<i64>());
+        let (Some(Ok(time)), Some(Ok(seq)), Some(Ok(num_lines))) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        let mut record = String::new();
+        for idx in 0..num_lines {
+            if idx != 0 {
+                record.push('\n');
+            }
+            record.push_str(&self.lines.next().ok_or_else(invalid)??);
+        }
+        Ok(((time, seq as u64), record))
+    }
+}
+
+impl Iterator for CsvRunReader {
+    type Item = io::Result<(SortKey, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.lines.next()? {
+            Ok(header) => Some(self.read_record(&header)),
+            Err(err) => Some(Err(err)),
+        }
+    }
 }
 
+/// Merge worker outputs in worker order, which is the order of the input blocks
+fn merge_in_order<F: FnMut()>(
+    output: &mut TdfCsvMergedOutput,
+    inputs: &[PathBuf],
+    cancel: &CancellationToken,
+    mut on_input: F,
+) -> io::Result<()> {
+    for input_path in inputs {
+        let input = BufReader::new(File::open(input_path)?);
+
+        for (idx, line) in input.lines().enumerate() {
+            cancel.check()?;
+            let line = line?;
+            if idx == 0 {
+                output.set_header(line.trim_start_matches(UTF8_BOM).to_string())?;
+                continue;
+            }
+            output.append_line(&line)?;
+        }
+
+        fs::remove_file(input_path)?;
+        on_input();
+    }
+    Ok(())
+}
+
+/// Merge worker outputs into chronological order, spilling sorted runs to disk to bound memory use
+fn merge_sorted_by_time<F: FnMut()>(
+    output: &mut TdfCsvMergedOutput,
+    inputs: &[PathBuf],
+    format: &CsvFormat,
+    output_unix: bool,
+    cancel: &CancellationToken,
+    mut on_input: F,
+) -> io::Result<()> {
+    let mut keys = SortKeys::new();
+    let mut runs = SortRuns::new();
+    let mut records: Vec<(SortKey, String)> = Vec::new();
+
+    for input_path in inputs {
+        let input = BufReader::new(File::open(input_path)?);
+
+        for (idx, line) in input.lines().enumerate() {
+            cancel.check()?;
+            let line = line?;
+            if idx == 0 {
+                output.set_header(line.trim_start_matches(UTF8_BOM).to_string())?;
+                continue;
+            }
+            // Continuation lines of trailing variable length arrays stay with their sample
+            if line.starts_with(format.delimiter)
+                && let Some((_, record)) = records.last_mut()
+            {
+                record.push('\n');
+                record.push_str(&line);
+                continue;
+            }
+            if records.len() >= SORT_RUN_ROWS {
+                runs.push(write_csv_run(
+                    output.sort_run_path(runs.len()),
+                    &mut records,
+                )?);
+            }
+            let key = keys.key(csv_line_time(&line, format, output_unix));
+            records.push((key, line));
+        }
+
+        fs::remove_file(input_path)?;
+        on_input();
+    }
+
+    records.sort_unstable_by_key(|(key, _)| *key);
+    let mut sources: Vec<SortedSource<String>> = Vec::new();
+    for run in runs.iter() {
+        sources.push(Box::new(CsvRunReader::open(run)?));
+    }
+    sources.push(Box::new(records.into_iter().map(Ok)));
+
+    merge_sorted(sources, |record| {
+        cancel.check()?;
+        for line in record.split('\n') {
+            output.append_line(line)?;
+        }
+        Ok(())
+    })
+}
+
+/// Merge the worker outputs of each output into its linearized files.
+///
+/// Outputs already in `merged_outputs` are appended to, and it is updated with every file of each
+/// output. Only files created by this merge are added to `output_files`, and the readings of
+/// each file written to are added to `output_rows`.
 pub fn merge<T: ProgressReporter>(
     args: &mut RunArgs<T>,
     output_files: &mut Vec<PathBuf>,
-    stats_tdf: &Arc<Mutex<HashMap<(Option<u64>, u16), HashMap<usize, TdfDecoderOutputs>>>>,
+    output_rows: &mut HashMap<PathBuf, usize>,
+    merged_outputs: &mut MergedOutputs,
+    stats_tdf: &TdfWorkerStats,
 ) -> io::Result<()> {
     let results = stats_tdf.lock().unwrap();
     let num_files: usize = results.values().map(|inner| inner.len()).sum();
@@ -297,26 +760,49 @@ pub fn merge<T: ProgressReporter>(
             *remote_id,
             *tdf_id,
             args.max_readings_per_output_file,
+            args.csv_utf8_bom,
+            args.csv_format.delimiter,
         );
+        let existing = merged_outputs
+            .remove(&(*remote_id, *tdf_id))
+            .unwrap_or_default();
+        output.resume(existing.clone())?;
+        let inputs: Vec<PathBuf> = worker_outputs
+            .keys()
+            .sorted()
+            .map(|worker| worker_outputs[worker].output.clone())
+            .collect();
 
-        for worker in worker_outputs.keys().sorted() {
-            let input_path = worker_outputs[worker].output.clone();
-            let input = BufReader::new(File::open(&input_path)?);
-
-            for (idx, line) in input.lines().enumerate() {
-                let line = line?;
-                if idx == 0 {
-                    output.set_header(line)?;
-                    continue;
-                }
-                output.append_line(&line)?;
-            }
-
-            fs::remove_file(input_path)?;
-
-            args.merge_reporter.increment(1);
-        }
-        output_files.extend(output.finish()?);
+        let merged = if args.sort_by_time {
+            merge_sorted_by_time(
+                &mut output,
+                &inputs,
+                &args.csv_format,
+                args.output_unix_time,
+                &args.cancel,
+                || args.merge_reporter.increment(1),
+            )
+        } else {
+            merge_in_order(&mut output, &inputs, &args.cancel, || {
+                args.merge_reporter.increment(1)
+            })
+        };
+        // Partially merged files are still reported, so a cancelled run can remove them
+        let files = output.finish()?;
+        output_files.extend(
+            files
+                .iter()
+                .filter(|file| !existing.contains(file))
+                .cloned(),
+        );
+        output_rows.extend(
+            files
+                .iter()
+                .zip(&output.file_readings)
+                .filter_map(|(file, readings)| Some((file.clone(), (*readings)?))),
+        );
+        merged_outputs.insert((*remote_id, *tdf_id), files);
+        merged?;
     }
     args.merge_reporter.stop();
 
@@ -328,18 +814,19 @@ mod tests {
     use super::*;
     use std::io::Read;
 
-    fn unique_temp_dir(name: &str) -> PathBuf {
-        let dir =
-            std::env::temp_dir().join(format!("infuse_decoder_{name}_{}", std::process::id()));
-        let _ = fs::remove_dir_all(&dir);
-        fs::create_dir_all(&dir).unwrap();
-        dir
-    }
-
     #[test]
     fn zero_max_readings_keeps_csv_output_in_one_file() {
-        let output_dir = unique_temp_dir("zero_max_readings_csv");
-        let mut output = TdfCsvMergedOutput::new(output_dir.clone(), "out".to_string(), None, 1, 0);
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let mut output = TdfCsvMergedOutput::new(
+            output_dir.clone(),
+            "out".to_string(),
+            None,
+            1,
+            0,
+            false,
+            ',',
+        );
 
         output.set_header("time,value".to_string()).unwrap();
         output.append_line("1,10").unwrap();
@@ -359,7 +846,195 @@ mod tests {
             .read_to_string(&mut contents)
             .unwrap();
         assert_eq!(contents, "time,value\n1,10\n2,20\n3,30\n");
+    }
 
-        fs::remove_dir_all(output_dir).unwrap();
+    #[test]
+    fn sort_by_time_merges_worker_outputs_chronologically() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let format = CsvFormat::default();
+        let inputs = [
+            output_dir.join("worker_0.csv"),
+            output_dir.join("worker_1.csv"),
+        ];
+        fs::write(
+            &inputs[0],
+            "time,value\n1.000000,a\n3.000000,c\n2,c-idx\n,c-continued\n",
+        )
+        .unwrap();
+        fs::write(&inputs[1], "time,value\n1.000000,b\n2.500000,d\n").unwrap();
+
+        let mut output = TdfCsvMergedOutput::new(
+            output_dir.clone(),
+            "out".to_string(),
+            None,
+            1,
+            0,
+            false,
+            ',',
+        );
+        let mut inputs_done = 0;
+        merge_sorted_by_time(
+            &mut output,
+            &inputs,
+            &format,
+            true,
+            &CancellationToken::new(),
+            || inputs_done += 1,
+        )
+        .unwrap();
+        let files = output.finish().unwrap();
+
+        assert_eq!(inputs_done, 2);
+        assert!(inputs.iter().all(|input| !input.exists()));
+        let mut contents = String::new();
+        File::open(&files[0])
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(
+            contents,
+            "time,value\n1.000000,a\n1.000000,b\n2.500000,d\n3.000000,c\n2,c-idx\n,c-continued\n"
+        );
+    }
+
+    #[test]
+    fn csv_run_files_round_trip_multi_line_records() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let mut records = vec![
+            ((20, 2), "b".to_string()),
+            ((10, 1), "a\n,a-continued".to_string()),
+        ];
+
+        let path = write_csv_run(output_dir.join("run.csv"), &mut records).unwrap();
+        let read: Vec<(SortKey, String)> = CsvRunReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert!(records.is_empty());
+        assert_eq!(
+            read,
+            vec![
+                ((10, 1), "a\n,a-continued".to_string()),
+                ((20, 2), "b".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn resumed_output_counts_array_rows_with_their_reading() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let new_output = || {
+            TdfCsvMergedOutput::new(
+                output_dir.clone(),
+                "out".to_string(),
+                None,
+                1,
+                2,
+                false,
+                ',',
+            )
+        };
+
+        let mut output = new_output();
+        output.set_header("time,value".to_string()).unwrap();
+        output.append_line("1,a").unwrap();
+        output.append_line(",a-continued").unwrap();
+        let files = output.finish().unwrap();
+
+        let mut output = new_output();
+        output.resume(files).unwrap();
+        for line in ["2,b", ",b-continued", "3,c"] {
+            output.append_line(line).unwrap();
+        }
+        let files = output.finish().unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(output.file_readings, vec![Some(2), Some(1)]);
+        assert_eq!(
+            fs::read_to_string(&files[0]).unwrap(),
+            "time,value\n1,a\n,a-continued\n2,b\n,b-continued\n"
+        );
+        assert_eq!(fs::read_to_string(&files[1]).unwrap(), "time,value\n3,c\n");
+    }
+
+    #[test]
+    fn utf8_bom_prefixes_each_split_csv_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let mut output =
+            TdfCsvMergedOutput::new(output_dir.clone(), "out".to_string(), None, 1, 1, true, ';');
+
+        output.set_header("time;value".to_string()).unwrap();
+        output.append_line("1;10").unwrap();
+        output.append_line("2;20").unwrap();
+
+        let files = output.finish().unwrap();
+        assert_eq!(files.len(), 2);
+        for (file, line) in files.iter().zip(["1;10", "2;20"]) {
+            let mut contents = String::new();
+            File::open(file)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            assert_eq!(contents, format!("\u{feff}time;value\n{line}\n"));
+        }
+    }
+
+    #[test]
+    fn unrepresentable_time_is_written_as_a_placeholder() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let mut writer = TdfCsvWriter::new(
+            0,
+            output_dir.clone(),
+            "out".to_string(),
+            false,
+            CsvFormat::default(),
+            false,
+        );
+        let temperature = 21500_i32.to_le_bytes();
+
+        for time in [tdf::time::unix_micros_to_tdf_time(10_000_000), i64::MAX] {
+            writer
+                .write(None, 4, time, None, 4, &mut Cursor::new(&temperature[..]))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let input = writer.output_path(None, 4).unwrap();
+        assert_eq!(
+            fs::read_to_string(&input).unwrap().lines().nth(2),
+            Some("invalid,21.5")
+        );
+
+        // The row is a reading of its own, rather than continuing the one before it
+        let mut output = TdfCsvMergedOutput::new(
+            output_dir.clone(),
+            "merged".to_string(),
+            None,
+            4,
+            1,
+            false,
+            ',',
+        );
+        merge_sorted_by_time(
+            &mut output,
+            &[input],
+            &CsvFormat::default(),
+            false,
+            &CancellationToken::new(),
+            || {},
+        )
+        .unwrap();
+        let files = output.finish().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(output.file_readings, vec![Some(1), Some(1)]);
+        assert_eq!(
+            fs::read_to_string(&files[1]).unwrap(),
+            "time,temperature\ninvalid,21.5\n"
+        );
     }
 }
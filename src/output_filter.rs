@@ -0,0 +1,342 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::io::{self, Cursor};
+use std::path::PathBuf;
+
+use tdf::TdfOutput;
+
+use crate::args::TdfSelection;
+use crate::battery::BatteryTracker;
+use crate::continuity::ContinuityTracker;
+use crate::output_arrow::TdfArrowWriter;
+use crate::output_common::OutputKey;
+use crate::resample::ResampleTap;
+use crate::sessions::{BootRecord, SessionIdOutput, SessionTracker};
+
+/// Range of TDF times to decode, `since` inclusive and `until` exclusive
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TimeWindow {
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl TimeWindow {
+    pub fn new(since: Option<DateTime<Utc>>, until: Option<DateTime<Utc>>) -> io::Result<Self> {
+        if let (Some(since), Some(until)) = (since, until)
+            && since >= until
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Start of time window ({since}) must be before the end ({until})"),
+            ));
+        }
+
+        let to_tdf =
+            |time: DateTime<Utc>| tdf::time::unix_micros_to_tdf_time(time.timestamp_micros());
+        Ok(Self {
+            since: since.map(to_tdf),
+            until: until.map(to_tdf),
+        })
+    }
+
+    pub fn contains(&self, tdf_time: i64) -> bool {
+        self.since.is_none_or(|since| tdf_time >= since)
+            && self.until.is_none_or(|until| tdf_time < until)
+    }
+}
+
+/// Advance past a TDF payload without decoding it
+pub(crate) fn skip_payload(size: u8, cursor: &mut Cursor<&[u8]>) -> io::Result<()> {
+    let end = cursor.position() + size as u64;
+    if end > cursor.get_ref().len() as u64 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "TDF payload extends past the end of the block",
+        ));
+    }
+    cursor.set_position(end);
+    Ok(())
+}
+
+/// TDF output that only records the range of sample times in a block, and its boot records
+#[derive(Default)]
+pub struct TimeScan {
+    pub first: i64,
+    pub last: i64,
+    pub samples: usize,
+    pub(crate) boot_records: Vec<BootRecord>,
+}
+
+impl TdfOutput for TimeScan {
+    fn write(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        tdf_time: i64,
+        _tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        if let Some(record) = BootRecord::peek(remote_id, tdf_id, size, cursor) {
+            self.boot_records.push(record);
+        }
+        if self.samples == 0 {
+            self.first = tdf_time;
+            self.last = tdf_time;
+        } else {
+            self.first = self.first.min(tdf_time);
+            self.last = self.last.max(tdf_time);
+        }
+        self.samples += 1;
+        skip_payload(size, cursor)
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        std::iter::empty()
+    }
+
+    fn written(&self, _remote_id: Option<u64>, _tdf_id: u16) -> usize {
+        0
+    }
+
+    fn output_path(&self, _remote_id: Option<u64>, _tdf_id: u16) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Forwards selected samples inside a time window to another output.
+///
+/// Samples from TDFs or remote IDs that weren't selected are skipped before they are decoded.
+/// Only samples outside the time window are counted as filtered. Boot records are followed
+/// regardless of the selection, so that rows are tagged with the right session. Samples that repeat
+/// an earlier sample are dropped before anything else.
+pub struct FilteredOutput<U: SessionIdOutput> {
+    pub inner: U,
+    window: TimeWindow,
+    selection: TdfSelection,
+    allowed_tdfs: Vec<bool>,
+    pub filtered: usize,
+    /// Earliest and latest TDF time of the samples written for each output
+    pub time_ranges: HashMap<OutputKey, (i64, i64)>,
+    /// Timeline of the samples written, for the continuity report
+    pub continuity: ContinuityTracker,
+    /// Boot sessions of the samples inside the time window
+    pub sessions: SessionTracker,
+    /// Battery measurements of the logging device inside the time window
+    pub battery: BatteryTracker,
+    /// Selected samples dropped for repeating an earlier sample, for each output
+    pub duplicates: HashMap<OutputKey, usize>,
+    /// Samples of the resampled TDFs inside the time window, regardless of the selection
+    pub(crate) resample: Option<ResampleTap>,
+    /// Copies of the samples written to the inner output
+    pub(crate) collected: Option<TdfArrowWriter>,
+    /// Positions of the duplicate samples in the current block
+    duplicate_samples: Vec<u16>,
+    sample_idx: u16,
+}
+
+impl<U: SessionIdOutput> FilteredOutput<U> {
+    pub fn new(inner: U, window: TimeWindow, selection: TdfSelection) -> Self {
+        let allowed_tdfs = (0..=tdf::TDF_ID_MAX)
+            .map(|tdf_id| selection.allows_tdf(tdf_id))
+            .collect();
+        Self {
+            inner,
+            window,
+            selection,
+            allowed_tdfs,
+            filtered: 0,
+            time_ranges: HashMap::new(),
+            continuity: ContinuityTracker::new(),
+            sessions: SessionTracker::new(None),
+            battery: BatteryTracker::new(),
+            duplicates: HashMap::new(),
+            resample: None,
+            collected: None,
+            duplicate_samples: Vec::new(),
+            sample_idx: 0,
+        }
+    }
+
+    /// Start decoding a block, dropping the samples at the given positions in it
+    pub fn start_block(&mut self, block_idx: usize, duplicate_samples: &[u16]) {
+        self.continuity.start_block(block_idx);
+        self.duplicate_samples.clear();
+        self.duplicate_samples.extend_from_slice(duplicate_samples);
+        self.sample_idx = 0;
+    }
+}
+
+impl<U: SessionIdOutput> TdfOutput for FilteredOutput<U> {
+    fn write(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        tdf_time: i64,
+        tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        let selected =
+            self.allowed_tdfs[tdf_id as usize] && self.selection.allows_remote(remote_id);
+        let sample_idx = self.sample_idx;
+        self.sample_idx = self.sample_idx.wrapping_add(1);
+        if self.duplicate_samples.binary_search(&sample_idx).is_ok() {
+            if selected {
+                *self.duplicates.entry((remote_id, tdf_id)).or_default() += 1;
+            }
+            return skip_payload(size, cursor);
+        }
+
+        if let Some(record) = BootRecord::peek(remote_id, tdf_id, size, cursor) {
+            self.sessions.record(record);
+        }
+        let in_window = self.window.contains(tdf_time);
+        if in_window {
+            self.sessions.sample(tdf_time);
+            self.battery
+                .sample(remote_id, tdf_id, tdf_time, size, cursor);
+            if let Some(tap) = self.resample.as_mut()
+                && tap.wants(remote_id, tdf_id)
+            {
+                tap.write(tdf_id, tdf_time, tdf_idx, size, cursor)?;
+            }
+        }
+
+        if !selected {
+            return skip_payload(size, cursor);
+        }
+        if !in_window {
+            self.filtered += 1;
+            return skip_payload(size, cursor);
+        }
+        if let Some(collected) = self.collected.as_mut() {
+            collected.write(
+                remote_id,
+                tdf_id,
+                tdf_time,
+                tdf_idx,
+                size,
+                &mut cursor.clone(),
+            )?;
+        }
+        self.inner.set_session_id(self.sessions.current_id());
+        self.inner
+            .write(remote_id, tdf_id, tdf_time, tdf_idx, size, cursor)?;
+
+        self.time_ranges
+            .entry((remote_id, tdf_id))
+            .and_modify(|(first, last)| {
+                *first = (*first).min(tdf_time);
+                *last = (*last).max(tdf_time);
+            })
+            .or_insert((tdf_time, tdf_time));
+        self.continuity.sample(remote_id, tdf_id, tdf_time);
+        Ok(())
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        self.inner.iter_written()
+    }
+
+    fn written(&self, remote_id: Option<u64>, tdf_id: u16) -> usize {
+        self.inner.written(remote_id, tdf_id)
+    }
+
+    fn output_path(&self, remote_id: Option<u64>, tdf_id: u16) -> Option<PathBuf> {
+        self.inner.output_path(remote_id, tdf_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_BLOCKS: &[u8] = include_bytes!("../data/tdf_test_blocks.bin");
+
+    fn utc(value: &str) -> DateTime<Utc> {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn window_bounds_are_inclusive_then_exclusive() {
+        let since = utc("2025-01-01T00:00:01Z");
+        let until = utc("2025-01-01T00:00:02Z");
+        let window = TimeWindow::new(Some(since), Some(until)).unwrap();
+        let to_tdf =
+            |time: DateTime<Utc>| tdf::time::unix_micros_to_tdf_time(time.timestamp_micros());
+
+        assert!(window.contains(to_tdf(since)));
+        assert!(!window.contains(to_tdf(since) - 1));
+        assert!(!window.contains(to_tdf(until)));
+        assert_eq!(
+            tdf::time::tdf_time_to_unix_micros(to_tdf(since)),
+            since.timestamp_micros()
+        );
+    }
+
+    #[test]
+    fn empty_window_is_rejected() {
+        let time = utc("2025-01-01T00:00:00Z");
+
+        let err = TimeWindow::new(Some(time), Some(time)).unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn filtered_and_written_samples_account_for_every_sample() {
+        let window = TimeWindow::new(Some(utc("2025-01-01T00:00:05Z")), None).unwrap();
+        let mut scan = TimeScan::default();
+        let mut output =
+            FilteredOutput::new(TdfArrowWriter::new(), window, TdfSelection::default());
+        for block in TEST_BLOCKS.chunks_exact(512) {
+            let _ = blocks::decode_block(&mut scan, block);
+            let _ = blocks::decode_block(&mut output, block);
+        }
+        let written: usize = output.iter_written().map(|(_, cnt)| *cnt).sum();
+
+        assert!(output.filtered > 0);
+        assert!(written > 0);
+        assert_eq!(written + output.filtered, scan.samples);
+    }
+
+    #[test]
+    fn unselected_tdfs_are_skipped() {
+        let selection = TdfSelection {
+            exclude_tdfs: vec![crate::args::parse_tdf_id("ACC_4G").unwrap()],
+            ..TdfSelection::default()
+        };
+        let mut all = TdfArrowWriter::new();
+        let mut output =
+            FilteredOutput::new(TdfArrowWriter::new(), TimeWindow::default(), selection);
+        for block in TEST_BLOCKS.chunks_exact(512) {
+            let _ = blocks::decode_block(&mut all, block);
+            let _ = blocks::decode_block(&mut output, block);
+        }
+
+        assert!(all.written(None, 11) > 0);
+        assert_eq!(output.written(None, 11), 0);
+        assert_eq!(output.written(None, 4), all.written(None, 4));
+        assert_eq!(output.filtered, 0);
+    }
+
+    #[test]
+    fn only_selected_samples_are_counted_as_filtered() {
+        let window = TimeWindow::new(Some(utc("2100-01-01T00:00:00Z")), None).unwrap();
+        let selection = TdfSelection {
+            include_tdfs: vec![crate::args::parse_tdf_id("ACC_4G").unwrap()],
+            ..TdfSelection::default()
+        };
+        let mut all = TdfArrowWriter::new();
+        let mut output = FilteredOutput::new(TdfArrowWriter::new(), window, selection);
+        for block in TEST_BLOCKS.chunks_exact(512) {
+            let _ = blocks::decode_block(&mut all, block);
+            let _ = blocks::decode_block(&mut output, block);
+        }
+
+        assert!(all.written(None, 4) > 0);
+        assert_eq!(output.iter_written().count(), 0);
+        assert_eq!(output.filtered, all.written(None, 11));
+    }
+}
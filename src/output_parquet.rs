@@ -2,44 +2,70 @@ use std::collections::HashMap;
 use std::collections::hash_map::Entry;
 use std::fs::File;
 use std::io::{self, Cursor, Read};
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use arrow_array::RecordBatch;
-use arrow_schema::SchemaRef;
+use arrow_array::{
+    Array, ArrayRef, BooleanArray, Int32Array, Int64Array, RecordBatch, RecordBatchReader,
+    StringArray, TimestampMicrosecondArray, UInt8Array, UInt32Array, UInt64Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow_select::interleave::interleave_record_batch;
 use itertools::Itertools;
 use parquet::arrow::ArrowWriter;
-use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
 use tdf::TdfOutput;
 use tdf::decoders_parquet::{TdfParquetBatchBuilder, TdfParquetRowMeta};
 
+use crate::battery::BatteryRow;
 use crate::output_common::{
-    OutputKey, increment_output_count, merged_output_path, rename_first_file_if_splitting,
-    touch_output_count, worker_output_path, written,
+    MergedOutputs, OutputKey, increment_output_count, merged_output_path,
+    rename_first_file_if_splitting, touch_output_count, worker_output_path, written,
 };
-use crate::{ProgressReporter, RunArgs, TdfDecoderOutputs};
+use crate::output_sort::{SORT_RUN_ROWS, SortKey, SortKeys, SortRuns, SortedSource, merge_sorted};
+use crate::resample::Resampler;
+use crate::sessions::{Session, SessionIdOutput};
+use crate::{CancellationToken, ProgressReporter, RunArgs, TdfWorkerStats};
 
 const DEFAULT_BATCH_ROWS: usize = 65536;
+const SESSION_ID_COLUMN: &str = "session_id";
+/// The session ID column follows the timestamp and sample index columns
+const SESSION_ID_COLUMN_IDX: usize = 2;
 
 struct TdfParquetOutputFile {
     path: PathBuf,
     tdf_id: u16,
     builder: TdfParquetBatchBuilder,
+    /// Session of each row in the builder, when the session ID column is enabled
+    session_ids: Option<Vec<Option<u32>>>,
+    schema: SchemaRef,
     writer: ArrowWriter<File>,
     finished: bool,
 }
 
 impl TdfParquetOutputFile {
-    fn new(path: PathBuf, tdf_id: u16, batch_rows: usize) -> io::Result<Self> {
+    fn new(path: PathBuf, tdf_id: u16, batch_rows: usize, session_ids: bool) -> io::Result<Self> {
         let builder = tdf::decoders_parquet::tdf_parquet_builder(tdf_id, batch_rows)
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown TDF ID"))?;
+        let mut schema = builder.schema();
+        if session_ids {
+            let mut fields: Vec<Field> =
+                schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+            fields.insert(
+                SESSION_ID_COLUMN_IDX,
+                Field::new(SESSION_ID_COLUMN, DataType::UInt32, true),
+            );
+            schema = Arc::new(Schema::new(fields));
+        }
         let file = File::create(path.clone())?;
-        let writer = ArrowWriter::try_new(file, builder.schema(), None).map_err(to_io_error)?;
+        let writer = ArrowWriter::try_new(file, schema.clone(), None).map_err(to_io_error)?;
 
         Ok(Self {
             path,
             tdf_id,
             builder,
+            session_ids: session_ids.then(Vec::new),
+            schema,
             writer,
             finished: false,
         })
@@ -48,10 +74,14 @@ impl TdfParquetOutputFile {
     fn append(
         &mut self,
         meta: TdfParquetRowMeta,
+        session_id: Option<u32>,
         size: u8,
         cursor: &mut Cursor<&[u8]>,
     ) -> io::Result<()> {
         self.builder.append(meta, size, cursor)?;
+        if let Some(session_ids) = self.session_ids.as_mut() {
+            session_ids.push(session_id);
+        }
         Ok(())
     }
 
@@ -60,7 +90,15 @@ impl TdfParquetOutputFile {
             return Ok(());
         }
 
-        let batch = self.builder.finish_batch().map_err(to_io_error)?;
+        let mut batch = self.builder.finish_batch().map_err(to_io_error)?;
+        if let Some(session_ids) = self.session_ids.as_mut() {
+            let mut columns = batch.columns().to_vec();
+            columns.insert(
+                SESSION_ID_COLUMN_IDX,
+                Arc::new(UInt32Array::from(std::mem::take(session_ids))),
+            );
+            batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(to_io_error)?;
+        }
         self.writer.write(&batch).map_err(to_io_error)?;
         self.builder = tdf::decoders_parquet::tdf_parquet_builder(self.tdf_id, batch_rows)
             .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Unknown TDF ID"))?;
@@ -90,6 +128,8 @@ pub struct TdfParquetWriter {
     output_folder: PathBuf,
     output_prefix: String,
     batch_rows: usize,
+    session_ids: bool,
+    session_id: Option<u32>,
     outputs: HashMap<(Option<u64>, u16), TdfParquetOutputFile>,
     output_cnt: HashMap<OutputKey, usize>,
 }
@@ -115,11 +155,19 @@ impl TdfParquetWriter {
             output_folder,
             output_prefix,
             batch_rows: batch_rows.max(1),
+            session_ids: false,
+            session_id: None,
             outputs: HashMap::new(),
             output_cnt: HashMap::new(),
         }
     }
 
+    /// Tag each row with the boot session it was logged in
+    pub fn with_session_ids(mut self, enabled: bool) -> Self {
+        self.session_ids = enabled;
+        self
+    }
+
     pub fn finish(&mut self) -> io::Result<()> {
         for output in self.outputs.values_mut() {
             output.finish(self.batch_rows)?;
@@ -132,6 +180,7 @@ impl TdfParquetWriter {
         output_folder: &std::path::Path,
         output_prefix: &str,
         batch_rows: usize,
+        session_ids: bool,
         remote_id: Option<u64>,
         tdf_id: u16,
     ) -> io::Result<TdfParquetOutputFile> {
@@ -144,7 +193,7 @@ impl TdfParquetWriter {
             "parquet",
         );
 
-        TdfParquetOutputFile::new(path, tdf_id, batch_rows)
+        TdfParquetOutputFile::new(path, tdf_id, batch_rows, session_ids)
     }
 }
 
@@ -154,6 +203,12 @@ impl Drop for TdfParquetWriter {
     }
 }
 
+impl SessionIdOutput for TdfParquetWriter {
+    fn set_session_id(&mut self, session_id: Option<u32>) {
+        self.session_id = session_id;
+    }
+}
+
 impl TdfOutput for TdfParquetWriter {
     fn output_path(&self, remote_id: Option<u64>, tdf_id: u16) -> Option<PathBuf> {
         self.outputs
@@ -184,6 +239,7 @@ impl TdfOutput for TdfParquetWriter {
                     &self.output_folder,
                     &self.output_prefix,
                     self.batch_rows,
+                    self.session_ids,
                     remote_id,
                     tdf_id,
                 )?;
@@ -193,18 +249,7 @@ impl TdfOutput for TdfParquetWriter {
             }
         };
 
-        let meta = match tdf_idx {
-            Some(idx) => TdfParquetRowMeta {
-                time_unix_micros: None,
-                sample_idx: Some(idx),
-            },
-            None => TdfParquetRowMeta {
-                time_unix_micros: Some(tdf::time::tdf_time_to_unix_micros(tdf_time)),
-                sample_idx: None,
-            },
-        };
-
-        output.append(meta, size, cursor)?;
+        output.append(row_meta(tdf_time, tdf_idx), self.session_id, size, cursor)?;
 
         if output.builder.rows() >= self.batch_rows {
             output.flush_batch(self.batch_rows)?;
@@ -224,6 +269,132 @@ impl TdfOutput for TdfParquetWriter {
     }
 }
 
+/// Write the boot sessions as a Parquet file
+pub fn write_sessions(path: &Path, sessions: &[Session]) -> io::Result<()> {
+    let timestamp = DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into()));
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("session_id", DataType::UInt32, true),
+        Field::new("start", timestamp.clone(), true),
+        Field::new("end", timestamp, true),
+        Field::new("application", DataType::UInt32, true),
+        Field::new("firmware_version", DataType::Utf8, true),
+        Field::new("reset_reason", DataType::UInt8, true),
+        Field::new("reset_uptime", DataType::UInt32, true),
+        Field::new("reset_hardware_flags", DataType::UInt32, true),
+        Field::new("reset_param_1", DataType::UInt32, true),
+        Field::new("reset_param_2", DataType::UInt32, true),
+        Field::new("reset_thread", DataType::Utf8, true),
+    ]));
+    let time = |time: Option<i64>| {
+        time.and_then(tdf::time::tdf_time_to_datetime)
+            .map(|datetime| datetime.timestamp_micros())
+    };
+    let reset = |value: fn(&crate::sessions::ResetInfo) -> u32| {
+        UInt32Array::from_iter(sessions.iter().map(|s| s.reset.as_ref().map(value)))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from_iter(
+            sessions.iter().map(|s| s.session_id),
+        )),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter(sessions.iter().map(|s| time(s.start)))
+                .with_timezone("+00:00"),
+        ),
+        Arc::new(
+            TimestampMicrosecondArray::from_iter(sessions.iter().map(|s| time(s.end)))
+                .with_timezone("+00:00"),
+        ),
+        Arc::new(UInt32Array::from_iter(
+            sessions.iter().map(|s| s.firmware.map(|f| f.application)),
+        )),
+        Arc::new(StringArray::from_iter(
+            sessions.iter().map(|s| s.firmware.map(|f| f.to_string())),
+        )),
+        Arc::new(UInt8Array::from_iter(
+            sessions.iter().map(|s| s.reset.as_ref().map(|r| r.reason)),
+        )),
+        Arc::new(reset(|r| r.uptime)),
+        Arc::new(reset(|r| r.hardware_flags)),
+        Arc::new(reset(|r| r.param_1)),
+        Arc::new(reset(|r| r.param_2)),
+        Arc::new(StringArray::from_iter(
+            sessions
+                .iter()
+                .map(|s| s.reset.as_ref().map(|r| r.thread.clone())),
+        )),
+    ];
+    write_single_batch(path, schema, columns)
+}
+
+/// Write the merged battery timeline as a Parquet file
+pub fn write_battery(path: &Path, rows: &[BatteryRow]) -> io::Result<()> {
+    let schema = Arc::new(Schema::new(vec![
+        Field::new(
+            "time",
+            DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+            true,
+        ),
+        Field::new("voltage_mv", DataType::UInt32, true),
+        Field::new("current_ua", DataType::Int32, true),
+        Field::new("soc", DataType::UInt8, true),
+        Field::new("charge", DataType::Int32, true),
+        Field::new("state", DataType::Utf8, true),
+        Field::new("cycle", DataType::UInt32, true),
+        Field::new("abnormal_drop", DataType::Boolean, false),
+    ]));
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(
+            TimestampMicrosecondArray::from_iter(rows.iter().map(|r| {
+                tdf::time::tdf_time_to_datetime(r.point.time)
+                    .map(|datetime| datetime.timestamp_micros())
+            }))
+            .with_timezone("+00:00"),
+        ),
+        Arc::new(UInt32Array::from_iter(
+            rows.iter().map(|r| r.point.voltage_mv),
+        )),
+        Arc::new(Int32Array::from_iter(
+            rows.iter().map(|r| r.point.current_ua),
+        )),
+        Arc::new(UInt8Array::from_iter(rows.iter().map(|r| r.point.soc))),
+        Arc::new(Int32Array::from_iter(rows.iter().map(|r| r.point.charge))),
+        Arc::new(StringArray::from_iter(
+            rows.iter().map(|r| r.state.map(|state| state.to_string())),
+        )),
+        Arc::new(UInt32Array::from_iter(rows.iter().map(|r| r.cycle))),
+        Arc::new(BooleanArray::from_iter(
+            rows.iter().map(|r| Some(r.abnormal_drop)),
+        )),
+    ];
+    write_single_batch(path, schema, columns)
+}
+
+/// Write the streams of a resampled output as a Parquet file
+pub fn write_resampled(path: &Path, resampler: Resampler) -> io::Result<()> {
+    write_batches(path, resampler.schema(), resampler)
+}
+
+fn write_single_batch(path: &Path, schema: SchemaRef, columns: Vec<ArrayRef>) -> io::Result<()> {
+    let batch = RecordBatch::try_new(schema.clone(), columns).map_err(to_io_error)?;
+    write_batches(path, schema, std::iter::once(Ok(batch)))
+}
+
+fn write_batches(
+    path: &Path,
+    schema: SchemaRef,
+    batches: impl Iterator<Item = io::Result<RecordBatch>>,
+) -> io::Result<()> {
+    let mut writer =
+        ArrowWriter::try_new(File::create(path)?, schema, None).map_err(to_io_error)?;
+    for batch in batches {
+        writer.write(&batch?).map_err(to_io_error)?;
+    }
+    writer.close().map_err(to_io_error)?;
+    Ok(())
+}
+
 struct TdfParquetMergedOutput {
     output_folder: PathBuf,
     output_prefix: String,
@@ -231,7 +402,11 @@ struct TdfParquetMergedOutput {
     tdf_id: u16,
     threshold_rows: Option<usize>,
     output_files: Vec<PathBuf>,
+    /// Rows in each of `output_files`, `None` until the file is finished
+    file_rows: Vec<Option<usize>>,
     writer: Option<ArrowWriter<File>>,
+    /// Copy of the last file of an earlier decode being written, and the file it replaces
+    resumed: Option<(PathBuf, PathBuf)>,
     rows_in_file: usize,
     part_idx: usize,
 }
@@ -254,12 +429,55 @@ impl TdfParquetMergedOutput {
                 value => Some(value),
             },
             output_files: Vec::new(),
+            file_rows: Vec::new(),
             writer: None,
+            resumed: None,
             rows_in_file: 0,
             part_idx: 0,
         }
     }
 
+    /// Continue appending to the output files of an earlier decode.
+    ///
+    /// Parquet files can't be appended to, so the rows of the last file are copied into a new one.
+    /// The copy only replaces the last file once it is finished, so the earlier outputs are left as
+    /// they were if the merge fails.
+    fn resume(&mut self, files: Vec<PathBuf>) -> io::Result<()> {
+        let Some(last) = files.last().cloned() else {
+            return Ok(());
+        };
+        let reader = open_reader(&last)?;
+        let copy = last.with_extension("parquet.resume");
+        let writer = ArrowWriter::try_new(File::create(&copy)?, reader.schema(), None)
+            .map_err(to_io_error)?;
+
+        self.writer = Some(writer);
+        self.resumed = Some((copy, last));
+        self.rows_in_file = 0;
+        self.part_idx = files.len();
+        self.file_rows = vec![None; files.len()];
+        self.output_files = files;
+        for batch in reader {
+            let copied = batch
+                .map_err(to_io_error)
+                .and_then(|batch| self.append_batch(&batch));
+            if let Err(err) = copied {
+                self.discard_resumed()?;
+                return Err(err);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the unfinished copy of a resumed file, leaving the file as it was
+    fn discard_resumed(&mut self) -> io::Result<()> {
+        if let Some((copy, _)) = self.resumed.take() {
+            self.writer = None;
+            std::fs::remove_file(copy)?;
+        }
+        Ok(())
+    }
+
     fn append_batch(&mut self, batch: &RecordBatch) -> io::Result<()> {
         let mut offset = 0;
 
@@ -308,6 +526,7 @@ impl TdfParquetMergedOutput {
         let writer = ArrowWriter::try_new(file, schema, None).map_err(to_io_error)?;
 
         self.output_files.push(path);
+        self.file_rows.push(None);
         self.writer = Some(writer);
         self.rows_in_file = 0;
         self.part_idx += 1;
@@ -330,6 +549,12 @@ impl TdfParquetMergedOutput {
     fn finish_current_file(&mut self) -> io::Result<()> {
         if let Some(mut writer) = self.writer.take() {
             writer.finish().map_err(to_io_error)?;
+            if let Some(rows) = self.file_rows.last_mut() {
+                *rows = Some(self.rows_in_file);
+            }
+        }
+        if let Some((copy, last)) = self.resumed.take() {
+            std::fs::rename(copy, last)?;
         }
         Ok(())
     }
@@ -363,12 +588,267 @@ impl TdfParquetMergedOutput {
             "parquet",
         )
     }
+
+    fn sort_run_path(&self, run_idx: usize) -> PathBuf {
+        merged_output_path(
+            &self.output_folder,
+            &self.output_prefix,
+            self.remote_id,
+            self.tdf_id,
+            None,
+            &format!("sort{run_idx:05}.parquet"),
+        )
+    }
+}
+
+/// Row of a buffered record batch
+type BatchRow = (Arc<RecordBatch>, usize);
+
+const SORT_TIME_COLUMN: &str = "sort_time";
+const SORT_SEQ_COLUMN: &str = "sort_seq";
+
+/// Timestamp of a row, `None` for rows that only have a sample index
+pub(crate) fn batch_row_time(times: Option<&TimestampMicrosecondArray>, row: usize) -> Option<i64> {
+    times
+        .filter(|times| times.is_valid(row))
+        .map(|times| times.value(row))
+}
+
+/// Gather rows from multiple batches into a single batch
+pub(crate) fn gather_rows(
+    rows: &[(usize, usize)],
+    batches: &[&RecordBatch],
+) -> io::Result<RecordBatch> {
+    interleave_record_batch(batches, rows).map_err(to_io_error)
+}
+
+/// Sort the buffered rows and write them, with their sort keys, to a temporary run file
+fn write_parquet_run(
+    path: PathBuf,
+    batches: &[RecordBatch],
+    rows: &mut Vec<(SortKey, usize, usize)>,
+) -> io::Result<PathBuf> {
+    rows.sort_unstable_by_key(|(key, _, _)| *key);
+
+    let schema = batches[0].schema();
+    let mut fields: Vec<Field> = schema.fields().iter().map(|f| f.as_ref().clone()).collect();
+    fields.push(Field::new(SORT_TIME_COLUMN, DataType::Int64, false));
+    fields.push(Field::new(SORT_SEQ_COLUMN, DataType::UInt64, false));
+    let run_schema = Arc::new(Schema::new(fields));
+
+    let batch_refs: Vec<&RecordBatch> = batches.iter().collect();
+    let mut writer = ArrowWriter::try_new(File::create(&path)?, run_schema.clone(), None)
+        .map_err(to_io_error)?;
+    for chunk in rows.chunks(DEFAULT_BATCH_ROWS) {
+        let indices: Vec<(usize, usize)> = chunk.iter().map(|(_, b, r)| (*b, *r)).collect();
+        let batch = gather_rows(&indices, &batch_refs)?;
+        let mut columns: Vec<ArrayRef> = batch.columns().to_vec();
+        columns.push(Arc::new(Int64Array::from_iter_values(
+            chunk.iter().map(|((time, _), _, _)| *time),
+        )));
+        columns.push(Arc::new(UInt64Array::from_iter_values(
+            chunk.iter().map(|((_, seq), _, _)| *seq),
+        )));
+        let batch = RecordBatch::try_new(run_schema.clone(), columns).map_err(to_io_error)?;
+        writer.write(&batch).map_err(to_io_error)?;
+    }
+    writer.close().map_err(to_io_error)?;
+    rows.clear();
+
+    Ok(path)
+}
+
+/// Reads rows back from a run file created by `write_parquet_run`
+struct ParquetRunReader {
+    reader: ParquetRecordBatchReader,
+    batch: Option<(Arc<RecordBatch>, Int64Array, UInt64Array)>,
+    row: usize,
+}
+
+impl ParquetRunReader {
+    fn open(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            reader: open_reader(path)?,
+            batch: None,
+            row: 0,
+        })
+    }
+
+    fn load_batch(&mut self, batch: RecordBatch) -> io::Result<()> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid Parquet sort run");
+        let num_columns = batch.num_columns();
+        let times = batch
+            .column(num_columns - 2)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(invalid)?
+            .clone();
+        let seqs = batch
+            .column(num_columns - 1)
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .ok_or_else(invalid)?
+            .clone();
+        let data = batch
+            .project(&(0..num_columns - 2).collect::<Vec<_>>())
+            .map_err(to_io_error)?;
+
+        self.batch = Some((Arc::new(data), times, seqs));
+        self.row = 0;
+        Ok(())
+    }
 }
 
+impl Iterator for ParquetRunReader {
+    type Item = io::Result<(SortKey, BatchRow)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self
+            .batch
+            .as_ref()
+            .is_none_or(|(batch, _, _)| self.row >= batch.num_rows())
+        {
+            let batch = match self.reader.next()? {
+                Ok(batch) => batch,
+                Err(err) => return Some(Err(to_io_error(err))),
+            };
+            if let Err(err) = self.load_batch(batch) {
+                return Some(Err(err));
+            }
+        }
+
+        let (batch, times, seqs) = self.batch.as_ref()?;
+        let row = self.row;
+        self.row += 1;
+        Some(Ok((
+            (times.value(row), seqs.value(row)),
+            (batch.clone(), row),
+        )))
+    }
+}
+
+/// Write gathered rows into the merged output as a single batch
+fn flush_rows(output: &mut TdfParquetMergedOutput, pending: &mut Vec<BatchRow>) -> io::Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let mut sources: Vec<Arc<RecordBatch>> = Vec::new();
+    let mut indices = Vec::with_capacity(pending.len());
+    for (batch, row) in pending.drain(..) {
+        let source_idx = match sources
+            .iter()
+            .position(|source| Arc::ptr_eq(source, &batch))
+        {
+            Some(idx) => idx,
+            None => {
+                sources.push(batch);
+                sources.len() - 1
+            }
+        };
+        indices.push((source_idx, row));
+    }
+
+    let source_refs: Vec<&RecordBatch> = sources.iter().map(|batch| batch.as_ref()).collect();
+    output.append_batch(&gather_rows(&indices, &source_refs)?)
+}
+
+/// Open a worker output or sort run for reading
+fn open_reader(path: &Path) -> io::Result<ParquetRecordBatchReader> {
+    ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+        .map_err(to_io_error)?
+        .with_batch_size(DEFAULT_BATCH_ROWS)
+        .build()
+        .map_err(to_io_error)
+}
+
+/// Merge worker outputs in worker order, which is the order of the input blocks
+fn merge_in_order<F: FnMut()>(
+    output: &mut TdfParquetMergedOutput,
+    inputs: &[PathBuf],
+    cancel: &CancellationToken,
+    mut on_input: F,
+) -> io::Result<()> {
+    for input_path in inputs {
+        for batch in open_reader(input_path)? {
+            cancel.check()?;
+            output.append_batch(&batch.map_err(to_io_error)?)?;
+        }
+
+        std::fs::remove_file(input_path)?;
+        on_input();
+    }
+    Ok(())
+}
+
+/// Merge worker outputs into chronological order, spilling sorted runs to disk to bound memory use
+fn merge_sorted_by_time<F: FnMut()>(
+    output: &mut TdfParquetMergedOutput,
+    inputs: &[PathBuf],
+    cancel: &CancellationToken,
+    mut on_input: F,
+) -> io::Result<()> {
+    let mut keys = SortKeys::new();
+    let mut runs = SortRuns::new();
+    let mut batches: Vec<RecordBatch> = Vec::new();
+    let mut rows: Vec<(SortKey, usize, usize)> = Vec::new();
+
+    for input_path in inputs {
+        for batch in open_reader(input_path)? {
+            cancel.check()?;
+            let batch = batch.map_err(to_io_error)?;
+            if rows.len() >= SORT_RUN_ROWS {
+                runs.push(write_parquet_run(
+                    output.sort_run_path(runs.len()),
+                    &batches,
+                    &mut rows,
+                )?);
+                batches.clear();
+            }
+
+            let times = batch
+                .column_by_name("timestamp")
+                .and_then(|column| column.as_any().downcast_ref::<TimestampMicrosecondArray>());
+            for row in 0..batch.num_rows() {
+                rows.push((keys.key(batch_row_time(times, row)), batches.len(), row));
+            }
+            batches.push(batch);
+        }
+
+        std::fs::remove_file(input_path)?;
+        on_input();
+    }
+
+    rows.sort_unstable_by_key(|(key, _, _)| *key);
+    let batches: Vec<Arc<RecordBatch>> = batches.into_iter().map(Arc::new).collect();
+    let mut sources: Vec<SortedSource<BatchRow>> = Vec::new();
+    for run in runs.iter() {
+        sources.push(Box::new(ParquetRunReader::open(run)?));
+    }
+    sources.push(Box::new(rows.into_iter().map(move |(key, batch, row)| {
+        Ok((key, (batches[batch].clone(), row)))
+    })));
+
+    let mut pending: Vec<BatchRow> = Vec::with_capacity(DEFAULT_BATCH_ROWS);
+    merge_sorted(sources, |row| {
+        pending.push(row);
+        if pending.len() >= DEFAULT_BATCH_ROWS {
+            cancel.check()?;
+            flush_rows(output, &mut pending)?;
+        }
+        Ok(())
+    })?;
+    flush_rows(output, &mut pending)
+}
+
+/// Merge the worker outputs of each output into its linearized files, like
+/// [`crate::output_csv::merge`]
 pub fn merge_with_threshold<T: ProgressReporter>(
     args: &mut RunArgs<T>,
     output_files: &mut Vec<PathBuf>,
-    stats_tdf: &Arc<Mutex<HashMap<(Option<u64>, u16), HashMap<usize, TdfDecoderOutputs>>>>,
+    output_rows: &mut HashMap<PathBuf, usize>,
+    merged_outputs: &mut MergedOutputs,
+    stats_tdf: &TdfWorkerStats,
     threshold_rows: usize,
 ) -> io::Result<()> {
     let results = stats_tdf.lock().unwrap();
@@ -384,25 +864,44 @@ pub fn merge_with_threshold<T: ProgressReporter>(
             *tdf_id,
             threshold_rows,
         );
+        let existing = merged_outputs
+            .remove(&(*remote_id, *tdf_id))
+            .unwrap_or_default();
+        output.resume(existing.clone())?;
+        let inputs: Vec<PathBuf> = worker_outputs
+            .keys()
+            .sorted()
+            .map(|worker| worker_outputs[worker].output.clone())
+            .collect();
 
-        for worker in worker_outputs.keys().sorted() {
-            let input_path = worker_outputs[worker].output.clone();
-            let file = File::open(&input_path)?;
-            let reader = ParquetRecordBatchReaderBuilder::try_new(file)
-                .map_err(to_io_error)?
-                .with_batch_size(DEFAULT_BATCH_ROWS)
-                .build()
-                .map_err(to_io_error)?;
-
-            for batch in reader {
-                output.append_batch(&batch.map_err(to_io_error)?)?;
-            }
-
-            std::fs::remove_file(input_path)?;
-            args.merge_reporter.increment(1);
+        let merged = if args.sort_by_time {
+            merge_sorted_by_time(&mut output, &inputs, &args.cancel, || {
+                args.merge_reporter.increment(1)
+            })
+        } else {
+            merge_in_order(&mut output, &inputs, &args.cancel, || {
+                args.merge_reporter.increment(1)
+            })
+        };
+        if merged.is_err() {
+            output.discard_resumed()?;
         }
-
-        output_files.extend(output.finish()?);
+        // Partially merged files are still reported, so a cancelled run can remove them
+        let files = output.finish()?;
+        output_files.extend(
+            files
+                .iter()
+                .filter(|file| !existing.contains(file))
+                .cloned(),
+        );
+        output_rows.extend(
+            files
+                .iter()
+                .zip(&output.file_rows)
+                .filter_map(|(file, rows)| Some((file.clone(), (*rows)?))),
+        );
+        merged_outputs.insert((*remote_id, *tdf_id), files);
+        merged?;
     }
 
     args.merge_reporter.stop();
@@ -410,6 +909,207 @@ pub fn merge_with_threshold<T: ProgressReporter>(
     Ok(())
 }
 
-fn to_io_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
-    io::Error::new(io::ErrorKind::Other, err)
+/// Row metadata for a sample, which has either an index or a timestamp
+pub(crate) fn row_meta(tdf_time: i64, tdf_idx: Option<u16>) -> TdfParquetRowMeta {
+    match tdf_idx {
+        Some(idx) => TdfParquetRowMeta {
+            time_unix_micros: None,
+            sample_idx: Some(idx),
+        },
+        // Times that can't be represented are written as nulls
+        None => TdfParquetRowMeta {
+            time_unix_micros: tdf::time::tdf_time_to_datetime(tdf_time)
+                .map(|datetime| datetime.timestamp_micros()),
+            sample_idx: None,
+        },
+    }
+}
+
+pub(crate) fn to_io_error<E: std::error::Error + Send + Sync + 'static>(err: E) -> io::Error {
+    io::Error::other(err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Float64Array, UInt16Array};
+
+    fn write_temperatures(path: &Path, rows: &[(Option<i64>, Option<u16>, f64)]) {
+        let schema = tdf::decoders_parquet::tdf_parquet_schema(4).unwrap();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(
+                TimestampMicrosecondArray::from(
+                    rows.iter().map(|(time, _, _)| *time).collect::<Vec<_>>(),
+                )
+                .with_timezone("+00:00"),
+            ),
+            Arc::new(UInt16Array::from(
+                rows.iter().map(|(_, idx, _)| *idx).collect::<Vec<_>>(),
+            )),
+            Arc::new(Float64Array::from(
+                rows.iter().map(|(_, _, value)| *value).collect::<Vec<_>>(),
+            )),
+        ];
+        let batch = RecordBatch::try_new(schema.clone(), columns).unwrap();
+        let mut writer = ArrowWriter::try_new(File::create(path).unwrap(), schema, None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+    }
+
+    fn read_temperatures(path: &Path) -> Vec<f64> {
+        ParquetRecordBatchReaderBuilder::try_new(File::open(path).unwrap())
+            .unwrap()
+            .build()
+            .unwrap()
+            .flat_map(|batch| {
+                let batch = batch.unwrap();
+                let values = batch
+                    .column_by_name("temperature")
+                    .unwrap()
+                    .as_any()
+                    .downcast_ref::<arrow_array::Float64Array>()
+                    .unwrap()
+                    .clone();
+                values.values().to_vec()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sort_by_time_merges_worker_outputs_chronologically() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let inputs = [
+            output_dir.join("worker_0.parquet"),
+            output_dir.join("worker_1.parquet"),
+        ];
+        write_temperatures(
+            &inputs[0],
+            &[
+                (Some(10), None, 1.0),
+                (Some(30), None, 4.0),
+                (None, Some(1), 5.0),
+            ],
+        );
+        write_temperatures(&inputs[1], &[(Some(10), None, 2.0), (Some(20), None, 3.0)]);
+
+        let mut output =
+            TdfParquetMergedOutput::new(output_dir.clone(), "out".to_string(), None, 4, 0);
+        merge_sorted_by_time(&mut output, &inputs, &CancellationToken::new(), || {}).unwrap();
+        let files = output.finish().unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert!(inputs.iter().all(|input| !input.exists()));
+        assert_eq!(read_temperatures(&files[0]), vec![1.0, 2.0, 3.0, 4.0, 5.0]);
+    }
+
+    #[test]
+    fn resumed_output_appends_to_the_last_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let inputs = [
+            output_dir.join("worker_0.parquet"),
+            output_dir.join("worker_1.parquet"),
+        ];
+        write_temperatures(&inputs[0], &[(Some(10), None, 1.0), (Some(20), None, 2.0)]);
+        write_temperatures(&inputs[1], &[(Some(30), None, 3.0), (Some(40), None, 4.0)]);
+
+        let mut output =
+            TdfParquetMergedOutput::new(output_dir.clone(), "out".to_string(), None, 4, 3);
+        merge_in_order(&mut output, &inputs[..1], &CancellationToken::new(), || {}).unwrap();
+        let files = output.finish().unwrap();
+        assert_eq!(files.len(), 1);
+
+        let mut output =
+            TdfParquetMergedOutput::new(output_dir.clone(), "out".to_string(), None, 4, 3);
+        output.resume(files).unwrap();
+        merge_in_order(&mut output, &inputs[1..], &CancellationToken::new(), || {}).unwrap();
+        let files = output.finish().unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(output.file_rows, vec![Some(3), Some(1)]);
+        assert_eq!(read_temperatures(&files[0]), vec![1.0, 2.0, 3.0]);
+        assert_eq!(read_temperatures(&files[1]), vec![4.0]);
+        assert!(!files[0].with_extension("parquet.resume").exists());
+    }
+
+    #[test]
+    fn failed_merge_leaves_the_resumed_file() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let input = output_dir.join("worker_0.parquet");
+        write_temperatures(&input, &[(Some(10), None, 1.0), (Some(20), None, 2.0)]);
+
+        let mut output =
+            TdfParquetMergedOutput::new(output_dir.clone(), "out".to_string(), None, 4, 0);
+        merge_in_order(&mut output, &[input], &CancellationToken::new(), || {}).unwrap();
+        let files = output.finish().unwrap();
+
+        let mut output =
+            TdfParquetMergedOutput::new(output_dir.clone(), "out".to_string(), None, 4, 0);
+        output.resume(files.clone()).unwrap();
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let missing = output_dir.join("worker_1.parquet");
+        assert!(merge_in_order(&mut output, &[missing], &cancel, || {}).is_err());
+        output.discard_resumed().unwrap();
+
+        assert_eq!(output.finish().unwrap(), files);
+        assert_eq!(read_temperatures(&files[0]), vec![1.0, 2.0]);
+        assert!(!files[0].with_extension("parquet.resume").exists());
+    }
+
+    #[test]
+    fn parquet_run_files_round_trip_sort_keys() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let input = output_dir.join("input.parquet");
+        write_temperatures(&input, &[(Some(20), None, 2.0), (Some(10), None, 1.0)]);
+        let batches: Vec<RecordBatch> =
+            ParquetRecordBatchReaderBuilder::try_new(File::open(&input).unwrap())
+                .unwrap()
+                .build()
+                .unwrap()
+                .map(|batch| batch.unwrap())
+                .collect();
+        let mut rows = vec![((20, 1), 0, 0), ((10, 2), 0, 1)];
+
+        let path = write_parquet_run(output_dir.join("run.parquet"), &batches, &mut rows).unwrap();
+        let read: Vec<(SortKey, BatchRow)> = ParquetRunReader::open(&path)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        assert!(rows.is_empty());
+        let keys: Vec<SortKey> = read.iter().map(|(key, _)| *key).collect();
+        assert_eq!(keys, vec![(10, 2), (20, 1)]);
+        assert_eq!(read[0].1.0.schema(), batches[0].schema());
+    }
+
+    #[test]
+    fn session_ids_follow_the_sample_index_column() {
+        let temp = tempfile::tempdir().unwrap();
+        let output_dir = temp.path().to_path_buf();
+        let mut writer =
+            TdfParquetWriter::new(0, output_dir.clone(), "out".to_string()).with_session_ids(true);
+        let payload = 2500i32.to_le_bytes();
+        for session_id in [None, Some(3)] {
+            writer.set_session_id(session_id);
+            writer
+                .write(None, 4, 0, None, 4, &mut Cursor::new(&payload[..]))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let path = writer.output_path(None, 4).unwrap();
+
+        let batch = open_reader(&path).unwrap().next().unwrap().unwrap();
+
+        assert_eq!(batch.schema().field(2).name(), SESSION_ID_COLUMN);
+        let session_ids = batch
+            .column(2)
+            .as_any()
+            .downcast_ref::<UInt32Array>()
+            .unwrap();
+        assert_eq!(session_ids.iter().collect::<Vec<_>>(), vec![None, Some(3)]);
+    }
 }
@@ -0,0 +1,426 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, TimestampMicrosecondType,
+    UInt8Type, UInt16Type, UInt32Type, UInt64Type,
+};
+use arrow_array::{Array, RecordBatch, RecordBatchReader};
+use arrow_schema::DataType;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+
+use crate::args::known_tdfs;
+use crate::output_parquet::to_io_error;
+
+const UTF8_BOM: char = '\u{feff}';
+
+/// Delimiters the CSV output can be written with, in the order they are looked for
+const CSV_DELIMITERS: [char; 3] = [',', ';', '\t'];
+
+/// Size, row count and TDF of an output file, for listing the outputs of a decode
+#[derive(Clone, Debug)]
+pub struct OutputFileInfo {
+    pub path: PathBuf,
+    pub size: u64,
+    /// Data rows, with the continuation lines of CSV arrays counted with their reading, `None` for
+    /// files that aren't CSV or Parquet
+    pub rows: Option<usize>,
+    /// TDF the file holds, `None` for derived outputs such as the boot sessions
+    pub tdf_name: Option<String>,
+}
+
+impl OutputFileInfo {
+    /// Read the size and row count of an output file.
+    ///
+    /// Parquet rows come from the file metadata, while CSV files are read through once.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let size = path.metadata()?.len();
+        let rows = match extension(path).as_str() {
+            "parquet" => Some(
+                SerializedFileReader::new(File::open(path)?)
+                    .map_err(to_io_error)?
+                    .metadata()
+                    .file_metadata()
+                    .num_rows() as usize,
+            ),
+            "csv" => Some(count_csv_rows(File::open(path)?)?),
+            _ => None,
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            size,
+            rows,
+            tdf_name: tdf_name(path),
+        })
+    }
+
+    /// Describe an output file whose rows are already known, reading them from the file otherwise
+    pub fn with_rows(path: &Path, rows: Option<usize>) -> io::Result<Self> {
+        let Some(rows) = rows else {
+            return Self::read(path);
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            size: path.metadata()?.len(),
+            rows: Some(rows),
+            tdf_name: tdf_name(path),
+        })
+    }
+
+    pub fn file_name(&self) -> String {
+        self.path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    }
+}
+
+/// First rows of an output file as text, one column per CSV column or Parquet leaf field
+#[derive(Debug, Default)]
+pub struct Preview {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Read up to `max_rows` rows from the start of a CSV or Parquet output
+pub fn read_preview(path: &Path, max_rows: usize) -> io::Result<Preview> {
+    match extension(path).as_str() {
+        "parquet" => preview_parquet(path, max_rows),
+        "csv" => preview_csv(File::open(path)?, max_rows),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Can't preview '{}'", path.display()),
+        )),
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// TDF of an output file, from a name ending in the TDF name and optionally a part index
+fn tdf_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy();
+    let stem = match stem.rsplit_once('_') {
+        Some((start, part)) if part.len() == 5 && part.bytes().all(|b| b.is_ascii_digit()) => start,
+        _ => &stem,
+    };
+    known_tdfs()
+        .into_iter()
+        .map(|(_, name)| name)
+        .filter(|name| {
+            stem == name
+                || stem
+                    .strip_suffix(name.as_str())
+                    .is_some_and(|start| start.ends_with('_'))
+        })
+        .max_by_key(String::len)
+}
+
+/// Readings in a CSV file, not counting the header, line breaks inside quoted values or the
+/// continuation lines of variable length arrays, which start with the delimiter
+fn count_csv_rows(file: impl Read) -> io::Result<usize> {
+    let mut reader = BufReader::new(file);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let delimiter = csv_delimiter(&header) as u8;
+    let mut quoted = false;
+    let mut line_start = true;
+    let mut rows: usize = 0;
+    loop {
+        let buffer = reader.fill_buf()?;
+        if buffer.is_empty() {
+            break;
+        }
+        for &byte in buffer {
+            if line_start && ![delimiter, b'\r', b'\n'].contains(&byte) {
+                rows += 1;
+            }
+            line_start = false;
+            match byte {
+                b'"' => quoted = !quoted,
+                b'\n' if !quoted => line_start = true,
+                _ => {}
+            }
+        }
+        let len = buffer.len();
+        reader.consume(len);
+    }
+    Ok(rows)
+}
+
+/// Delimiter of a CSV file, found from its header
+fn csv_delimiter(header: &str) -> char {
+    CSV_DELIMITERS
+        .into_iter()
+        .find(|delimiter| header.contains(*delimiter))
+        .unwrap_or(',')
+}
+
+fn preview_csv(file: impl Read, max_rows: usize) -> io::Result<Preview> {
+    let mut reader = BufReader::new(file);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let header = header.trim_start_matches(UTF8_BOM).trim_end();
+    let delimiter = csv_delimiter(header);
+
+    let mut preview = Preview {
+        columns: split_csv_record(header, delimiter),
+        rows: Vec::new(),
+    };
+    let mut record = String::new();
+    while preview.rows.len() < max_rows && reader.read_line(&mut record)? > 0 {
+        // Quoted values can span lines
+        if record.matches('"').count() % 2 == 1 {
+            continue;
+        }
+        preview.rows.push(split_csv_record(
+            record.trim_end_matches(['\r', '\n']),
+            delimiter,
+        ));
+        record.clear();
+    }
+    Ok(preview)
+}
+
+/// Values of a CSV record, with quotes removed and doubled quotes unescaped
+fn split_csv_record(record: &str, delimiter: char) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = record.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                values.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => values.push(String::new()),
+            c => values.last_mut().unwrap().push(c),
+        }
+    }
+    values
+}
+
+fn preview_parquet(path: &Path, max_rows: usize) -> io::Result<Preview> {
+    let mut reader = ParquetRecordBatchReaderBuilder::try_new(File::open(path)?)
+        .map_err(to_io_error)?
+        .with_batch_size(max_rows.max(1))
+        .with_limit(max_rows)
+        .build()
+        .map_err(to_io_error)?;
+
+    let batch = match reader.next().transpose().map_err(to_io_error)? {
+        Some(batch) => batch,
+        None => RecordBatch::new_empty(reader.schema()),
+    };
+    let mut preview = Preview::default();
+    let mut columns = Vec::new();
+    for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+        leaf_columns(field.name(), column.as_ref(), &mut columns);
+    }
+    preview.rows = (0..batch.num_rows().min(max_rows))
+        .map(|row| {
+            columns
+                .iter()
+                .map(|(_, array)| format_value(*array, row))
+                .collect()
+        })
+        .collect();
+    preview.columns = columns.into_iter().map(|(name, _)| name).collect();
+    Ok(preview)
+}
+
+/// Columns of a batch with struct fields flattened, named like the CSV output (e.g. `sample.x`)
+fn leaf_columns<'a>(name: &str, array: &'a dyn Array, out: &mut Vec<(String, &'a dyn Array)>) {
+    match array.data_type() {
+        DataType::Struct(fields) => {
+            for (field, column) in fields.iter().zip(array.as_struct().columns()) {
+                leaf_columns(&format!("{name}.{}", field.name()), column.as_ref(), out);
+            }
+        }
+        _ => out.push((name.to_string(), array)),
+    }
+}
+
+fn format_value(array: &dyn Array, row: usize) -> String {
+    macro_rules! primitive_value {
+        ($($arrow_type:ty),*) => {
+            $(
+                if let Some(array) = array.as_primitive_opt::<$arrow_type>() {
+                    return array.value(row).to_string();
+                }
+            )*
+        };
+    }
+
+    if array.is_null(row) {
+        return String::new();
+    }
+    match array.data_type() {
+        DataType::Timestamp(_, _) => {
+            let time = array
+                .as_primitive_opt::<TimestampMicrosecondType>()
+                .map(|array| array.value(row))
+                .and_then(chrono::DateTime::from_timestamp_micros);
+            time.map_or_else(String::new, |time| {
+                time.format("%Y-%m-%d %H:%M:%S%.6f").to_string()
+            })
+        }
+        DataType::Boolean => array.as_boolean().value(row).to_string(),
+        DataType::Utf8 => array.as_string::<i32>().value(row).to_string(),
+        DataType::Binary => array
+            .as_binary::<i32>()
+            .value(row)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect(),
+        DataType::List(_) => format_items(array.as_list::<i32>().value(row).as_ref()),
+        DataType::FixedSizeList(_, _) => {
+            format_items(array.as_fixed_size_list().value(row).as_ref())
+        }
+        DataType::Struct(_) => {
+            let mut columns = Vec::new();
+            leaf_columns("", array, &mut columns);
+            format!(
+                "{{{}}}",
+                columns
+                    .iter()
+                    .map(|(name, column)| format!(
+                        "{}: {}",
+                        name.trim_start_matches('.'),
+                        format_value(*column, row)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        }
+        _ => {
+            primitive_value!(
+                Int8Type,
+                Int16Type,
+                Int32Type,
+                Int64Type,
+                UInt8Type,
+                UInt16Type,
+                UInt32Type,
+                UInt64Type,
+                Float32Type,
+                Float64Type
+            );
+            format!("<{}>", array.data_type())
+        }
+    }
+}
+
+fn format_items(items: &dyn Array) -> String {
+    let values: Vec<String> = (0..items.len())
+        .map(|row| format_value(items, row))
+        .collect();
+    format!("[{}]", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_records_are_split_and_counted() {
+        let csv = "\u{feff}time;name;value\n1;\"a;\"\"b\"\"\";2,5\n2;\"line\nbreak\";3,5\n3;c;4,5";
+        assert_eq!(count_csv_rows(csv.as_bytes()).unwrap(), 3);
+        assert_eq!(count_csv_rows("time,value\n1,2\n".as_bytes()).unwrap(), 1);
+        assert_eq!(count_csv_rows("".as_bytes()).unwrap(), 0);
+        // Continuation lines of variable length arrays belong to the reading before them
+        let arrays = "time,value\n1,a\n,a-continued\n,a-continued\n2,b\n";
+        assert_eq!(count_csv_rows(arrays.as_bytes()).unwrap(), 2);
+
+        let preview = preview_csv(csv.as_bytes(), 2).unwrap();
+        assert_eq!(preview.columns, vec!["time", "name", "value"]);
+        assert_eq!(
+            preview.rows,
+            vec![vec!["1", "a;\"b\"", "2,5"], vec!["2", "line\nbreak", "3,5"]]
+        );
+    }
+
+    #[test]
+    fn parquet_fields_are_flattened() {
+        use arrow_array::builder::{Int16Builder, ListBuilder};
+        use arrow_array::{ArrayRef, Int16Array, StructArray, TimestampMicrosecondArray};
+        use arrow_schema::Field;
+        use std::sync::Arc;
+
+        let sample = StructArray::from(vec![
+            (
+                Arc::new(Field::new("x", DataType::Int16, false)),
+                Arc::new(Int16Array::from(vec![1, -2, 3])) as ArrayRef,
+            ),
+            (
+                Arc::new(Field::new("y", DataType::Int16, false)),
+                Arc::new(Int16Array::from(vec![4, 5, 6])) as ArrayRef,
+            ),
+        ]);
+        let mut items = ListBuilder::new(Int16Builder::new());
+        items.append_value([Some(7), Some(8)]);
+        items.append_null();
+        items.append_value([]);
+        let batch = RecordBatch::try_from_iter([
+            (
+                "time",
+                Arc::new(
+                    TimestampMicrosecondArray::from(vec![0, 1_500_000, 3_000_000])
+                        .with_timezone("+00:00"),
+                ) as ArrayRef,
+            ),
+            ("sample", Arc::new(sample) as ArrayRef),
+            ("items", Arc::new(items.finish()) as ArrayRef),
+        ])
+        .unwrap();
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("out_ACC_4G_00001.parquet");
+        let mut writer = parquet::arrow::ArrowWriter::try_new(
+            File::create(&path).unwrap(),
+            batch.schema(),
+            None,
+        )
+        .unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let info = OutputFileInfo::read(&path).unwrap();
+        assert_eq!(info.rows, Some(3));
+        assert_eq!(info.tdf_name.as_deref(), Some("ACC_4G"));
+        assert!(info.size > 0);
+
+        let preview = read_preview(&path, 2).unwrap();
+        assert_eq!(
+            preview.columns,
+            vec!["time", "sample.x", "sample.y", "items"]
+        );
+        assert_eq!(
+            preview.rows,
+            vec![
+                vec!["1970-01-01 00:00:00.000000", "1", "4", "[7, 8]"],
+                vec!["1970-01-01 00:00:01.500000", "-2", "5", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn output_names_give_the_tdf() {
+        let name = |path: &str| tdf_name(Path::new(path));
+        assert_eq!(name("out/ANNOUNCE.csv").as_deref(), Some("ANNOUNCE"));
+        assert_eq!(
+            name("out/dev_0123456789abcdef_BATTERY_STATE_00002.parquet").as_deref(),
+            Some("BATTERY_STATE")
+        );
+        assert_eq!(name("out/dev_sessions.csv"), None);
+        assert_eq!(name("out/devBATTERY_STATE.csv"), None);
+    }
+}
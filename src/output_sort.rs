@@ -0,0 +1,151 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of rows held in memory before a sorted run is spilled to disk
+pub(crate) const SORT_RUN_ROWS: usize = 1 << 18;
+
+/// Chronological sort key: timestamp in microseconds, then position in block order
+pub(crate) type SortKey = (i64, u64);
+
+/// Source of items that are already sorted by key
+pub(crate) type SortedSource<T> = Box<dyn Iterator<Item = io::Result<(SortKey, T)>>>;
+
+/// Sorted runs spilled to disk, removed when dropped so that failed or cancelled merges don't
+/// leave them behind
+pub(crate) struct SortRuns {
+    paths: Vec<PathBuf>,
+}
+
+impl SortRuns {
+    pub(crate) fn new() -> Self {
+        Self { paths: Vec::new() }
+    }
+
+    pub(crate) fn push(&mut self, path: PathBuf) {
+        self.paths.push(path);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Path> {
+        self.paths.iter().map(PathBuf::as_path)
+    }
+}
+
+impl Drop for SortRuns {
+    fn drop(&mut self) {
+        for path in self.paths.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Assigns sort keys to rows in block order.
+///
+/// Rows without a timestamp (index array samples and unparsable times) inherit the timestamp of
+/// the previous row, so they stay grouped with the sample that started their array.
+pub(crate) struct SortKeys {
+    last_time: i64,
+    seq: u64,
+}
+
+impl SortKeys {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_time: i64::MIN,
+            seq: 0,
+        }
+    }
+
+    pub(crate) fn key(&mut self, time: Option<i64>) -> SortKey {
+        if let Some(time) = time {
+            self.last_time = time;
+        }
+        self.seq += 1;
+        (self.last_time, self.seq)
+    }
+}
+
+/// K-way merge of sources that are individually sorted by key, emitting items in key order
+pub(crate) fn merge_sorted<T, S, F>(sources: Vec<S>, mut emit: F) -> io::Result<()>
+where
+    S: Iterator<Item = io::Result<(SortKey, T)>>,
+    F: FnMut(T) -> io::Result<()>,
+{
+    let mut sources = sources;
+    let mut heads: Vec<Option<T>> = Vec::with_capacity(sources.len());
+    let mut heap = BinaryHeap::new();
+
+    for (idx, source) in sources.iter_mut().enumerate() {
+        match source.next().transpose()? {
+            Some((key, item)) => {
+                heap.push(Reverse((key, idx)));
+                heads.push(Some(item));
+            }
+            None => heads.push(None),
+        }
+    }
+
+    while let Some(Reverse((_, idx))) = heap.pop() {
+        let item = heads[idx]
+            .take()
+            .expect("Merge source head should be populated");
+        emit(item)?;
+
+        if let Some((key, item)) = sources[idx].next().transpose()? {
+            heap.push(Reverse((key, idx)));
+            heads[idx] = Some(item);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_orders_by_time_then_block_order() {
+        let mut keys = SortKeys::new();
+        let first: Vec<io::Result<(SortKey, &str)>> = vec![
+            Ok((keys.key(Some(10)), "a")),
+            Ok((keys.key(None), "a-idx")),
+            Ok((keys.key(Some(30)), "c")),
+        ];
+        let second: Vec<io::Result<(SortKey, &str)>> =
+            vec![Ok((keys.key(Some(10)), "b")), Ok((keys.key(Some(20)), "d"))];
+
+        let mut out = Vec::new();
+        merge_sorted(vec![first.into_iter(), second.into_iter()], |item| {
+            out.push(item);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(out, vec!["a", "a-idx", "b", "d", "c"]);
+    }
+
+    #[test]
+    fn merge_propagates_source_errors() {
+        let source: Vec<io::Result<(SortKey, u8)>> = vec![
+            Ok(((0, 0), 1)),
+            Err(io::Error::new(io::ErrorKind::InvalidData, "bad run")),
+        ];
+        let mut out = Vec::new();
+
+        let err = merge_sorted(vec![source.into_iter()], |item| {
+            out.push(item);
+            Ok(())
+        })
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(out, vec![1]);
+    }
+}
@@ -0,0 +1,235 @@
+use arrow_array::RecordBatch;
+
+use crate::resample::{Stream, column_names};
+
+/// Samples summarised by each envelope of the finest decimation level
+const BASE_CHUNK_SAMPLES: usize = 8;
+
+/// Smallest and largest value of a run of samples, with the times they occurred at
+#[derive(Clone, Copy, Debug)]
+struct Envelope {
+    min_time: f64,
+    min: f64,
+    max_time: f64,
+    max: f64,
+}
+
+impl Envelope {
+    fn of(times: &[f64], values: &[f64]) -> Self {
+        let mut envelope = Self {
+            min_time: times[0],
+            min: values[0],
+            max_time: times[0],
+            max: values[0],
+        };
+        for (time, value) in times.iter().zip(values).skip(1) {
+            envelope = envelope.merge(&Self {
+                min_time: *time,
+                min: *value,
+                max_time: *time,
+                max: *value,
+            });
+        }
+        envelope
+    }
+
+    fn merge(&self, other: &Self) -> Self {
+        let (min_time, min) = if other.min < self.min {
+            (other.min_time, other.min)
+        } else {
+            (self.min_time, self.min)
+        };
+        let (max_time, max) = if other.max > self.max {
+            (other.max_time, other.max)
+        } else {
+            (self.max_time, self.max)
+        };
+        Self {
+            min_time,
+            min,
+            max_time,
+            max,
+        }
+    }
+
+    /// The two extremes, in time order
+    fn points(&self) -> [[f64; 2]; 2] {
+        let min = [self.min_time, self.min];
+        let max = [self.max_time, self.max];
+        if self.min_time <= self.max_time {
+            [min, max]
+        } else {
+            [max, min]
+        }
+    }
+}
+
+/// One numeric field of a TDF over time, decimated for plotting.
+///
+/// The smallest and largest value of successively larger runs of samples are kept, so that
+/// zoomed out views of millions of samples only draw a few thousand points while keeping the
+/// peaks that a plain subsample would miss.
+pub struct PlotSeries {
+    pub name: String,
+    /// Sample times as Unix seconds, in order
+    times: Vec<f64>,
+    values: Vec<f64>,
+    /// Envelopes of runs of 8, 16, 32... samples
+    levels: Vec<Vec<Envelope>>,
+}
+
+impl PlotSeries {
+    /// Series from samples in time order, samples without a value are skipped
+    pub fn new(name: String, times: &[f64], values: &[f64]) -> Self {
+        let (times, values): (Vec<f64>, Vec<f64>) = times
+            .iter()
+            .zip(values)
+            .filter(|(_, value)| !value.is_nan())
+            .unzip();
+
+        let mut levels: Vec<Vec<Envelope>> = Vec::new();
+        if times.len() > BASE_CHUNK_SAMPLES {
+            levels.push(
+                times
+                    .chunks(BASE_CHUNK_SAMPLES)
+                    .zip(values.chunks(BASE_CHUNK_SAMPLES))
+                    .map(|(times, values)| Envelope::of(times, values))
+                    .collect(),
+            );
+        }
+        while let Some(level) = levels.last()
+            && level.len() > 1
+        {
+            let next = level
+                .chunks(2)
+                .map(|pair| match pair {
+                    [first, second] => first.merge(second),
+                    [first] => *first,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        Self {
+            name,
+            times,
+            values,
+            levels,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Time of the first and last sample
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        Some((*self.times.first()?, *self.times.last()?))
+    }
+
+    /// Points to draw for the time range `[start, end]`, at most about `max_points` of them.
+    ///
+    /// The samples either side of the range are included so that lines run to the edges.
+    pub fn points(&self, start: f64, end: f64, max_points: usize) -> Vec<[f64; 2]> {
+        let first = self
+            .times
+            .partition_point(|time| *time < start)
+            .saturating_sub(1);
+        let last = (self.times.partition_point(|time| *time <= end) + 1).min(self.times.len());
+        if first >= last {
+            return Vec::new();
+        }
+
+        let count = last - first;
+        let max_points = max_points.max(2);
+        if count <= max_points || self.levels.is_empty() {
+            return (first..last)
+                .map(|idx| [self.times[idx], self.values[idx]])
+                .collect();
+        }
+
+        // Each envelope is drawn as two points
+        let mut level = 0;
+        let mut chunk = BASE_CHUNK_SAMPLES;
+        while level + 1 < self.levels.len() && 2 * count.div_ceil(chunk) > max_points {
+            level += 1;
+            chunk *= 2;
+        }
+        self.levels[level][first / chunk..last.div_ceil(chunk)]
+            .iter()
+            .flat_map(Envelope::points)
+            .collect()
+    }
+}
+
+/// Numeric fields of a TDF that can be plotted, with struct fields flattened (e.g. `sample_x`)
+pub fn plot_fields(tdf_id: u16) -> Vec<String> {
+    tdf::decoders_parquet::tdf_parquet_schema(tdf_id)
+        .map(|schema| column_names("", &schema))
+        .unwrap_or_default()
+}
+
+/// Series of the named fields of a TDF's batches, in time order
+pub fn plot_series(tdf_id: u16, batches: &[RecordBatch], fields: &[String]) -> Vec<PlotSeries> {
+    let stream = Stream::new(tdf_id, batches);
+    let times: Vec<f64> = stream
+        .times
+        .iter()
+        .map(|time| *time as f64 / 1_000_000.0)
+        .collect();
+
+    plot_fields(tdf_id)
+        .into_iter()
+        .zip(stream.columns.iter())
+        .filter(|(name, _)| fields.contains(name))
+        .map(|(name, values)| PlotSeries::new(name, &times, values))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimation_keeps_peaks() {
+        let times: Vec<f64> = (0..100_000).map(|idx| idx as f64 / 100.0).collect();
+        let mut values: Vec<f64> = times.iter().map(|time| time.sin()).collect();
+        values[54_321] = 10.0;
+        values[12_345] = -10.0;
+        values[100] = f64::NAN;
+        let series = PlotSeries::new("value".to_string(), &times, &values);
+        assert_eq!(series.len(), 99_999);
+        assert_eq!(series.time_range(), Some((0.0, 999.99)));
+
+        let points = series.points(0.0, 1000.0, 1000);
+        assert!(points.len() <= 1000 && points.len() > 500);
+        assert!(points.is_sorted_by(|a, b| a[0] <= b[0]));
+        assert!(points.contains(&[543.21, 10.0]));
+        assert!(points.contains(&[123.45, -10.0]));
+
+        // Zoomed in views draw the samples themselves, and one either side of the view
+        let points = series.points(500.0, 501.0, 1000);
+        assert_eq!(points.len(), 103);
+        assert_eq!(points[1], [500.0, 500f64.sin()]);
+        assert!(series.points(2000.0, 3000.0, 1000).len() <= 1);
+    }
+
+    #[test]
+    fn decoded_tdfs_plot_as_series() {
+        let data = std::fs::read("data/tdf_test_blocks.bin").unwrap();
+        let (_, batches) = crate::decode_to_arrow(&data, 512).unwrap();
+        let series = plot_series(4, &batches[&(None, 4)], &["temperature".to_string()]);
+
+        assert_eq!(plot_fields(4), vec!["temperature"]);
+        assert_eq!(plot_fields(11), vec!["sample_x", "sample_y", "sample_z"]);
+        assert_eq!(series.len(), 1);
+        assert_eq!(series[0].name, "temperature");
+        assert_eq!(series[0].len(), 24);
+        assert!(plot_series(4, &batches[&(None, 4)], &[]).is_empty());
+    }
+}
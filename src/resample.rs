@@ -0,0 +1,659 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::io::{self, Cursor};
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, TimestampMicrosecondType,
+    UInt8Type, UInt16Type, UInt32Type, UInt64Type,
+};
+use arrow_array::{Array, ArrayRef, Float64Array, RecordBatch, TimestampMicrosecondArray};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use itertools::Itertools;
+use tdf::TdfOutput;
+
+use crate::ArrowBatches;
+use crate::args::ResampleMethod;
+use crate::output_arrow::TdfArrowWriter;
+use crate::output_parquet::to_io_error;
+
+pub const DEFAULT_RESAMPLE_TOLERANCE_SECONDS: f64 = 1.0;
+/// Refuse grids that would take an unreasonable time to write, usually caused by a bad timestamp
+const MAX_RESAMPLED_ROWS: i64 = 100_000_000;
+const RESAMPLE_BATCH_ROWS: usize = 65536;
+/// Samples collected by a decode worker before they are converted for the resampler
+const TAP_BATCH_ROWS: usize = 4096;
+const TIMESTAMP_COLUMN: &str = "timestamp";
+const SAMPLE_IDX_COLUMN: &str = "sample_idx";
+const MICROS_PER_SECOND: f64 = 1_000_000.0;
+
+/// TDFs to join into a single table, and the grid to resample them onto
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResampleConfig {
+    /// TDFs of the logging device, in column order
+    pub tdfs: Vec<u16>,
+    /// Rows per second of the output
+    pub rate_hz: f64,
+    pub method: ResampleMethod,
+    /// Furthest a sample can be from a grid time and still be used for it
+    pub tolerance_seconds: f64,
+}
+
+impl ResampleConfig {
+    pub fn validate(&self) -> io::Result<()> {
+        let invalid = |msg: String| Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+
+        if self.tdfs.is_empty() {
+            return invalid("No TDFs selected for resampling".to_string());
+        }
+        if let Some(tdf_id) = self
+            .tdfs
+            .iter()
+            .find(|tdf_id| tdf::decoders_parquet::tdf_parquet_schema(**tdf_id).is_none())
+        {
+            return invalid(format!("TDF {tdf_id} can't be resampled"));
+        }
+        if !(self.rate_hz.is_finite() && self.rate_hz > 0.0) || self.interval_micros() == 0 {
+            return invalid(format!(
+                "Resample rate ({} Hz) must be positive and at most 1 MHz",
+                self.rate_hz
+            ));
+        }
+        if !(self.tolerance_seconds.is_finite() && self.tolerance_seconds >= 0.0) {
+            return invalid(format!(
+                "Resample tolerance ({} s) must not be negative",
+                self.tolerance_seconds
+            ));
+        }
+        Ok(())
+    }
+
+    fn interval_micros(&self) -> i64 {
+        (MICROS_PER_SECOND / self.rate_hz).round() as i64
+    }
+
+    fn tolerance_micros(&self) -> i64 {
+        (self.tolerance_seconds * MICROS_PER_SECOND).round() as i64
+    }
+}
+
+/// Timestamped samples of one TDF, in time order once [`Stream::sort`] has been called
+#[derive(Debug)]
+pub struct Stream {
+    pub(crate) times: Vec<i64>,
+    /// Values of each numeric field, NaN where missing
+    pub(crate) columns: Vec<Vec<f64>>,
+    /// Number of samples at or before the current grid time
+    next: usize,
+}
+
+impl Stream {
+    /// Gather the numeric fields of the batches, samples without a timestamp are skipped
+    pub(crate) fn new(tdf_id: u16, batches: &[RecordBatch]) -> Self {
+        let mut stream = Self::empty(tdf_id);
+        stream.extend(batches);
+        stream.sort();
+        stream
+    }
+
+    fn empty(tdf_id: u16) -> Self {
+        let num_columns = tdf::decoders_parquet::tdf_parquet_schema(tdf_id)
+            .map_or(0, |schema| column_names("", &schema).len());
+        Self {
+            times: Vec::new(),
+            columns: vec![Vec::new(); num_columns],
+            next: 0,
+        }
+    }
+
+    /// Append the numeric fields of the batches, samples without a timestamp are skipped
+    fn extend(&mut self, batches: &[RecordBatch]) {
+        for batch in batches {
+            let Some(batch_times) = batch
+                .column_by_name(TIMESTAMP_COLUMN)
+                .and_then(|column| column.as_primitive_opt::<TimestampMicrosecondType>())
+            else {
+                continue;
+            };
+            let mut values = Vec::new();
+            for (field, column) in batch.schema().fields().iter().zip(batch.columns()) {
+                if !is_sample_meta(field) {
+                    leaf_values(column.as_ref(), &mut values);
+                }
+            }
+            for row in 0..batch.num_rows() {
+                if batch_times.is_null(row) {
+                    continue;
+                }
+                self.times.push(batch_times.value(row));
+                for (column, values) in self.columns.iter_mut().zip(values.iter()) {
+                    column.push(values[row]);
+                }
+            }
+        }
+    }
+
+    /// Append the samples of a later part of the input
+    fn append(&mut self, mut other: Stream) {
+        self.times.append(&mut other.times);
+        for (column, other) in self.columns.iter_mut().zip(other.columns.iter_mut()) {
+            column.append(other);
+        }
+    }
+
+    /// Samples are gathered in input order, which isn't necessarily time order
+    fn sort(&mut self) {
+        if self.times.is_sorted() {
+            return;
+        }
+        let order: Vec<usize> = (0..self.times.len())
+            .sorted_by_key(|idx| self.times[*idx])
+            .collect();
+        self.times = order.iter().map(|idx| self.times[*idx]).collect();
+        for column in self.columns.iter_mut() {
+            *column = order.iter().map(|idx| column[*idx]).collect();
+        }
+    }
+
+    /// Samples to combine for grid time `time`, as `(first, second, weight of second)`
+    fn source(
+        &mut self,
+        time: i64,
+        method: ResampleMethod,
+        tolerance: i64,
+    ) -> Option<(usize, usize, f64)> {
+        while self.next < self.times.len() && self.times[self.next] <= time {
+            self.next += 1;
+        }
+        let previous = self.next.checked_sub(1);
+        let following = (self.next < self.times.len()).then_some(self.next);
+
+        match method {
+            ResampleMethod::Nearest => {
+                let nearest = previous
+                    .into_iter()
+                    .chain(following)
+                    .min_by_key(|idx| (self.times[*idx] - time).abs())?;
+                ((self.times[nearest] - time).abs() <= tolerance).then_some((nearest, nearest, 0.0))
+            }
+            ResampleMethod::Linear => {
+                let previous = previous?;
+                if self.times[previous] == time {
+                    return Some((previous, previous, 0.0));
+                }
+                let following = following?;
+                let (start, end) = (self.times[previous], self.times[following]);
+                (time - start <= tolerance && end - time <= tolerance).then(|| {
+                    let weight = (time - start) as f64 / (end - start) as f64;
+                    (previous, following, weight)
+                })
+            }
+        }
+    }
+
+    /// Seconds since the last sample at or before the current grid time
+    fn staleness(&self, time: i64) -> Option<f64> {
+        let previous = self.next.checked_sub(1)?;
+        Some((time - self.times[previous]) as f64 / MICROS_PER_SECOND)
+    }
+}
+
+/// Joins TDF streams onto a common time grid, producing record batches of the joined table.
+///
+/// Each TDF contributes a column for each of its numeric fields, named `<TDF>_<field>`, and a
+/// `<TDF>_staleness` column with the seconds since its last sample.
+pub struct Resampler {
+    schema: SchemaRef,
+    streams: Vec<Stream>,
+    method: ResampleMethod,
+    interval: i64,
+    tolerance: i64,
+    /// Next grid time and the last grid time, in Unix microseconds
+    next_time: i64,
+    end_time: i64,
+}
+
+impl Resampler {
+    pub fn new(batches: &ArrowBatches, config: &ResampleConfig) -> io::Result<Self> {
+        let streams = config
+            .tdfs
+            .iter()
+            .map(|tdf_id| {
+                let stream_batches = batches.get(&(None, *tdf_id)).map_or(&[][..], Vec::as_slice);
+                (*tdf_id, Stream::new(*tdf_id, stream_batches))
+            })
+            .collect();
+        Self::from_streams(streams, config)
+    }
+
+    /// Resample the streams of each TDF of the logging device, as collected by [`ResampleTap`]
+    pub fn from_streams(
+        mut streams: HashMap<u16, Stream>,
+        config: &ResampleConfig,
+    ) -> io::Result<Self> {
+        config.validate()?;
+        let tdfs: Vec<u16> = config.tdfs.iter().copied().unique().collect();
+
+        let mut fields = vec![Field::new(
+            TIMESTAMP_COLUMN,
+            DataType::Timestamp(TimeUnit::Microsecond, Some("+00:00".into())),
+            false,
+        )];
+        let mut tdf_streams = Vec::new();
+        for tdf_id in tdfs {
+            let name = tdf::decoders::tdf_name(&tdf_id);
+            if let Some(schema) = tdf::decoders_parquet::tdf_parquet_schema(tdf_id) {
+                fields.extend(
+                    column_names(&name, &schema)
+                        .into_iter()
+                        .map(|column| Field::new(column, DataType::Float64, true)),
+                );
+            }
+            fields.push(Field::new(
+                format!("{name}_staleness"),
+                DataType::Float64,
+                true,
+            ));
+            let mut stream = streams
+                .remove(&tdf_id)
+                .unwrap_or_else(|| Stream::empty(tdf_id));
+            stream.sort();
+            tdf_streams.push(stream);
+        }
+        let streams = tdf_streams;
+
+        // Grid times are multiples of the interval, so that separate decodes line up
+        let interval = config.interval_micros();
+        let first = streams
+            .iter()
+            .filter_map(|s| s.times.first())
+            .min()
+            .copied();
+        let last = streams.iter().filter_map(|s| s.times.last()).max().copied();
+        let (next_time, end_time) = match first.zip(last) {
+            Some((first, last)) => (first.div_euclid(interval) * interval, last),
+            None => (1, 0),
+        };
+        let rows = (end_time - next_time) / interval + 1;
+        if rows > MAX_RESAMPLED_ROWS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Resampling would produce {rows} rows, lower the rate or limit the time range"
+                ),
+            ));
+        }
+
+        Ok(Self {
+            schema: Arc::new(Schema::new(fields)),
+            streams,
+            method: config.method,
+            interval,
+            tolerance: config.tolerance_micros(),
+            next_time,
+            end_time,
+        })
+    }
+
+    pub fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    /// Rows still to be produced
+    pub fn num_rows(&self) -> usize {
+        if self.next_time > self.end_time {
+            return 0;
+        }
+        ((self.end_time - self.next_time) / self.interval + 1) as usize
+    }
+
+    fn next_batch(&mut self) -> io::Result<RecordBatch> {
+        let remaining = self.num_rows();
+        let times: Vec<i64> = (0..remaining.min(RESAMPLE_BATCH_ROWS))
+            .map(|row| self.next_time + row as i64 * self.interval)
+            .collect();
+        self.next_time += times.len() as i64 * self.interval;
+
+        let mut columns: Vec<ArrayRef> = vec![Arc::new(
+            TimestampMicrosecondArray::from(times.clone()).with_timezone("+00:00"),
+        )];
+        for stream in self.streams.iter_mut() {
+            let mut values = vec![Vec::with_capacity(times.len()); stream.columns.len()];
+            let mut staleness = Vec::with_capacity(times.len());
+            for time in times.iter() {
+                let source = stream.source(*time, self.method, self.tolerance);
+                for (column, values) in stream.columns.iter().zip(values.iter_mut()) {
+                    values.push(source.and_then(|(first, second, weight)| {
+                        let value = column[first] + (column[second] - column[first]) * weight;
+                        (!value.is_nan()).then_some(value)
+                    }));
+                }
+                staleness.push(stream.staleness(*time));
+            }
+            columns.extend(
+                values
+                    .into_iter()
+                    .map(|values| Arc::new(Float64Array::from(values)) as ArrayRef),
+            );
+            columns.push(Arc::new(Float64Array::from(staleness)));
+        }
+        RecordBatch::try_new(self.schema.clone(), columns).map_err(to_io_error)
+    }
+}
+
+impl Iterator for Resampler {
+    type Item = io::Result<RecordBatch>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        (self.next_time <= self.end_time).then(|| self.next_batch())
+    }
+}
+
+/// Collects the samples of the resampled TDFs while a decode worker writes its outputs, so that
+/// the input isn't decoded again for the resampled output.
+///
+/// Samples are kept as the numeric values used by the resampler, rather than as record batches.
+pub(crate) struct ResampleTap {
+    tdfs: Vec<u16>,
+    writer: TdfArrowWriter,
+    pending: usize,
+    streams: HashMap<u16, Stream>,
+}
+
+impl ResampleTap {
+    pub(crate) fn new(tdfs: &[u16]) -> Self {
+        Self {
+            tdfs: tdfs.to_vec(),
+            writer: TdfArrowWriter::new_with_batch_rows(TAP_BATCH_ROWS),
+            pending: 0,
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Whether the sample is one of the resampled TDFs of the logging device
+    pub(crate) fn wants(&self, remote_id: Option<u64>, tdf_id: u16) -> bool {
+        remote_id.is_none() && self.tdfs.contains(&tdf_id)
+    }
+
+    /// Collect a sample without advancing the cursor
+    pub(crate) fn write(
+        &mut self,
+        tdf_id: u16,
+        tdf_time: i64,
+        tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        let mut cursor = cursor.clone();
+        self.writer
+            .write(None, tdf_id, tdf_time, tdf_idx, size, &mut cursor)?;
+        self.pending += 1;
+        if self.pending >= TAP_BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let writer = std::mem::replace(
+            &mut self.writer,
+            TdfArrowWriter::new_with_batch_rows(TAP_BATCH_ROWS),
+        );
+        for ((_, tdf_id), batches) in writer.finish()? {
+            self.streams
+                .entry(tdf_id)
+                .or_insert_with(|| Stream::empty(tdf_id))
+                .extend(&batches);
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Samples collected for each TDF, in the order they were logged
+    pub(crate) fn finish(mut self) -> io::Result<HashMap<u16, Stream>> {
+        self.flush()?;
+        Ok(self.streams)
+    }
+}
+
+/// Join the streams collected by each worker, in block order
+pub(crate) fn combine_streams(
+    workers: impl IntoIterator<Item = HashMap<u16, Stream>>,
+) -> HashMap<u16, Stream> {
+    let mut streams: HashMap<u16, Stream> = HashMap::new();
+    for worker in workers {
+        for (tdf_id, stream) in worker {
+            match streams.entry(tdf_id) {
+                Entry::Occupied(mut entry) => entry.get_mut().append(stream),
+                Entry::Vacant(entry) => {
+                    entry.insert(stream);
+                }
+            }
+        }
+    }
+    streams
+}
+
+fn is_sample_meta(field: &Field) -> bool {
+    field.name() == TIMESTAMP_COLUMN || field.name() == SAMPLE_IDX_COLUMN
+}
+
+fn is_resampled(data_type: &DataType) -> bool {
+    matches!(
+        data_type,
+        DataType::Int8
+            | DataType::Int16
+            | DataType::Int32
+            | DataType::Int64
+            | DataType::UInt8
+            | DataType::UInt16
+            | DataType::UInt32
+            | DataType::UInt64
+            | DataType::Float32
+            | DataType::Float64
+    )
+}
+
+/// Names of the numeric fields of a TDF schema, with struct fields flattened
+pub(crate) fn column_names(prefix: &str, schema: &Schema) -> Vec<String> {
+    fn leaf_names(prefix: &str, field: &Field, out: &mut Vec<String>) {
+        let name = match prefix {
+            "" => field.name().to_string(),
+            prefix => format!("{prefix}_{}", field.name()),
+        };
+        match field.data_type() {
+            DataType::Struct(fields) => fields.iter().for_each(|f| leaf_names(&name, f, out)),
+            data_type if is_resampled(data_type) => out.push(name),
+            _ => {}
+        }
+    }
+
+    let mut names = Vec::new();
+    for field in schema
+        .fields()
+        .iter()
+        .filter(|field| !is_sample_meta(field))
+    {
+        leaf_names(prefix, field, &mut names);
+    }
+    names
+}
+
+/// Values of the numeric fields of an array, in the same order as [`column_names`]
+fn leaf_values(array: &dyn Array, out: &mut Vec<Vec<f64>>) {
+    macro_rules! numeric_values {
+        ($($arrow_type:ty),*) => {
+            $(
+                if let Some(array) = array.as_primitive_opt::<$arrow_type>() {
+                    out.push(
+                        array
+                            .iter()
+                            .map(|value| value.map_or(f64::NAN, |value| value as f64))
+                            .collect(),
+                    );
+                    return;
+                }
+            )*
+        };
+    }
+
+    match array.data_type() {
+        DataType::Struct(_) => array
+            .as_struct()
+            .columns()
+            .iter()
+            .for_each(|column| leaf_values(column.as_ref(), out)),
+        data_type if is_resampled(data_type) => {
+            numeric_values!(
+                Int8Type,
+                Int16Type,
+                Int32Type,
+                Int64Type,
+                UInt8Type,
+                UInt16Type,
+                UInt32Type,
+                UInt64Type,
+                Float32Type,
+                Float64Type
+            );
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_array::{Int16Array, StructArray, UInt16Array};
+
+    const SECOND: i64 = 1_000_000;
+
+    fn acc_batch(times: &[i64], x: &[i16]) -> RecordBatch {
+        let schema = tdf::decoders_parquet::tdf_parquet_schema(11).unwrap();
+        let DataType::Struct(fields) = schema.field(2).data_type().clone() else {
+            panic!("ACC sample should be a struct");
+        };
+        let axis = |values: &[i16]| Arc::new(Int16Array::from(values.to_vec())) as ArrayRef;
+        let sample = StructArray::new(fields, vec![axis(x), axis(x), axis(x)], None);
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(TimestampMicrosecondArray::from(times.to_vec()).with_timezone("+00:00")),
+                Arc::new(UInt16Array::from(vec![None; times.len()])),
+                Arc::new(sample),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn temperature_batch(times: &[i64], temperature: &[f64]) -> RecordBatch {
+        RecordBatch::try_new(
+            tdf::decoders_parquet::tdf_parquet_schema(4).unwrap(),
+            vec![
+                Arc::new(TimestampMicrosecondArray::from(times.to_vec()).with_timezone("+00:00")),
+                Arc::new(UInt16Array::from(vec![None; times.len()])),
+                Arc::new(Float64Array::from(temperature.to_vec())),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn resample(method: ResampleMethod, tolerance_seconds: f64) -> RecordBatch {
+        let mut batches = ArrowBatches::new();
+        // Out of order batches, as decoded from a ring buffer that wrapped
+        batches.insert(
+            (None, 11),
+            vec![
+                acc_batch(&[2 * SECOND, 3 * SECOND], &[20, 30]),
+                acc_batch(&[0, SECOND], &[0, 10]),
+            ],
+        );
+        batches.insert((None, 4), vec![temperature_batch(&[SECOND], &[21.5])]);
+        let config = ResampleConfig {
+            tdfs: vec![11, 4],
+            rate_hz: 2.0,
+            method,
+            tolerance_seconds,
+        };
+
+        let batches: Vec<RecordBatch> = Resampler::new(&batches, &config)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(batches.len(), 1);
+        batches.into_iter().next().unwrap()
+    }
+
+    fn column(batch: &RecordBatch, name: &str) -> Vec<Option<f64>> {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_primitive::<Float64Type>()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn streams_are_interpolated_onto_the_grid() {
+        let batch = resample(ResampleMethod::Linear, 1.0);
+
+        assert_eq!(batch.num_rows(), 7);
+        assert_eq!(
+            batch.schema().field(1).name(),
+            "ACC_4G_sample_x",
+            "Struct fields are flattened"
+        );
+        assert_eq!(
+            column(&batch, "ACC_4G_sample_x"),
+            vec![
+                Some(0.0),
+                Some(5.0),
+                Some(10.0),
+                Some(15.0),
+                Some(20.0),
+                Some(25.0),
+                Some(30.0)
+            ]
+        );
+        // A single sample can only be used at its exact time
+        assert_eq!(
+            column(&batch, "AMBIENT_TEMPERATURE_temperature")[1..4],
+            [None, Some(21.5), None]
+        );
+        assert_eq!(
+            column(&batch, "AMBIENT_TEMPERATURE_staleness")[1..4],
+            [None, Some(0.0), Some(0.5)]
+        );
+    }
+
+    #[test]
+    fn nearest_samples_respect_the_tolerance() {
+        let batch = resample(ResampleMethod::Nearest, 0.5);
+
+        assert_eq!(
+            column(&batch, "AMBIENT_TEMPERATURE_temperature"),
+            vec![None, Some(21.5), Some(21.5), Some(21.5), None, None, None]
+        );
+        assert_eq!(column(&batch, "ACC_4G_sample_x")[1], Some(0.0));
+    }
+
+    #[test]
+    fn invalid_configs_are_rejected() {
+        let config = ResampleConfig {
+            tdfs: vec![11],
+            rate_hz: 0.0,
+            method: ResampleMethod::Linear,
+            tolerance_seconds: 1.0,
+        };
+        let err = config.validate().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let config = ResampleConfig {
+            tdfs: Vec::new(),
+            rate_hz: 1.0,
+            ..config
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use byteorder::{LittleEndian, ReadBytesExt};
+use serde::{Deserialize, Serialize};
+use std::io::{self, Cursor};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+
+use tdf::TdfOutput;
+
+use crate::CancellationToken;
+use crate::continuity::serialize_tdf_time;
+use crate::input::ConcatenatedInput;
+use crate::output_filter::skip_payload;
+
+const TDF_ANNOUNCE: u16 = 1;
+const TDF_REBOOT_INFO: u16 = 6;
+const TDF_ANNOUNCE_V2: u16 = 7;
+/// Length of the thread name in REBOOT_INFO
+const REBOOT_THREAD_LEN: usize = 8;
+
+/// TDF output that can tag each row with the boot session it was logged in
+pub trait SessionIdOutput: TdfOutput {
+    /// Session of the rows written after this call, `None` before the first boot record
+    fn set_session_id(&mut self, session_id: Option<u32>);
+}
+
+/// Application and firmware version from ANNOUNCE or ANNOUNCE_V2
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Firmware {
+    pub application: u32,
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u16,
+    pub build_num: u32,
+}
+
+impl std::fmt::Display for Firmware {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}+{:08x}",
+            self.major, self.minor, self.revision, self.build_num
+        )
+    }
+}
+
+/// Cause of a reset, from the REBOOT_INFO logged when the device next boots
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResetInfo {
+    pub reason: u8,
+    pub hardware_flags: u32,
+    /// Uptime in seconds when the reset occurred
+    pub uptime: u32,
+    /// Reason specific parameters, the faulting PC and LR for exceptions
+    pub param_1: u32,
+    pub param_2: u32,
+    pub thread: String,
+}
+
+/// Boot related sample of the logging device
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum BootRecord {
+    Reboot { count: u32, reset: ResetInfo },
+    Announce { reboots: u32, firmware: Firmware },
+}
+
+impl BootRecord {
+    /// Parse the payload of a boot related TDF, `None` for other TDFs or truncated payloads
+    fn parse(tdf_id: u16, payload: &[u8]) -> Option<Self> {
+        let mut cursor = Cursor::new(payload);
+        match tdf_id {
+            TDF_REBOOT_INFO => {
+                let reason = cursor.read_u8().ok()?;
+                let hardware_flags = cursor.read_u32::<LittleEndian>().ok()?;
+                // ANNOUNCE only logs the low 16 bits of the reboot counter, so sessions are
+                // identified by those for both records to agree
+                let count = cursor.read_u32::<LittleEndian>().ok()? & u32::from(u16::MAX);
+                let uptime = cursor.read_u32::<LittleEndian>().ok()?;
+                let param_1 = cursor.read_u32::<LittleEndian>().ok()?;
+                let param_2 = cursor.read_u32::<LittleEndian>().ok()?;
+                let start = cursor.position() as usize;
+                let name = payload.get(start..(start + REBOOT_THREAD_LEN).min(payload.len()))?;
+                let thread = String::from_utf8_lossy(name)
+                    .trim_end_matches(char::from(0))
+                    .to_string();
+
+                Some(BootRecord::Reboot {
+                    count,
+                    reset: ResetInfo {
+                        reason,
+                        hardware_flags,
+                        uptime,
+                        param_1,
+                        param_2,
+                        thread,
+                    },
+                })
+            }
+            TDF_ANNOUNCE | TDF_ANNOUNCE_V2 => {
+                let application = cursor.read_u32::<LittleEndian>().ok()?;
+                let major = cursor.read_u8().ok()?;
+                let minor = cursor.read_u8().ok()?;
+                let revision = cursor.read_u16::<LittleEndian>().ok()?;
+                let build_num = cursor.read_u32::<LittleEndian>().ok()?;
+                if tdf_id == TDF_ANNOUNCE_V2 {
+                    // Board CRC
+                    cursor.read_u16::<LittleEndian>().ok()?;
+                }
+                // KV CRC, blocks and uptime
+                for _ in 0..3 {
+                    cursor.read_u32::<LittleEndian>().ok()?;
+                }
+                let reboots = cursor.read_u16::<LittleEndian>().ok()? as u32;
+
+                Some(BootRecord::Announce {
+                    reboots,
+                    firmware: Firmware {
+                        application,
+                        major,
+                        minor,
+                        revision,
+                        build_num,
+                    },
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a sample of the logging device without advancing the cursor
+    pub(crate) fn peek(
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        size: u8,
+        cursor: &Cursor<&[u8]>,
+    ) -> Option<Self> {
+        if remote_id.is_some()
+            || !matches!(tdf_id, TDF_ANNOUNCE | TDF_REBOOT_INFO | TDF_ANNOUNCE_V2)
+        {
+            return None;
+        }
+        let start = cursor.position() as usize;
+        let payload = cursor.get_ref().get(start..start + size as usize)?;
+        Self::parse(tdf_id, payload)
+    }
+}
+
+/// Period between two reboots of the logging device
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Session {
+    /// Reboot counter of the device during the session (its low 16 bits), `None` for data logged
+    /// before the first decoded boot record
+    pub session_id: Option<u32>,
+    /// Time of the first sample of the session
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub start: Option<i64>,
+    /// Time of the last sample of the session
+    #[serde(serialize_with = "serialize_tdf_time")]
+    pub end: Option<i64>,
+    pub firmware: Option<Firmware>,
+    /// Reset that ended the session
+    pub reset: Option<ResetInfo>,
+    /// Reset that started the session
+    #[serde(skip)]
+    boot: Option<ResetInfo>,
+}
+
+impl Session {
+    /// Extend the session with a later part of it
+    fn extend(&mut self, next: Session) {
+        self.session_id = self.session_id.or(next.session_id);
+        self.start = self.start.or(next.start);
+        self.end = next.end.or(self.end);
+        self.firmware = self.firmware.or(next.firmware);
+    }
+}
+
+/// Session as saved in the manifest of an incremental decode, with its TDF times and the reset
+/// that started it
+#[derive(Serialize, Deserialize)]
+struct SavedSession {
+    session_id: Option<u32>,
+    start: Option<i64>,
+    end: Option<i64>,
+    firmware: Option<Firmware>,
+    boot: Option<ResetInfo>,
+}
+
+/// Tracks the boot sessions in the blocks decoded by one worker.
+///
+/// Like [`crate::continuity::ContinuityTracker`], the trackers of all workers are combined in
+/// block order, with [`SessionTracker::append`].
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<SavedSession>", into = "Vec<SavedSession>")]
+pub struct SessionTracker {
+    sessions: Vec<Session>,
+}
+
+impl From<Vec<SavedSession>> for SessionTracker {
+    fn from(saved: Vec<SavedSession>) -> Self {
+        let sessions = saved
+            .into_iter()
+            .map(|session| Session {
+                session_id: session.session_id,
+                start: session.start,
+                end: session.end,
+                firmware: session.firmware,
+                reset: None,
+                boot: session.boot,
+            })
+            .collect();
+        Self { sessions }
+    }
+}
+
+impl From<SessionTracker> for Vec<SavedSession> {
+    fn from(tracker: SessionTracker) -> Self {
+        tracker
+            .sessions
+            .into_iter()
+            .map(|session| SavedSession {
+                session_id: session.session_id,
+                start: session.start,
+                end: session.end,
+                firmware: session.firmware,
+                boot: session.boot,
+            })
+            .collect()
+    }
+}
+
+impl SessionTracker {
+    /// Tracker for a range of blocks that starts in session `initial_id`
+    pub fn new(initial_id: Option<u32>) -> Self {
+        Self {
+            sessions: vec![Session {
+                session_id: initial_id,
+                ..Default::default()
+            }],
+        }
+    }
+
+    fn current(&mut self) -> &mut Session {
+        if self.sessions.is_empty() {
+            self.sessions.push(Session::default());
+        }
+        self.sessions.last_mut().unwrap()
+    }
+
+    pub fn current_id(&self) -> Option<u32> {
+        self.sessions.last().and_then(|session| session.session_id)
+    }
+
+    pub(crate) fn record(&mut self, record: BootRecord) {
+        let current = self.current();
+        match record {
+            BootRecord::Reboot { count, reset } => {
+                // The data so far is either from the same boot or has no known session yet
+                let same_boot = current.boot.is_none()
+                    && current
+                        .session_id
+                        .map_or(current.start.is_none(), |id| id == count);
+                if !same_boot {
+                    self.sessions.push(Session::default());
+                }
+                let current = self.current();
+                current.session_id = Some(count);
+                current.boot = Some(reset);
+            }
+            BootRecord::Announce { reboots, firmware } => {
+                match current.session_id {
+                    // Data before the first boot record of the range is from the announced boot
+                    None => current.session_id = Some(reboots),
+                    Some(id) if id == reboots => {}
+                    // The REBOOT_INFO of this boot wasn't logged
+                    Some(_) => self.sessions.push(Session {
+                        session_id: Some(reboots),
+                        ..Default::default()
+                    }),
+                }
+                self.current().firmware = Some(firmware);
+            }
+        }
+    }
+
+    pub fn sample(&mut self, time: i64) {
+        let current = self.current();
+        current.start.get_or_insert(time);
+        current.end = Some(time);
+    }
+
+    /// Append the tracker of the following range of blocks
+    pub fn append(&mut self, next: SessionTracker) {
+        for session in next.sessions {
+            match self.sessions.last_mut() {
+                // A range starts part way through the session of the previous range
+                Some(prev)
+                    if session.boot.is_none()
+                        && session.session_id.is_none_or(|id| {
+                            prev.session_id.is_none_or(|prev_id| prev_id == id)
+                        }) =>
+                {
+                    prev.extend(session)
+                }
+                _ => self.sessions.push(session),
+            }
+        }
+    }
+
+    /// Sessions in the order they were logged, with the resets that ended them
+    pub fn sessions(&self) -> Vec<Session> {
+        let mut sessions = self.sessions.clone();
+        // The cause of a reset is logged at the start of the following session
+        for idx in 1..sessions.len() {
+            sessions[idx - 1].reset = sessions[idx].boot.clone();
+        }
+        // Sessions without any decoded samples (e.g. outside the time range) aren't reported
+        sessions.retain(|session| session.start.is_some());
+        sessions
+    }
+}
+
+/// Whether any boot record was decoded, so that the sessions are worth writing out
+pub fn has_boot_records(sessions: &[Session]) -> bool {
+    sessions.iter().any(|session| session.session_id.is_some())
+}
+
+/// TDF output that only follows the session of the logging device
+struct SessionScan {
+    current_id: Option<u32>,
+}
+
+impl TdfOutput for SessionScan {
+    fn write(
+        &mut self,
+        remote_id: Option<u64>,
+        tdf_id: u16,
+        _tdf_time: i64,
+        _tdf_idx: Option<u16>,
+        size: u8,
+        cursor: &mut Cursor<&[u8]>,
+    ) -> io::Result<()> {
+        match BootRecord::peek(remote_id, tdf_id, size, cursor) {
+            Some(BootRecord::Reboot { count, .. }) => self.current_id = Some(count),
+            Some(BootRecord::Announce { reboots, .. }) => self.current_id = Some(reboots),
+            None => {}
+        }
+        skip_payload(size, cursor)
+    }
+
+    fn iter_written(&self) -> impl Iterator<Item = (&(Option<u64>, u16), &usize)> {
+        std::iter::empty()
+    }
+
+    fn written(&self, _remote_id: Option<u64>, _tdf_id: u16) -> usize {
+        0
+    }
+
+    fn output_path(&self, _remote_id: Option<u64>, _tdf_id: u16) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Blocks scanned at a time when looking back for the session in progress, doubling after each
+/// chunk without a boot record
+const SESSION_SCAN_BLOCKS: usize = 64;
+const SESSION_SCAN_MAX_BLOCKS: usize = 65536;
+
+/// Session in progress at `start_block`, from the last boot record in the blocks before it.
+///
+/// The blocks are scanned backwards from `start_block`, so that only the blocks logged since that
+/// boot record are read.
+pub(crate) fn initial_session_id(
+    input: &ConcatenatedInput,
+    block_size: usize,
+    start_block: usize,
+    cancel: &CancellationToken,
+) -> io::Result<Option<u32>> {
+    let mut end = start_block;
+    let mut chunk = SESSION_SCAN_BLOCKS;
+
+    while end > 0 {
+        let start = end.saturating_sub(chunk);
+        let mut scan = SessionScan { current_id: None };
+        input.for_each_block(block_size, start, end - start, |block| {
+            if cancel.is_cancelled() {
+                return ControlFlow::Break(());
+            }
+            // Malformed blocks are skipped, as they are when decoding
+            let _ = blocks::decode_block(&mut scan, block);
+            ControlFlow::Continue(())
+        })?;
+        cancel.check()?;
+
+        if scan.current_id.is_some() {
+            return Ok(scan.current_id);
+        }
+        end = start;
+        chunk = (chunk * 2).min(SESSION_SCAN_MAX_BLOCKS);
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// TDF with a global timestamp of `seconds`
+    pub(crate) fn tdf(tdf_id: u16, seconds: u32, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&(tdf_id | 0x4000).to_le_bytes());
+        out.push(payload.len() as u8);
+        out.extend_from_slice(&seconds.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(payload);
+        out
+    }
+
+    pub(crate) fn reboot_info(reason: u8, count: u32, uptime: u32) -> Vec<u8> {
+        let mut out = vec![reason];
+        for value in [0, count, uptime, 0x1234, 0x5678] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(b"main\0\0\0\0");
+        out
+    }
+
+    pub(crate) fn announce(reboots: u16, major: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0xabcdu32.to_le_bytes());
+        out.extend_from_slice(&[major, 2]);
+        out.extend_from_slice(&3u16.to_le_bytes());
+        out.extend_from_slice(&0xdeadbeefu32.to_le_bytes());
+        out.extend_from_slice(&[0; 12]);
+        out.extend_from_slice(&reboots.to_le_bytes());
+        out.push(0);
+        out
+    }
+
+    fn feed(tracker: &mut SessionTracker, tdf_id: u16, time: i64, payload: &[u8]) {
+        if let Some(record) = BootRecord::parse(tdf_id, payload) {
+            tracker.record(record);
+        }
+        tracker.sample(time);
+    }
+
+    #[test]
+    fn boot_records_are_parsed() {
+        let payload = tdf(TDF_REBOOT_INFO, 100, &reboot_info(3, 7, 3600));
+        let cursor = Cursor::new(&payload[9..]);
+
+        let record = BootRecord::peek(None, TDF_REBOOT_INFO, payload[2], &cursor).unwrap();
+
+        let BootRecord::Reboot { count, reset } = record else {
+            panic!("Expected a reboot record");
+        };
+        assert_eq!(count, 7);
+        assert_eq!(reset.uptime, 3600);
+        assert_eq!(reset.param_1, 0x1234);
+        assert_eq!(reset.thread, "main");
+        assert_eq!(
+            BootRecord::peek(Some(1), TDF_REBOOT_INFO, payload[2], &cursor),
+            None
+        );
+    }
+
+    #[test]
+    fn sessions_are_stitched_across_workers() {
+        let mut first = SessionTracker::new(None);
+        feed(&mut first, 4, 10, &[]);
+        feed(&mut first, TDF_ANNOUNCE, 20, &announce(5, 1));
+        feed(&mut first, TDF_REBOOT_INFO, 30, &reboot_info(2, 6, 500));
+        feed(&mut first, 4, 40, &[]);
+        // Second worker starts part way through session 6, then boots without a REBOOT_INFO
+        let mut second = SessionTracker::new(None);
+        feed(&mut second, 4, 50, &[]);
+        feed(&mut second, TDF_ANNOUNCE, 60, &announce(6, 2));
+        feed(&mut second, TDF_ANNOUNCE, 70, &announce(7, 2));
+
+        first.append(second);
+        let sessions = first.sessions();
+
+        let ids: Vec<Option<u32>> = sessions.iter().map(|s| s.session_id).collect();
+        assert_eq!(ids, vec![Some(5), Some(6), Some(7)]);
+        assert_eq!((sessions[0].start, sessions[0].end), (Some(10), Some(20)));
+        assert_eq!(sessions[0].reset.as_ref().unwrap().uptime, 500);
+        assert_eq!((sessions[1].start, sessions[1].end), (Some(30), Some(60)));
+        assert_eq!(sessions[1].firmware.unwrap().major, 2);
+        assert_eq!(sessions[1].reset, None);
+        assert_eq!(sessions[2].firmware.unwrap().to_string(), "2.2.3+deadbeef");
+    }
+
+    #[test]
+    fn announce_matches_wrapped_reboot_count() {
+        let mut tracker = SessionTracker::new(None);
+        feed(
+            &mut tracker,
+            TDF_REBOOT_INFO,
+            10,
+            &reboot_info(2, 0x10005, 500),
+        );
+        feed(&mut tracker, TDF_ANNOUNCE, 20, &announce(5, 1));
+
+        let sessions = tracker.sessions();
+
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].session_id, Some(5));
+        assert_eq!(sessions[0].firmware.unwrap().major, 1);
+    }
+}
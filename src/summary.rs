@@ -0,0 +1,378 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::battery::BatteryReport;
+use crate::continuity::ContinuityReport;
+use crate::output_common::OutputKey;
+use crate::sessions::Session;
+use crate::{CancellationToken, TdfDecoderOutputs, input};
+
+const HASH_BUFFER_SIZE: usize = 1 << 20;
+
+/// Machine-readable record of a decode run, written next to the outputs as JSON
+#[derive(Debug, Serialize)]
+pub struct DecodeSummary {
+    pub decoder_version: &'static str,
+    pub device_id: String,
+    /// UTC time the decode started, RFC3339 formatted
+    pub started: String,
+    /// Wall-clock duration of the decode
+    pub duration_seconds: f64,
+    pub block_size: usize,
+    pub input_files: Vec<InputFileSummary>,
+    /// Number of blocks of each type
+    pub blocks: BTreeMap<&'static str, usize>,
+    /// Selected samples dropped for being outside the time window
+    pub filtered_samples: usize,
+    /// Blocks dropped for repeating the contents of an earlier block
+    pub duplicate_blocks: usize,
+    /// Samples dropped for repeating an earlier sample, also counted per TDF
+    pub duplicate_samples: usize,
+    pub tdfs: Vec<TdfSummary>,
+    pub output_files: Vec<PathBuf>,
+    pub continuity: ContinuityReport,
+    /// Boot sessions of the logging device, in the order they were logged
+    pub sessions: Vec<Session>,
+    /// Battery cycles and usage of the logging device, if it logged battery TDFs
+    pub battery: Option<BatteryReport>,
+    /// How much of the input was decoded by earlier runs, for incremental decodes
+    pub incremental: Option<IncrementalSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct IncrementalSummary {
+    /// Blocks decoded by earlier runs, whose rows weren't written again
+    pub previous_blocks: usize,
+    /// Why the outputs of an earlier run were decoded again rather than appended to
+    pub restart_reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InputFileSummary {
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TdfSummary {
+    /// Hexadecimal ID of the device that forwarded the data, `None` for the logging device
+    pub remote_id: Option<String>,
+    pub tdf_id: u16,
+    pub tdf_name: String,
+    pub samples: usize,
+    /// Samples dropped for repeating an earlier sample
+    pub duplicates: usize,
+    /// Earliest sample time, RFC3339 formatted
+    pub first_time: Option<String>,
+    /// Latest sample time, RFC3339 formatted
+    pub last_time: Option<String>,
+}
+
+impl DecodeSummary {
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()
+    }
+}
+
+/// Path of the summary for a decode with the given output prefix
+pub fn summary_path(output_folder: &Path, output_prefix: &str) -> PathBuf {
+    if output_prefix.is_empty() {
+        output_folder.join("summary.json")
+    } else {
+        output_folder.join(format!("{output_prefix}_summary.json"))
+    }
+}
+
+/// Earliest and latest sample time of any TDF, `None` if nothing was decoded
+pub(crate) fn time_span(
+    tdfs: &HashMap<OutputKey, TdfTotals>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let (first, last) = tdfs
+        .values()
+        .filter_map(|totals| totals.time_range)
+        .reduce(|(first, last), (next_first, next_last)| {
+            (first.min(next_first), last.max(next_last))
+        })?;
+    tdf::time::tdf_time_to_datetime(first).zip(tdf::time::tdf_time_to_datetime(last))
+}
+
+pub(crate) fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Micros, true)
+}
+
+fn format_tdf_time(tdf_time: i64) -> Option<String> {
+    tdf::time::tdf_time_to_datetime(tdf_time).map(format_time)
+}
+
+/// Size and SHA-256 hash of each input file, reading the files without a hash in `hashed`
+pub(crate) fn hash_input_files(
+    files: &[PathBuf],
+    mapped: &input::MappedInputs,
+    hashed: &HashMap<PathBuf, String>,
+    cancel: &CancellationToken,
+) -> io::Result<Vec<InputFileSummary>> {
+    files
+        .iter()
+        .map(|path| {
+            let (size, sha256) = match hashed.get(path) {
+                Some(sha256) => (mapped.input_len(path)?, sha256.clone()),
+                None => {
+                    let (size, sha256, _) = hash_file(path, mapped, &[], cancel)?;
+                    (size, sha256)
+                }
+            };
+            Ok(InputFileSummary {
+                path: path.clone(),
+                size,
+                sha256,
+            })
+        })
+        .collect()
+}
+
+/// Size and SHA-256 hash of a file, along with the hash of its first `prefix` bytes for each of
+/// `prefixes`, in the same order. Prefixes longer than the file have no hash.
+pub(crate) fn hash_file(
+    path: &Path,
+    mapped: &input::MappedInputs,
+    prefixes: &[u64],
+    cancel: &CancellationToken,
+) -> io::Result<(u64, String, Vec<Option<String>>)> {
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+    let mut file = mapped.open(path)?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    let mut pending: Vec<u64> = prefixes.iter().copied().sorted().dedup().collect();
+    pending.reverse();
+    let mut prefix_hashes = HashMap::new();
+    loop {
+        while let Some(&prefix) = pending.last()
+            && prefix == size
+        {
+            prefix_hashes.insert(prefix, hex_string(&hasher.clone().finalize()));
+            pending.pop();
+        }
+        cancel.check()?;
+        // Reads stop at the next prefix, so that its hash can be taken
+        let limit = pending.last().map_or(buffer.len() as u64, |prefix| {
+            (prefix - size).min(buffer.len() as u64)
+        }) as usize;
+        let count = file.read(&mut buffer[..limit]).map_err(|err| {
+            io::Error::new(
+                err.kind(),
+                format!("Failed to hash input file '{}': {err}", path.display()),
+            )
+        })?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+        size += count as u64;
+    }
+    let prefix_hashes = prefixes
+        .iter()
+        .map(|prefix| prefix_hashes.get(prefix).cloned())
+        .collect();
+    Ok((size, hex_string(&hasher.finalize()), prefix_hashes))
+}
+
+pub(crate) fn hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Block type with the given [`block_key`]
+pub(crate) fn block_type(key: &str) -> Option<blocks::BlockTypes> {
+    use blocks::BlockTypes;
+    [
+        BlockTypes::TDF,
+        BlockTypes::REMOTE,
+        BlockTypes::OTHER,
+        BlockTypes::EMPTY,
+        BlockTypes::ERROR,
+    ]
+    .into_iter()
+    .find(|block_type| block_key(*block_type) == key)
+}
+
+/// Stable key for a block type in the summary
+pub fn block_key(block_type: blocks::BlockTypes) -> &'static str {
+    match block_type {
+        blocks::BlockTypes::TDF => "tdf",
+        blocks::BlockTypes::REMOTE => "remote",
+        blocks::BlockTypes::OTHER => "other",
+        blocks::BlockTypes::EMPTY => "empty",
+        blocks::BlockTypes::ERROR => "error",
+    }
+}
+
+/// Percentage of the non-empty blocks that couldn't be decoded, `None` if there were none
+pub fn error_block_percent(block_stats: &HashMap<blocks::BlockTypes, usize>) -> Option<f64> {
+    let count = |block_type| block_stats.get(&block_type).copied().unwrap_or_default();
+    let errors = count(blocks::BlockTypes::ERROR);
+    let total = errors
+        + count(blocks::BlockTypes::TDF)
+        + count(blocks::BlockTypes::REMOTE)
+        + count(blocks::BlockTypes::OTHER);
+    (total > 0).then(|| 100.0 * errors as f64 / total as f64)
+}
+
+/// Samples of a TDF over every decode of an incremental output
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TdfTotals {
+    pub samples: usize,
+    /// Samples dropped for repeating an earlier sample
+    pub duplicates: usize,
+    /// Earliest and latest TDF time of the samples
+    pub time_range: Option<(i64, i64)>,
+}
+
+/// Add the sample counts and time ranges of each worker's outputs to `totals`
+pub(crate) fn add_worker_outputs(
+    totals: &mut HashMap<OutputKey, TdfTotals>,
+    results: &HashMap<OutputKey, HashMap<usize, TdfDecoderOutputs>>,
+) {
+    for (key, worker_outputs) in results {
+        let total = totals.entry(*key).or_default();
+        for output in worker_outputs.values() {
+            total.samples += output.num_output;
+            total.time_range = match (total.time_range, output.time_range) {
+                (Some((first, last)), Some((worker_first, worker_last))) => {
+                    Some((first.min(worker_first), last.max(worker_last)))
+                }
+                (range, worker_range) => range.or(worker_range),
+            };
+        }
+    }
+}
+
+/// Summary of each TDF, by remote ID and TDF ID
+pub(crate) fn tdf_summaries(totals: &HashMap<OutputKey, TdfTotals>) -> Vec<TdfSummary> {
+    totals
+        .iter()
+        .sorted_by_key(|(key, _)| **key)
+        .map(|((remote_id, tdf_id), total)| TdfSummary {
+            remote_id: remote_id.map(|id| format!("{id:016x}")),
+            tdf_id: *tdf_id,
+            tdf_name: tdf::decoders::tdf_name(tdf_id),
+            samples: total.samples,
+            duplicates: total.duplicates,
+            first_time: total
+                .time_range
+                .and_then(|(first, _)| format_tdf_time(first)),
+            last_time: total.time_range.and_then(|(_, last)| format_tdf_time(last)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_files_are_hashed() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let path = dir.join("input.bin");
+        std::fs::write(&path, b"abc").unwrap();
+
+        let summaries = hash_input_files(
+            std::slice::from_ref(&path),
+            &input::MappedInputs::default(),
+            &HashMap::new(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(summaries[0].size, 3);
+        assert_eq!(
+            summaries[0].sha256,
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn time_span_covers_all_tdfs() {
+        let totals = |time_range| TdfTotals {
+            time_range,
+            ..TdfTotals::default()
+        };
+        let day = |day: i64| (day * 86_400) << 16;
+        let mut tdfs = HashMap::from([
+            ((None, 4), totals(Some((day(2), day(3))))),
+            ((None, 5), totals(None)),
+        ]);
+        tdfs.insert((Some(0xab), 4), totals(Some((day(1), day(2)))));
+
+        let (first, last) = time_span(&tdfs).unwrap();
+        assert_eq!(first, tdf::time::tdf_time_to_datetime(day(1)).unwrap());
+        assert_eq!(last, tdf::time::tdf_time_to_datetime(day(3)).unwrap());
+
+        tdfs.clear();
+        assert_eq!(time_span(&tdfs), None);
+    }
+
+    #[test]
+    fn error_percent_ignores_empty_blocks() {
+        use blocks::BlockTypes;
+
+        let mut stats = HashMap::from([(BlockTypes::EMPTY, 10)]);
+        assert_eq!(error_block_percent(&stats), None);
+
+        stats.extend([
+            (BlockTypes::TDF, 6),
+            (BlockTypes::REMOTE, 1),
+            (BlockTypes::OTHER, 1),
+            (BlockTypes::ERROR, 2),
+        ]);
+        assert_eq!(error_block_percent(&stats), Some(20.0));
+    }
+
+    #[test]
+    fn tdf_time_ranges_combine_across_workers() {
+        let output = |num_output, time_range| TdfDecoderOutputs {
+            output: PathBuf::new(),
+            num_output,
+            time_range,
+        };
+        let mut results = HashMap::new();
+        results.insert(
+            (Some(0xab), 4),
+            HashMap::from([
+                (0, output(2, Some((200, 300)))),
+                (1, output(3, Some((100, 250)))),
+            ]),
+        );
+        results.insert((None, 4), HashMap::from([(0, output(0, None))]));
+
+        let mut totals = HashMap::new();
+        add_worker_outputs(&mut totals, &results);
+        for (key, duplicates) in [((None, 4), 1), ((Some(0xcd), 4), 2)] {
+            totals
+                .entry(key)
+                .or_insert_with(TdfTotals::default)
+                .duplicates += duplicates;
+        }
+
+        let summaries = tdf_summaries(&totals);
+
+        assert_eq!(summaries.len(), 3);
+        assert_eq!(summaries[0].remote_id, None);
+        assert_eq!(summaries[0].first_time, None);
+        assert_eq!(summaries[1].remote_id.as_deref(), Some("00000000000000ab"));
+        assert_eq!(summaries[1].samples, 5);
+        assert_eq!(summaries[1].first_time, format_tdf_time(100));
+        assert_eq!(summaries[1].last_time, format_tdf_time(300));
+        assert_eq!(summaries[2].remote_id.as_deref(), Some("00000000000000cd"));
+        assert_eq!((summaries[2].samples, summaries[2].duplicates), (0, 2));
+    }
+}
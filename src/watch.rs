@@ -0,0 +1,308 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::fs_util;
+
+/// Name of the log of processed input sets, in the root of the output folder
+pub const WATCH_LOG_NAME: &str = "watch_log.jsonl";
+
+/// Size and modification time of an input file, which change when the file is written to
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InputFile {
+    pub name: String,
+    pub size: u64,
+    /// Modification time, RFC3339 formatted
+    pub modified: Option<String>,
+}
+
+impl InputFile {
+    fn read(path: &Path) -> io::Result<Self> {
+        let metadata = path.metadata()?;
+        Ok(Self {
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(|time| {
+                DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Nanos, true)
+            }),
+        })
+    }
+}
+
+/// Input files of one device found in a folder of the watched directory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InputSet {
+    input_root: PathBuf,
+    /// Folder of the files, relative to the watched directory
+    pub folder: PathBuf,
+    pub device_id: u64,
+    pub files: Vec<InputFile>,
+}
+
+impl InputSet {
+    pub fn paths(&self) -> Vec<PathBuf> {
+        let folder = self.input_root.join(&self.folder);
+        self.files
+            .iter()
+            .map(|file| folder.join(&file.name))
+            .collect()
+    }
+
+    /// Output folder of the set, mirroring its folder in the watched directory
+    pub fn output_folder(&self, output_root: &Path) -> PathBuf {
+        output_root
+            .join(&self.folder)
+            .join(format!("{:016x}", self.device_id))
+    }
+
+    fn key(&self) -> (PathBuf, u64) {
+        (self.folder.clone(), self.device_id)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchStatus {
+    Decoded,
+    Failed,
+}
+
+/// Line of the watch log
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WatchLogEntry {
+    /// UTC time the decode finished, RFC3339 formatted
+    pub time: String,
+    pub folder: PathBuf,
+    pub device_id: String,
+    pub files: Vec<InputFile>,
+    pub status: WatchStatus,
+    pub output_folder: PathBuf,
+    pub error: Option<String>,
+}
+
+/// Finds the input sets of a watched directory that haven't been decoded yet.
+///
+/// Decoded sets are recorded in an append-only log in the output folder, so a restarted watcher
+/// only decodes sets that are new or have changed since. A set is only ready once it is unchanged
+/// between two scans, so that files still being copied aren't decoded.
+pub struct Watcher {
+    input_root: PathBuf,
+    output_root: PathBuf,
+    log_path: PathBuf,
+    /// Files of the last decode of each set, successful or not
+    processed: HashMap<(PathBuf, u64), Vec<InputFile>>,
+    /// Files of each set at the previous scan
+    previous_scan: HashMap<(PathBuf, u64), Vec<InputFile>>,
+}
+
+impl Watcher {
+    /// Watch `input_root`, continuing from the log in `output_root`.
+    ///
+    /// Only sets that were decoded successfully are skipped after a restart, lines of the log that
+    /// can't be read (such as a line cut short by a crash) are ignored.
+    pub fn open(input_root: &Path, output_root: &Path) -> io::Result<Self> {
+        fs::create_dir_all(output_root)?;
+        let log_path = output_root.join(WATCH_LOG_NAME);
+        let mut processed = HashMap::new();
+        match File::open(&log_path) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let Ok(entry) = serde_json::from_str::<WatchLogEntry>(&line?) else {
+                        continue;
+                    };
+                    let Ok(device_id) = u64::from_str_radix(&entry.device_id, 16) else {
+                        continue;
+                    };
+                    let key = (entry.folder, device_id);
+                    match entry.status {
+                        WatchStatus::Decoded => processed.insert(key, entry.files),
+                        WatchStatus::Failed => processed.remove(&key),
+                    };
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Self {
+            input_root: input_root.to_path_buf(),
+            output_root: output_root.to_path_buf(),
+            log_path,
+            processed,
+            previous_scan: HashMap::new(),
+        })
+    }
+
+    /// Scan the watched directory, returning the sets that are ready to decode and warnings for
+    /// folders that couldn't be scanned
+    pub fn scan(&mut self) -> io::Result<(Vec<InputSet>, Vec<String>)> {
+        let mut sets = Vec::new();
+        let mut warnings = Vec::new();
+        // The output folder may be inside the watched directory
+        let output_root = fs::canonicalize(&self.output_root)?;
+        let mut folders = vec![PathBuf::new()];
+        while let Some(folder) = folders.pop() {
+            let path = self.input_root.join(&folder);
+            if fs::canonicalize(&path).is_ok_and(|path| path == output_root) {
+                continue;
+            }
+            let entries = match fs::read_dir(&path) {
+                Ok(entries) => entries,
+                // Only the watched directory itself has to be readable
+                Err(err) if folder.as_os_str().is_empty() => return Err(err),
+                Err(err) => {
+                    warnings.push(format!("Skipping '{}': {err}", path.display()));
+                    continue;
+                }
+            };
+            for entry in entries.flatten() {
+                if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+                    folders.push(folder.join(entry.file_name()));
+                }
+            }
+
+            let found = match fs_util::find_infuse_iot_files(&path) {
+                Ok(found) => found,
+                Err(err) => {
+                    warnings.push(format!("Skipping '{}': {err}", path.display()));
+                    continue;
+                }
+            };
+            for (device_id, paths) in found {
+                // Files can be removed between listing and reading them
+                let Ok(files) = paths
+                    .iter()
+                    .map(|path| InputFile::read(path))
+                    .collect::<io::Result<Vec<_>>>()
+                else {
+                    continue;
+                };
+                sets.push(InputSet {
+                    input_root: self.input_root.clone(),
+                    folder: folder.clone(),
+                    device_id,
+                    files,
+                });
+            }
+        }
+
+        let previous_scan = std::mem::take(&mut self.previous_scan);
+        let mut ready: Vec<InputSet> = sets
+            .iter()
+            .filter(|set| {
+                let key = set.key();
+                previous_scan.get(&key) == Some(&set.files)
+                    && self.processed.get(&key) != Some(&set.files)
+            })
+            .cloned()
+            .collect();
+        ready.sort_by_key(InputSet::key);
+        self.previous_scan = sets.into_iter().map(|set| (set.key(), set.files)).collect();
+        Ok((ready, warnings))
+    }
+
+    /// Append the result of decoding a set to the log.
+    ///
+    /// Failed sets aren't decoded again until they change, or the watcher is restarted.
+    pub fn record(&mut self, set: &InputSet, result: Result<(), String>) -> io::Result<()> {
+        let entry = WatchLogEntry {
+            time: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            folder: set.folder.clone(),
+            device_id: format!("{:016x}", set.device_id),
+            files: set.files.clone(),
+            status: match result {
+                Ok(()) => WatchStatus::Decoded,
+                Err(_) => WatchStatus::Failed,
+            },
+            output_folder: set.output_folder(&self.output_root),
+            error: result.err(),
+        };
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&self.log_path)?;
+        // Start a new line after a line that was cut short
+        let mut last = [b'\n'];
+        if log.metadata()?.len() > 0 {
+            log.seek(SeekFrom::End(-1))?;
+            log.read_exact(&mut last)?;
+        }
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+        log.write_all(line.as_bytes())?;
+        log.sync_data()?;
+
+        self.processed.insert(set.key(), set.files.clone());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sets_are_decoded_once_they_stop_changing() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let input = dir.join("input");
+        let card = input.join("card");
+        fs::create_dir_all(&card).unwrap();
+        let output = input.join("output");
+        let first = card.join("infuse_0123456789abcdef_0.bin");
+        fs::write(&first, [0; 512]).unwrap();
+
+        let mut watcher = Watcher::open(&input, &output).unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+        let (ready, _) = watcher.scan().unwrap();
+        assert_eq!(ready.len(), 1);
+        assert_eq!(ready[0].folder, PathBuf::from("card"));
+        assert_eq!(ready[0].device_id, 0x0123456789abcdef);
+        assert_eq!(ready[0].paths(), vec![first]);
+        assert_eq!(
+            ready[0].output_folder(&output),
+            output.join("card").join("0123456789abcdef")
+        );
+        watcher.record(&ready[0], Ok(())).unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+
+        // Completed sets are remembered across restarts, until they change
+        let mut watcher = Watcher::open(&input, &output).unwrap();
+        watcher.scan().unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+        fs::write(card.join("infuse_0123456789abcdef_1.bin"), [0; 512]).unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+        let (ready, _) = watcher.scan().unwrap();
+        assert_eq!(ready[0].files.len(), 2);
+
+        // Failed sets are retried after a restart, and a cut short last line is ignored
+        watcher
+            .record(&ready[0], Err("failed".to_string()))
+            .unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+        let mut log = OpenOptions::new()
+            .append(true)
+            .open(output.join(WATCH_LOG_NAME))
+            .unwrap();
+        log.write_all(b"{\"time\":").unwrap();
+        let mut watcher = Watcher::open(&input, &output).unwrap();
+        watcher.scan().unwrap();
+        let (ready, _) = watcher.scan().unwrap();
+        assert_eq!(ready.len(), 1);
+        watcher.record(&ready[0], Ok(())).unwrap();
+        let mut watcher = Watcher::open(&input, &output).unwrap();
+        watcher.scan().unwrap();
+        assert!(watcher.scan().unwrap().0.is_empty());
+    }
+}
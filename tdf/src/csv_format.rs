@@ -0,0 +1,356 @@
+use std::fmt::{Display, LowerHex, Write};
+use std::io::{Error, ErrorKind, Result};
+
+/// Representation of a variable length array struct that is the last field of a TDF
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CsvVlaMode {
+    /// First item on the TDF row, each additional item on its own row with blank prefix columns
+    Rows,
+    /// All items as a JSON array in a single cell
+    Json,
+    /// One set of columns per item, up to the provided number of items
+    Wide(usize),
+}
+
+/// Formatting options applied to every field of a CSV row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CsvFormat {
+    /// Separator between fields
+    pub delimiter: char,
+    /// Character used in place of `.` for non-integer values
+    pub decimal_separator: char,
+    /// Fixed number of fractional digits for floating point values (`None` is shortest round-trip)
+    pub float_precision: Option<usize>,
+    /// Representation of trailing variable length arrays
+    pub vla_mode: CsvVlaMode,
+}
+
+impl Default for CsvFormat {
+    fn default() -> Self {
+        Self {
+            delimiter: ',',
+            decimal_separator: '.',
+            float_precision: None,
+            vla_mode: CsvVlaMode::Rows,
+        }
+    }
+}
+
+fn format_float<T: Display>(value: T, precision: Option<usize>) -> String {
+    match precision {
+        Some(precision) => format!("{value:.precision$}"),
+        None => format!("{value}"),
+    }
+}
+
+impl CsvFormat {
+    /// Reject combinations that would produce ambiguous output
+    pub fn validate(&self) -> Result<()> {
+        if self.delimiter == self.decimal_separator {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "CSV delimiter and decimal separator must differ (both are {:?})",
+                    self.delimiter
+                ),
+            ));
+        }
+        if self.vla_mode == CsvVlaMode::Wide(0) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Wide variable length array output requires at least one item",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Join header names with the field delimiter
+    pub fn join<S: AsRef<str>>(&self, fields: &[S]) -> String {
+        let mut out = String::new();
+        for (idx, field) in fields.iter().enumerate() {
+            if idx != 0 {
+                out.push(self.delimiter);
+            }
+            out.push_str(field.as_ref());
+        }
+        out
+    }
+
+    /// Format a floating point value with the configured precision and decimal separator
+    pub fn float<T: Display>(&self, value: T) -> String {
+        self.decimal(format_float(value, self.float_precision))
+    }
+
+    /// Replace the decimal point in an already formatted number
+    pub fn decimal(&self, formatted: String) -> String {
+        if self.decimal_separator == '.' {
+            formatted
+        } else {
+            formatted.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+}
+
+/// Incrementally constructed CSV row (or rows, for trailing variable length arrays)
+pub struct CsvRow<'a> {
+    format: &'a CsvFormat,
+    out: String,
+    first: bool,
+}
+
+impl<'a> CsvRow<'a> {
+    pub fn new(format: &'a CsvFormat) -> Self {
+        Self {
+            format,
+            out: String::new(),
+            first: true,
+        }
+    }
+
+    fn separate(&mut self) {
+        if !self.first {
+            self.out.push(self.format.delimiter);
+        }
+        self.first = false;
+    }
+
+    /// Integer (or other non-floating point) value
+    pub fn display<T: Display>(&mut self, value: T) {
+        self.separate();
+        let _ = write!(self.out, "{value}");
+    }
+
+    /// Floating point value
+    pub fn float<T: Display>(&mut self, value: T) {
+        self.separate();
+        let formatted = self.format.float(value);
+        self.out.push_str(&formatted);
+    }
+
+    /// Hexadecimal value with a `0x` prefix, zero padded to `digits`
+    pub fn hex<T: LowerHex>(&mut self, value: T, digits: usize) {
+        self.separate();
+        let _ = write!(self.out, "0x{value:0digits$x}");
+    }
+
+    /// Pre-formatted text that never contains the delimiter (e.g. hex payloads)
+    pub fn text(&mut self, value: &str) {
+        self.separate();
+        self.out.push_str(value);
+    }
+
+    /// Quoted string value, with embedded quotes doubled
+    pub fn quoted(&mut self, value: &str) {
+        self.separate();
+        self.out.push('"');
+        self.out.push_str(&value.replace('"', "\"\""));
+        self.out.push('"');
+    }
+
+    /// Empty cell
+    pub fn empty(&mut self) {
+        self.separate();
+    }
+
+    /// Start a continuation line, leaving the first `blank_columns` cells empty
+    pub fn next_line(&mut self, blank_columns: usize) {
+        self.out.push('\n');
+        for _ in 0..blank_columns {
+            self.out.push(self.format.delimiter);
+        }
+        self.first = true;
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// JSON array of variable length array items, for `CsvVlaMode::Json`
+///
+/// Items are objects keyed by field name when the array element is a struct, otherwise plain values.
+/// Numbers always use `.` as the decimal separator so the cell remains valid JSON.
+pub struct CsvJsonArray<'a> {
+    format: &'a CsvFormat,
+    objects: bool,
+    out: String,
+    first_item: bool,
+    first_field: bool,
+}
+
+impl<'a> CsvJsonArray<'a> {
+    pub fn new(format: &'a CsvFormat, objects: bool) -> Self {
+        Self {
+            format,
+            objects,
+            out: String::from("["),
+            first_item: true,
+            first_field: true,
+        }
+    }
+
+    pub fn start_item(&mut self) {
+        if !self.first_item {
+            self.out.push(',');
+        }
+        self.first_item = false;
+        self.first_field = true;
+        if self.objects {
+            self.out.push('{');
+        }
+    }
+
+    pub fn end_item(&mut self) {
+        if self.objects {
+            self.out.push('}');
+        }
+    }
+
+    fn key(&mut self, key: Option<&str>) {
+        if let Some(key) = key {
+            if !self.first_field {
+                self.out.push(',');
+            }
+            self.first_field = false;
+            self.string(key);
+            self.out.push(':');
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.out.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                c if (c as u32) < 0x20 => {
+                    let _ = write!(self.out, "\\u{:04x}", c as u32);
+                }
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    pub fn display<T: Display>(&mut self, key: Option<&str>, value: T) {
+        self.key(key);
+        let _ = write!(self.out, "{value}");
+    }
+
+    pub fn float<T: Display>(&mut self, key: Option<&str>, value: T) {
+        self.key(key);
+        let formatted = format_float(value, self.format.float_precision);
+        // NaN and infinities have no JSON representation
+        if formatted.contains(|c: char| c.is_ascii_alphabetic()) {
+            self.out.push_str("null");
+        } else {
+            self.out.push_str(&formatted);
+        }
+    }
+
+    pub fn hex<T: LowerHex>(&mut self, key: Option<&str>, value: T, digits: usize) {
+        self.key(key);
+        let formatted = format!("0x{value:0digits$x}");
+        self.string(&formatted);
+    }
+
+    pub fn text(&mut self, key: Option<&str>, value: &str) {
+        self.key(key);
+        self.string(value);
+    }
+
+    pub fn finish(mut self) -> String {
+        self.out.push(']');
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_format_matches_plain_display() {
+        let format = CsvFormat::default();
+        let mut row = CsvRow::new(&format);
+
+        row.display(12u32);
+        row.float(2.5f64);
+        row.float(0.1f32);
+        row.hex(0xabu8, 4);
+        row.quoted("a\"b");
+        row.empty();
+
+        assert_eq!(row.finish(), "12,2.5,0.1,0x00ab,\"a\"\"b\",");
+    }
+
+    #[test]
+    fn european_format_uses_semicolon_and_decimal_comma() {
+        let format = CsvFormat {
+            delimiter: ';',
+            decimal_separator: ',',
+            float_precision: Some(3),
+            ..CsvFormat::default()
+        };
+        let mut row = CsvRow::new(&format);
+
+        row.display(-4i16);
+        row.float(2.5f64);
+        row.next_line(2);
+        row.float(-1.0f64);
+
+        assert_eq!(row.finish(), "-4;2,500\n;;-1,000");
+    }
+
+    #[test]
+    fn matching_delimiter_and_decimal_separator_is_rejected() {
+        let format = CsvFormat {
+            decimal_separator: ',',
+            ..CsvFormat::default()
+        };
+
+        assert_eq!(
+            format.validate().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn json_array_keeps_point_decimal_separator() {
+        let format = CsvFormat {
+            delimiter: ';',
+            decimal_separator: ',',
+            ..CsvFormat::default()
+        };
+        let mut items = CsvJsonArray::new(&format, true);
+
+        items.start_item();
+        items.display(Some("pci"), 11u16);
+        items.float(Some("time_diff"), 2.5f64);
+        items.hex(Some("id"), 0xabu8, 2);
+        items.end_item();
+        items.start_item();
+        items.display(Some("pci"), 12u16);
+        items.float(Some("time_diff"), f64::NAN);
+        items.hex(Some("id"), 0xcdu8, 2);
+        items.end_item();
+
+        assert_eq!(
+            items.finish(),
+            r#"[{"pci":11,"time_diff":2.5,"id":"0xab"},{"pci":12,"time_diff":null,"id":"0xcd"}]"#
+        );
+    }
+
+    #[test]
+    fn wide_mode_requires_at_least_one_item() {
+        let format = CsvFormat {
+            vla_mode: CsvVlaMode::Wide(0),
+            ..CsvFormat::default()
+        };
+
+        assert_eq!(
+            format.validate().unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
@@ -2,6 +2,8 @@ use std::io::{Cursor, Error, ErrorKind, Read, Result};
 
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt};
 
+use crate::csv_format::{CsvFormat, CsvJsonArray, CsvRow, CsvVlaMode};
+
 pub fn tdf_fields(tdf_id: &u16) -> Vec<&'static str> {
     match tdf_id {
         1 => vec![
@@ -204,6 +206,39 @@ pub fn tdf_fields(tdf_id: &u16) -> Vec<&'static str> {
     }
 }
 
+/// Header columns for a TDF, given the representation of trailing variable length arrays
+pub fn tdf_csv_fields(tdf_id: &u16, vla_mode: CsvVlaMode) -> Vec<String> {
+    let fields = tdf_fields(tdf_id);
+    let variable: Option<(&str, usize)> = match tdf_id {
+        34 => Some(("neighbours", 7)),
+        52 => Some(("frame", 0)),
+        _ => None,
+    };
+    let Some((name, base_count)) = variable else {
+        return fields.into_iter().map(String::from).collect();
+    };
+
+    let (base, items) = fields.split_at(base_count);
+    let mut out: Vec<String> = base.iter().map(|field| field.to_string()).collect();
+    match vla_mode {
+        CsvVlaMode::Rows => out.extend(items.iter().map(|field| field.to_string())),
+        CsvVlaMode::Json => out.push(name.to_string()),
+        CsvVlaMode::Wide(max_items) => {
+            for idx in 0..max_items {
+                for item in items {
+                    out.push(format!("{name}[{idx}]{}", &item[name.len()..]));
+                }
+            }
+        }
+    }
+    out
+}
+
+fn tdf_field_skip(cursor: &mut Cursor<&[u8]>, num: usize) -> Result<()> {
+    let mut buf = vec![0u8; num];
+    cursor.read_exact(&mut buf)
+}
+
 fn tdf_field_read_string_to_str(
     cursor: &mut Cursor<&[u8]>,
     cursor_start: u64,
@@ -213,8 +248,8 @@ fn tdf_field_read_string_to_str(
     let buf = crate::decoders::tdf_field_read_string(cursor, cursor_start, num, size)?;
 
     match String::from_utf8(buf) {
-        Ok(val) => Ok(format!("\"{}\"", val.trim_matches(char::from(0)))),
-        Err(..) => Ok(String::from("\"\"")),
+        Ok(val) => Ok(val.trim_matches(char::from(0)).to_string()),
+        Err(..) => Ok(String::new()),
     }
 }
 
@@ -255,539 +290,475 @@ fn tdf_variable_item_count(size: u8, base_size: usize, item_size: usize) -> Resu
     Ok(bytes_remaining / item_size)
 }
 
-pub fn tdf_read_into_str(tdf_id: &u16, size: u8, cursor: &mut Cursor<&[u8]>) -> Result<String> {
+pub fn tdf_read_into_str(
+    tdf_id: &u16,
+    size: u8,
+    cursor: &mut Cursor<&[u8]>,
+    format: &CsvFormat,
+) -> Result<String> {
     let cursor_start = cursor.position();
+    let mut row = CsvRow::new(format);
 
-    let res = match tdf_id {
-        1 =>
-            Ok(format!(
-                "0x{:08x},{},{},{},0x{:08x},0x{:08x},{},{},{},0x{:02x}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        2 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_i32::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        3 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-            )),
-        4 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-            )),
-        5 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u8()?,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000000.0,
-            )),
-        6 =>
-            Ok(format!(
-                "{},0x{:08x},{},{},0x{:08x},0x{:08x},{}",
-                cursor.read_u8()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                tdf_field_read_string_to_str(cursor, cursor_start, 8, size)?,
-            )),
-        7 =>
-            Ok(format!(
-                "0x{:08x},{},{},{},0x{:08x},0x{:04x},0x{:08x},{},{},{},0x{:02x}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        8 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i16::<LittleEndian>()? as f64 / 100.0,
-            )),
-        10 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        11 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        12 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        13 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        14 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        15 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        16 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        17 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        18 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        19 =>
-            Ok(format!(
-                "{},{},{},{},{}",
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-            )),
-        20 =>
-            Ok(format!(
-                "{},{},{},{},{},{},{},0x{:02x},{},{},{},0x{:02x},0x{:02x},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},0x{:04x},{},{},{},{},{},{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_i32::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 100000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 100000.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_i32::<LittleEndian>()? as f64 / 100000.0,
-                cursor.read_i16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-            )),
-        21 =>
-            Ok(format!(
-                "{},{},{},{},{},{},{},{},{}",
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()? as f64 / -1.0,
-                cursor.read_i8()?,
-            )),
-        22 =>
-            Ok(format!(
-                "{},{},{},{},{},{},{},{},{}",
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-            )),
-        23 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        24 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        25 =>
-            Ok(format!(
-                "0x{:08x},{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
-        26 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        27 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        28 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        29 =>
-            Ok(format!(
-                "{},0x{:012x},{}",
-                cursor.read_u8()?,
-                cursor.read_u48::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        30 =>
-            Ok(format!(
-                "{},0x{:012x},{}",
-                cursor.read_u8()?,
-                cursor.read_u48::<LittleEndian>()?,
-                cursor.read_i8()?,
-            )),
-        31 =>
-            Ok(format!(
-                "{},0x{:012x},{}",
-                cursor.read_u8()?,
-                cursor.read_u48::<LittleEndian>()?,
-                cursor.read_i32::<LittleEndian>()?,
-            )),
-        32 =>
-            Ok(format!(
-                "0x{:08x},{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
-        33 =>
-            Ok(format!(
-                "0x{:08x},{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()?,
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
+    match tdf_id {
+        1 => {
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.hex(cursor.read_u8()?, 2);
+        }
+        2 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_i32::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+        }
+        3 => {
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+        }
+        4 => {
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+        }
+        5 => {
+            row.display(cursor.read_u8()?);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000000.0);
+        }
+        6 => {
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.quoted(&tdf_field_read_string_to_str(
+                cursor,
+                cursor_start,
+                8,
+                size,
+            )?);
+        }
+        7 => {
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.hex(cursor.read_u16::<LittleEndian>()?, 4);
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.hex(cursor.read_u8()?, 2);
+        }
+        8 => {
+            row.float(cursor.read_i16::<LittleEndian>()? as f64 / 100.0);
+        }
+        10 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        11 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        12 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        13 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        14 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        15 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        16 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        17 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        18 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        19 => {
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+        }
+        20 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u8()?, 2);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_i32::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u8()?, 2);
+            row.hex(cursor.read_u8()?, 2);
+            row.display(cursor.read_u8()?);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 100000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 100000.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+            row.hex(cursor.read_u16::<LittleEndian>()?, 4);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 100000.0);
+            row.float(cursor.read_i16::<LittleEndian>()? as f64 / 100.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+        }
+        21 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.float(cursor.read_u8()? as f64 / -1.0);
+            row.display(cursor.read_i8()?);
+        }
+        22 => {
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+        }
+        23 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        24 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        25 => {
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
+        26 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        27 => {
+            row.display(cursor.read_u8()?);
+        }
+        28 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+        }
+        29 => {
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u48::<LittleEndian>()?, 12);
+            row.display(cursor.read_u8()?);
+        }
+        30 => {
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u48::<LittleEndian>()?, 12);
+            row.display(cursor.read_i8()?);
+        }
+        31 => {
+            row.display(cursor.read_u8()?);
+            row.hex(cursor.read_u48::<LittleEndian>()?, 12);
+            row.display(cursor.read_i32::<LittleEndian>()?);
+        }
+        32 => {
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
+        33 => {
+            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
         34 => {
             let item_count = tdf_variable_item_count(size, 16, 10)?;
-            if item_count == 0 {
-                Ok(format!(
-                    "{},{},{},{},{},{},{},{},{},{},{},{}",
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u32::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u32::<LittleEndian>()?,
-                    cursor.read_u8()? as f64 / -1.0,
-                    cursor.read_i8()?,
-                    "",
-                    "",
-                    "",
-                    "",
-                    "",
-                ))
-            } else {
-                let mut out = format!(
-                    "{},{},{},{},{},{},{},{},{},{},{},{}",
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u32::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u32::<LittleEndian>()?,
-                    cursor.read_u8()? as f64 / -1.0,
-                    cursor.read_i8()?,
-                    cursor.read_u32::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()?,
-                    cursor.read_u16::<LittleEndian>()? as f64 / 1000.0,
-                    cursor.read_u8()? as f64 / -1.0,
-                    cursor.read_i8()?,
-                );
-                for _ in 1..item_count {
-                    out.push_str(&format!(
-                        "\n,,,,,,,,{},{},{},{},{}",
-                        cursor.read_u32::<LittleEndian>()?,
-                        cursor.read_u16::<LittleEndian>()?,
-                        cursor.read_u16::<LittleEndian>()? as f64 / 1000.0,
-                        cursor.read_u8()? as f64 / -1.0,
-                        cursor.read_i8()?,
-                    ));
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.float(cursor.read_u8()? as f64 / -1.0);
+            row.display(cursor.read_i8()?);
+            match format.vla_mode {
+                CsvVlaMode::Rows => {
+                    if item_count == 0 {
+                        row.empty();
+                        row.empty();
+                        row.empty();
+                        row.empty();
+                        row.empty();
+                    } else {
+                        row.display(cursor.read_u32::<LittleEndian>()?);
+                        row.display(cursor.read_u16::<LittleEndian>()?);
+                        row.float(cursor.read_u16::<LittleEndian>()? as f64 / 1000.0);
+                        row.float(cursor.read_u8()? as f64 / -1.0);
+                        row.display(cursor.read_i8()?);
+                        for _ in 1..item_count {
+                            row.next_line(8);
+                            row.display(cursor.read_u32::<LittleEndian>()?);
+                            row.display(cursor.read_u16::<LittleEndian>()?);
+                            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 1000.0);
+                            row.float(cursor.read_u8()? as f64 / -1.0);
+                            row.display(cursor.read_i8()?);
+                        }
+                    }
+                }
+                CsvVlaMode::Json => {
+                    let mut items = CsvJsonArray::new(format, true);
+                    for _ in 0..item_count {
+                        items.start_item();
+                        items.display(Some("earfcn"), cursor.read_u32::<LittleEndian>()?);
+                        items.display(Some("pci"), cursor.read_u16::<LittleEndian>()?);
+                        items.float(
+                            Some("time_diff"),
+                            cursor.read_u16::<LittleEndian>()? as f64 / 1000.0,
+                        );
+                        items.float(Some("rsrp"), cursor.read_u8()? as f64 / -1.0);
+                        items.display(Some("rsrq"), cursor.read_i8()?);
+                        items.end_item();
+                    }
+                    row.quoted(&items.finish());
+                }
+                CsvVlaMode::Wide(max_items) => {
+                    for idx in 0..max_items {
+                        if idx < item_count {
+                            row.display(cursor.read_u32::<LittleEndian>()?);
+                            row.display(cursor.read_u16::<LittleEndian>()?);
+                            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 1000.0);
+                            row.float(cursor.read_u8()? as f64 / -1.0);
+                            row.display(cursor.read_i8()?);
+                        } else {
+                            row.empty();
+                            row.empty();
+                            row.empty();
+                            row.empty();
+                            row.empty();
+                        }
+                    }
+                    // Items that don't fit in the columns are dropped
+                    tdf_field_skip(cursor, item_count.saturating_sub(max_items) * 10)?;
                 }
-                Ok(out)
             }
-        },
-        35 =>
-            Ok(format!(
-                "0x{:012x},{},{}",
-                cursor.read_u48::<BigEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_i8()?,
-            )),
-        36 =>
-            Ok(format!(
-                "{}",
-                cursor.read_f32::<LittleEndian>()?,
-            )),
-        37 =>
-            Ok(format!(
-                "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},0x{:02x},{}",
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-                cursor.read_i32::<LittleEndian>()? as f64 / 100000.0,
-                cursor.read_u32::<LittleEndian>()? as f64 / 100000.0,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u16::<LittleEndian>()? as f64 / 100.0,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-            )),
-        38 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i32::<LittleEndian>()?,
-            )),
-        39 =>
-            Ok(format!(
-                "0x{:016x},{}",
-                cursor.read_u64::<LittleEndian>()?,
-                cursor.read_i8()?,
-            )),
-        40 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i8()?,
-            )),
-        41 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        42 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i32::<LittleEndian>()?,
-            )),
-        43 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u32::<LittleEndian>()?,
-                tdf_field_read_string_to_str(cursor, cursor_start, 0, size)?,
-            )),
-        44 =>
-            Ok(format!(
-                "{},{},{}",
-                cursor.read_i8()?,
-                cursor.read_i16::<LittleEndian>()?,
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
-        45 =>
-            Ok(format!(
-                "{}",
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
-        46 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        47 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        48 =>
-            Ok(format!(
-                "0x{:012x},{},{},{},{},{},{},{},{}",
-                cursor.read_u48::<BigEndian>()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-                cursor.read_i8()?,
-                cursor.read_u16::<LittleEndian>()?,
-                cursor.read_u8()?,
-            )),
-        49 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        50 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        51 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u8()?,
-                cursor.read_u8()?,
-            )),
+        }
+        35 => {
+            row.hex(cursor.read_u48::<BigEndian>()?, 12);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_i8()?);
+        }
+        36 => {
+            row.float(cursor.read_f32::<LittleEndian>()?);
+        }
+        37 => {
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 10000000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+            row.float(cursor.read_i32::<LittleEndian>()? as f64 / 100000.0);
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 100000.0);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+            row.float(cursor.read_u16::<LittleEndian>()? as f64 / 100.0);
+            row.hex(cursor.read_u8()?, 2);
+            row.display(cursor.read_u8()?);
+        }
+        38 => {
+            row.display(cursor.read_i32::<LittleEndian>()?);
+        }
+        39 => {
+            row.hex(cursor.read_u64::<LittleEndian>()?, 16);
+            row.display(cursor.read_i8()?);
+        }
+        40 => {
+            row.display(cursor.read_i8()?);
+        }
+        41 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        42 => {
+            row.display(cursor.read_i32::<LittleEndian>()?);
+        }
+        43 => {
+            row.display(cursor.read_u32::<LittleEndian>()?);
+            row.quoted(&tdf_field_read_string_to_str(
+                cursor,
+                cursor_start,
+                0,
+                size,
+            )?);
+        }
+        44 => {
+            row.display(cursor.read_i8()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
+        45 => {
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
+        46 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        47 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        48 => {
+            row.hex(cursor.read_u48::<BigEndian>()?, 12);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_i8()?);
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.display(cursor.read_u8()?);
+        }
+        49 => {
+            row.display(cursor.read_u8()?);
+        }
+        50 => {
+            row.display(cursor.read_u8()?);
+        }
+        51 => {
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u8()?);
+        }
         52 => {
             let item_count = tdf_variable_item_count(size, 0, 4)?;
-            if item_count == 0 {
-                Ok(format!(
-                    "{}",
-                    "",
-                ))
-            } else {
-                let mut out = format!(
-                    "0x{:08x}",
-                    cursor.read_u32::<LittleEndian>()?,
-                );
-                for _ in 1..item_count {
-                    out.push_str(&format!(
-                        "\n,0x{:08x}",
-                        cursor.read_u32::<LittleEndian>()?,
-                    ));
+            match format.vla_mode {
+                CsvVlaMode::Rows => {
+                    if item_count == 0 {
+                        row.empty();
+                    } else {
+                        row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+                        for _ in 1..item_count {
+                            row.next_line(1);
+                            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+                        }
+                    }
+                }
+                CsvVlaMode::Json => {
+                    let mut items = CsvJsonArray::new(format, false);
+                    for _ in 0..item_count {
+                        items.start_item();
+                        items.hex(None, cursor.read_u32::<LittleEndian>()?, 8);
+                        items.end_item();
+                    }
+                    row.quoted(&items.finish());
+                }
+                CsvVlaMode::Wide(max_items) => {
+                    for idx in 0..max_items {
+                        if idx < item_count {
+                            row.hex(cursor.read_u32::<LittleEndian>()?, 8);
+                        } else {
+                            row.empty();
+                        }
+                    }
+                    // Items that don't fit in the columns are dropped
+                    tdf_field_skip(cursor, item_count.saturating_sub(max_items) * 4)?;
                 }
-                Ok(out)
             }
-        },
-        53 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u16::<LittleEndian>()?,
-            )),
-        54 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        55 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        56 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u8()?,
-            )),
-        57 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u8()?,
-                cursor.read_u32::<LittleEndian>()?,
-            )),
-        58 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        59 =>
-            Ok(format!(
-                "{}",
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        60 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_i16::<LittleEndian>()?,
-                cursor.read_i16::<LittleEndian>()?,
-            )),
-        61 =>
-            Ok(format!(
-                "{},{}",
-                cursor.read_u16::<LittleEndian>()?,
-                tdf_field_read_vla_to_str(cursor, cursor_start, size)?,
-            )),
-        62 =>
-            Ok(format!(
-                "{}",
-                cursor.read_u32::<LittleEndian>()? as f64 / 1000.0,
-            )),
+        }
+        53 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+        }
+        54 => {
+            row.display(cursor.read_u8()?);
+        }
+        55 => {
+            row.display(cursor.read_u8()?);
+        }
+        56 => {
+            row.display(cursor.read_u8()?);
+        }
+        57 => {
+            row.display(cursor.read_u8()?);
+            row.display(cursor.read_u32::<LittleEndian>()?);
+        }
+        58 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        59 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        60 => {
+            row.display(cursor.read_i16::<LittleEndian>()?);
+            row.display(cursor.read_i16::<LittleEndian>()?);
+        }
+        61 => {
+            row.display(cursor.read_u16::<LittleEndian>()?);
+            row.text(&tdf_field_read_vla_to_str(cursor, cursor_start, size)?);
+        }
+        62 => {
+            row.float(cursor.read_u32::<LittleEndian>()? as f64 / 1000.0);
+        }
         _ => {
             let mut buf = vec![0; size as usize];
             cursor.read_exact(&mut buf)?;
-            Ok(format!("{}", hex::encode(buf)))
+            row.text(&hex::encode(buf));
         }
     };
     let cursor_end = cursor.position();
@@ -807,7 +778,7 @@ pub fn tdf_read_into_str(tdf_id: &u16, size: u8, cursor: &mut Cursor<&[u8]>) ->
     if underflow > 0 {
         crate::decoders::tdf_field_read_string(cursor, cursor_start, 0, underflow as u8)?;
     }
-    res
+    Ok(row.finish())
 }
 
 #[cfg(test)]
@@ -846,7 +817,8 @@ mod tests {
         let bytes = tdf34_base_bytes();
         let mut cursor = Cursor::new(bytes.as_slice());
 
-        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor).unwrap();
+        let row =
+            tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &CsvFormat::default()).unwrap();
 
         assert_eq!(row, "1,2,3,4,5,-6,-7,,,,,");
     }
@@ -857,7 +829,8 @@ mod tests {
         push_tdf34_neighbour(&mut bytes, 100, 11, 2500, 8, -9);
         let mut cursor = Cursor::new(bytes.as_slice());
 
-        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor).unwrap();
+        let row =
+            tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &CsvFormat::default()).unwrap();
 
         assert_eq!(row, "1,2,3,4,5,-6,-7,100,11,2.5,-8,-9");
     }
@@ -869,7 +842,8 @@ mod tests {
         push_tdf34_neighbour(&mut bytes, 200, 12, 3000, 10, -11);
         let mut cursor = Cursor::new(bytes.as_slice());
 
-        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor).unwrap();
+        let row =
+            tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &CsvFormat::default()).unwrap();
 
         assert_eq!(
             row,
@@ -877,6 +851,108 @@ mod tests {
         );
     }
 
+    #[test]
+    fn trailing_variable_array_rows_follow_csv_format() {
+        let mut bytes = tdf34_base_bytes();
+        push_tdf34_neighbour(&mut bytes, 100, 11, 2500, 8, -9);
+        push_tdf34_neighbour(&mut bytes, 200, 12, 3000, 10, -11);
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let format = CsvFormat {
+            delimiter: ';',
+            decimal_separator: ',',
+            ..CsvFormat::default()
+        };
+
+        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &format).unwrap();
+
+        assert_eq!(
+            row,
+            "1;2;3;4;5;-6;-7;100;11;2,5;-8;-9\n;;;;;;;;200;12;3;-10;-11"
+        );
+    }
+
+    #[test]
+    fn trailing_variable_array_json_mode_uses_single_cell() {
+        let mut bytes = tdf34_base_bytes();
+        push_tdf34_neighbour(&mut bytes, 100, 11, 2500, 8, -9);
+        push_tdf34_neighbour(&mut bytes, 200, 12, 3000, 10, -11);
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let format = CsvFormat {
+            vla_mode: CsvVlaMode::Json,
+            ..CsvFormat::default()
+        };
+
+        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &format).unwrap();
+
+        assert_eq!(
+            row,
+            r#"1,2,3,4,5,-6,-7,"[{""earfcn"":100,""pci"":11,""time_diff"":2.5,""rsrp"":-8,""rsrq"":-9},{""earfcn"":200,""pci"":12,""time_diff"":3,""rsrp"":-10,""rsrq"":-11}]""#
+        );
+        assert_eq!(
+            tdf_csv_fields(&34, CsvVlaMode::Json).last().unwrap(),
+            "neighbours"
+        );
+    }
+
+    #[test]
+    fn trailing_variable_array_json_mode_plain_values() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&0x12345678u32.to_le_bytes());
+        bytes.extend_from_slice(&0x90abcdefu32.to_le_bytes());
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let format = CsvFormat {
+            vla_mode: CsvVlaMode::Json,
+            ..CsvFormat::default()
+        };
+
+        let row = tdf_read_into_str(&52, bytes.len() as u8, &mut cursor, &format).unwrap();
+
+        assert_eq!(row, r#""[""0x12345678"",""0x90abcdef""]""#);
+    }
+
+    #[test]
+    fn trailing_variable_array_wide_mode_pads_and_truncates() {
+        let mut bytes = tdf34_base_bytes();
+        push_tdf34_neighbour(&mut bytes, 100, 11, 2500, 8, -9);
+        let format = CsvFormat {
+            vla_mode: CsvVlaMode::Wide(2),
+            ..CsvFormat::default()
+        };
+
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &format).unwrap();
+        assert_eq!(row, "1,2,3,4,5,-6,-7,100,11,2.5,-8,-9,,,,,");
+
+        push_tdf34_neighbour(&mut bytes, 200, 12, 3000, 10, -11);
+        push_tdf34_neighbour(&mut bytes, 300, 13, 3500, 12, -13);
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let row = tdf_read_into_str(&34, bytes.len() as u8, &mut cursor, &format).unwrap();
+        assert_eq!(row, "1,2,3,4,5,-6,-7,100,11,2.5,-8,-9,200,12,3,-10,-11");
+        assert_eq!(cursor.position(), bytes.len() as u64);
+
+        let fields = tdf_csv_fields(&34, CsvVlaMode::Wide(2));
+        assert_eq!(fields.len(), 7 + 2 * 5);
+        assert_eq!(fields[7], "neighbours[0].earfcn");
+        assert_eq!(fields[16], "neighbours[1].rsrq");
+    }
+
+    #[test]
+    fn float_precision_only_applies_to_converted_fields() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&21500i32.to_le_bytes());
+        bytes.extend_from_slice(&101325000u32.to_le_bytes());
+        bytes.extend_from_slice(&4550u16.to_le_bytes());
+        let mut cursor = Cursor::new(bytes.as_slice());
+        let format = CsvFormat {
+            float_precision: Some(2),
+            ..CsvFormat::default()
+        };
+
+        let row = tdf_read_into_str(&3, bytes.len() as u8, &mut cursor, &format).unwrap();
+
+        assert_eq!(row, "21.50,101325.00,45.50");
+    }
+
     #[test]
     fn trailing_variable_array_items_use_single_field_formatting() {
         let mut bytes = Vec::new();
@@ -884,7 +960,8 @@ mod tests {
         bytes.extend_from_slice(&0x90abcdefu32.to_le_bytes());
         let mut cursor = Cursor::new(bytes.as_slice());
 
-        let row = tdf_read_into_str(&52, bytes.len() as u8, &mut cursor).unwrap();
+        let row =
+            tdf_read_into_str(&52, bytes.len() as u8, &mut cursor, &CsvFormat::default()).unwrap();
 
         assert_eq!(row, "0x12345678\n,0x90abcdef");
     }
@@ -897,7 +974,8 @@ mod tests {
         bytes.extend_from_slice(&[0xab, 0xcd, 0xef]);
         let mut cursor = Cursor::new(bytes.as_slice());
 
-        let row = tdf_read_into_str(&25, bytes.len() as u8, &mut cursor).unwrap();
+        let row =
+            tdf_read_into_str(&25, bytes.len() as u8, &mut cursor, &CsvFormat::default()).unwrap();
 
         assert_eq!(row, "0x12345678,9,abcdef");
     }
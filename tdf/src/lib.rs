@@ -6,6 +6,7 @@ use std::{
     path::PathBuf,
 };
 
+pub mod csv_format;
 pub mod decoders;
 pub mod decoders_csv;
 pub mod decoders_parquet;
@@ -15,6 +16,9 @@ const TDF_TIME_MASK: u16 = 0xC000;
 const TDF_ARRAY_MASK: u16 = 0x3000;
 const TDF_ID_MASK: u16 = 0x0FFF;
 
+/// Largest TDF ID that can be encoded in a TDF header
+pub const TDF_ID_MAX: u16 = TDF_ID_MASK;
+
 const TDF_TIME_NONE: u16 = 0x0000;
 const TDF_TIME_GLOBAL: u16 = 0x4000;
 const TDF_TIME_RELATIVE_U16: u16 = 0x8000;
@@ -3,10 +3,13 @@ use chrono::DateTime;
 const GPS_UNIX_OFFSET_SECONDS_BASE: i64 = 315964800;
 const GPS_UNIX_OFFSET_SECONDS_LEAP: i64 = 18;
 
+/// TDF time units per second
+pub const TDF_TICKS_PER_SECOND: i64 = 65536;
+
 pub fn tdf_time_to_unix(tdf_time: i64) -> (i64, u32) {
     let unix_seconds =
         (tdf_time >> 16) + GPS_UNIX_OFFSET_SECONDS_BASE - GPS_UNIX_OFFSET_SECONDS_LEAP;
-    let unix_nano = (1_000_000_000 * (tdf_time & 0xFFFF)) / 65536;
+    let unix_nano = (1_000_000_000 * (tdf_time & 0xFFFF)) / TDF_TICKS_PER_SECOND;
 
     (unix_seconds, unix_nano as u32)
 }
@@ -16,6 +19,16 @@ pub fn tdf_time_to_unix_micros(tdf_time: i64) -> i64 {
     (unix_seconds * 1_000_000) + (unix_nano as i64 / 1_000)
 }
 
+pub fn unix_micros_to_tdf_time(unix_micros: i64) -> i64 {
+    let unix_seconds = unix_micros.div_euclid(1_000_000);
+    let micros = unix_micros.rem_euclid(1_000_000);
+    let tdf_seconds = unix_seconds - GPS_UNIX_OFFSET_SECONDS_BASE + GPS_UNIX_OFFSET_SECONDS_LEAP;
+    // Round up so that the TDF time is never earlier than the requested time
+    let subseconds = (micros * TDF_TICKS_PER_SECOND + 999_999) / 1_000_000;
+
+    (tdf_seconds << 16) + subseconds
+}
+
 pub fn tdf_time_to_datetime(tdf_time: i64) -> Option<chrono::DateTime<chrono::Utc>> {
     let (unix_seconds, unix_nano) = tdf_time_to_unix(tdf_time);
     DateTime::from_timestamp(unix_seconds as i64, unix_nano as u32)